@@ -0,0 +1,31 @@
+fn main() {
+    // The C header is only generated for builds exporting the C API.
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+/// Generates the C header into `OUT_DIR`, where the tests compare it with the committed
+/// include/qmldiff.h. The committed header is only rewritten if `QMLDIFF_GENERATE_HEADER` is set,
+/// so that building the crate doesn't modify its sources.
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    // cbindgen parses the whole crate.
+    println!("cargo:rerun-if-changed=src");
+    println!("cargo:rerun-if-changed=Cargo.toml");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=QMLDIFF_GENERATE_HEADER");
+
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).unwrap();
+    match cbindgen::generate_with_config(&crate_dir, config) {
+        // Only rewritten if the declarations have changed.
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/qmldiff.h", out_dir));
+            if std::env::var_os("QMLDIFF_GENERATE_HEADER").is_some() {
+                bindings.write_to_file(format!("{}/include/qmldiff.h", crate_dir));
+            }
+        }
+        Err(error) => println!("cargo:warning=Cannot generate the C header: {}", error),
+    }
+}
@@ -0,0 +1,246 @@
+//! Simulates a QML engine host using the C API the way a real integration would: the diffs (and
+//! the hashtab stored along with them) are loaded into a context once, then every QML file of the
+//! tree is fed through `qmldiff_ctx_process_file` from several threads at once.
+//!
+//! Usage: `cargo run --example host_sim -- <diff directory> <QML root> [threads] [rounds]`
+//!
+//! Every round processes the whole tree again, so a high amount of rounds turns the example into
+//! a stress test of the state kept by the library.
+
+use std::{
+    ffi::{c_char, CStr, CString},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+// Links the static library's exported symbols into the example.
+use qmldiff as _;
+
+type ExternalLoaderFunc = unsafe extern "C" fn(file_name: *const c_char);
+type ExternalContentsLoaderFunc =
+    unsafe extern "C" fn(file_name: *const c_char, length: *mut usize) -> *const c_char;
+
+#[repr(C)]
+struct QMLDiffContext {
+    _opaque: [u8; 0],
+}
+
+extern "C" {
+    fn qmldiff_ctx_new() -> *mut QMLDiffContext;
+    fn qmldiff_ctx_free(ctx: *mut QMLDiffContext);
+    fn qmldiff_free_string(value: *const c_char);
+    fn qmldiff_ctx_set_external_loader(
+        ctx: *mut QMLDiffContext,
+        external_loader: ExternalLoaderFunc,
+    );
+    fn qmldiff_ctx_set_external_contents_loader(
+        ctx: *mut QMLDiffContext,
+        contents_loader: ExternalContentsLoaderFunc,
+    );
+    fn qmldiff_ctx_build_change_files(ctx: *mut QMLDiffContext, root_dir: *const c_char) -> i32;
+    fn qmldiff_ctx_is_modified(ctx: *mut QMLDiffContext, file_name: *const c_char) -> bool;
+    fn qmldiff_ctx_get_modified_files(ctx: *mut QMLDiffContext) -> *const c_char;
+    fn qmldiff_ctx_process_file(
+        ctx: *mut QMLDiffContext,
+        file_name: *const c_char,
+        raw_contents: *const c_char,
+        contents_size: usize,
+    ) -> *const c_char;
+    fn qmldiff_ctx_get_stats(ctx: *mut QMLDiffContext) -> *const c_char;
+}
+
+/// The context shared by the threads. The library locks the state of a context itself.
+#[derive(Clone, Copy)]
+struct Context(*mut QMLDiffContext);
+
+unsafe impl Send for Context {}
+unsafe impl Sync for Context {}
+
+static EXTERNAL_LOADS: AtomicUsize = AtomicUsize::new(0);
+
+/// Notified about `LOAD EXTERNAL` statements. A real host would register the file with its
+/// resource system here.
+unsafe extern "C" fn external_loader(file_name: *const c_char) {
+    EXTERNAL_LOADS.fetch_add(1, Ordering::Relaxed);
+    eprintln!(
+        "[host_sim]: External file requested: {}",
+        CStr::from_ptr(file_name).to_string_lossy()
+    );
+}
+
+/// Asked for the contents of `LOAD`ed files. Returning NULL lets qmldiff read them from disk.
+unsafe extern "C" fn external_contents_loader(
+    _file_name: *const c_char,
+    _length: *mut usize,
+) -> *const c_char {
+    std::ptr::null()
+}
+
+/// Copies a string returned by the library, and frees it.
+unsafe fn take_string(value: *const c_char) -> Option<String> {
+    if value.is_null() {
+        return None;
+    }
+    let copied = CStr::from_ptr(value).to_string_lossy().to_string();
+    qmldiff_free_string(value);
+    Some(copied)
+}
+
+fn collect_qml_files(directory: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(directory) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_qml_files(&path, files);
+        } else if path.extension().is_some_and(|e| e == "qml") {
+            files.push(path);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Results {
+    processed: AtomicUsize,
+    modified: AtomicUsize,
+    unchanged: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+/// Processes the files the way the QML engine would load them - the file name the diffs refer to
+/// is the path within the QML root.
+fn process(ctx: Context, qml_root: &Path, files: &[PathBuf], results: &Results, print: bool) {
+    for path in files {
+        let name = format!(
+            "/{}",
+            path.strip_prefix(qml_root).unwrap().to_string_lossy()
+        );
+        let Ok(contents) = std::fs::read_to_string(path) else {
+            results.failed.fetch_add(1, Ordering::Relaxed);
+            continue;
+        };
+        let c_name = CString::new(name.clone()).unwrap();
+        let c_contents = CString::new(contents.clone()).unwrap();
+        results.processed.fetch_add(1, Ordering::Relaxed);
+        unsafe {
+            if !qmldiff_ctx_is_modified(ctx.0, c_name.as_ptr()) {
+                continue;
+            }
+            let processed = qmldiff_ctx_process_file(
+                ctx.0,
+                c_name.as_ptr(),
+                c_contents.as_ptr(),
+                contents.len(),
+            );
+            match take_string(processed) {
+                Some(processed) => {
+                    results.modified.fetch_add(1, Ordering::Relaxed);
+                    if print {
+                        println!(
+                            "[host_sim]: {} ({} -> {} bytes)",
+                            name,
+                            contents.len(),
+                            processed.len()
+                        );
+                    }
+                }
+                // Either no directive modified the file, or it failed to process - the library
+                // logs the errors, and counts them in its stats.
+                None => {
+                    results.unchanged.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.len() < 2 {
+        eprintln!("Usage: host_sim <diff directory> <QML root> [threads] [rounds]");
+        std::process::exit(2);
+    }
+    let diff_dir = &args[0];
+    let qml_root = PathBuf::from(&args[1]);
+    let threads: usize = args
+        .get(2)
+        .map_or(4, |e| e.parse::<usize>().unwrap().max(1));
+    let rounds: usize = args.get(3).map_or(1, |e| e.parse().unwrap());
+
+    // The initialization sequence - the loaders have to be set before the diffs are loaded.
+    let ctx = Context(unsafe { qmldiff_ctx_new() });
+    let c_diff_dir = CString::new(diff_dir.as_str()).unwrap();
+    let loaded = unsafe {
+        qmldiff_ctx_set_external_loader(ctx.0, external_loader);
+        qmldiff_ctx_set_external_contents_loader(ctx.0, external_contents_loader);
+        qmldiff_ctx_build_change_files(ctx.0, c_diff_dir.as_ptr())
+    };
+    println!("[host_sim]: Loaded {} diff file(s)", loaded);
+    // A file system hook layer would install its hooks for these paths.
+    let modified =
+        unsafe { take_string(qmldiff_ctx_get_modified_files(ctx.0)) }.unwrap_or_default();
+    println!(
+        "[host_sim]: The diffs affect {} file(s)",
+        modified.lines().count()
+    );
+
+    let mut files = Vec::new();
+    collect_qml_files(&qml_root, &mut files);
+    files.sort();
+    println!("[host_sim]: Found {} QML file(s)", files.len());
+
+    let results = Arc::new(Results::default());
+    let files = Arc::new(files);
+    let qml_root = Arc::new(qml_root);
+    for round in 0..rounds {
+        let workers: Vec<_> = (0..threads)
+            .map(|thread| {
+                let (results, files, qml_root) = (results.clone(), files.clone(), qml_root.clone());
+                std::thread::spawn(move || {
+                    // Every thread loads a different part of the tree, like a QML engine
+                    // loading components in parallel.
+                    let chunk: Vec<_> = files
+                        .iter()
+                        .skip(thread)
+                        .step_by(threads)
+                        .cloned()
+                        .collect();
+                    process(ctx, &qml_root, &chunk, &results, round == 0);
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+    }
+
+    println!(
+        "[host_sim]: {} file(s) loaded in {} round(s) by {} thread(s): {} modified, {} affected but \
+         unchanged, {} unreadable",
+        results.processed.load(Ordering::Relaxed),
+        rounds,
+        threads,
+        results.modified.load(Ordering::Relaxed),
+        results.unchanged.load(Ordering::Relaxed),
+        results.failed.load(Ordering::Relaxed),
+    );
+    println!(
+        "[host_sim]: {} external file(s) requested",
+        EXTERNAL_LOADS.load(Ordering::Relaxed)
+    );
+    let stats = unsafe { take_string(qmldiff_ctx_get_stats(ctx.0)) }.unwrap_or_default();
+    print!("{}", stats);
+    unsafe { qmldiff_ctx_free(ctx.0) };
+    // Makes the example usable as a CI check.
+    let files_failed = stats
+        .lines()
+        .find_map(|line| line.strip_prefix("files_failed: "))
+        .map_or(0, |count| count.trim().parse().unwrap_or(0));
+    if files_failed != 0 || results.failed.load(Ordering::Relaxed) != 0 {
+        std::process::exit(1);
+    }
+}
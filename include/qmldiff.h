@@ -0,0 +1,196 @@
+#ifndef QMLDIFF_H
+#define QMLDIFF_H
+
+/* Generated by cbindgen from src/ffi.rs - do not edit. The API is described in README.MD. */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#if !defined(QMLDIFF_NO_HASHRULES)
+#define QMLDIFF_HASHRULES
+#endif
+#if !defined(QMLDIFF_NO_SIGNATURES)
+#define QMLDIFF_SIGNATURES
+#endif
+
+typedef struct QMLDiffContext QMLDiffContext;
+
+typedef void (*CExternalLoaderFunc)(const char *file_name);
+
+typedef const char *(*CExternalContentsLoaderFunc)(const char *file_name, size_t *length);
+
+typedef void *(*CVfsOpenFunc)(const char *path);
+
+typedef ptrdiff_t (*CVfsReadFunc)(void *handle, char *buffer, size_t size);
+
+typedef void (*CVfsCloseFunc)(void *handle);
+
+typedef void (*CVfsListEntryFunc)(const char *name, void *context);
+
+
+
+
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct QMLDiffContext *qmldiff_ctx_new(void);
+
+void qmldiff_ctx_free(struct QMLDiffContext *ctx);
+
+void qmldiff_free_string(const char *value);
+
+void qmldiff_ctx_set_external_loader(struct QMLDiffContext *ctx, CExternalLoaderFunc external_loader);
+
+void qmldiff_ctx_set_external_contents_loader(struct QMLDiffContext *ctx, CExternalContentsLoaderFunc contents_loader);
+
+void qmldiff_ctx_set_vfs(struct QMLDiffContext *ctx, CVfsOpenFunc open, CVfsReadFunc read, CVfsCloseFunc close, bool (*list)(const char *directory, CVfsListEntryFunc emit, void *context));
+
+void qmldiff_ctx_set_version(struct QMLDiffContext *ctx, const char *version);
+
+void qmldiff_ctx_set_define(struct QMLDiffContext *ctx, const char *name, const char *value);
+
+void qmldiff_ctx_set_require_hashed(struct QMLDiffContext *ctx, bool require_hashed);
+
+void qmldiff_ctx_set_defer_hashed_files(struct QMLDiffContext *ctx, bool defer);
+
+void qmldiff_ctx_set_strict_rebuild_order(struct QMLDiffContext *ctx, bool strict);
+
+void qmldiff_ctx_set_qml_extensions(struct QMLDiffContext *ctx, bool enabled);
+
+void qmldiff_ctx_set_limits(struct QMLDiffContext *ctx, size_t max_load_depth, size_t max_slot_depth, size_t max_expansion_tokens, size_t max_diff_tokens);
+
+#if defined(QMLDIFF_SIGNATURES)
+bool qmldiff_ctx_set_public_key(struct QMLDiffContext *ctx, const uint8_t *key);
+#endif
+
+void qmldiff_ctx_set_emitter_config(struct QMLDiffContext *ctx, size_t indent_width, bool use_tabs, bool crlf, size_t max_inline_tokens);
+
+void qmldiff_ctx_set_max_line_width(struct QMLDiffContext *ctx, size_t max_line_width);
+
+void qmldiff_ctx_set_minify(struct QMLDiffContext *ctx, bool minify);
+
+void qmldiff_ctx_set_return_unchanged(struct QMLDiffContext *ctx, bool return_unchanged);
+
+void qmldiff_ctx_set_strict(struct QMLDiffContext *ctx, bool strict);
+
+bool qmldiff_ctx_set_id_check(struct QMLDiffContext *ctx, const char *mode);
+
+void qmldiff_ctx_set_debug(struct QMLDiffContext *ctx, bool debug);
+
+#if defined(QMLDIFF_HASHRULES)
+void qmldiff_load_rules(const char *rules);
+#endif
+
+#if defined(QMLDIFF_HASHRULES)
+bool qmldiff_append_rules(const char *rules);
+#endif
+
+bool qmldiff_ctx_add_external_diff(struct QMLDiffContext *ctx, const char *change_file_contents, const char *file_identifier);
+
+int32_t qmldiff_ctx_build_change_files(struct QMLDiffContext *ctx, const char *root_dir);
+
+bool qmldiff_ctx_is_modified(struct QMLDiffContext *ctx, const char *file_name);
+
+const char *qmldiff_ctx_get_modified_files(struct QMLDiffContext *ctx);
+
+const char *qmldiff_ctx_get_affecting_diffs(struct QMLDiffContext *ctx, const char *file_name);
+
+const char *qmldiff_ctx_get_diff_metadata(struct QMLDiffContext *ctx, const char *diff_name);
+
+void qmldiff_ctx_disable_slots_while_processing(struct QMLDiffContext *ctx);
+
+void qmldiff_ctx_enable_slots_while_processing(struct QMLDiffContext *ctx);
+
+const char *qmldiff_ctx_process_file(struct QMLDiffContext *ctx, const char *file_name, const char *raw_contents, size_t contents_size);
+
+size_t qmldiff_ctx_process_files(struct QMLDiffContext *ctx, size_t count, const char *const *file_names, const char *const *contents, const char **results);
+
+ptrdiff_t qmldiff_ctx_query_file(struct QMLDiffContext *ctx, const char *file_name, const char *contents, const char *selector);
+
+const char *qmldiff_ctx_get_created_file(struct QMLDiffContext *ctx, const char *file_name);
+
+const char *qmldiff_ctx_get_redirect_source(struct QMLDiffContext *ctx, const char *file_name);
+
+const char *qmldiff_ctx_get_stats(struct QMLDiffContext *ctx);
+
+void qmldiff_start_saving_thread(void);
+
+void qmldiff_set_save_interval(uint32_t seconds);
+
+bool qmldiff_save_hashtab_now(void);
+
+bool qmldiff_stop_saving_thread(void);
+
+void qmldiff_set_external_loader(CExternalLoaderFunc external_loader);
+
+void qmldiff_set_external_contents_loader(CExternalContentsLoaderFunc contents_loader);
+
+void qmldiff_set_vfs(CVfsOpenFunc open, CVfsReadFunc read, CVfsCloseFunc close, bool (*list)(const char *directory, CVfsListEntryFunc emit, void *context));
+
+void qmldiff_set_version(const char *version);
+
+void qmldiff_set_define(const char *name, const char *value);
+
+void qmldiff_set_require_hashed(bool require_hashed);
+
+void qmldiff_set_defer_hashed_files(bool defer);
+
+void qmldiff_set_strict_rebuild_order(bool strict);
+
+void qmldiff_set_qml_extensions(bool enabled);
+
+void qmldiff_set_limits(size_t max_load_depth, size_t max_slot_depth, size_t max_expansion_tokens, size_t max_diff_tokens);
+
+#if defined(QMLDIFF_SIGNATURES)
+bool qmldiff_set_public_key(const uint8_t *key);
+#endif
+
+void qmldiff_set_emitter_config(size_t indent_width, bool use_tabs, bool crlf, size_t max_inline_tokens);
+
+void qmldiff_set_max_line_width(size_t max_line_width);
+
+void qmldiff_set_minify(bool minify);
+
+void qmldiff_set_return_unchanged(bool return_unchanged);
+
+void qmldiff_set_strict(bool strict);
+
+bool qmldiff_set_id_check(const char *mode);
+
+void qmldiff_set_debug(bool debug);
+
+bool qmldiff_add_external_diff(const char *change_file_contents, const char *file_identifier);
+
+int32_t qmldiff_build_change_files(const char *root_dir);
+
+bool qmldiff_is_modified(const char *file_name);
+
+const char *qmldiff_get_modified_files(void);
+
+const char *qmldiff_get_affecting_diffs(const char *file_name);
+
+const char *qmldiff_get_diff_metadata(const char *diff_name);
+
+void qmldiff_disable_slots_while_processing(void);
+
+void qmldiff_enable_slots_while_processing(void);
+
+const char *qmldiff_process_file(const char *file_name, const char *raw_contents, size_t contents_size);
+
+size_t qmldiff_process_files(size_t count, const char *const *file_names, const char *const *contents, const char **results);
+
+ptrdiff_t qmldiff_query_file(const char *file_name, const char *contents, const char *selector);
+
+const char *qmldiff_get_created_file(const char *file_name);
+
+const char *qmldiff_get_redirect_source(const char *file_name);
+
+const char *qmldiff_get_stats(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* QMLDIFF_H */
@@ -0,0 +1,35 @@
+//! The lexers, parsers and emitters of qmldiff, without anything which needs `std` (the file
+//! system, threads, hashtabs or the global state of the C API). Only `alloc` is required.
+//!
+//! The modules are the same files the `qmldiff` crate is built from, so they keep its paths:
+//! `parser::qml::parser::Parser` parses QML, `parser::qml::emitter` emits it back.
+#![cfg_attr(not(test), no_std)]
+#![allow(dead_code)]
+extern crate alloc;
+
+#[path = "../../src/hash.rs"]
+pub mod hash;
+pub mod parser;
+
+#[cfg(test)]
+mod test {
+    use alloc::boxed::Box;
+
+    use crate::parser::{
+        common::StringCharacterTokenizer,
+        qml::{
+            emitter::{emit, flatten_lines, EmitterConfig},
+            lexer::Lexer,
+            parser::Parser,
+        },
+    };
+
+    #[test]
+    fn test_parse_and_emit_without_std() {
+        let qml = "import QtQuick 2.15\nItem { id: root; width: 5 }\n";
+        let lexer = Lexer::new(StringCharacterTokenizer::new(qml.into()));
+        let tree = Parser::new(Box::new(lexer)).parse().unwrap();
+        let emitted = flatten_lines(&emit(&tree, &EmitterConfig::default()));
+        assert!(emitted.contains("width: 5"));
+    }
+}
@@ -0,0 +1,6 @@
+// The diff parser resolves `LOAD` statements through the file system, so only the lexer and the
+// emitter are available without `std`.
+#[path = "../../../../src/parser/diff/emitter.rs"]
+pub mod emitter;
+#[path = "../../../../src/parser/diff/lexer.rs"]
+pub mod lexer;
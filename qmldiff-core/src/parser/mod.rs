@@ -0,0 +1,6 @@
+#[path = "../../../src/parser/common.rs"]
+pub mod common;
+pub mod diff;
+pub mod qml;
+#[path = "../../../src/parser/qmldir.rs"]
+pub mod qmldir;
@@ -0,0 +1,6 @@
+#[path = "../../../../src/parser/qml/emitter.rs"]
+pub mod emitter;
+#[path = "../../../../src/parser/qml/lexer.rs"]
+pub mod lexer;
+#[path = "../../../../src/parser/qml/parser.rs"]
+pub mod parser;
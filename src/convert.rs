@@ -0,0 +1,131 @@
+//! Conversions between QML code, the token streams diffs insert, and the parsed objects they're
+//! inserted into - for tools which build or inspect QML code the way the diffs do.
+//!
+//! ```
+//! use qmldiff::convert::{object_to_tokens, string_to_object, tokens_to_string};
+//!
+//! let object = string_to_object("Rectangle { width: 100; Text { text: \"hi\" } }").unwrap();
+//! assert_eq!(object.name, "Rectangle");
+//! assert_eq!(object.children.len(), 2);
+//!
+//! // Objects survive being turned into tokens and back.
+//! let code = tokens_to_string(&object_to_tokens(&object));
+//! assert_eq!(string_to_object(&code).unwrap(), object);
+//! ```
+
+use anyhow::{Error, Result};
+
+use crate::{
+    parser::{
+        common::StringCharacterTokenizer,
+        qml::{
+            emitter::{
+                emit_object, emit_object_list_to_token_stream, emit_object_to_token_stream,
+                emit_significant_token_stream, flatten_lines_with, EmitterConfig,
+            },
+            lexer::{Lexer, TokenType},
+            parser::{Object, TreeElement},
+        },
+    },
+    util::common_util::parse_qml_from_chain,
+};
+
+/// Splits QML code into tokens. Unlike iterating over the `Lexer`, code which cannot be
+/// tokenized is an error instead of being skipped.
+///
+/// ```
+/// use qmldiff::{convert::string_to_tokens, parser::qml::lexer::TokenType};
+///
+/// let tokens = string_to_tokens("width: 100").unwrap();
+/// assert_eq!(tokens[0], TokenType::Identifier("width".to_string()));
+/// assert_eq!(tokens.last(), Some(&TokenType::Number("100".to_string())));
+/// ```
+pub fn string_to_tokens(code: &str) -> Result<Vec<TokenType>> {
+    let mut lexer = Lexer::new(StringCharacterTokenizer::new(code.to_string()));
+    let mut tokens = Vec::new();
+    loop {
+        match lexer.next_token()? {
+            TokenType::EndOfStream => return Ok(tokens),
+            token => tokens.push(token),
+        }
+    }
+}
+
+/// Joins the tokens back into code, keeping their whitespace and comments.
+///
+/// ```
+/// use qmldiff::convert::{string_to_tokens, tokens_to_string};
+///
+/// let code = "x: a + b // sum";
+/// assert_eq!(tokens_to_string(&string_to_tokens(code).unwrap()), code);
+/// ```
+pub fn tokens_to_string(tokens: &[TokenType]) -> String {
+    tokens.iter().map(|e| e.to_string()).collect()
+}
+
+/// Joins the tokens without their whitespace and comments - the way selectors compare the
+/// values of properties.
+///
+/// ```
+/// use qmldiff::convert::{string_to_tokens, tokens_to_significant_string};
+///
+/// let tokens = string_to_tokens("parent.width / 2 /* half */").unwrap();
+/// assert_eq!(tokens_to_significant_string(&tokens), "parent.width/2");
+/// ```
+pub fn tokens_to_significant_string(tokens: &[TokenType]) -> String {
+    emit_significant_token_stream(tokens)
+}
+
+/// The tokens of the whole object - `Name { ... }`.
+pub fn object_to_tokens(object: &Object) -> Vec<TokenType> {
+    emit_object_to_token_stream(object, false)
+}
+
+/// The tokens of the object's children, without its name and braces - what an `INSERT` needs to
+/// add the same children to another object.
+///
+/// ```
+/// use qmldiff::convert::{object_body_to_tokens, string_to_object, tokens_to_significant_string};
+///
+/// let object = string_to_object("Item { width: 1 }").unwrap();
+/// assert_eq!(tokens_to_significant_string(&object_body_to_tokens(&object)), "width:1");
+/// ```
+pub fn object_body_to_tokens(object: &Object) -> Vec<TokenType> {
+    emit_object_to_token_stream(object, true)
+}
+
+/// The tokens of a list of objects - `[Name { ... }, Name { ... }]`.
+pub fn object_list_to_tokens(objects: &[Object]) -> Vec<TokenType> {
+    emit_object_list_to_token_stream(objects)
+}
+
+/// Parses the tokens of a single object. Imports and pragmas are not allowed.
+pub fn tokens_to_object(tokens: Vec<TokenType>) -> Result<Object> {
+    let mut elements = parse_qml_from_chain(tokens)?;
+    match (elements.pop(), elements.is_empty()) {
+        (Some(TreeElement::Object(object)), true) => Ok(object),
+        _ => Err(Error::msg("Expected the code of a single object")),
+    }
+}
+
+/// Parses the code of a single object.
+pub fn string_to_object(code: &str) -> Result<Object> {
+    tokens_to_object(string_to_tokens(code)?)
+}
+
+/// Emits the object as formatted code.
+///
+/// ```
+/// use qmldiff::{
+///     convert::{object_to_string, string_to_object},
+///     parser::qml::emitter::EmitterConfig,
+/// };
+///
+/// let object = string_to_object("Item { id: root; Text {} }").unwrap();
+/// let code = object_to_string(&object, &EmitterConfig::default());
+/// assert!(code.starts_with("Item {\n    id: root\n"));
+/// assert_eq!(string_to_object(&code).unwrap(), object);
+/// ```
+pub fn object_to_string(object: &Object, config: &EmitterConfig) -> String {
+    flatten_lines_with(&emit_object(object, 0, config), config)
+}
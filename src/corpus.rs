@@ -0,0 +1,238 @@
+use std::fmt::Write;
+
+/// How big the generated QML files should be.
+#[derive(Debug, Clone)]
+pub struct CorpusConfig {
+    pub files: usize,
+    /// How many levels of child objects every file has.
+    pub depth: usize,
+    /// The amount of child objects of every object above the deepest level.
+    pub width: usize,
+    /// The amount of statements in the body of every function.
+    pub function_lines: usize,
+    pub seed: u64,
+}
+
+impl Default for CorpusConfig {
+    fn default() -> Self {
+        Self {
+            files: 10,
+            depth: 4,
+            width: 3,
+            function_lines: 8,
+            seed: 0,
+        }
+    }
+}
+
+const OBJECT_TYPES: &[&str] = &[
+    "Item",
+    "Rectangle",
+    "Text",
+    "Column",
+    "Row",
+    "MouseArea",
+    "Image",
+    "Flickable",
+];
+const NUMERIC_PROPERTIES: &[&str] = &["width", "height", "x", "y", "z", "opacity", "spacing"];
+const STRING_PROPERTIES: &[&str] = &["text", "color", "source", "objectName"];
+const WORDS: &[&str] = &[
+    "lorem", "ipsum", "dolor", "sit", "amet", "alpha", "beta", "gamma", "delta", "value",
+];
+
+/// A small xorshift generator - the same seed always produces the same corpus.
+struct Random(u64);
+
+impl Random {
+    fn new(seed: u64) -> Self {
+        // The state of xorshift must never be zero.
+        Self(seed.wrapping_mul(0x9E3779B97F4A7C15) | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, max: usize) -> usize {
+        (self.next() % max as u64) as usize
+    }
+
+    fn chance(&mut self, percent: usize) -> bool {
+        self.below(100) < percent
+    }
+
+    fn pick<'a>(&mut self, values: &[&'a str]) -> &'a str {
+        values[self.below(values.len())]
+    }
+}
+
+struct Generator<'a> {
+    config: &'a CorpusConfig,
+    random: Random,
+    output: String,
+    /// Keeps the ids and the names of the declarations unique within a file.
+    counter: usize,
+}
+
+impl Generator<'_> {
+    fn line(&mut self, indent: usize, text: &str) {
+        let _ = writeln!(self.output, "{}{}", "    ".repeat(indent), text);
+    }
+
+    fn next_name(&mut self, prefix: &str) -> String {
+        self.counter += 1;
+        format!("{}{}", prefix, self.counter)
+    }
+
+    fn string_value(&mut self) -> String {
+        format!(
+            "\"{} {}\"",
+            self.random.pick(WORDS),
+            self.random.below(1000)
+        )
+    }
+
+    fn function_body(&mut self, indent: usize) {
+        let mut depth = 0;
+        for _ in 0..self.config.function_lines {
+            let word = self.random.pick(WORDS);
+            let number = self.random.below(100);
+            match self.random.below(5) {
+                0 if depth < 2 => {
+                    self.line(indent + depth, &format!("if ({} > {}) {{", word, number));
+                    depth += 1;
+                }
+                1 if depth > 0 => {
+                    depth -= 1;
+                    self.line(indent + depth, "}");
+                }
+                2 => self.line(
+                    indent + depth,
+                    &format!("console.log(\"{}\", {} * {});", word, word, number),
+                ),
+                _ => self.line(
+                    indent + depth,
+                    &format!("{} = ({} + {}) % 7;", word, word, number),
+                ),
+            }
+        }
+        while depth > 0 {
+            depth -= 1;
+            self.line(indent + depth, "}");
+        }
+        let word = self.random.pick(WORDS);
+        self.line(indent, &format!("return {};", word));
+    }
+
+    fn object(&mut self, name: &str, indent: usize, depth: usize) {
+        // Leaves are sometimes written on a single line.
+        if depth == 0 && self.random.chance(30) {
+            let property = self.random.pick(STRING_PROPERTIES);
+            let value = self.string_value();
+            self.line(
+                indent,
+                &format!("{} {{ {}: {}; visible: true }}", name, property, value),
+            );
+            return;
+        }
+
+        self.line(indent, &format!("{} {{", name));
+        let inner = indent + 1;
+        let id = self.next_name("object");
+        self.line(inner, &format!("id: {}", id));
+        let first = self.random.below(NUMERIC_PROPERTIES.len());
+        for i in 0..1 + self.random.below(3) {
+            let property = NUMERIC_PROPERTIES[(first + i) % NUMERIC_PROPERTIES.len()];
+            let value = self.random.below(500);
+            self.line(inner, &format!("{}: {}", property, value));
+        }
+        if self.random.chance(50) {
+            let property = self.random.pick(STRING_PROPERTIES);
+            let value = self.string_value();
+            self.line(inner, &format!("{}: {}", property, value));
+        }
+        if self.random.chance(40) {
+            self.line(inner, "anchors.fill: parent");
+        }
+        if self.random.chance(20) {
+            let comment = self.random.pick(WORDS);
+            self.line(inner, &format!("// {}", comment));
+        }
+        if self.random.chance(50) {
+            let property = self.next_name("counter");
+            let value = self.random.below(100);
+            self.line(inner, &format!("property int {}: {}", property, value));
+        }
+        if self.random.chance(30) {
+            let property = self.next_name("label");
+            let value = self.string_value();
+            self.line(
+                inner,
+                &format!("readonly property string {}: {}", property, value),
+            );
+        }
+        if self.random.chance(30) {
+            let signal = self.next_name("activated");
+            self.line(inner, &format!("signal {}(int index)", signal));
+        }
+        if self.random.chance(20) {
+            let name = self.next_name("Mode");
+            self.line(inner, &format!("enum {} {{", name));
+            self.line(inner + 1, "First,");
+            self.line(inner + 1, "Second = 5");
+            self.line(inner, "}");
+        }
+        if self.random.chance(40) {
+            let function = self.next_name("compute");
+            let word = self.random.pick(WORDS);
+            self.line(inner, &format!("function {}({}, index) {{", function, word));
+            self.function_body(inner + 1);
+            self.line(inner, "}");
+        }
+        if self.random.chance(30) {
+            self.line(inner, "onVisibleChanged: {");
+            self.function_body(inner + 1);
+            self.line(inner, "}");
+        }
+        if self.random.chance(10) {
+            self.line(inner, "states: [");
+            self.line(inner + 1, "State { name: \"on\" },");
+            self.line(inner + 1, "State { name: \"off\" }");
+            self.line(inner, "]");
+        }
+
+        if depth > 0 {
+            for _ in 0..self.config.width {
+                let child = self.random.pick(OBJECT_TYPES);
+                self.object(child, inner, depth - 1);
+            }
+        }
+        self.line(indent, "}");
+    }
+}
+
+/// Generates a pseudo-QML file. The files are meant to be parsed, hashed and diffed by benchmarks
+/// and fuzzers - they don't have to make sense when run.
+pub fn generate_qml(config: &CorpusConfig, index: usize) -> String {
+    let mut generator = Generator {
+        config,
+        random: Random::new(config.seed.wrapping_add(index as u64)),
+        output: String::new(),
+        counter: 0,
+    };
+    generator.line(0, "import QtQuick 2.15");
+    generator.line(0, "");
+    generator.object("Item", 0, config.depth);
+    generator.output
+}
+
+/// The names and contents of all the files of the corpus.
+pub fn generate_corpus(config: &CorpusConfig) -> Vec<(String, String)> {
+    (0..config.files)
+        .map(|i| (format!("Generated{}.qml", i), generate_qml(config, i)))
+        .collect()
+}
@@ -0,0 +1,389 @@
+use std::{
+    fmt::Display,
+    fs::read_to_string,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Error, Result};
+
+use crate::{
+    hashtab::{merge_hash_file, HashTab},
+    id_check::IdCheck,
+    limits::Limits,
+    parser::{
+        diff::parser::{Change, Defines, NodeTree},
+        qml::{emitter::EmitterConfig, lexer::QMLDiffExtensions},
+        qmldir::{is_qmldir, QmlDir},
+    },
+    processor::{
+        build_created_file, count_matches, find_and_process, find_and_process_qmldir,
+        find_redirect_source, RebuildOrder,
+    },
+    qml_compare::compare_trees,
+    slots::Slots,
+    util::common_util::{
+        filter_out_non_matching_versions, filter_out_unmet_requirements, load_diff_file,
+        order_changes, parse_qml, tokenize_qml,
+    },
+};
+
+/// A check made on a patched file.
+#[derive(Debug, Clone)]
+pub enum TestAssertion {
+    /// The patched file has to be semantically equivalent to this file.
+    Expect(PathBuf),
+    /// The selector has to match at least one object of the patched file.
+    Exists(String),
+    /// The selector must not match any object of the patched file.
+    Missing(String),
+    /// The diffs have to fail to apply to the file, with an error mentioning this text.
+    Error(String),
+}
+
+/// A QML file the diffs of a test are applied to.
+#[derive(Debug, Clone)]
+pub struct TestedFile {
+    /// `None` for the files the diffs `CREATE` or `REDIRECT` to.
+    pub path: Option<PathBuf>,
+    /// The name the diffs refer to the file by in their `AFFECT` statements.
+    pub name: String,
+    pub assertions: Vec<TestAssertion>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiffTest {
+    pub name: String,
+    pub diffs: Vec<PathBuf>,
+    pub hashtabs: Vec<PathBuf>,
+    pub version: Option<String>,
+    pub defines: Defines,
+    pub files: Vec<TestedFile>,
+}
+
+#[derive(Debug, Clone)]
+pub struct TestFailure {
+    /// The name of the checked file.
+    pub file: String,
+    pub message: String,
+}
+
+impl Display for TestFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.file, self.message)
+    }
+}
+
+/// Parses a test manifest. Every test starts with a `TEST <name>` line, followed by:
+/// - `DIFF <path>` - a diff applied by the test. Can be repeated
+/// - `HASHTAB <path>` - a hashtab used to resolve hashed diffs. Can be repeated
+/// - `VERSION <version>` / `DEFINE <name>[=<value>]` - the environment checked by the diffs
+/// - `INPUT <path> [AS <name>]` - a QML file the diffs are applied to, known to the diffs as
+///   `name` (its file name by default). Can be repeated
+/// - `CREATED <name>` - a file the diffs `CREATE` or `REDIRECT` to. Can be repeated
+/// - `EXPECT <path>` / `EXISTS <selector>` / `MISSING <selector>` / `ERROR <text>` - checks made
+///   on the patched version of the last `INPUT` or `CREATED` file
+///
+/// Paths are relative to the manifest. Empty lines and lines starting with `#` are ignored.
+pub fn parse_manifest(path: &Path) -> Result<Vec<DiffTest>> {
+    let contents = read_to_string(path)?;
+    let directory = path.parent().unwrap_or(Path::new(""));
+    let mut tests: Vec<DiffTest> = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |message: &str| {
+            Error::msg(format!("{}:{}: {}", path.to_string_lossy(), i + 1, message))
+        };
+        let (directive, argument) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let argument = argument.trim();
+        if argument.is_empty() {
+            return Err(error(&format!("{} requires an argument", directive)));
+        }
+        if directive == "TEST" {
+            tests.push(DiffTest {
+                name: argument.to_string(),
+                ..DiffTest::default()
+            });
+            continue;
+        }
+        let Some(test) = tests.last_mut() else {
+            return Err(error("Expected a TEST line"));
+        };
+        let assertion = match directive {
+            "DIFF" => {
+                test.diffs.push(directory.join(argument));
+                continue;
+            }
+            "HASHTAB" => {
+                test.hashtabs.push(directory.join(argument));
+                continue;
+            }
+            "VERSION" => {
+                test.version = Some(argument.to_string());
+                continue;
+            }
+            "DEFINE" => {
+                let (name, value) = argument.split_once('=').unwrap_or((argument, ""));
+                test.defines.insert(name.to_string(), value.to_string());
+                continue;
+            }
+            "INPUT" => {
+                let (input, name) = match argument.split_once(" AS ") {
+                    Some((input, name)) => (input.trim(), name.trim().to_string()),
+                    None => (
+                        argument,
+                        Path::new(argument)
+                            .file_name()
+                            .unwrap()
+                            .to_string_lossy()
+                            .to_string(),
+                    ),
+                };
+                test.files.push(TestedFile {
+                    path: Some(directory.join(input)),
+                    name,
+                    assertions: Vec::new(),
+                });
+                continue;
+            }
+            "CREATED" => {
+                test.files.push(TestedFile {
+                    path: None,
+                    name: argument.to_string(),
+                    assertions: Vec::new(),
+                });
+                continue;
+            }
+            "EXPECT" => TestAssertion::Expect(directory.join(argument)),
+            "EXISTS" => TestAssertion::Exists(argument.to_string()),
+            "MISSING" => TestAssertion::Missing(argument.to_string()),
+            "ERROR" => TestAssertion::Error(argument.to_string()),
+            _ => return Err(error(&format!("Unknown directive {}", directive))),
+        };
+        match test.files.last_mut() {
+            Some(file) => file.assertions.push(assertion),
+            None => {
+                return Err(error(&format!(
+                    "{} has to follow an INPUT or CREATED line",
+                    directive
+                )))
+            }
+        }
+    }
+
+    Ok(tests)
+}
+
+/// Checks the patched file, or the error the diffs failed with.
+fn check_assertion(
+    patched: &std::result::Result<String, String>,
+    name: &str,
+    assertion: &TestAssertion,
+) -> Result<Option<String>> {
+    let patched = match (patched, assertion) {
+        (Err(error), TestAssertion::Error(text)) => {
+            return Ok((!error.contains(text.as_str()))
+                .then(|| format!("The error doesn't mention \"{}\": {}", text, error)))
+        }
+        (Ok(_), TestAssertion::Error(text)) => {
+            return Ok(Some(format!(
+                "The diffs were applied, but were expected to fail with \"{}\"",
+                text
+            )))
+        }
+        (Err(error), _) => return Ok(Some(format!("Cannot apply the diffs: {}", error))),
+        (Ok(patched), _) => patched,
+    };
+    Ok(match assertion {
+        // qmldir files aren't QML - their entries are compared instead.
+        TestAssertion::Expect(expected_path) if is_qmldir(name) => {
+            let expected = QmlDir::parse(&read_to_string(expected_path)?);
+            let actual = QmlDir::parse(patched);
+            (expected.0 != actual.0).then(|| {
+                format!(
+                    "The output differs from {}:\n{}",
+                    expected_path.to_string_lossy(),
+                    actual
+                )
+            })
+        }
+        TestAssertion::Expect(expected_path) => {
+            let expected = parse_qml(
+                read_to_string(expected_path)?,
+                name,
+                None,
+                None,
+                QMLDiffExtensions::NONE,
+            )?;
+            let actual = parse_qml(
+                patched.to_string(),
+                name,
+                None,
+                None,
+                QMLDiffExtensions::NONE,
+            )?;
+            let differences = compare_trees(&expected, &actual);
+            (!differences.is_empty()).then(|| {
+                let mut message = format!(
+                    "The output differs from {}:",
+                    expected_path.to_string_lossy()
+                );
+                for difference in differences {
+                    message += &format!("\n{}", difference);
+                }
+                message
+            })
+        }
+        TestAssertion::Exists(selector) | TestAssertion::Missing(selector) => {
+            let count = count_matches(
+                tokenize_qml(
+                    patched.to_string(),
+                    name,
+                    None,
+                    None,
+                    QMLDiffExtensions::NONE,
+                ),
+                &NodeTree::parse(selector)?,
+            )?;
+            match (assertion, count) {
+                (TestAssertion::Exists(_), 0) => Some(format!("{} does not exist", selector)),
+                (TestAssertion::Missing(_), 1..) => {
+                    Some(format!("{} exists ({} match(es))", selector, count))
+                }
+                _ => None,
+            }
+        }
+        TestAssertion::Error(_) => unreachable!(),
+    })
+}
+
+fn load_changes(test: &DiffTest, hashtab: &HashTab) -> Result<(Vec<Change>, Slots)> {
+    let mut changes = Vec::new();
+    for path in &test.diffs {
+        let root_dir = path.parent().map(|e| e.to_string_lossy().to_string());
+        let mut diff = load_diff_file(
+            root_dir,
+            path,
+            hashtab,
+            None,
+            false,
+            false,
+            &Limits::default(),
+        )?;
+        let source = path.to_string_lossy();
+        filter_out_non_matching_versions(&mut diff, test.version.clone(), &source);
+        filter_out_unmet_requirements(&mut diff, &test.defines, &source);
+        changes.extend(diff);
+    }
+    order_changes(&mut changes)?;
+    let mut slots = Slots::new();
+    slots.update_slots(&mut changes);
+    slots.process_slots(&mut changes)?;
+    Ok((changes, slots))
+}
+
+/// Applies the changes to a file of the test - read from its `INPUT`, or built by the diffs
+/// which `CREATE` or `REDIRECT` to it.
+fn apply_changes(
+    test: &DiffTest,
+    file: &TestedFile,
+    changes: &[Change],
+    slots: &mut Slots,
+) -> Result<String> {
+    let emitter = EmitterConfig::default();
+    let created = build_created_file(&file.name, changes, slots, &emitter)?;
+    let redirected_from = find_redirect_source(&file.name, changes)?;
+    let contents = match (&file.path, created, redirected_from) {
+        (Some(path), None, None) => read_to_string(path)?,
+        (Some(_), _, _) => bail!(
+            "Cannot CREATE / REDIRECT to {} - the file already exists",
+            file.name
+        ),
+        (None, Some(created), _) => created,
+        (None, None, Some(original)) => {
+            let Some(path) = test
+                .files
+                .iter()
+                .find(|e| e.name == original)
+                .and_then(|e| e.path.as_ref())
+            else {
+                bail!(
+                    "{} is REDIRECTed from {}, which is not an INPUT of the test",
+                    file.name,
+                    original
+                );
+            };
+            read_to_string(path)?
+        }
+        (None, None, None) => bail!("No diff CREATEs or REDIRECTs to {}", file.name),
+    };
+    let (patched, _) = if is_qmldir(&file.name) {
+        find_and_process_qmldir(&file.name, &contents, changes, None)?
+    } else {
+        find_and_process(
+            &file.name,
+            tokenize_qml(
+                contents.clone(),
+                &file.name,
+                None,
+                None,
+                QMLDiffExtensions::NONE,
+            ),
+            changes,
+            slots,
+            None,
+            None,
+            &emitter,
+            IdCheck::Warn,
+            None,
+            RebuildOrder::default(),
+        )?
+    };
+    Ok(patched.unwrap_or(contents))
+}
+
+/// Applies the diffs of the test to its files, and checks the patched files.
+/// Returns all the failed checks - an empty list means the test has passed.
+pub fn run_test(test: &DiffTest) -> Result<Vec<TestFailure>> {
+    let mut hashtab = HashTab::new();
+    for path in &test.hashtabs {
+        merge_hash_file(path, &mut hashtab, None, None)?;
+    }
+    // Diffs which cannot be loaded fail to apply to every file.
+    let mut loaded = load_changes(test, &hashtab);
+
+    let mut failures = Vec::new();
+    for file in &test.files {
+        let failure = |message: String| TestFailure {
+            file: file.name.clone(),
+            message,
+        };
+        let patched = match &mut loaded {
+            Ok((changes, slots)) => apply_changes(test, file, changes, slots),
+            Err(error) => Err(Error::msg(format!("{:#}", error))),
+        }
+        .map_err(|e| format!("{:#}", e));
+        if let Err(error) = &patched {
+            if !file
+                .assertions
+                .iter()
+                .any(|e| matches!(e, TestAssertion::Error(_)))
+            {
+                failures.push(failure(format!("Cannot apply the diffs: {}", error)));
+                continue;
+            }
+        }
+        for assertion in &file.assertions {
+            match check_assertion(&patched, &file.name, assertion) {
+                Ok(None) => {}
+                Ok(Some(message)) => failures.push(failure(message)),
+                Err(error) => failures.push(failure(format!("{:?}", error))),
+            }
+        }
+    }
+
+    Ok(failures)
+}
@@ -0,0 +1,1899 @@
+//! The C API, along with the state it keeps between the calls.
+
+#[cfg(feature = "hashrules")]
+use crate::hashrules::HashRules;
+use crate::hashtab::{merge_hash_data, serialize_hashtab, write_hashtab_file, HashTab};
+use crate::id_check::IdCheck;
+use crate::limits::Limits;
+use crate::parser::diff::hash_processor::{resolve_hashed_file_name, resolve_hashed_files};
+use crate::parser::diff::parser::{Change, Defines, NodeTree, ObjectToChange};
+use crate::parser::qml::emitter::EmitterConfig;
+use crate::parser::qml::lexer::QMLDiffExtensions;
+use crate::parser::qmldir::is_qmldir;
+use crate::processor::{
+    build_created_file, count_matches, find_and_process, find_and_process_qmldir,
+    find_redirect_source, modified_files, RebuildOrder,
+};
+#[cfg(feature = "signatures")]
+use crate::signature::{signature_path, TrustedKey, PUBLIC_KEY_LENGTH};
+use crate::slots::Slots;
+use crate::util::common_util::{describe_failures, parse_diff, FailurePolicy, SourceFormat};
+use anyhow::Result;
+use lazy_static::lazy_static;
+use lib_util::{
+    include_if_building_hashtab, is_building_hashtab, CVfsCloseFunc, CVfsListEntryFunc,
+    CVfsOpenFunc, CVfsReadFunc, CVirtualFileSystem, ProcessingStats,
+};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::os::raw::c_void;
+use std::time::{Duration, Instant};
+use std::{
+    ffi::{c_char, CStr, CString},
+    sync::{Condvar, Mutex},
+    thread::JoinHandle,
+};
+
+use crate::parser::diff::parser::ExternalLoader;
+use crate::util::common_util::{
+    filter_out_non_matching_versions, filter_out_unmet_requirements, order_changes, tokenize_qml,
+};
+
+#[path = "util/lib_util.rs"]
+mod lib_util;
+
+type CExternalLoaderFunc = unsafe extern "C" fn(file_name: *const c_char) -> c_void;
+/// Returns a pointer to the contents of `file_name` and writes their length into `length`,
+/// or returns NULL if the host cannot provide the file. The buffer stays owned by the host;
+/// qmldiff copies it right after the callback returns.
+type CExternalContentsLoaderFunc =
+    unsafe extern "C" fn(file_name: *const c_char, length: *mut usize) -> *const c_char;
+
+/// Shared with the saver thread, to wake it up before the save interval passes.
+#[derive(Default)]
+struct SaverState {
+    /// Set to save the hashtab right away.
+    save_requested: bool,
+    stopping: bool,
+}
+
+/// The hashtab built from the processed files while `QMLDIFF_HASHTAB_CREATE` is set.
+#[derive(Default)]
+struct BuiltHashtab {
+    hashtab: HashTab,
+    /// The version set on the context which processed the files, saved into the hashtab file.
+    version: Option<String>,
+}
+
+#[derive(Clone, Copy, Default)]
+struct CExternalLoader {
+    notify: Option<CExternalLoaderFunc>,
+    contents: Option<CExternalContentsLoaderFunc>,
+}
+
+/// The loaded diffs, and the settings they are loaded and applied with. Hosts create contexts with
+/// `qmldiff_ctx_new` and pass them to the `qmldiff_ctx_*` functions, which can be called from many
+/// threads at once. The functions without a context work on the default one.
+///
+/// The hashtab built while `QMLDIFF_HASHTAB_CREATE` is set is shared by all the contexts.
+pub struct QMLDiffContext {
+    hashtab: Mutex<HashTab>,
+    slots: Mutex<Slots>,
+    changes: Mutex<Vec<Change>>,
+    post_init: Mutex<bool>,
+    current_version: Mutex<Option<String>>,
+    defines: Mutex<Defines>,
+    slots_disabled: Mutex<bool>,
+    require_hashed: Mutex<bool>,
+    defer_hashed_files: Mutex<bool>,
+    strict_rebuild_order: Mutex<bool>,
+    qml_extensions: Mutex<QMLDiffExtensions>,
+    limits: Mutex<Limits>,
+    debug: Mutex<bool>,
+    external_loader: Mutex<CExternalLoader>,
+    vfs: Mutex<CVirtualFileSystem>,
+    stats: Mutex<ProcessingStats>,
+    emitter_config: Mutex<EmitterConfig>,
+    return_unchanged: Mutex<bool>,
+    failure_policy: Mutex<FailurePolicy>,
+    id_check: Mutex<IdCheck>,
+    #[cfg(feature = "signatures")]
+    trusted_key: Mutex<Option<TrustedKey>>,
+}
+
+impl Default for QMLDiffContext {
+    fn default() -> Self {
+        Self {
+            hashtab: Mutex::new(HashTab::new()),
+            slots: Mutex::new(Slots::new()),
+            changes: Mutex::new(Vec::new()),
+            post_init: Mutex::new(false),
+            current_version: Mutex::new(None),
+            defines: Mutex::new(Defines::new()),
+            slots_disabled: Mutex::new(false),
+            require_hashed: Mutex::new(false),
+            defer_hashed_files: Mutex::new(false),
+            strict_rebuild_order: Mutex::new(false),
+            qml_extensions: Mutex::new(QMLDiffExtensions::NONE),
+            limits: Mutex::new(Limits::default()),
+            debug: Mutex::new(std::env::var_os("QMLDIFF_DEBUG").is_some()),
+            external_loader: Mutex::new(CExternalLoader::default()),
+            vfs: Mutex::new(CVirtualFileSystem::default()),
+            stats: Mutex::new(ProcessingStats::default()),
+            emitter_config: Mutex::new(EmitterConfig::default()),
+            return_unchanged: Mutex::new(false),
+            failure_policy: Mutex::new(FailurePolicy::Lenient),
+            id_check: Mutex::new(IdCheck::Warn),
+            #[cfg(feature = "signatures")]
+            trusted_key: Mutex::new(None),
+        }
+    }
+}
+
+lazy_static! {
+    /// The context of the functions which don't take one.
+    static ref DEFAULT_CONTEXT: QMLDiffContext = QMLDiffContext::default();
+    /// The deprecated functions which have already been called.
+    static ref DEPRECATION_LOGGED: Mutex<HashSet<&'static str>> = Mutex::new(HashSet::new());
+    static ref HASHTAB: Mutex<BuiltHashtab> = Mutex::new(BuiltHashtab::default());
+    static ref SAVER: (Mutex<SaverState>, Condvar) = (Mutex::new(SaverState::default()), Condvar::new());
+    static ref SAVER_THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+    /// How often the saver thread saves the hashtab - `QMLDIFF_HASHTAB_SAVE_INTERVAL` seconds,
+    /// or a minute.
+    static ref SAVE_INTERVAL: Mutex<Duration> = Mutex::new(Duration::from_secs(
+        std::env::var("QMLDIFF_HASHTAB_SAVE_INTERVAL")
+            .ok()
+            .and_then(|e| e.parse::<u64>().ok())
+            .map_or(60, |e| e.max(1))
+    ));
+}
+
+#[cfg(feature = "hashrules")]
+lazy_static! {
+    static ref HASHTAB_RULES: Mutex<Option<HashRules>> = Mutex::new(None);
+}
+
+/// The default context, for `function` - one of the functions kept from before the contexts.
+/// The first call of every such function logs that it's deprecated.
+fn legacy_context(function: &'static str) -> *mut QMLDiffContext {
+    if DEPRECATION_LOGGED.lock().unwrap().insert(function) {
+        eprintln!(
+            "[qmldiff]: {} is deprecated - create a context with qmldiff_ctx_new and use {}",
+            function,
+            function.replacen("qmldiff_", "qmldiff_ctx_", 1)
+        );
+    }
+    // Only ever borrowed immutably - the state of a context is behind its mutexes.
+    &*DEFAULT_CONTEXT as *const QMLDiffContext as *mut QMLDiffContext
+}
+
+/// Creates a context with nothing loaded and the default settings. It has to be freed with
+/// `qmldiff_ctx_free`.
+#[no_mangle]
+extern "C" fn qmldiff_ctx_new() -> *mut QMLDiffContext {
+    Box::into_raw(Box::default())
+}
+
+/// Frees a context created by `qmldiff_ctx_new`. NULL is ignored.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_free(ctx: *mut QMLDiffContext) {
+    if !ctx.is_null() {
+        drop(Box::from_raw(ctx));
+    }
+}
+
+/// Frees a string returned by the library. NULL is ignored.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_free_string(value: *const c_char) {
+    if !value.is_null() {
+        drop(CString::from_raw(value as *mut c_char));
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_external_loader(
+    ctx: *mut QMLDiffContext,
+    external_loader: CExternalLoaderFunc,
+) {
+    (*ctx).external_loader.lock().unwrap().notify = Some(external_loader);
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_external_contents_loader(
+    ctx: *mut QMLDiffContext,
+    contents_loader: CExternalContentsLoaderFunc,
+) {
+    (*ctx).external_loader.lock().unwrap().contents = Some(contents_loader);
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_vfs(
+    ctx: *mut QMLDiffContext,
+    open: CVfsOpenFunc,
+    read: CVfsReadFunc,
+    close: CVfsCloseFunc,
+    // Spelled out, so that the C header declares it as a nullable function pointer.
+    list: Option<
+        unsafe extern "C" fn(
+            directory: *const c_char,
+            emit: CVfsListEntryFunc,
+            context: *mut c_void,
+        ) -> bool,
+    >,
+) {
+    *(*ctx).vfs.lock().unwrap() = CVirtualFileSystem {
+        open: Some(open),
+        read: Some(read),
+        close: Some(close),
+        list,
+    };
+    eprintln!("[qmldiff]: Configured the host VFS.");
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_version(ctx: *mut QMLDiffContext, version: *const c_char) {
+    let version = from_c_string(version);
+    eprintln!("[qmldiff]: Set system version to {}", version);
+    *(*ctx).current_version.lock().unwrap() = Some(version);
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_define(
+    ctx: *mut QMLDiffContext,
+    name: *const c_char,
+    value: *const c_char,
+) {
+    let name: String = from_c_string(name);
+    let value: String = if value.is_null() {
+        String::new()
+    } else {
+        from_c_string(value)
+    };
+    eprintln!("[qmldiff]: Set define {} = '{}'", name, value);
+    (*ctx).defines.lock().unwrap().insert(name, value);
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_require_hashed(
+    ctx: *mut QMLDiffContext,
+    require_hashed: bool,
+) {
+    *(*ctx).require_hashed.lock().unwrap() = require_hashed;
+    eprintln!(
+        "[qmldiff]: Unhashed diffs will be {}",
+        if require_hashed { "rejected" } else { "accepted" }
+    );
+}
+
+/// Makes the diffs `AFFECT`ing files by hashes missing from the hashtab load anyway. The names of
+/// these files are resolved once a hashtab loaded later has them, or once a file with a matching
+/// name is processed.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_defer_hashed_files(ctx: *mut QMLDiffContext, defer: bool) {
+    *(*ctx).defer_hashed_files.lock().unwrap() = defer;
+    eprintln!(
+        "[qmldiff]: Files named by unknown hashes will {}",
+        if defer {
+            "be resolved later"
+        } else {
+            "fail the diffs"
+        }
+    );
+}
+
+/// Makes the `AFFECT REBUILD` changes of a file apply in the order of the changes, instead of
+/// before all its `AFFECT` changes. A rebuild following an `AFFECT` of the same file then fails.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_strict_rebuild_order(ctx: *mut QMLDiffContext, strict: bool) {
+    *(*ctx).strict_rebuild_order.lock().unwrap() = strict;
+    eprintln!(
+        "[qmldiff]: Rebuilds will be applied {}",
+        if strict {
+            "in the order of the changes"
+        } else {
+            "before the other changes"
+        }
+    );
+}
+
+/// Makes the extension tokens (`~&hashed&~`, `~{slot}~`, `~#anchor~`) recognized in the QML files
+/// being processed. By default these are plain QML, and such code is read as ordinary symbols.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_qml_extensions(ctx: *mut QMLDiffContext, enabled: bool) {
+    *(*ctx).qml_extensions.lock().unwrap() = if enabled {
+        QMLDiffExtensions::ALL
+    } else {
+        QMLDiffExtensions::NONE
+    };
+    eprintln!(
+        "[qmldiff]: Extension tokens in the QML files will be {}",
+        if enabled { "recognized" } else { "ignored" }
+    );
+}
+
+/// Limits what the diffs can make qmldiff do - see `Limits`. A limit of 0 disables it.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_limits(
+    ctx: *mut QMLDiffContext,
+    max_load_depth: usize,
+    max_slot_depth: usize,
+    max_expansion_tokens: usize,
+    max_diff_tokens: usize,
+) {
+    let limits = Limits {
+        max_load_depth,
+        max_slot_depth,
+        max_expansion_tokens,
+        max_diff_tokens,
+    };
+    *(*ctx).limits.lock().unwrap() = limits;
+    (*ctx).slots.lock().unwrap().1 = limits;
+    eprintln!("[qmldiff]: Set the limits to {:?}", limits);
+}
+
+/// Makes the diffs and the hashtabs loaded by `qmldiff_build_change_files` (and the files they
+/// `LOAD`) require a valid signature by `key` - the raw 32 bytes of an ed25519 public key. The
+/// signature of a file is read from `<file>.sig`. NULL stops verifying the signatures. Returns
+/// false if the key is invalid - the files are rejected until a valid one is set.
+///
+/// # Safety
+/// `key` has to be NULL or point to 32 bytes.
+#[cfg(feature = "signatures")]
+#[no_mangle]
+pub unsafe extern "C" fn qmldiff_ctx_set_public_key(
+    ctx: *mut QMLDiffContext,
+    key: *const u8,
+) -> bool {
+    let trusted_key = &(*ctx).trusted_key;
+    if key.is_null() {
+        *trusted_key.lock().unwrap() = None;
+        eprintln!("[qmldiff]: Signatures will not be verified");
+        return true;
+    }
+    match TrustedKey::from_bytes(&*(key as *const [u8; PUBLIC_KEY_LENGTH])) {
+        Ok(trusted) => {
+            *trusted_key.lock().unwrap() = Some(trusted);
+            eprintln!("[qmldiff]: Only signed diffs and hashtabs will be loaded");
+            true
+        }
+        Err(error) => {
+            eprintln!("[qmldiff]: Cannot set the public key: {}", error);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_emitter_config(
+    ctx: *mut QMLDiffContext,
+    indent_width: usize,
+    use_tabs: bool,
+    crlf: bool,
+    max_inline_tokens: usize,
+) {
+    let mut config = (*ctx).emitter_config.lock().unwrap();
+    *config = EmitterConfig {
+        indent_width,
+        use_tabs,
+        crlf,
+        max_inline_tokens,
+        ..*config
+    };
+}
+
+/// Breaks the lines of bindings longer than `max_line_width` columns after commas and operators.
+/// 0 - the default - never breaks them.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_max_line_width(
+    ctx: *mut QMLDiffContext,
+    max_line_width: usize,
+) {
+    (*ctx).emitter_config.lock().unwrap().max_line_width = max_line_width;
+}
+
+/// Emits the processed files without indentation or empty lines, collapsing every run of
+/// whitespace in their code. Comments are kept.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_minify(ctx: *mut QMLDiffContext, minify: bool) {
+    (*ctx).emitter_config.lock().unwrap().minify = minify;
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_return_unchanged(
+    ctx: *mut QMLDiffContext,
+    return_unchanged: bool,
+) {
+    *(*ctx).return_unchanged.lock().unwrap() = return_unchanged;
+    eprintln!(
+        "[qmldiff]: Files left unchanged by the diffs will be returned {}",
+        if return_unchanged {
+            "as they were"
+        } else {
+            "as NULL"
+        }
+    );
+}
+
+/// In strict mode, no file of a `qmldiff_process_files` batch is patched if any of them fails to
+/// process. Otherwise (the default), only the files which failed are returned as NULL.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_strict(ctx: *mut QMLDiffContext, strict: bool) {
+    *(*ctx).failure_policy.lock().unwrap() = if strict {
+        FailurePolicy::Strict
+    } else {
+        FailurePolicy::Lenient
+    };
+    eprintln!(
+        "[qmldiff]: Failing files will {}",
+        if strict {
+            "leave their whole batch unpatched"
+        } else {
+            "be skipped"
+        }
+    );
+}
+
+/// Sets what happens when the diffs make a file assign the same id to several objects - `off`,
+/// `warn` (the default) or `error`, which fails processing the file. Returns false if the mode is
+/// unknown, in which case it's left as it was.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_id_check(
+    ctx: *mut QMLDiffContext,
+    mode: *const c_char,
+) -> bool {
+    let mode = from_c_string(mode);
+    match mode.parse::<IdCheck>() {
+        Ok(id_check) => {
+            *(*ctx).id_check.lock().unwrap() = id_check;
+            eprintln!("[qmldiff]: Set the id check to {}", mode);
+            true
+        }
+        Err(error) => {
+            eprintln!("[qmldiff]: Cannot set the id check: {}", error);
+            false
+        }
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_set_debug(ctx: *mut QMLDiffContext, debug: bool) {
+    *(*ctx).debug.lock().unwrap() = debug;
+    eprintln!(
+        "[qmldiff]: Debug mode {}",
+        if debug { "enabled" } else { "disabled" }
+    );
+}
+
+#[cfg(feature = "hashrules")]
+#[no_mangle]
+extern "C" fn qmldiff_load_rules(rules: *const c_char) {
+    let rules: String = unsafe { from_c_string(rules) };
+    match HashRules::compile(&rules) {
+        Ok(rules_ok) => {
+            *HASHTAB_RULES.lock().unwrap() = Some(rules_ok);
+            eprintln!("[qmldiff]: Configured hashtab rules.");
+        }
+        Err(error) => {
+            eprintln!("[qmldiff]: Error loading rules: {}", error);
+        }
+    }
+}
+
+/// Adds rules to the ones already loaded, and makes the saver thread save the hashtab collected so
+/// far right away, processing all the rules. Returns false if the rules cannot be compiled, in which
+/// case the loaded rules are kept as they were.
+#[cfg(feature = "hashrules")]
+#[no_mangle]
+extern "C" fn qmldiff_append_rules(rules: *const c_char) -> bool {
+    let rules: String = unsafe { from_c_string(rules) };
+    let rules = match HashRules::compile(&rules) {
+        Ok(rules) => rules,
+        Err(error) => {
+            eprintln!("[qmldiff]: Error appending rules: {}", error);
+            return false;
+        }
+    };
+    {
+        let mut loaded_rules = HASHTAB_RULES.lock().unwrap();
+        match loaded_rules.as_mut() {
+            Some(loaded) => loaded.append(rules),
+            None => *loaded_rules = Some(rules),
+        }
+    }
+    eprintln!("[qmldiff]: Appended hashtab rules.");
+    if is_building_hashtab() {
+        request_save();
+    }
+    true
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_add_external_diff(
+    ctx: *mut QMLDiffContext,
+    change_file_contents: *const c_char,
+    file_identifier: *const c_char,
+) -> bool {
+    if is_building_hashtab() {
+        return false;
+    }
+
+    let ctx = &*ctx;
+    let file_identifier: String = from_c_string(file_identifier);
+
+    if *ctx.post_init.lock().unwrap() {
+        eprintln!(
+            "[qmldiff]: Cannot build changes from external {} after init has completed!",
+            &file_identifier
+        );
+    }
+    let change_file_contents: String = from_c_string(change_file_contents);
+    match parse_diff(
+        None,
+        change_file_contents,
+        &file_identifier,
+        &ctx.hashtab.lock().unwrap(),
+        ctx.external_loader(),
+        *ctx.require_hashed.lock().unwrap(),
+        *ctx.defer_hashed_files.lock().unwrap(),
+        &ctx.limits.lock().unwrap(),
+    ) {
+        Err(problem) => {
+            eprintln!(
+                "[qmldiff]: Failed to load external {}: {:?}",
+                &file_identifier, problem
+            );
+            false
+        }
+        Ok(mut contents) => {
+            filter_out_non_matching_versions(
+                &mut contents,
+                ctx.current_version.lock().unwrap().clone(),
+                &file_identifier,
+            );
+            filter_out_unmet_requirements(
+                &mut contents,
+                &ctx.defines.lock().unwrap(),
+                &file_identifier,
+            );
+            ctx.slots.lock().unwrap().update_slots(&mut contents);
+            eprintln!("[qmldiff]: Loaded external {}", &file_identifier);
+            let mut changes = ctx.changes.lock().unwrap();
+            changes.extend(contents);
+            order_loaded_changes(&mut changes);
+            true
+        }
+    }
+}
+
+/// How the host provides the files loaded at runtime. Copied out of a context, so that the context
+/// isn't locked while the files are read.
+#[derive(Clone, Copy)]
+struct HostFiles {
+    vfs: CVirtualFileSystem,
+    #[cfg(feature = "signatures")]
+    trusted_key: Option<TrustedKey>,
+}
+
+impl HostFiles {
+    /// Reads a file through the host VFS, or from the disk if the VFS doesn't provide it.
+    fn read(&self, path: &str) -> std::io::Result<Vec<u8>> {
+        match self.vfs.read_file(path) {
+            Some(data) => Ok(data),
+            None => std::fs::read(path),
+        }
+    }
+
+    /// Checks the signature of a file loaded at runtime, if a trusted key has been set.
+    #[cfg(feature = "signatures")]
+    fn verify_signature(&self, path: &str, contents: &[u8]) -> Result<()> {
+        match self.trusted_key {
+            Some(key) => {
+                let signature = self.read(&signature_path(path)).ok();
+                key.verify(path, contents, signature.as_deref())
+            }
+            None => Ok(()),
+        }
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    fn verify_signature(&self, _path: &str, _contents: &[u8]) -> Result<()> {
+        Ok(())
+    }
+
+    #[cfg(feature = "signatures")]
+    fn requires_signatures(&self) -> bool {
+        self.trusted_key.is_some()
+    }
+
+    #[cfg(not(feature = "signatures"))]
+    fn requires_signatures(&self) -> bool {
+        false
+    }
+
+    /// Reads a diff or a hashtab loaded at runtime, and checks its signature.
+    fn read_verified(&self, path: &str) -> Result<Vec<u8>> {
+        let contents = self.read(path)?;
+        self.verify_signature(path, &contents)?;
+        Ok(contents)
+    }
+}
+
+/// Provides the files `LOAD`ed by the diffs through the host's callbacks and VFS.
+struct HostLoader {
+    callbacks: CExternalLoader,
+    files: HostFiles,
+}
+
+impl ExternalLoader for HostLoader {
+    fn load_external(&mut self, file: &str) {
+        if let Some(notify) = self.callbacks.notify {
+            let c_string = CString::new(file).unwrap();
+            unsafe {
+                notify(c_string.as_ptr());
+            }
+        }
+    }
+
+    fn verify_contents(&mut self, file: &str, contents: &str) -> Result<()> {
+        self.files.verify_signature(file, contents.as_bytes())
+    }
+
+    fn load_contents(&mut self, file: &str) -> Option<String> {
+        let provided = self.callbacks.contents.and_then(|contents| {
+            let c_string = CString::new(file).unwrap();
+            let mut length = 0usize;
+            let data = unsafe { contents(c_string.as_ptr(), &mut length) };
+            if data.is_null() {
+                return None;
+            }
+            let bytes = unsafe { std::slice::from_raw_parts(data as *const u8, length) };
+            Some(String::from_utf8_lossy(bytes).into())
+        });
+        provided.or_else(|| self.files.vfs.read_to_string(file))
+    }
+}
+
+impl QMLDiffContext {
+    fn host_files(&self) -> HostFiles {
+        HostFiles {
+            vfs: *self.vfs.lock().unwrap(),
+            #[cfg(feature = "signatures")]
+            trusted_key: *self.trusted_key.lock().unwrap(),
+        }
+    }
+
+    /// The loader passed to the diff parser, or None if the host provides no files.
+    fn external_loader(&self) -> Option<Box<dyn ExternalLoader>> {
+        let callbacks = *self.external_loader.lock().unwrap();
+        let files = self.host_files();
+        if callbacks.notify.is_none()
+            && callbacks.contents.is_none()
+            && !files.vfs.is_set()
+            && !files.requires_signatures()
+        {
+            None
+        } else {
+            Some(Box::new(HostLoader { callbacks, files }))
+        }
+    }
+
+    /// Resolves the deferred files named by the hash of `file_name`.
+    fn resolve_hashed_file(&self, file_name: &str) {
+        if *self.defer_hashed_files.lock().unwrap()
+            && resolve_hashed_file_name(&mut self.changes.lock().unwrap(), file_name)
+        {
+            eprintln!("[qmldiff]: Resolved the hashed name of {}", file_name);
+        }
+    }
+
+    fn load_hashtab(&self, root_dir: &str) {
+        let mut hashtab = self.hashtab.lock().unwrap();
+        let path = std::path::Path::new(&root_dir).join("hashtab");
+        let path = path.to_string_lossy();
+        let result = self.host_files().read_verified(&path).and_then(|data| {
+            merge_hash_data(
+                data.as_slice(),
+                &path,
+                &mut hashtab,
+                self.current_version.lock().unwrap().clone(),
+                None,
+            )
+        });
+        if let Err(x) = result {
+            eprintln!("[qmldiff]: Failed to load hashtab: {}", x);
+        } else {
+            eprintln!(
+                "[qmldiff]: Hashtab loaded! Cached {} entries",
+                hashtab.len()
+            );
+        }
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_build_change_files(
+    ctx: *mut QMLDiffContext,
+    root_dir: *const c_char,
+) -> i32 {
+    if is_building_hashtab() {
+        return 0;
+    }
+
+    let ctx = &*ctx;
+    let root_dir: String = from_c_string(root_dir);
+
+    if *ctx.post_init.lock().unwrap() {
+        eprintln!(
+            "[qmldiff]: Cannot build changes from {} after init has completed!",
+            &root_dir
+        );
+    }
+    let mut loaded_files = 0i32;
+    let mut all_changes = Vec::new();
+    let mut slots = Slots::new();
+
+    eprintln!("[qmldiff]: Iterating over directory {}", &root_dir);
+
+    ctx.load_hashtab(&root_dir);
+    // The hashtab of this directory may name the files deferred by the diffs loaded before.
+    let resolved = resolve_hashed_files(
+        &mut ctx.changes.lock().unwrap(),
+        &ctx.hashtab.lock().unwrap(),
+    );
+    if !resolved.is_empty() {
+        eprintln!(
+            "[qmldiff]: Resolved the hashed names of {}",
+            resolved.join(", ")
+        );
+    }
+
+    let host_files = ctx.host_files();
+    let listed = host_files
+        .vfs
+        .list_directory(&root_dir)
+        .map(|names| {
+            names
+                .into_iter()
+                .map(|name| format!("{}/{}", root_dir.trim_end_matches('/'), name))
+                .collect::<Vec<_>>()
+        })
+        .or_else(|| {
+            std::fs::read_dir(&root_dir).ok().map(|dir| {
+                dir.flatten()
+                    .map(|file| file.path().to_string_lossy().to_string())
+                    .collect()
+            })
+        });
+    if let Some(mut files) = listed {
+        files.retain(|path| path.ends_with(".qmd"));
+        files.sort();
+        for file in &files {
+            let fname_start = match file.rfind("/") {
+                Some(e) => e + 1,
+                None => 0,
+            };
+            eprintln!("[qmldiff]: Loading file {}", &file[fname_start..]);
+            let loaded = host_files.read_verified(file).and_then(|contents| {
+                parse_diff(
+                    Some(root_dir.clone()),
+                    String::from_utf8_lossy(&contents).into(),
+                    file,
+                    &ctx.hashtab.lock().unwrap(),
+                    ctx.external_loader(),
+                    *ctx.require_hashed.lock().unwrap(),
+                    *ctx.defer_hashed_files.lock().unwrap(),
+                    &ctx.limits.lock().unwrap(),
+                )
+            });
+            match loaded {
+                Err(problem) => {
+                    eprintln!("[qmldiff]: Failed to load file {}: {:?}", file, problem)
+                }
+                Ok(mut contents) => {
+                    filter_out_non_matching_versions(
+                        &mut contents,
+                        ctx.current_version.lock().unwrap().clone(),
+                        file,
+                    );
+                    filter_out_unmet_requirements(
+                        &mut contents,
+                        &ctx.defines.lock().unwrap(),
+                        file,
+                    );
+                    all_changes.extend(contents);
+                    loaded_files += 1;
+                }
+            }
+        }
+    }
+
+    order_loaded_changes(&mut all_changes);
+    slots.update_slots(&mut all_changes);
+    ctx.slots.lock().unwrap().0.extend(slots.0);
+    let mut changes = ctx.changes.lock().unwrap();
+    changes.extend(all_changes);
+    order_loaded_changes(&mut changes);
+    loaded_files
+}
+
+/// Orders the changes by the `PRIORITY` and `AFTER DIFF` statements of their diffs. If the diffs
+/// cannot be ordered, they stay in the order they were loaded in.
+fn order_loaded_changes(changes: &mut Vec<Change>) {
+    if let Err(error) = order_changes(changes) {
+        eprintln!("[qmldiff]: Cannot order the diffs: {}", error);
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_is_modified(
+    ctx: *mut QMLDiffContext,
+    file_name: *const c_char,
+) -> bool {
+    let file_name: String = from_c_string(file_name);
+
+    if is_building_hashtab() {
+        return true;
+    }
+
+    let ctx = &*ctx;
+    ctx.resolve_hashed_file(&file_name);
+    ctx.changes
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|e| match &e.destination {
+            ObjectToChange::FileTokenStream(z) => z == &file_name,
+            destination => destination.affects_file(&file_name),
+        })
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_get_modified_files(ctx: *mut QMLDiffContext) -> *const c_char {
+    let files = (*ctx).get_modified_files();
+    into_c_string_or_null(if files.is_empty() {
+        None
+    } else {
+        Some(files.join("\n"))
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_get_affecting_diffs(
+    ctx: *mut QMLDiffContext,
+    file_name: *const c_char,
+) -> *const c_char {
+    let file_name: String = from_c_string(file_name);
+
+    let mut diffs: Vec<&str> = Vec::new();
+    let changes = (*ctx).changes.lock().unwrap();
+    for change in changes.iter() {
+        let affects = match &change.destination {
+            ObjectToChange::FileTokenStream(z) | ObjectToChange::NewFile(z) => z == &file_name,
+            ObjectToChange::Redirect { redirected, .. } => redirected == &file_name,
+            destination => destination.affects_file(&file_name),
+        };
+        if affects && !diffs.contains(&change.source.as_str()) {
+            diffs.push(change.source.as_str());
+        }
+    }
+    into_c_string_or_null(if diffs.is_empty() {
+        None
+    } else {
+        Some(diffs.join("\n"))
+    })
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_get_diff_metadata(
+    ctx: *mut QMLDiffContext,
+    diff_name: *const c_char,
+) -> *const c_char {
+    let diff_name: String = from_c_string(diff_name);
+
+    let changes = (*ctx).changes.lock().unwrap();
+    into_c_string_or_null(
+        changes
+            .iter()
+            .find(|e| e.source.as_str() == diff_name)
+            .map(|change| {
+                let metadata = &change.metadata;
+                [
+                    ("name", &metadata.name),
+                    ("author", &metadata.author),
+                    ("version", &metadata.version),
+                ]
+                .iter()
+                .filter_map(|(key, value)| value.as_ref().map(|e| format!("{}: {}\n", key, e)))
+                .collect()
+            }),
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_disable_slots_while_processing(ctx: *mut QMLDiffContext) {
+    *(*ctx).slots_disabled.lock().unwrap() = true;
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_enable_slots_while_processing(ctx: *mut QMLDiffContext) {
+    *(*ctx).slots_disabled.lock().unwrap() = false;
+}
+
+impl QMLDiffContext {
+    /// Seals the slots if this is the first time files are processed. Returns whether slots are
+    /// disabled.
+    fn enter_post_init(&self) -> bool {
+        let mut post_init = self.post_init.lock().unwrap();
+        let are_slots_disabled = *self.slots_disabled.lock().unwrap();
+        if !*post_init && !are_slots_disabled {
+            eprintln!(
+                "[qmldiff]: Was asked to process the first slot. Sealing slots, entering postinit..."
+            );
+            *post_init = true;
+            if let Err(error) = self
+                .slots
+                .lock()
+                .unwrap()
+                .process_slots(&mut self.changes.lock().unwrap())
+            {
+                eprintln!("[qmldiff]: Cannot expand the slots: {:?}", error);
+            }
+        }
+        are_slots_disabled
+    }
+
+    /// Applies the loaded changes to a single file, and records it in the stats. The changes and
+    /// slots are locked by the caller, so that they can be reused for many files. If no change
+    /// modifies the file, its original contents are returned if `return_unchanged` is set, None
+    /// otherwise.
+    fn process_contents(
+        &self,
+        file_name: &str,
+        contents: String,
+        changes: &[Change],
+        slots: &mut Slots,
+        return_unchanged: bool,
+    ) -> Result<Option<String>> {
+        let start_time = Instant::now();
+        eprintln!("[qmldiff]: Processing file {}...", file_name);
+        let debug = *self.debug.lock().unwrap();
+        let emitter_config = *self.emitter_config.lock().unwrap();
+        let mut trace = Vec::new();
+        let original = return_unchanged.then(|| contents.clone());
+        let (format, contents) = SourceFormat::detect(&contents);
+        let result = if is_qmldir(file_name) {
+            find_and_process_qmldir(file_name, contents, changes, None)
+        } else {
+            let extensions = *self.qml_extensions.lock().unwrap();
+            let tree = tokenize_qml(contents.to_string(), file_name, None, None, extensions);
+            find_and_process(
+                file_name,
+                tree,
+                changes,
+                slots,
+                None,
+                debug.then_some(&mut trace),
+                &emitter_config,
+                *self.id_check.lock().unwrap(),
+                None,
+                if *self.strict_rebuild_order.lock().unwrap() {
+                    RebuildOrder::Strict
+                } else {
+                    RebuildOrder::First
+                },
+            )
+        };
+        for change in trace {
+            eprintln!("[qmldiff]: [debug] {}: {}", file_name, change);
+        }
+        match result {
+            Ok((emitted, applied)) => {
+                self.stats
+                    .lock()
+                    .unwrap()
+                    .record_success(&applied, start_time.elapsed());
+                if emitted.is_none() {
+                    eprintln!("[qmldiff]: No change modified {}.", file_name);
+                }
+                Ok(emitted.map(|e| format.restore(e)).or(original))
+            }
+            Err(e) => {
+                self.stats
+                    .lock()
+                    .unwrap()
+                    .record_failure(file_name, start_time.elapsed());
+                eprintln!("[qmldiff]: Error while processing file tree: {:?}", e);
+                Err(e)
+            }
+        }
+    }
+
+    /// Locks the loaded changes and slots, and passes them to `f`.
+    fn with_changes_and_slots<T>(&self, f: impl FnOnce(&Vec<Change>, &mut Slots) -> T) -> T {
+        let are_slots_disabled = self.enter_post_init();
+        let changes = self.changes.lock().unwrap();
+        // Fake slots - when slots are disabled, use the always-empty set of slots in their stead.
+        let mut fake_slots = Slots::new();
+        let mut real_slots;
+        let slots = if are_slots_disabled {
+            &mut fake_slots
+        } else {
+            real_slots = self.slots.lock().unwrap();
+            &mut *real_slots
+        };
+        f(&changes, slots)
+    }
+
+    /// The files `AFFECT`ed by the loaded changes. Like processing a file, this seals the slots.
+    pub fn get_modified_files(&self) -> Vec<String> {
+        self.with_changes_and_slots(|changes, _| {
+            modified_files(changes).map(String::from).collect()
+        })
+    }
+
+    /// Applies the loaded changes to many in-memory files. The changes and slots are locked only
+    /// once for the whole batch. Returns the patched contents of every file, or None if processing
+    /// it failed. In strict mode, None is returned for every file if any of them failed.
+    pub fn process_files<I>(&self, files: I) -> Vec<Option<String>>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        let return_unchanged = *self.return_unchanged.lock().unwrap();
+        let policy = *self.failure_policy.lock().unwrap();
+        let mut failures = Vec::new();
+        let files: Vec<(String, String)> = files.into_iter().collect();
+        for (file_name, _) in &files {
+            self.resolve_hashed_file(file_name);
+        }
+        let processed: Vec<Option<String>> = self.with_changes_and_slots(|changes, slots| {
+            files
+                .into_iter()
+                .map(|(file_name, contents)| {
+                    self.process_contents(&file_name, contents, changes, slots, return_unchanged)
+                        .unwrap_or_else(|e| {
+                            failures.push((file_name, e));
+                            None
+                        })
+                })
+                .collect()
+        });
+        if !failures.is_empty() && policy == FailurePolicy::Strict {
+            eprintln!(
+                "[qmldiff]: Strict mode - none of the {} file(s) will be patched. {}",
+                processed.len(),
+                describe_failures(&failures)
+            );
+            return vec![None; processed.len()];
+        }
+        processed
+    }
+
+    /// Builds a file created by a `CREATE` statement, with the changes affecting it applied.
+    /// Returns None if no loaded change creates the file, or if building it failed.
+    pub fn get_created_file(&self, file_name: &str) -> Option<String> {
+        let emitter_config = *self.emitter_config.lock().unwrap();
+        self.with_changes_and_slots(|changes, slots| {
+            match build_created_file(file_name, changes, slots, &emitter_config) {
+                // The file has to be returned even if no change modifies it - it doesn't exist
+                // otherwise.
+                Ok(Some(contents)) => self
+                    .process_contents(file_name, contents, changes, slots, true)
+                    .ok()
+                    .flatten(),
+                Ok(None) => None,
+                Err(e) => {
+                    eprintln!(
+                        "[qmldiff]: Error while creating file {}: {:?}",
+                        file_name, e
+                    );
+                    None
+                }
+            }
+        })
+    }
+
+    /// Counts the objects of a QML file matched by a tree selector, without applying any change.
+    pub fn query_file(&self, file_name: &str, contents: String, selector: &str) -> Result<usize> {
+        let tree = NodeTree::parse(selector)?;
+        let extensions = *self.qml_extensions.lock().unwrap();
+        count_matches(
+            tokenize_qml(contents, file_name, None, None, extensions),
+            &tree,
+        )
+    }
+}
+
+/// Copies a NUL-terminated string passed by the host. Invalid UTF-8 sequences are replaced,
+/// rather than failing the call.
+unsafe fn from_c_string(value: *const c_char) -> String {
+    CStr::from_ptr(value).to_string_lossy().into()
+}
+
+/// Copies the contents of a file passed by the host. At most `size` bytes are read - the data
+/// doesn't have to be NUL-terminated, but ends at the first NUL byte if it is. A `size` of 0
+/// means the size isn't known, and the data is read up to the NUL terminator.
+unsafe fn from_c_contents(file_name: &str, data: *const c_char, size: usize) -> String {
+    let bytes = if size == 0 {
+        CStr::from_ptr(data).to_bytes()
+    } else {
+        let bytes = std::slice::from_raw_parts(data as *const u8, size);
+        match bytes.iter().position(|e| *e == 0) {
+            Some(end) => &bytes[..end],
+            None => bytes,
+        }
+    };
+    match String::from_utf8_lossy(bytes) {
+        Cow::Borrowed(contents) => contents.into(),
+        Cow::Owned(contents) => {
+            eprintln!(
+                "[qmldiff]: {} is not valid UTF-8 - the invalid sequences have been replaced",
+                file_name
+            );
+            contents
+        }
+    }
+}
+
+fn into_c_string_or_null(value: Option<String>) -> *const c_char {
+    match value {
+        Some(value) => {
+            let c_string = CString::new(value).unwrap();
+            let ret = c_string.as_ptr();
+            std::mem::forget(c_string);
+            ret
+        }
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_process_file(
+    ctx: *mut QMLDiffContext,
+    file_name: *const c_char,
+    raw_contents: *const c_char,
+    contents_size: usize,
+) -> *const c_char {
+    let ctx = &*ctx;
+    let file_name: String = from_c_string(file_name);
+    let contents = from_c_contents(&file_name, raw_contents, contents_size);
+
+    let extensions = *ctx.qml_extensions.lock().unwrap();
+    let version = ctx.current_version.lock().unwrap().clone();
+    if include_if_building_hashtab(&file_name, &contents, extensions, version) {
+        return std::ptr::null();
+    }
+
+    into_c_string_or_null(ctx.process_files([(file_name, contents)]).pop().unwrap())
+}
+
+/// `file_names` and `contents` must point to `count` NUL-terminated strings each, `results` must
+/// have room for `count` pointers.
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_process_files(
+    ctx: *mut QMLDiffContext,
+    count: usize,
+    file_names: *const *const c_char,
+    contents: *const *const c_char,
+    results: *mut *const c_char,
+) -> usize {
+    let ctx = &*ctx;
+    let file_names = std::slice::from_raw_parts(file_names, count);
+    let contents = std::slice::from_raw_parts(contents, count);
+    let results = std::slice::from_raw_parts_mut(results, count);
+
+    let extensions = *ctx.qml_extensions.lock().unwrap();
+    let version = ctx.current_version.lock().unwrap().clone();
+    let mut to_process = Vec::with_capacity(count);
+    for (index, (file_name, raw_contents)) in file_names.iter().zip(contents).enumerate() {
+        let file_name: String = from_c_string(*file_name);
+        results[index] = std::ptr::null();
+        let contents = from_c_contents(&file_name, *raw_contents, 0);
+        if !include_if_building_hashtab(&file_name, &contents, extensions, version.clone()) {
+            to_process.push((index, file_name, contents));
+        }
+    }
+
+    let indices = to_process.iter().map(|e| e.0).collect::<Vec<_>>();
+    let processed = ctx.process_files(to_process.into_iter().map(|(_, name, data)| (name, data)));
+    let mut succeeded = 0;
+    for (index, result) in indices.into_iter().zip(processed) {
+        succeeded += result.is_some() as usize;
+        results[index] = into_c_string_or_null(result);
+    }
+    succeeded
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_query_file(
+    ctx: *mut QMLDiffContext,
+    file_name: *const c_char,
+    contents: *const c_char,
+    selector: *const c_char,
+) -> isize {
+    let file_name: String = from_c_string(file_name);
+    let contents = from_c_contents(&file_name, contents, 0);
+    let selector = from_c_string(selector);
+    match (*ctx).query_file(&file_name, contents, &selector) {
+        Ok(count) => count as isize,
+        Err(e) => {
+            eprintln!(
+                "[qmldiff]: Error while querying {} in {}: {:?}",
+                selector, file_name, e
+            );
+            -1
+        }
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_get_created_file(
+    ctx: *mut QMLDiffContext,
+    file_name: *const c_char,
+) -> *const c_char {
+    let file_name: String = from_c_string(file_name);
+    if is_building_hashtab() {
+        return std::ptr::null();
+    }
+    into_c_string_or_null((*ctx).get_created_file(&file_name))
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_get_redirect_source(
+    ctx: *mut QMLDiffContext,
+    file_name: *const c_char,
+) -> *const c_char {
+    let file_name: String = from_c_string(file_name);
+    if is_building_hashtab() {
+        return std::ptr::null();
+    }
+    match find_redirect_source(&file_name, &(*ctx).changes.lock().unwrap()) {
+        Ok(original) => into_c_string_or_null(original),
+        Err(e) => {
+            eprintln!("[qmldiff]: Error while redirecting {}: {:?}", file_name, e);
+            std::ptr::null()
+        }
+    }
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_ctx_get_stats(ctx: *mut QMLDiffContext) -> *const c_char {
+    into_c_string_or_null(Some((*ctx).stats.lock().unwrap().to_string()))
+}
+
+/// Wakes the saver thread up, if it's running.
+#[cfg(feature = "hashrules")]
+fn request_save() {
+    let (state, condvar) = &*SAVER;
+    state.lock().unwrap().save_requested = true;
+    condvar.notify_all();
+}
+
+/// Waits for the save interval to pass, or until a save is requested. Returns false once the
+/// thread should stop.
+fn wait_for_save() -> bool {
+    let interval = *SAVE_INTERVAL.lock().unwrap();
+    let (state, condvar) = &*SAVER;
+    let mut state = state.lock().unwrap();
+    if !state.save_requested && !state.stopping {
+        state = condvar.wait_timeout(state, interval).unwrap().0;
+    }
+    state.save_requested = false;
+    !state.stopping
+}
+
+/// Processes the rules on a copy of the hashtab collected so far, and writes it into the file
+/// set by `QMLDIFF_HASHTAB_CREATE`, along with the version of the files it was built from. If
+/// `wait` isn't set, and the hashtab is being used, nothing is saved. Returns whether the hashtab
+/// has been saved.
+fn save_hashtab(wait: bool) -> bool {
+    let Some(dist_hashmap_path) = std::env::var_os("QMLDIFF_HASHTAB_CREATE") else {
+        return false;
+    };
+    // Held until the file is written, so that the saves cannot overlap.
+    let built = if wait {
+        HASHTAB.lock().unwrap()
+    } else {
+        match HASHTAB.try_lock() {
+            Ok(ht) => ht,
+            Err(_) => {
+                eprintln!("[qmldiff]: Cannot save hashtab right now. Waiting...");
+                return false;
+            }
+        }
+    };
+    #[allow(unused_mut)]
+    let mut to_process_rules = built.hashtab.clone();
+    #[cfg(feature = "hashrules")]
+    if let Some(rules) = HASHTAB_RULES.lock().unwrap().as_ref() {
+        eprintln!("[qmldiff]: Processing rules.");
+        rules.process(&mut to_process_rules);
+        eprintln!(
+            "[qmldiff]: The rules derived {} entries from the {} collected.",
+            to_process_rules.len() - built.hashtab.len(),
+            built.hashtab.len()
+        );
+    } else {
+        eprintln!("[qmldiff]: No rules to process.");
+    }
+    let string = serialize_hashtab(&to_process_rules, built.version.clone());
+    if let Err(e) = write_hashtab_file(&dist_hashmap_path, &string) {
+        eprintln!("[qmldiff]: {}", e);
+        false
+    } else {
+        eprintln!(
+            "[qmldiff]: Hashtab saved to {}",
+            &dist_hashmap_path.to_string_lossy()
+        );
+        true
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn qmldiff_start_saving_thread() {
+    if std::env::var_os("QMLDIFF_HASHTAB_CREATE").is_some() {
+        let mut thread = SAVER_THREAD.lock().unwrap();
+        if thread.is_some() {
+            eprintln!("[qmldiff]: Hashtab saver already started!");
+            return;
+        }
+        SAVER.0.lock().unwrap().stopping = false;
+        *thread = Some(std::thread::spawn(|| {
+            eprintln!("[qmldiff]: Hashtab saver started!");
+            while wait_for_save() {
+                save_hashtab(false);
+            }
+        }));
+    }
+}
+
+/// Sets how often the saver thread saves the hashtab, in seconds. Takes effect after the next save.
+#[no_mangle]
+pub extern "C" fn qmldiff_set_save_interval(seconds: u32) {
+    *SAVE_INTERVAL.lock().unwrap() = Duration::from_secs(seconds.max(1) as u64);
+}
+
+/// Saves the hashtab collected so far right away, in the calling thread. Returns whether the
+/// hashtab has been saved - false if no hashtab is being built.
+#[no_mangle]
+pub extern "C" fn qmldiff_save_hashtab_now() -> bool {
+    save_hashtab(true)
+}
+
+/// Stops the saver thread, waiting for a save in progress to finish, then saves the hashtab one
+/// last time. Meant to be called when the program exits, so that the entries collected since the
+/// last save are not lost. Returns whether the hashtab has been saved.
+#[no_mangle]
+pub extern "C" fn qmldiff_stop_saving_thread() -> bool {
+    let (state, condvar) = &*SAVER;
+    state.lock().unwrap().stopping = true;
+    condvar.notify_all();
+    if let Some(thread) = SAVER_THREAD.lock().unwrap().take() {
+        let _ = thread.join();
+        eprintln!("[qmldiff]: Hashtab saver stopped.");
+    }
+    save_hashtab(true)
+}
+
+// The functions working on the default context. They are deprecated, but kept for the hosts
+// written before the contexts - they behave like the `qmldiff_ctx_*` functions they forward to.
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_set_external_loader(external_loader: CExternalLoaderFunc) {
+    qmldiff_ctx_set_external_loader(
+        legacy_context("qmldiff_set_external_loader"),
+        external_loader,
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_set_external_contents_loader(
+    contents_loader: CExternalContentsLoaderFunc,
+) {
+    qmldiff_ctx_set_external_contents_loader(
+        legacy_context("qmldiff_set_external_contents_loader"),
+        contents_loader,
+    )
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_set_vfs(
+    open: CVfsOpenFunc,
+    read: CVfsReadFunc,
+    close: CVfsCloseFunc,
+    list: Option<
+        unsafe extern "C" fn(
+            directory: *const c_char,
+            emit: CVfsListEntryFunc,
+            context: *mut c_void,
+        ) -> bool,
+    >,
+) {
+    qmldiff_ctx_set_vfs(legacy_context("qmldiff_set_vfs"), open, read, close, list)
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_set_version(version: *const c_char) {
+    qmldiff_ctx_set_version(legacy_context("qmldiff_set_version"), version)
+}
+
+#[no_mangle]
+unsafe extern "C" fn qmldiff_set_define(name: *const c_char, value: *const c_char) {
+    qmldiff_ctx_set_define(legacy_context("qmldiff_set_define"), name, value)
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_require_hashed(require_hashed: bool) {
+    unsafe {
+        qmldiff_ctx_set_require_hashed(legacy_context("qmldiff_set_require_hashed"), require_hashed)
+    }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_defer_hashed_files(defer: bool) {
+    unsafe {
+        qmldiff_ctx_set_defer_hashed_files(legacy_context("qmldiff_set_defer_hashed_files"), defer)
+    }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_strict_rebuild_order(strict: bool) {
+    unsafe {
+        qmldiff_ctx_set_strict_rebuild_order(
+            legacy_context("qmldiff_set_strict_rebuild_order"),
+            strict,
+        )
+    }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_qml_extensions(enabled: bool) {
+    unsafe { qmldiff_ctx_set_qml_extensions(legacy_context("qmldiff_set_qml_extensions"), enabled) }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_limits(
+    max_load_depth: usize,
+    max_slot_depth: usize,
+    max_expansion_tokens: usize,
+    max_diff_tokens: usize,
+) {
+    unsafe {
+        qmldiff_ctx_set_limits(
+            legacy_context("qmldiff_set_limits"),
+            max_load_depth,
+            max_slot_depth,
+            max_expansion_tokens,
+            max_diff_tokens,
+        )
+    }
+}
+
+/// # Safety
+/// `key` has to be NULL or point to 32 bytes.
+#[cfg(feature = "signatures")]
+#[no_mangle]
+pub unsafe extern "C" fn qmldiff_set_public_key(key: *const u8) -> bool {
+    qmldiff_ctx_set_public_key(legacy_context("qmldiff_set_public_key"), key)
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_emitter_config(
+    indent_width: usize,
+    use_tabs: bool,
+    crlf: bool,
+    max_inline_tokens: usize,
+) {
+    unsafe {
+        qmldiff_ctx_set_emitter_config(
+            legacy_context("qmldiff_set_emitter_config"),
+            indent_width,
+            use_tabs,
+            crlf,
+            max_inline_tokens,
+        )
+    }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_max_line_width(max_line_width: usize) {
+    unsafe {
+        qmldiff_ctx_set_max_line_width(legacy_context("qmldiff_set_max_line_width"), max_line_width)
+    }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_minify(minify: bool) {
+    unsafe { qmldiff_ctx_set_minify(legacy_context("qmldiff_set_minify"), minify) }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_return_unchanged(return_unchanged: bool) {
+    unsafe {
+        qmldiff_ctx_set_return_unchanged(
+            legacy_context("qmldiff_set_return_unchanged"),
+            return_unchanged,
+        )
+    }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_strict(strict: bool) {
+    unsafe { qmldiff_ctx_set_strict(legacy_context("qmldiff_set_strict"), strict) }
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * `mode` must be a NUL-terminated string.
+ */
+pub unsafe extern "C" fn qmldiff_set_id_check(mode: *const c_char) -> bool {
+    qmldiff_ctx_set_id_check(legacy_context("qmldiff_set_id_check"), mode)
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_set_debug(debug: bool) {
+    unsafe { qmldiff_ctx_set_debug(legacy_context("qmldiff_set_debug"), debug) }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_add_external_diff(
+    change_file_contents: *const c_char,
+    file_identifier: *const c_char,
+) -> bool {
+    unsafe {
+        qmldiff_ctx_add_external_diff(
+            legacy_context("qmldiff_add_external_diff"),
+            change_file_contents,
+            file_identifier,
+        )
+    }
+}
+
+#[no_mangle]
+extern "C" fn qmldiff_build_change_files(root_dir: *const c_char) -> i32 {
+    unsafe {
+        qmldiff_ctx_build_change_files(legacy_context("qmldiff_build_change_files"), root_dir)
+    }
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * no
+ */
+pub unsafe extern "C" fn qmldiff_is_modified(file_name: *const c_char) -> bool {
+    qmldiff_ctx_is_modified(legacy_context("qmldiff_is_modified"), file_name)
+}
+
+#[no_mangle]
+pub extern "C" fn qmldiff_get_modified_files() -> *const c_char {
+    unsafe { qmldiff_ctx_get_modified_files(legacy_context("qmldiff_get_modified_files")) }
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * no
+ */
+pub unsafe extern "C" fn qmldiff_get_affecting_diffs(file_name: *const c_char) -> *const c_char {
+    qmldiff_ctx_get_affecting_diffs(legacy_context("qmldiff_get_affecting_diffs"), file_name)
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * no
+ */
+pub unsafe extern "C" fn qmldiff_get_diff_metadata(diff_name: *const c_char) -> *const c_char {
+    qmldiff_ctx_get_diff_metadata(legacy_context("qmldiff_get_diff_metadata"), diff_name)
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * no
+ */
+pub unsafe extern "C" fn qmldiff_disable_slots_while_processing() {
+    qmldiff_ctx_disable_slots_while_processing(legacy_context(
+        "qmldiff_disable_slots_while_processing",
+    ))
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * no
+ */
+pub unsafe extern "C" fn qmldiff_enable_slots_while_processing() {
+    qmldiff_ctx_enable_slots_while_processing(legacy_context(
+        "qmldiff_enable_slots_while_processing",
+    ))
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * no
+ */
+pub unsafe extern "C" fn qmldiff_process_file(
+    file_name: *const c_char,
+    raw_contents: *const c_char,
+    contents_size: usize,
+) -> *const c_char {
+    qmldiff_ctx_process_file(
+        legacy_context("qmldiff_process_file"),
+        file_name,
+        raw_contents,
+        contents_size,
+    )
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * `file_names` and `contents` must point to `count` NUL-terminated strings each,
+ * `results` must have room for `count` pointers.
+ */
+pub unsafe extern "C" fn qmldiff_process_files(
+    count: usize,
+    file_names: *const *const c_char,
+    contents: *const *const c_char,
+    results: *mut *const c_char,
+) -> usize {
+    qmldiff_ctx_process_files(
+        legacy_context("qmldiff_process_files"),
+        count,
+        file_names,
+        contents,
+        results,
+    )
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * `file_name`, `contents` and `selector` must be NUL-terminated strings.
+ */
+pub unsafe extern "C" fn qmldiff_query_file(
+    file_name: *const c_char,
+    contents: *const c_char,
+    selector: *const c_char,
+) -> isize {
+    qmldiff_ctx_query_file(
+        legacy_context("qmldiff_query_file"),
+        file_name,
+        contents,
+        selector,
+    )
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * `file_name` must be a NUL-terminated string.
+ */
+pub unsafe extern "C" fn qmldiff_get_created_file(file_name: *const c_char) -> *const c_char {
+    qmldiff_ctx_get_created_file(legacy_context("qmldiff_get_created_file"), file_name)
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * `file_name` must be a NUL-terminated string.
+ */
+pub unsafe extern "C" fn qmldiff_get_redirect_source(file_name: *const c_char) -> *const c_char {
+    qmldiff_ctx_get_redirect_source(legacy_context("qmldiff_get_redirect_source"), file_name)
+}
+
+#[no_mangle]
+/**
+ * # Safety
+ * no
+ */
+pub unsafe extern "C" fn qmldiff_get_stats() -> *const c_char {
+    qmldiff_ctx_get_stats(legacy_context("qmldiff_get_stats"))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::BTreeSet,
+        ffi::{CStr, CString},
+    };
+
+    #[cfg(feature = "hashrules")]
+    use super::{qmldiff_append_rules, HASHTAB_RULES};
+    use super::{
+        qmldiff_ctx_add_external_diff, qmldiff_ctx_free, qmldiff_ctx_get_stats,
+        qmldiff_ctx_is_modified, qmldiff_ctx_new, qmldiff_ctx_process_file,
+        qmldiff_ctx_set_id_check, qmldiff_free_string, qmldiff_query_file,
+    };
+    #[cfg(feature = "hashrules")]
+    use crate::hashtab::HashTab;
+
+    /// The names of the functions declared by include/qmldiff.h.
+    fn declared_functions() -> BTreeSet<String> {
+        include_str!("../include/qmldiff.h")
+            .lines()
+            .filter(|line| !line.starts_with("typedef"))
+            .filter_map(|line| line.split('(').next())
+            .filter_map(|declaration| declaration.split([' ', '*']).next_back())
+            .filter(|name| name.starts_with("qmldiff_"))
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    /// The names of the functions exported by this module.
+    fn exported_functions() -> BTreeSet<String> {
+        let source = include_str!("ffi.rs");
+        source
+            .split("#[no_mangle]")
+            .skip(1)
+            .filter_map(|after| after.split(" fn ").nth(1))
+            .filter_map(|function| function.split('(').next())
+            .filter(|name| name.starts_with("qmldiff_"))
+            .map(|name| name.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_header_declares_every_exported_function() {
+        let exported = exported_functions();
+        assert!(exported.contains("qmldiff_process_file"));
+        assert_eq!(declared_functions(), exported);
+    }
+
+    #[test]
+    fn test_header_is_up_to_date() {
+        let generated = std::fs::read_to_string(concat!(env!("OUT_DIR"), "/qmldiff.h")).unwrap();
+        assert!(
+            generated == include_str!("../include/qmldiff.h"),
+            "include/qmldiff.h is out of date - regenerate it with QMLDIFF_GENERATE_HEADER=1"
+        );
+    }
+
+    #[cfg(feature = "hashrules")]
+    #[test]
+    fn test_append_rules() {
+        let append = |rules: &str| {
+            let rules = CString::new(rules).unwrap();
+            qmldiff_append_rules(rules.as_ptr())
+        };
+        assert!(append("A\nfirst\n#\n"));
+        assert!(append("A\nsecond\n#\n"));
+        // Rules which cannot be compiled don't change the loaded ones.
+        assert!(!append("A\nthird\n#\nX\n"));
+
+        let mut tab = HashTab::new();
+        HASHTAB_RULES
+            .lock()
+            .unwrap()
+            .as_ref()
+            .unwrap()
+            .process(&mut tab);
+        assert_eq!(
+            tab.values().map(String::as_str).collect::<BTreeSet<_>>(),
+            BTreeSet::from(["first", "second"])
+        );
+    }
+
+    #[test]
+    fn test_query_file() {
+        let query = |selector: &str| {
+            let file_name = CString::new("Main.qml").unwrap();
+            let contents = CString::new("Item { Rectangle {} Rectangle {} }").unwrap();
+            let selector = CString::new(selector).unwrap();
+            unsafe { qmldiff_query_file(file_name.as_ptr(), contents.as_ptr(), selector.as_ptr()) }
+        };
+        assert_eq!(query("Item > Rectangle"), 2);
+        // Malformed selectors from the host fail the call instead of aborting the host.
+        assert_eq!(query("Item {"), -1);
+        assert_eq!(query("Item[.color=\"red]"), -1);
+    }
+
+    #[test]
+    fn test_contexts_are_independent() {
+        let diff = CString::new(
+            "AFFECT Main.qml\n    TRAVERSE Item\n        LOCATE AFTER ALL\n        INSERT {\n            width: 1\n        }\n    END TRAVERSE\nEND AFFECT",
+        )
+        .unwrap();
+        let diff_name = CString::new("main.qmd").unwrap();
+        let file_name = CString::new("Main.qml").unwrap();
+        let contents = CString::new("Item {\n}\n").unwrap();
+        unsafe {
+            let patched = qmldiff_ctx_new();
+            let untouched = qmldiff_ctx_new();
+            assert!(qmldiff_ctx_add_external_diff(
+                patched,
+                diff.as_ptr(),
+                diff_name.as_ptr()
+            ));
+            assert!(qmldiff_ctx_is_modified(patched, file_name.as_ptr()));
+            assert!(!qmldiff_ctx_is_modified(untouched, file_name.as_ptr()));
+
+            let result =
+                qmldiff_ctx_process_file(patched, file_name.as_ptr(), contents.as_ptr(), 0);
+            let processed = CStr::from_ptr(result).to_string_lossy().to_string();
+            assert!(processed.contains("width: 1"));
+            qmldiff_free_string(result);
+            let result =
+                qmldiff_ctx_process_file(untouched, file_name.as_ptr(), contents.as_ptr(), 0);
+            assert!(result.is_null());
+
+            qmldiff_ctx_free(patched);
+            qmldiff_ctx_free(untouched);
+        }
+    }
+
+    #[test]
+    fn test_stats_count_the_changes_which_modified_files() {
+        let diffs = [
+            (
+                "insert.qmd",
+                "AFFECT Main.qml\n    TRAVERSE Item\n        LOCATE AFTER ALL\n        INSERT {\n            width: 1\n        }\n    END TRAVERSE\nEND AFFECT",
+            ),
+            (
+                "check.qmd",
+                "AFFECT Main.qml\n    TRAVERSE Item\n        ASSERT NOT Rectangle\n    END TRAVERSE\nEND AFFECT",
+            ),
+        ];
+        let file_name = CString::new("Main.qml").unwrap();
+        let contents = CString::new("Item {\n}\n").unwrap();
+        let stats = unsafe {
+            let ctx = qmldiff_ctx_new();
+            for (name, diff) in diffs {
+                let (name, diff) = (CString::new(name).unwrap(), CString::new(diff).unwrap());
+                assert!(qmldiff_ctx_add_external_diff(
+                    ctx,
+                    diff.as_ptr(),
+                    name.as_ptr()
+                ));
+            }
+            qmldiff_free_string(qmldiff_ctx_process_file(
+                ctx,
+                file_name.as_ptr(),
+                contents.as_ptr(),
+                0,
+            ));
+            let stats = qmldiff_ctx_get_stats(ctx);
+            let copied = CStr::from_ptr(stats).to_string_lossy().to_string();
+            qmldiff_free_string(stats);
+            qmldiff_ctx_free(ctx);
+            copied
+        };
+        // Both diffs affect the file, but only one of them changes it.
+        assert!(stats.contains("changes_applied: 1\n"));
+        assert!(stats.contains("diff_file insert.qmd: 1\n"));
+        assert!(!stats.contains("check.qmd"));
+    }
+
+    #[test]
+    fn test_id_check() {
+        let diff = CString::new(
+            "AFFECT Main.qml\n    TRAVERSE Item\n        LOCATE AFTER ALL\n        INSERT {\n            Text {\n                id: root\n            }\n        }\n    END TRAVERSE\nEND AFFECT",
+        )
+        .unwrap();
+        let diff_name = CString::new("ids.qmd").unwrap();
+        let file_name = CString::new("Main.qml").unwrap();
+        let contents = CString::new("Item {\n    id: root\n}\n").unwrap();
+        let mode = |mode: &str| CString::new(mode).unwrap();
+        unsafe {
+            let ctx = qmldiff_ctx_new();
+            assert!(qmldiff_ctx_add_external_diff(
+                ctx,
+                diff.as_ptr(),
+                diff_name.as_ptr()
+            ));
+            // Duplicate ids are only warned about by default.
+            let result = qmldiff_ctx_process_file(ctx, file_name.as_ptr(), contents.as_ptr(), 0);
+            assert!(!result.is_null());
+            qmldiff_free_string(result);
+
+            assert!(!qmldiff_ctx_set_id_check(ctx, mode("strict").as_ptr()));
+            assert!(qmldiff_ctx_set_id_check(ctx, mode("error").as_ptr()));
+            let result = qmldiff_ctx_process_file(ctx, file_name.as_ptr(), contents.as_ptr(), 0);
+            assert!(result.is_null());
+            qmldiff_ctx_free(ctx);
+        }
+    }
+}
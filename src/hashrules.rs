@@ -1,7 +1,8 @@
 use anyhow::{Error, Result};
 use regex::{Captures, Regex};
+use std::{collections::BTreeSet, fmt::Display};
 
-use crate::{hash::hash, hashtab::HashTab};
+use crate::{hash::hash, hashtab::HashTab, util::output::warning};
 
 #[derive(Debug)]
 enum MatchConditionEqualityCheck {
@@ -64,12 +65,51 @@ impl MatchCondition {
         }
         Ok(res)
     }
+
+    /// The captures of the regex in the value, if it matches and passes all the checks.
+    fn captures<'a>(&self, value: &'a str) -> Option<Captures<'a>> {
+        let captures = self.regex.captures(value)?;
+        for (i, matcher) in self.equality_checks.iter().enumerate() {
+            if !matcher.matches(captures.get(i).map_or("", |e| e.as_str())) {
+                return None;
+            }
+        }
+        Some(captures)
+    }
+}
+
+fn captures_to_strings(captures: &Captures) -> Vec<String> {
+    captures
+        .iter()
+        .map(|e| e.map_or(String::new(), |e| e.as_str().to_string()))
+        .collect()
 }
 
 #[derive(Debug)]
 enum RuleCondition {
     EmitAlways,
     Match(MatchCondition),
+    /// Every combination of entries matched by the conditions, one entry per condition.
+    Join(Vec<MatchCondition>),
+}
+
+impl Display for RuleCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::EmitAlways => f.write_str("always"),
+            Self::Match(cond) => write!(f, "match /{}/", cond.regex.as_str()),
+            Self::Join(conds) => {
+                f.write_str("join ")?;
+                for (i, cond) in conds.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(" + ")?;
+                    }
+                    write!(f, "/{}/", cond.regex.as_str())?;
+                }
+                Ok(())
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -78,6 +118,24 @@ struct Rule {
     values: Vec<String>,
 }
 
+/// The hashtab entries matched by a rule, and the strings derived from them.
+#[derive(Debug)]
+pub struct RuleMatch {
+    /// The matched entries - one for match rules, one per condition for join rules, none for
+    /// rules which always emit their values.
+    pub entries: Vec<String>,
+    /// The regex captures of every entry, starting with the whole match (`$0`).
+    pub captures: Vec<Vec<String>>,
+    pub derived: Vec<String>,
+}
+
+/// What a single rule did. Collected by `HashRules::explain`.
+#[derive(Debug)]
+pub struct RuleExplanation {
+    pub condition: String,
+    pub matches: Vec<RuleMatch>,
+}
+
 #[derive(Debug)]
 pub struct HashRules {
     rules: Vec<Rule>,
@@ -96,6 +154,27 @@ impl HashRules {
             let condition = match match_opcode {
                 'M' => RuleCondition::Match(MatchCondition::compile(rest, &mut lines)?),
                 'A' => RuleCondition::EmitAlways,
+                'J' => {
+                    let count = rest.parse::<usize>()?;
+                    if count == 0 {
+                        return Err(Error::msg("A join rule needs at least one match condition"));
+                    }
+                    let mut conditions = Vec::new();
+                    for _ in 0..count {
+                        match lines.next() {
+                            Some(line) if line.starts_with('M') => {
+                                conditions.push(MatchCondition::compile(&line[1..], &mut lines)?)
+                            }
+                            _ => {
+                                return Err(Error::msg(format!(
+                                    "Expected {} match conditions after the join rule",
+                                    count
+                                )))
+                            }
+                        }
+                    }
+                    RuleCondition::Join(conditions)
+                }
                 e => {
                     return Err(Error::msg(format!("Unknown condition {}", e)));
                 }
@@ -114,11 +193,59 @@ impl HashRules {
         Ok(HashRules { rules })
     }
 
+    /// Adds the rules of `other` after these ones.
+    pub fn append(&mut self, other: HashRules) {
+        self.rules.extend(other.rules);
+    }
+
     pub fn process(&self, tab: &mut HashTab) {
+        self.process_with(tab, None);
+    }
+
+    /// The hashes the rules themselves refer to - in `H` conditions and `[[hash]]` references.
+    pub fn referenced_hashes(&self) -> BTreeSet<u64> {
+        let hash_reference_regex = Regex::new("\\[\\[([\\d]*)\\]\\]").unwrap();
+        let mut references = BTreeSet::new();
+        for rule in &self.rules {
+            let conditions = match &rule.condition {
+                RuleCondition::EmitAlways => &[][..],
+                RuleCondition::Match(cond) => std::slice::from_ref(cond),
+                RuleCondition::Join(conds) => conds.as_slice(),
+            };
+            for cond in conditions {
+                for check in &cond.equality_checks {
+                    if let MatchConditionEqualityCheck::Hash(hash) = check {
+                        references.insert(*hash);
+                    }
+                }
+            }
+            for value in &rule.values {
+                for reference in hash_reference_regex.captures_iter(value) {
+                    if let Ok(hash) = reference[1].parse::<u64>() {
+                        references.insert(hash);
+                    }
+                }
+            }
+        }
+        references
+    }
+
+    /// Runs the rules on a copy of the hashtab, returning what every rule matched and derived.
+    pub fn explain(&self, tab: &HashTab) -> Vec<RuleExplanation> {
+        let mut explanations = Vec::new();
+        self.process_with(&mut tab.clone(), Some(&mut explanations));
+        explanations
+    }
+
+    fn process_with(&self, tab: &mut HashTab, mut explanations: Option<&mut Vec<RuleExplanation>>) {
+        let hash_reference_regex = Regex::new("\\[\\[([\\d]*)\\]\\]").unwrap();
+        let capture_reference_regex = Regex::new("\\$([\\d]*)").unwrap();
+        let join_reference_regex = Regex::new("\\$([\\d]+):([\\d]+)").unwrap();
+        let quiet = explanations.is_some();
         // Iterate over own rules
         macro_rules! include {
-            ($val: expr, $tab: expr) => {
-                let value_final = Regex::new("\\[\\[([\\d]*)\\]\\]").unwrap().replace_all(
+            ($val: expr, $tab: expr) => {{
+                let value_final = hash_reference_regex.replace_all(
                     &$val,
                     |h: &Captures| {
                         let hashed = h[1].parse::<u64>();
@@ -126,10 +253,10 @@ impl HashRules {
                             if let Some(original) = tab.get(&hashed) {
                                 return original.to_string();
                             } else {
-                                eprintln!("No hash {} present in hashtab!", hashed);
+                                warning!("No hash {} present in hashtab!", hashed);
                             }
                         } else {
-                            eprintln!("Not a valid hash {}!", h[1].to_string());
+                            warning!("Not a valid hash {}!", h[1].to_string());
                         }
 
                         "INVALID!".to_string()
@@ -137,34 +264,43 @@ impl HashRules {
                 );
                 let h = hash(&value_final);
                 $tab.insert(h, value_final.to_string());
-                eprintln!(
-                    "[qmldiff] [Hashtab Rule Processor]: Hashed derived '{}'",
-                    &value_final
-                );
-            };
+                if !quiet {
+                    eprintln!(
+                        "[qmldiff] [Hashtab Rule Processor]: Hashed derived '{}'",
+                        &value_final
+                    );
+                }
+                value_final.to_string()
+            }};
         }
         for rule in &self.rules {
+            let mut explanation = RuleExplanation {
+                condition: rule.condition.to_string(),
+                matches: Vec::new(),
+            };
             match &rule.condition {
                 RuleCondition::EmitAlways => {
                     // Just emit the output as a hash.
+                    let mut derived = Vec::new();
                     for v in &rule.values {
-                        include!(v, tab);
+                        derived.push(include!(v, tab));
                     }
+                    explanation.matches.push(RuleMatch {
+                        entries: Vec::new(),
+                        captures: Vec::new(),
+                        derived,
+                    });
                 }
                 RuleCondition::Match(cond) => {
                     // Iterate over all entries in hashtable. Find matches
                     let mut tab_temp = HashTab::new();
-                    'hashiter: for (_, string) in tab.iter() {
-                        if let Some(r#match) = cond.regex.captures(string) {
-                            for (i, matcher) in cond.equality_checks.iter().enumerate() {
-                                if !matcher.matches(r#match.get(i).unwrap().as_str()) {
-                                    continue 'hashiter;
-                                }
-                            }
+                    for (_, string) in tab.iter() {
+                        if let Some(r#match) = cond.captures(string) {
                             // Value matches
                             // Emit.
+                            let mut derived = Vec::new();
                             for value_to_emit in &rule.values {
-                                let value_final = Regex::new("\\$([\\d]*)").unwrap().replace_all(
+                                let value_final = capture_reference_regex.replace_all(
                                     value_to_emit,
                                     |h: &Captures| {
                                         let capture_index = h[1].parse::<usize>();
@@ -172,25 +308,136 @@ impl HashRules {
                                             if let Some(original) = r#match.get(capture_index) {
                                                 return original.as_str();
                                             } else {
-                                                eprintln!(
+                                                warning!(
                                                     "No capture {} present in parent!",
                                                     capture_index
                                                 );
                                             }
                                         } else {
-                                            eprintln!("Not a valid hash {}!", &h[1]);
+                                            warning!("Not a valid hash {}!", &h[1]);
                                         }
 
                                         "INVALID!"
                                     },
                                 );
-                                include!(value_final, tab_temp);
+                                derived.push(include!(value_final, tab_temp));
                             }
+                            explanation.matches.push(RuleMatch {
+                                entries: vec![string.clone()],
+                                captures: vec![captures_to_strings(&r#match)],
+                                derived,
+                            });
                         }
                     }
                     tab.extend(tab_temp);
                 }
+                RuleCondition::Join(conds) => {
+                    // The entries matched by every condition, then all their combinations.
+                    let candidates = conds
+                        .iter()
+                        .map(|cond| {
+                            let mut matches = tab
+                                .values()
+                                .filter_map(|string| {
+                                    let captures = cond.captures(string)?;
+                                    Some((string.clone(), captures_to_strings(&captures)))
+                                })
+                                .collect::<Vec<_>>();
+                            matches.sort();
+                            matches
+                        })
+                        .collect::<Vec<_>>();
+                    let mut tab_temp = HashTab::new();
+                    let mut indices = vec![0; candidates.len()];
+                    let mut done = candidates.iter().any(|e| e.is_empty());
+                    while !done {
+                        let combination = indices
+                            .iter()
+                            .zip(&candidates)
+                            .map(|(i, matches)| &matches[*i])
+                            .collect::<Vec<_>>();
+                        let mut derived = Vec::new();
+                        for value_to_emit in &rule.values {
+                            let value_final =
+                                join_reference_regex.replace_all(value_to_emit, |h: &Captures| {
+                                    let entry = h[1].parse::<usize>().ok();
+                                    let group = h[2].parse::<usize>().ok();
+                                    match (entry, group) {
+                                        (Some(entry), Some(group)) => {
+                                            if let Some(capture) = entry
+                                                .checked_sub(1)
+                                                .and_then(|e| combination.get(e))
+                                                .and_then(|e| e.1.get(group))
+                                            {
+                                                return capture.clone();
+                                            }
+                                            warning!(
+                                                "No capture {}:{} present in parents!",
+                                                entry,
+                                                group
+                                            );
+                                        }
+                                        _ => warning!("Not a valid capture {}!", &h[0]),
+                                    }
+
+                                    "INVALID!".to_string()
+                                });
+                            derived.push(include!(value_final, tab_temp));
+                        }
+                        explanation.matches.push(RuleMatch {
+                            entries: combination.iter().map(|e| e.0.clone()).collect(),
+                            captures: combination.iter().map(|e| e.1.clone()).collect(),
+                            derived,
+                        });
+
+                        // Advance to the next combination.
+                        done = true;
+                        for (index, matches) in indices.iter_mut().zip(&candidates).rev() {
+                            *index += 1;
+                            if *index < matches.len() {
+                                done = false;
+                                break;
+                            }
+                            *index = 0;
+                        }
+                    }
+                    tab.extend(tab_temp);
+                }
+            }
+            if let Some(explanations) = explanations.as_deref_mut() {
+                // The hashtab's iteration order is random - sort the matches.
+                explanation
+                    .matches
+                    .sort_by(|a, b| a.entries.cmp(&b.entries));
+                explanations.push(explanation);
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::HashRules;
+    use crate::{hash::hash, hashtab::HashTab};
+
+    #[test]
+    fn test_join_rule() {
+        let mut tab = HashTab::new();
+        for value in ["/usr/share/app", "Page.qml", "Main.qml", "unrelated"] {
+            tab.insert(hash(value), value.to_string());
+        }
+        let rules = HashRules::compile(
+            "J2\nM^(/usr/[a-z/]+)$\n-\n-\nM^([A-Z][a-z]+)\\.qml$\n-\nEPage\n$1:1/$2:0\n#\n",
+        )
+        .unwrap();
+        rules.process(&mut tab);
+        assert_eq!(
+            tab.get(&hash("/usr/share/app/Page.qml"))
+                .map(String::as_str),
+            Some("/usr/share/app/Page.qml")
+        );
+        assert!(!tab.contains_key(&hash("/usr/share/app/Main.qml")));
+
+        assert!(HashRules::compile("J2\nM^(a)$\n-\n-\nfoo\n#\n").is_err());
+    }
+}
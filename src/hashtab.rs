@@ -1,23 +1,186 @@
-use anyhow::Result;
-use std::{collections::HashMap, fs::File, io::Read, path::Path};
+use anyhow::{Error, Result};
+#[cfg(feature = "compression")]
+use flate2::{read::GzDecoder, write::GzEncoder};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufRead, BufReader, Read, Write},
+    path::Path,
+    str::FromStr,
+};
 
 use crate::{
     hash::hash,
     parser::qml::{
-        lexer::TokenType,
+        lexer::{QMLDiffExtensions, TokenType},
     },
+    util::{common_util::tokenize_qml, output::warning},
 };
 
 pub type HashTab = HashMap<u64, String>;
 pub type InvHashTab = HashMap<String, u64>;
 
 const INTERNAL_HASHTAB_VERSION_ALLOWED_KEY: u64 = 17607111715072197239u64; // Hash of "!*HashTab-Version"
+const INTERNAL_HASHTAB_SOURCES_KEY: u64 = 3719528568659694624u64; // Hash of "!*HashTab-Sources"
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashTabCompression {
+    Gzip,
+    Zstd,
+}
+
+impl FromStr for HashTabCompression {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "gzip" | "gz" => Ok(Self::Gzip),
+            "zstd" | "zst" => Ok(Self::Zstd),
+            _ => Err(Error::msg(format!(
+                "Unknown compression {} - expected gzip or zstd",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(feature = "compression")]
+pub fn compress_hashtab(data: &[u8], compression: HashTabCompression) -> Result<Vec<u8>> {
+    match compression {
+        HashTabCompression::Gzip => {
+            use std::io::Write;
+            let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::best());
+            encoder.write_all(data)?;
+            Ok(encoder.finish()?)
+        }
+        HashTabCompression::Zstd => Ok(zstd::encode_all(data, 19)?),
+    }
+}
+
+/// Wraps the data, decompressing it if it starts with a gzip or zstd magic.
+/// Uncompressed hashtabs always start with the zero hash of the header entry.
+#[cfg(feature = "compression")]
+pub fn decompressing_reader<'a, R: Read + 'a>(data: R) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(data);
+    let magic = reader.fill_buf()?;
+    Ok(if magic.starts_with(&GZIP_MAGIC) {
+        Box::new(GzDecoder::new(reader))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        Box::new(zstd::Decoder::with_buffer(reader)?)
+    } else {
+        Box::new(reader)
+    })
+}
+
+/// Without the `compression` feature, only uncompressed data can be read.
+#[cfg(not(feature = "compression"))]
+pub fn decompressing_reader<'a, R: Read + 'a>(data: R) -> Result<Box<dyn Read + 'a>> {
+    let mut reader = BufReader::new(data);
+    let magic = reader.fill_buf()?;
+    if magic.starts_with(&GZIP_MAGIC) || magic.starts_with(&ZSTD_MAGIC) {
+        return Err(Error::msg(
+            "Cannot read compressed data - qmldiff was built without the `compression` feature",
+        ));
+    }
+    Ok(Box::new(reader))
+}
 
 pub struct HashTabFile {
     pub hashtab: HashTab,
     pub version: String,
 }
 
+/// A QML file a hashtab has been built from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceFile {
+    /// The path within the QML root.
+    pub path: String,
+    pub contents_hash: u64,
+}
+
+/// Which file of the QML tree every entry of a hashtab has been found in. Stored along with the
+/// hashtab by `create-hashtab --sources`, in a reserved entry - older versions of qmldiff load it
+/// as an entry nothing refers to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HashTabSources {
+    pub files: Vec<SourceFile>,
+    /// The index within `files` of the first file every entry has been found in.
+    pub entries: HashMap<u64, u32>,
+}
+
+impl HashTabSources {
+    /// Registers a file, returning its id.
+    pub fn add_file(&mut self, path: &str, contents: &str) -> u32 {
+        self.files.push(SourceFile {
+            path: path.to_string(),
+            contents_hash: hash(contents),
+        });
+        (self.files.len() - 1) as u32
+    }
+
+    /// Attributes the entries found in a file to it, unless an earlier file already had them.
+    pub fn record(&mut self, file_id: u32, file_entries: &HashTab) {
+        for hash in file_entries.keys() {
+            self.entries.entry(*hash).or_insert(file_id);
+        }
+    }
+
+    /// The file the entry has first been found in.
+    pub fn source_of(&self, hash: u64) -> Option<&SourceFile> {
+        self.entries
+            .get(&hash)
+            .and_then(|e| self.files.get(*e as usize))
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        let mut output = Vec::new();
+        output.extend((self.files.len() as u32).to_be_bytes());
+        for file in &self.files {
+            output.extend(file.contents_hash.to_be_bytes());
+            output.extend((file.path.len() as u32).to_be_bytes());
+            output.extend(file.path.bytes());
+        }
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_unstable();
+        for (hash, file_id) in entries {
+            output.extend(hash.to_be_bytes());
+            output.extend(file_id.to_be_bytes());
+        }
+        output
+    }
+
+    fn deserialize(mut data: &[u8]) -> Result<Self> {
+        fn take<'a>(data: &mut &'a [u8], length: usize) -> Result<&'a [u8]> {
+            if data.len() < length {
+                return Err(Error::msg("The sources of the hashtab are truncated"));
+            }
+            let (taken, rest) = data.split_at(length);
+            *data = rest;
+            Ok(taken)
+        }
+        let mut sources = Self::default();
+        let file_count = u32::from_be_bytes(take(&mut data, 4)?.try_into()?);
+        for _ in 0..file_count {
+            let contents_hash = u64::from_be_bytes(take(&mut data, 8)?.try_into()?);
+            let length = u32::from_be_bytes(take(&mut data, 4)?.try_into()?) as usize;
+            let path = String::from_utf8_lossy(take(&mut data, length)?).into();
+            sources.files.push(SourceFile {
+                path,
+                contents_hash,
+            });
+        }
+        while !data.is_empty() {
+            let hash = u64::from_be_bytes(take(&mut data, 8)?.try_into()?);
+            let file_id = u32::from_be_bytes(take(&mut data, 4)?.try_into()?);
+            sources.entries.insert(hash, file_id);
+        }
+        Ok(sources)
+    }
+}
+
 pub fn hash_token_stream(tokens: &Vec<TokenType>, hashtab: &mut HashTab) {
     let mut last_was_dot = false;
     let mut dot_accumulator: Option<String> = None;
@@ -69,16 +232,143 @@ pub fn hash_token_stream(tokens: &Vec<TokenType>, hashtab: &mut HashTab) {
     }
 }
 
+/// Hashes a file of a QML tree the way `create-hashtab` does - the names of all of its path
+/// components, the paths of the directories leading to it, and its own path, like `/dir/File.qml`
+/// (the leading slash is added if missing). The identifiers and strings of QML files are hashed
+/// as well. If `sources` is given, the entries found in the file are attributed to it.
+pub fn hash_qml_source(
+    path: &str,
+    contents: &str,
+    hashtab: &mut HashTab,
+    sources: Option<&mut HashTabSources>,
+) {
+    let path = if path.starts_with('/') {
+        path.to_string()
+    } else {
+        format!("/{}", path)
+    };
+    let mut directory = String::new();
+    let mut components = path.split('/').filter(|e| !e.is_empty()).peekable();
+    while let Some(component) = components.next() {
+        hashtab.insert(hash(component), component.to_string());
+        if components.peek().is_some() {
+            directory.push('/');
+            directory.push_str(component);
+            hashtab.insert(hash(&directory), directory.clone());
+        }
+    }
+
+    let mut file_tab = HashTab::new();
+    file_tab.insert(hash(&path), path.clone());
+    if path.ends_with(".qml") {
+        hash_token_stream(
+            &tokenize_qml(
+                contents.to_string(),
+                &path,
+                None,
+                None,
+                QMLDiffExtensions::NONE,
+            ),
+            &mut file_tab,
+        );
+    }
+    if let Some(sources) = sources {
+        let file_id = sources.add_file(&path, contents);
+        sources.record(file_id, &file_tab);
+    }
+    hashtab.extend(file_tab);
+}
+
+/// Builds a hashtab out of QML files which don't have to exist on disk, like the ones extracted
+/// from resource bundles. `files` yields the path of every file within the QML tree, and its
+/// contents. The entries are attributed to the first file they have been found in, so the order
+/// of the files should be stable.
+pub fn build_hashtab_from_sources<I, P, C>(
+    files: I,
+    mut sources: Option<&mut HashTabSources>,
+) -> HashTab
+where
+    I: IntoIterator<Item = (P, C)>,
+    P: AsRef<str>,
+    C: AsRef<str>,
+{
+    let mut hashtab = HashTab::new();
+    for (path, contents) in files {
+        hash_qml_source(
+            path.as_ref(),
+            contents.as_ref(),
+            &mut hashtab,
+            sources.as_deref_mut(),
+        );
+    }
+    hashtab
+}
+
 pub fn merge_hash_file<P>(
     hashtab_file: P,
     destination: &mut HashTab,
     current_version: Option<String>,
-    mut inv_destination: Option<&mut InvHashTab>,
+    inv_destination: Option<&mut InvHashTab>,
 ) -> Result<()>
 where
     P: AsRef<Path>,
 {
-    let mut data_file = File::open(&hashtab_file)?;
+    merge_hash_data(
+        File::open(&hashtab_file)?,
+        &hashtab_file.as_ref().to_string_lossy(),
+        destination,
+        current_version,
+        inv_destination,
+    )
+}
+
+/// Like `merge_hash_file`, but also reads which files the entries have been found in, if the
+/// hashtab has been created with them.
+pub fn merge_hash_file_with_sources<P>(
+    hashtab_file: P,
+    destination: &mut HashTab,
+    sources: &mut HashTabSources,
+) -> Result<()>
+where
+    P: AsRef<Path>,
+{
+    read_hash_data(
+        File::open(&hashtab_file)?,
+        &hashtab_file.as_ref().to_string_lossy(),
+        destination,
+        None,
+        None,
+        Some(sources),
+    )
+}
+
+/// Like `merge_hash_file`, but reads the hashtab from memory or any other source.
+pub fn merge_hash_data<R: Read>(
+    data: R,
+    hashtab_name: &str,
+    destination: &mut HashTab,
+    current_version: Option<String>,
+    inv_destination: Option<&mut InvHashTab>,
+) -> Result<()> {
+    read_hash_data(
+        data,
+        hashtab_name,
+        destination,
+        current_version,
+        inv_destination,
+        None,
+    )
+}
+
+fn read_hash_data<R: Read>(
+    data: R,
+    hashtab_name: &str,
+    destination: &mut HashTab,
+    current_version: Option<String>,
+    mut inv_destination: Option<&mut InvHashTab>,
+    mut sources: Option<&mut HashTabSources>,
+) -> Result<()> {
+    let mut data_file = decompressing_reader(data)?;
     loop {
         let mut hash_value = [0u8; 8];
         let mut str_len = [0u8; 4];
@@ -94,11 +384,17 @@ where
             let this_file_version = String::from(String::from_utf8_lossy(&str_content));
             if let Some(ref allowed_version) = current_version {
                 if this_file_version != *allowed_version {
-                    println!("The file {} is only valid for QML environment version {}. Currently running {}. Loading skipped.", hashtab_file.as_ref().display(), this_file_version, allowed_version);
+                    warning!("The file {} is only valid for QML environment version {}. Currently running {}. Loading skipped.", hashtab_name, this_file_version, allowed_version);
                     return Ok(());
                 }
             }
         }
+        if hash_value_int == INTERNAL_HASHTAB_SOURCES_KEY {
+            if let Some(sources) = sources.as_deref_mut() {
+                *sources = HashTabSources::deserialize(&str_content)?;
+            }
+            continue;
+        }
         if hash_value_int != 0 {
             let str: String = String::from_utf8_lossy(&str_content).into();
             if let Some(ref mut rev) = inv_destination {
@@ -111,6 +407,16 @@ where
 }
 
 pub fn serialize_hashtab(hashtab: &HashTab, current_version: Option<String>) -> Vec<u8> {
+    serialize_hashtab_with_sources(hashtab, current_version, None)
+}
+
+/// Serializes the hashtab in the extended format, which also stores where the entries have been
+/// found.
+pub fn serialize_hashtab_with_sources(
+    hashtab: &HashTab,
+    current_version: Option<String>,
+    sources: Option<&HashTabSources>,
+) -> Vec<u8> {
     let mut output = Vec::new();
     {
         let magic_string = "Hashtab file for QMLDIFF. Do not edit.".bytes();
@@ -129,8 +435,177 @@ pub fn serialize_hashtab(hashtab: &HashTab, current_version: Option<String>) ->
     if let Some(current_version) = current_version {
         append_hash!(INTERNAL_HASHTAB_VERSION_ALLOWED_KEY, current_version);
     }
-    for (hash, str) in hashtab {
+    // Sort the entries, so that the same hashtab always serializes to the same bytes.
+    let mut entries = hashtab.iter().collect::<Vec<_>>();
+    entries.sort_unstable_by_key(|(hash, _)| **hash);
+    for (hash, str) in entries {
         append_hash!(hash, str);
     }
+    if let Some(sources) = sources {
+        let data = sources.serialize();
+        output.extend(INTERNAL_HASHTAB_SOURCES_KEY.to_be_bytes());
+        output.extend((data.len() as u32).to_be_bytes());
+        output.extend(data);
+    }
     output
 }
+
+/// Writes a serialized hashtab into a temporary file next to `path`, then renames it to `path`,
+/// so that a crash while writing never leaves a truncated hashtab behind.
+pub fn write_hashtab_file<P: AsRef<Path>>(path: P, data: &[u8]) -> Result<()> {
+    let path = path.as_ref();
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| Error::msg(format!("{} is not a file path", path.display())))?;
+    let temp_path = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+    let result = File::create(&temp_path)
+        .and_then(|mut file| {
+            file.write_all(data)?;
+            file.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temp_path, path));
+    if let Err(error) = result {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(Error::msg(format!(
+            "Cannot write the hashtab {}: {}",
+            path.display(),
+            error
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        build_hashtab_from_sources, hash_token_stream, merge_hash_file,
+        merge_hash_file_with_sources, serialize_hashtab, serialize_hashtab_with_sources,
+        write_hashtab_file, HashTab, HashTabSources,
+    };
+    #[cfg(feature = "compression")]
+    use super::{compress_hashtab, HashTabCompression};
+    use crate::hash::hash;
+    use crate::{parser::qml::lexer::QMLDiffExtensions, util::common_util::tokenize_qml};
+
+    const QML: &str = r#"
+import QtQuick 2.5
+
+Rectangle {
+    id: root
+    property string title: "Hello"
+    width: parent.width / 2
+    Text {
+        text: root.title + qsTr("World")
+        anchors.centerIn: parent
+    }
+}
+"#;
+
+    fn build_hashtab() -> HashTab {
+        // Every HashMap is seeded differently, so each of these iterates in a different order.
+        let mut hashtab = HashTab::new();
+        hash_token_stream(
+            &tokenize_qml(QML.into(), "test.qml", None, None, QMLDiffExtensions::NONE),
+            &mut hashtab,
+        );
+        hashtab
+    }
+
+    #[test]
+    fn test_hashtab_serialization_is_deterministic() {
+        let first = serialize_hashtab(&build_hashtab(), Some("1.0".into()));
+        for _ in 0..8 {
+            assert_eq!(
+                first,
+                serialize_hashtab(&build_hashtab(), Some("1.0".into()))
+            );
+        }
+    }
+
+    #[test]
+    fn test_hashtab_sources() {
+        let hashtab = build_hashtab();
+        let mut sources = HashTabSources::default();
+        let file_id = sources.add_file("/Main.qml", QML);
+        sources.record(file_id, &hashtab);
+        let data = serialize_hashtab_with_sources(&hashtab, None, Some(&sources));
+
+        let path = std::env::temp_dir().join("qmldiff-hashtab-sources");
+        std::fs::write(&path, &data).unwrap();
+        let mut loaded = HashTab::new();
+        let mut loaded_sources = HashTabSources::default();
+        merge_hash_file_with_sources(&path, &mut loaded, &mut loaded_sources).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, hashtab);
+        assert_eq!(loaded_sources, sources);
+        assert_eq!(
+            loaded_sources
+                .source_of(hash("root"))
+                .map(|e| e.path.as_str()),
+            Some("/Main.qml")
+        );
+    }
+
+    #[test]
+    fn test_hashtab_from_sources() {
+        let mut sources = HashTabSources::default();
+        let hashtab = build_hashtab_from_sources(
+            [
+                ("dir/Main.qml", QML),
+                ("/dir/Other.qml", "Item { id: root }"),
+            ],
+            Some(&mut sources),
+        );
+        for entry in [
+            "dir",
+            "/dir",
+            "Main.qml",
+            "/dir/Main.qml",
+            "/dir/Other.qml",
+            "title",
+        ] {
+            assert_eq!(hashtab.get(&hash(entry)).map(String::as_str), Some(entry));
+        }
+        // Everything the file-based hashing finds is there.
+        assert!(build_hashtab().keys().all(|e| hashtab.contains_key(e)));
+        assert_eq!(
+            sources.source_of(hash("root")).map(|e| e.path.as_str()),
+            Some("/dir/Main.qml")
+        );
+    }
+
+    #[test]
+    fn test_hashtab_files_are_replaced() {
+        let directory = std::env::temp_dir().join("qmldiff-hashtab-write");
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = directory.join("hashtab");
+        std::fs::write(&path, "old contents").unwrap();
+        let hashtab = build_hashtab();
+        write_hashtab_file(&path, &serialize_hashtab(&hashtab, None)).unwrap();
+        let mut loaded = HashTab::new();
+        merge_hash_file(&path, &mut loaded, None, None).unwrap();
+        assert_eq!(hashtab, loaded);
+        // Only the hashtab itself is left - no temporary file.
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 1);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn test_compressed_hashtabs_are_loaded_transparently() {
+        let hashtab = build_hashtab();
+        let data = serialize_hashtab(&hashtab, None);
+        for compression in [HashTabCompression::Gzip, HashTabCompression::Zstd] {
+            let path = std::env::temp_dir().join(format!("qmldiff-hashtab-{:?}", compression));
+            std::fs::write(&path, compress_hashtab(&data, compression).unwrap()).unwrap();
+            let mut loaded = HashTab::new();
+            merge_hash_file(&path, &mut loaded, None, None).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(hashtab, loaded);
+        }
+    }
+}
@@ -0,0 +1,264 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeMap,
+    fmt::Display,
+    rc::{Rc, Weak},
+    str::FromStr,
+};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    parser::qml::parser::{AssignmentChildValue, Object, ObjectChild},
+    refcell_translation::{
+        TranslatedObject, TranslatedObjectChild, TranslatedObjectRef, TranslatedTree,
+    },
+};
+
+/// What to do when the changes make a file assign the same id to several objects.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum IdCheck {
+    Off,
+    /// Print a warning, and emit the file anyway.
+    #[default]
+    Warn,
+    /// Fail to process the file.
+    Error,
+}
+
+impl FromStr for IdCheck {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "off" => Ok(Self::Off),
+            "warn" => Ok(Self::Warn),
+            "error" => Ok(Self::Error),
+            _ => Err(Error::msg(format!(
+                "Unknown id check {} - expected off, warn or error",
+                s
+            ))),
+        }
+    }
+}
+
+/// An id assigned to several objects of a patched file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateId {
+    pub id: String,
+    pub count: usize,
+    /// How many times the original file assigned it.
+    pub original_count: usize,
+    /// The diffs whose changes assigned it again.
+    pub sources: Vec<String>,
+}
+
+impl Display for DuplicateId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the id {} is assigned {} times (", self.id, self.count)?;
+        if self.original_count != 0 {
+            write!(f, "{} in the original file, ", self.original_count)?;
+        }
+        let sources: Vec<_> = self.sources.iter().map(|e| format!("'{}'", e)).collect();
+        write!(f, "added by {})", sources.join(", "))
+    }
+}
+
+/// Objects which start a new scope of ids.
+fn is_component(name: &str) -> bool {
+    name == "Component"
+}
+
+fn count_raw_ids(object: &Object, counts: &mut BTreeMap<String, usize>) {
+    if is_component(&object.name) {
+        return;
+    }
+    for child in &object.children {
+        match child {
+            ObjectChild::Assignment(assignment) if assignment.name == "id" => {
+                if let Some(id) = child.get_str_value() {
+                    *counts.entry(id).or_default() += 1;
+                }
+            }
+            ObjectChild::Object(object) => count_raw_ids(object, counts),
+            ObjectChild::ObjectAssignment(assignment) if assignment.name != "delegate" => {
+                count_raw_ids(&assignment.value, counts)
+            }
+            ObjectChild::ObjectListAssignment(list) => {
+                for object in &list.values {
+                    count_raw_ids(object, counts);
+                }
+            }
+            ObjectChild::ObjectProperty(prop) => count_raw_ids(&prop.default_value, counts),
+            ObjectChild::Assignment(assignment) if assignment.name != "delegate" => {
+                if let AssignmentChildValue::Object(object) = &assignment.value {
+                    count_raw_ids(object, counts);
+                }
+            }
+            ObjectChild::Property(prop) => {
+                if let Some(AssignmentChildValue::Object(object)) = &prop.default_value {
+                    count_raw_ids(object, counts);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn count_translated_ids(object: &TranslatedObject, counts: &mut BTreeMap<String, usize>) {
+    if is_component(&object.name) {
+        return;
+    }
+    for child in &object.children {
+        match child {
+            TranslatedObjectChild::Assignment(assignment) if assignment.name == "id" => {
+                if let Some(id) = child.get_str_value() {
+                    *counts.entry(id).or_default() += 1;
+                }
+            }
+            TranslatedObjectChild::Object(object) => count_translated_ids(&object.borrow(), counts),
+            TranslatedObjectChild::ObjectAssignment(assignment)
+                if assignment.name != "delegate" =>
+            {
+                count_translated_ids(&assignment.value.borrow(), counts)
+            }
+            TranslatedObjectChild::ObjectListAssignment(list) => {
+                count_translated_ids(&list.value.borrow(), counts)
+            }
+            TranslatedObjectChild::ObjectProperty(prop) => {
+                count_translated_ids(&prop.default_value.borrow(), counts)
+            }
+            TranslatedObjectChild::Assignment(assignment) if assignment.name != "delegate" => {
+                if let AssignmentChildValue::Object(object) = &assignment.value {
+                    count_raw_ids(object, counts);
+                }
+            }
+            TranslatedObjectChild::Property(prop) => {
+                if let Some(AssignmentChildValue::Object(object)) = &prop.default_value {
+                    count_raw_ids(object, counts);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// How many objects of the tree every id is assigned to. Inline components, `Component` objects
+/// and delegates have ids of their own, and are not included.
+pub fn count_ids(tree: &TranslatedTree) -> BTreeMap<String, usize> {
+    let mut counts = BTreeMap::new();
+    count_translated_ids(&tree.root.borrow(), &mut counts);
+    counts
+}
+
+fn index_translated_ids(
+    object: &TranslatedObjectRef,
+    index: &mut BTreeMap<String, Weak<RefCell<TranslatedObject>>>,
+) {
+    let object_ref = object.borrow();
+    if is_component(&object_ref.name) {
+        return;
+    }
+    if let Some(id) = object_ref.id() {
+        index.entry(id).or_insert_with(|| Rc::downgrade(object));
+    }
+    for child in &object_ref.children {
+        match child {
+            TranslatedObjectChild::Object(object) => index_translated_ids(object, index),
+            TranslatedObjectChild::ObjectAssignment(assignment)
+                if assignment.name != "delegate" =>
+            {
+                index_translated_ids(&assignment.value, index)
+            }
+            TranslatedObjectChild::ObjectListAssignment(list) => {
+                index_translated_ids(&list.value, index)
+            }
+            TranslatedObjectChild::ObjectProperty(prop) => {
+                index_translated_ids(&prop.default_value, index)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The objects of a file by their ids - what selectors starting with an id only (`#volumeSlider`)
+/// look up. The changes move, remove and add objects, so the index is rebuilt whenever it turns
+/// out to be stale. Like in `count_ids`, the ids of inline components, `Component` objects and
+/// delegates are not included.
+#[derive(Debug, Default)]
+pub struct IdIndex(RefCell<BTreeMap<String, Weak<RefCell<TranslatedObject>>>>);
+
+impl IdIndex {
+    pub fn new(root: &TranslatedObjectRef) -> Self {
+        let mut index = BTreeMap::new();
+        index_translated_ids(root, &mut index);
+        Self(RefCell::new(index))
+    }
+
+    fn lookup(&self, id: &str) -> Option<TranslatedObjectRef> {
+        self.0
+            .borrow()
+            .get(id)?
+            .upgrade()
+            .filter(|e| e.borrow().id().is_some_and(|e| e == id))
+    }
+
+    /// The object within `root` which assigns itself the id - the first one, if several do.
+    pub fn get(&self, root: &TranslatedObjectRef, id: &str) -> Option<TranslatedObjectRef> {
+        if let Some(object) = self.lookup(id) {
+            return Some(object);
+        }
+        *self.0.borrow_mut() = Self::new(root).0.into_inner();
+        self.lookup(id)
+    }
+}
+
+/// Follows the ids of a file as the changes are applied to it, to tell which diffs have
+/// assigned them again.
+pub struct IdTracker {
+    original: BTreeMap<String, usize>,
+    current: BTreeMap<String, usize>,
+    sources: BTreeMap<String, Vec<String>>,
+}
+
+impl IdTracker {
+    pub fn new(tree: &TranslatedTree) -> Self {
+        let original = count_ids(tree);
+        Self {
+            current: original.clone(),
+            original,
+            sources: BTreeMap::new(),
+        }
+    }
+
+    /// Records the ids assigned by a change from the diff `source`.
+    pub fn update(&mut self, tree: &TranslatedTree, source: &str) {
+        let counts = count_ids(tree);
+        for (id, count) in &counts {
+            if *count > self.current.get(id).copied().unwrap_or(0) {
+                let sources = self.sources.entry(id.clone()).or_default();
+                if !sources.iter().any(|e| e == source) {
+                    sources.push(source.to_string());
+                }
+            }
+        }
+        self.current = counts;
+    }
+
+    /// The ids the changes have made ambiguous. Ids the original file already assigned several
+    /// times are only included if a diff assigned them again.
+    pub fn duplicates(&self) -> Vec<DuplicateId> {
+        self.current
+            .iter()
+            .filter(|(_, count)| **count > 1)
+            .filter_map(|(id, count)| {
+                Some(DuplicateId {
+                    id: id.clone(),
+                    count: *count,
+                    original_count: self.original.get(id).copied().unwrap_or(0),
+                    sources: self.sources.get(id)?.clone(),
+                })
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::Path,
+    str::FromStr,
+};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    parser::qml::parser::{AssignmentChildValue, Import, Object, ObjectChild, TreeElement},
+    refcell_translation::{TranslatedObject, TranslatedObjectChild, TranslatedTree},
+};
+
+/// What to do when the changes make a file use types whose modules it doesn't import.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MissingImports {
+    /// Print a warning, and emit the file anyway.
+    #[default]
+    Warn,
+    /// Add the missing imports to the file.
+    Add,
+}
+
+impl FromStr for MissingImports {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "warn" => Ok(Self::Warn),
+            "add" => Ok(Self::Add),
+            _ => Err(Error::msg(format!(
+                "Unknown missing imports policy {} - expected warn or add",
+                s
+            ))),
+        }
+    }
+}
+
+/// The module a type is imported from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImportedModule {
+    pub module: String,
+    /// Versionless imports are only supported by Qt 6.
+    pub version: Option<String>,
+}
+
+impl Display for ImportedModule {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.module)?;
+        if let Some(version) = &self.version {
+            write!(f, " {}", version)?;
+        }
+        Ok(())
+    }
+}
+
+/// Maps the names of types to the modules they are imported from. Read from files with a type
+/// per line:
+/// ```text
+/// # <type> <module> [version]
+/// ColorOverlay QtGraphicalEffects 1.15
+/// MultiEffect QtQuick.Effects
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ImportMap(pub BTreeMap<String, ImportedModule>);
+
+impl ImportMap {
+    pub fn parse(contents: &str) -> Result<Self> {
+        let mut map = BTreeMap::new();
+        for (i, line) in contents.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let (name, module, version) = match parts.as_slice() {
+                [name, module] => (name, module, None),
+                [name, module, version] => (name, module, Some(version.to_string())),
+                _ => {
+                    return Err(Error::msg(format!(
+                        "Line {}: expected <type> <module> [version], got {}",
+                        i + 1,
+                        line
+                    )))
+                }
+            };
+            map.insert(
+                name.to_string(),
+                ImportedModule {
+                    module: module.to_string(),
+                    version,
+                },
+            );
+        }
+        Ok(Self(map))
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        Self::parse(&std::fs::read_to_string(path)?).map_err(|error| {
+            Error::msg(format!(
+                "Cannot read the import map {}: {}",
+                path.display(),
+                error
+            ))
+        })
+    }
+}
+
+/// The import analysis done on the files the changes are applied to.
+#[derive(Debug, Clone, Default)]
+pub struct ImportCheck {
+    pub map: ImportMap,
+    pub mode: MissingImports,
+}
+
+/// A type used by a patched file, whose module the file doesn't import.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MissingImport {
+    pub type_name: String,
+    pub module: ImportedModule,
+}
+
+impl Display for MissingImport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} (from {})", self.type_name, self.module)
+    }
+}
+
+fn add_type(name: &str, types: &mut BTreeSet<String>) {
+    let name = name
+        .strip_prefix("list<")
+        .and_then(|e| e.strip_suffix('>'))
+        .unwrap_or(name);
+    // Qualified names (`Effects.ColorOverlay`) go through an aliased import which has to exist.
+    if !name.contains('.') {
+        types.insert(name.to_string());
+    }
+}
+
+fn add_value_types(value: &AssignmentChildValue, types: &mut BTreeSet<String>) {
+    if let AssignmentChildValue::Object(object) = value {
+        add_raw_types(object, types);
+    }
+}
+
+fn add_raw_types(object: &Object, types: &mut BTreeSet<String>) {
+    add_type(&object.name, types);
+    for child in &object.children {
+        match child {
+            ObjectChild::Object(object) => add_raw_types(object, types),
+            ObjectChild::ObjectAssignment(assignment) => add_raw_types(&assignment.value, types),
+            ObjectChild::ObjectListAssignment(list) => {
+                for object in &list.values {
+                    add_raw_types(object, types);
+                }
+            }
+            ObjectChild::Component(component) => add_raw_types(&component.object, types),
+            ObjectChild::ObjectProperty(prop) => {
+                prop.r#type.iter().for_each(|e| add_type(e, types));
+                add_raw_types(&prop.default_value, types);
+            }
+            ObjectChild::Property(prop) => {
+                prop.r#type.iter().for_each(|e| add_type(e, types));
+                prop.default_value
+                    .iter()
+                    .for_each(|e| add_value_types(e, types));
+            }
+            ObjectChild::Assignment(assignment) => add_value_types(&assignment.value, types),
+            _ => {}
+        }
+    }
+}
+
+fn add_translated_types(object: &TranslatedObject, types: &mut BTreeSet<String>) {
+    add_type(&object.name, types);
+    for child in &object.children {
+        match child {
+            TranslatedObjectChild::Object(object) => add_translated_types(&object.borrow(), types),
+            TranslatedObjectChild::ObjectAssignment(assignment)
+            | TranslatedObjectChild::Component(assignment) => {
+                add_translated_types(&assignment.value.borrow(), types)
+            }
+            // The objects of a list are the children of its value, which has no type of its own.
+            TranslatedObjectChild::ObjectListAssignment(list) => {
+                for child in &list.value.borrow().children {
+                    if let TranslatedObjectChild::Object(object) = child {
+                        add_translated_types(&object.borrow(), types);
+                    }
+                }
+            }
+            TranslatedObjectChild::ObjectProperty(prop) => {
+                prop.r#type.iter().for_each(|e| add_type(e, types));
+                add_translated_types(&prop.default_value.borrow(), types);
+            }
+            TranslatedObjectChild::Property(prop) => {
+                prop.r#type.iter().for_each(|e| add_type(e, types));
+                prop.default_value
+                    .iter()
+                    .for_each(|e| add_value_types(e, types));
+            }
+            TranslatedObjectChild::Assignment(assignment) => {
+                add_value_types(&assignment.value, types)
+            }
+            _ => {}
+        }
+    }
+}
+
+/// The names of all the types the tree uses - the types of its objects and of its properties.
+pub fn used_types(tree: &TranslatedTree) -> BTreeSet<String> {
+    let mut types = BTreeSet::new();
+    add_translated_types(&tree.root.borrow(), &mut types);
+    types
+}
+
+impl ImportCheck {
+    /// The types the changes have started using, whose modules the tree doesn't import. Types
+    /// the original file (with its `original_types`) already used are never reported - they're
+    /// provided by something the map doesn't know about.
+    pub fn missing_imports(
+        &self,
+        tree: &TranslatedTree,
+        original_types: &BTreeSet<String>,
+    ) -> Vec<MissingImport> {
+        let imported: BTreeSet<&str> = tree
+            .leftovers
+            .iter()
+            .filter_map(|e| match e {
+                TreeElement::Import(import) => Some(import.object_name.as_str()),
+                _ => None,
+            })
+            .collect();
+        used_types(tree)
+            .difference(original_types)
+            .filter_map(|name| {
+                let module = self.map.0.get(name)?;
+                (!imported.contains(module.module.as_str())).then(|| MissingImport {
+                    type_name: name.clone(),
+                    module: module.clone(),
+                })
+            })
+            .collect()
+    }
+
+    /// Imports the modules of the missing types into the tree.
+    pub fn add_imports(tree: &mut TranslatedTree, missing: &[MissingImport]) {
+        let mut added = BTreeSet::new();
+        for missing in missing {
+            if added.insert(&missing.module.module) {
+                tree.leftovers.push(TreeElement::Import(Import {
+                    object_name: missing.module.module.clone(),
+                    version: missing.module.version.clone(),
+                    alias: None,
+                }));
+            }
+        }
+    }
+}
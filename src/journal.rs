@@ -0,0 +1,173 @@
+use std::{
+    fmt::{Display, Write},
+    time::Duration,
+};
+
+/// A single mutation performed on a QML file while applying a change.
+#[derive(Debug, Clone)]
+pub struct JournalEntry {
+    pub file: String,
+    /// The diff file which requested the mutation.
+    pub source: String,
+    pub action: &'static str,
+    /// The TRAVERSE / ASSERT / REPLICATE directives leading to the root that was modified.
+    pub scope: Vec<String>,
+    pub selector: Option<String>,
+    pub cursor: Option<usize>,
+    pub inserted: Vec<String>,
+    pub removed: Vec<String>,
+    /// The directives which revert this mutation, when executed within `scope`.
+    pub undo: Vec<String>,
+}
+
+/// Records every mutation done by the processor, so that the behavior of two
+/// versions of a diff pack can be compared.
+#[derive(Debug, Default)]
+pub struct MutationJournal(pub Vec<JournalEntry>);
+
+pub fn escape_json(string: &str) -> String {
+    let mut out = String::with_capacity(string.len() + 2);
+    out.push('"');
+    for chr in string.chars() {
+        match chr {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => write!(out, "\\u{:04x}", c as u32).unwrap(),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn escape_json_list(list: &[String]) -> String {
+    format!(
+        "[{}]",
+        list.iter()
+            .map(|e| escape_json(e))
+            .collect::<Vec<_>>()
+            .join(",")
+    )
+}
+
+impl JournalEntry {
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"file\":{},\"source\":{},\"action\":{},\"scope\":{},\"selector\":{},\"cursor\":{},\"inserted\":{},\"removed\":{}}}",
+            escape_json(&self.file),
+            escape_json(&self.source),
+            escape_json(self.action),
+            escape_json_list(&self.scope),
+            self.selector.as_deref().map_or("null".into(), escape_json),
+            self.cursor.map_or("null".into(), |e| e.to_string()),
+            escape_json_list(&self.inserted),
+            escape_json_list(&self.removed),
+        )
+    }
+}
+
+impl MutationJournal {
+    pub fn new() -> Self {
+        Self(Vec::new())
+    }
+
+    pub fn record(&mut self, entry: JournalEntry) {
+        self.0.push(entry);
+    }
+
+    /// Serializes the journal as JSON lines - one entry per line.
+    pub fn to_jsonl(&self) -> String {
+        let mut out = String::new();
+        for entry in &self.0 {
+            out.push_str(&entry.to_json());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// What a single directive of a change matched in the tree. Collected in debug mode.
+#[derive(Debug, Clone)]
+pub struct DirectiveTrace {
+    pub directive: String,
+    /// The full names of the matched nodes.
+    pub matched: Vec<String>,
+}
+
+/// How a single change was applied to a file, and how long it took.
+#[derive(Debug, Clone)]
+pub struct ChangeTrace {
+    pub source: String,
+    pub directives: Vec<DirectiveTrace>,
+    pub duration: Duration,
+}
+
+impl Display for ChangeTrace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({}us)", self.source, self.duration.as_micros())?;
+        for directive in &self.directives {
+            write!(
+                f,
+                "\n    {} -> [{}]",
+                directive.directive,
+                directive.matched.join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape_json, JournalEntry, MutationJournal};
+
+    #[test]
+    fn test_escape_json() {
+        assert_eq!(escape_json("plain"), "\"plain\"");
+        assert_eq!(
+            escape_json("text: \"a\\b\"\n\tnext\r"),
+            "\"text: \\\"a\\\\b\\\"\\n\\tnext\\r\""
+        );
+        assert_eq!(escape_json("\u{1}\u{1f}é"), "\"\\u0001\\u001fé\"");
+    }
+
+    #[test]
+    fn test_journal_to_jsonl() {
+        let mut journal = MutationJournal::new();
+        journal.record(JournalEntry {
+            file: "/ui/Main.qml".into(),
+            source: "mods/toolbar.qmd".into(),
+            action: "REPLACE",
+            scope: vec!["TRAVERSE Rectangle > Text#title".into()],
+            selector: Some("text".into()),
+            cursor: Some(2),
+            inserted: vec!["text: \"Hello\"".into()],
+            removed: vec!["text: \"Welcome\"".into()],
+            undo: vec!["REPLACE text WITH { text: \"Welcome\" }".into()],
+        });
+        journal.record(JournalEntry {
+            file: "/ui/Main.qml".into(),
+            source: "mods/toolbar.qmd".into(),
+            action: "REMOVE",
+            scope: Vec::new(),
+            selector: None,
+            cursor: None,
+            inserted: Vec::new(),
+            removed: Vec::new(),
+            undo: Vec::new(),
+        });
+        // The undo directives are only used for the reverse diff.
+        assert_eq!(
+            journal.to_jsonl(),
+            concat!(
+                r#"{"file":"/ui/Main.qml","source":"mods/toolbar.qmd","action":"REPLACE","scope":["TRAVERSE Rectangle > Text#title"],"selector":"text","cursor":2,"inserted":["text: \"Hello\""],"removed":["text: \"Welcome\""]}"#,
+                "\n",
+                r#"{"file":"/ui/Main.qml","source":"mods/toolbar.qmd","action":"REMOVE","scope":[],"selector":null,"cursor":null,"inserted":[],"removed":[]}"#,
+                "\n"
+            )
+        );
+    }
+}
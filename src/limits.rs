@@ -0,0 +1,130 @@
+//! Limits on what the diffs can make qmldiff do. Diffs of third-party packs can't be trusted -
+//! without these, deeply nested `LOAD`s or slots could overflow the stack, and slots inserting
+//! each other many times over could use up all the memory.
+
+use anyhow::Result;
+
+use crate::{
+    parser::{
+        common::StringCharacterTokenizer,
+        diff::lexer::{Lexer, TokenType},
+    },
+    util::common_util::{error_details, kind_error, ErrorKind},
+};
+
+/// The limits the diffs have to fit within. A limit of 0 disables it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    /// How deep `LOAD`s can be nested - a file loading a file which loads another...
+    pub max_load_depth: usize,
+    /// How deep slots can be nested - a slot inserting a slot which inserts another...
+    pub max_slot_depth: usize,
+    /// How many tokens a slot or a template can expand into.
+    pub max_expansion_tokens: usize,
+    /// How many tokens a single diff file can consist of, counting the tokens of its QML code.
+    pub max_diff_tokens: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_load_depth: 32,
+            max_slot_depth: 64,
+            max_expansion_tokens: 1_000_000,
+            max_diff_tokens: 10_000_000,
+        }
+    }
+}
+
+fn exceeds(value: usize, limit: usize) -> bool {
+    limit != 0 && value > limit
+}
+
+impl Limits {
+    pub fn unlimited() -> Self {
+        Self {
+            max_load_depth: 0,
+            max_slot_depth: 0,
+            max_expansion_tokens: 0,
+            max_diff_tokens: 0,
+        }
+    }
+
+    /// `chain` are the files being loaded, starting with the top-level one.
+    pub fn check_load_depth(&self, chain: &[String]) -> Result<()> {
+        if exceeds(chain.len() - 1, self.max_load_depth) {
+            return Err(kind_error(
+                ErrorKind::Limit,
+                format!(
+                    "LOADs are nested deeper than {} levels: {}",
+                    self.max_load_depth,
+                    chain.join(" -> ")
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn check_diff_tokens(&self, count: usize, diff_name: &str) -> Result<()> {
+        if exceeds(count, self.max_diff_tokens) {
+            return Err(kind_error(
+                ErrorKind::Limit,
+                format!(
+                    "The diff {} consists of {} tokens - more than the limit of {}",
+                    diff_name, count, self.max_diff_tokens
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Reads the tokens of a diff, failing as soon as there are more of them than
+    /// `max_diff_tokens` - an oversized diff is never read whole.
+    pub fn tokenize_diff(&self, contents: String, diff_name: &str) -> Result<Vec<TokenType>> {
+        let mut count = 0;
+        Lexer::new(StringCharacterTokenizer::new(contents))
+            .tokenize_with(|token| {
+                count += match token {
+                    TokenType::QMLCode { qml_code, .. } => qml_code.len() + 1,
+                    _ => 1,
+                };
+                self.check_diff_tokens(count, diff_name)
+            })
+            .map_err(|error| match error_details(&error).kind {
+                ErrorKind::Limit => error,
+                _ => kind_error(
+                    ErrorKind::Parse,
+                    format!("Cannot read the diff {}: {}", diff_name, error),
+                ),
+            })
+    }
+
+    /// `chain` are the slots being expanded, starting with the outermost one.
+    pub fn check_slot_depth(&self, chain: &[String]) -> Result<()> {
+        if exceeds(chain.len(), self.max_slot_depth) {
+            return Err(kind_error(
+                ErrorKind::Limit,
+                format!(
+                    "Slots are nested deeper than {} levels: {}",
+                    self.max_slot_depth,
+                    chain.join(" -> ")
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// `name` is the slot or the template being expanded.
+    pub fn check_expansion_tokens(&self, count: usize, name: &str) -> Result<()> {
+        if exceeds(count, self.max_expansion_tokens) {
+            return Err(kind_error(
+                ErrorKind::Limit,
+                format!(
+                    "{} expands into more than {} tokens",
+                    name, self.max_expansion_tokens
+                ),
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,265 @@
+use crate::parser::diff::{
+    lexer::Keyword,
+    parser::{
+        Change, FileChangeAction, LocateRebuildActionSelector, LocationSelector, NodeSelector,
+        PropRequirement, RebuildInstruction, RemoveRebuildAction, ReplaceRebuildActionWhat,
+    },
+};
+
+/// TRAVERSE paths longer than this are considered fragile - every level can be restructured
+/// by a vendor update.
+const MAX_ROBUST_DEPTH: usize = 3;
+
+/// How fragile a selector is. The higher the score, the more likely the selector is to break
+/// (or to silently match something else) once the QML tree is updated.
+#[derive(Debug, Default, Clone)]
+pub struct SelectorScore {
+    pub score: u32,
+    pub reasons: Vec<String>,
+    pub suggestions: Vec<String>,
+}
+
+impl SelectorScore {
+    fn add(&mut self, points: u32, reason: String) {
+        self.score += points;
+        self.reasons.push(reason);
+    }
+
+    fn suggest(&mut self, suggestion: String) {
+        if !self.suggestions.contains(&suggestion) {
+            self.suggestions.push(suggestion);
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectorReport {
+    pub source: String,
+    pub destination: String,
+    /// The directive the selector belongs to, as written in the diff.
+    pub directive: String,
+    pub score: SelectorScore,
+}
+
+/// QML types start with an uppercase letter. Lowercase names refer to properties,
+/// functions and signals, which are unique within their parent.
+fn is_type_name(name: &str) -> bool {
+    name.starts_with(char::is_uppercase)
+}
+
+fn score_node(node: &NodeSelector, score: &mut SelectorScore) {
+    if node.is_simple() && is_type_name(&node.object_name) {
+        score.add(
+            2,
+            format!("`{}` is selected by its type only", node.object_name),
+        );
+        score.suggest(format!(
+            "select `{}` by its id (`{}#id`)",
+            node.object_name, node.object_name
+        ));
+    }
+    let mut props = node.props.iter().collect::<Vec<_>>();
+    props.sort_by_key(|e| e.0);
+    for (name, requirement) in props {
+        match requirement {
+            PropRequirement::Equals(_) if name == "id" => {}
+            PropRequirement::Equals(value)
+                if value.starts_with(['"', '\'', '`']) || value.contains("qsTr") =>
+            {
+                score.add(
+                    2,
+                    format!("depends on the exact string value of `{}`", name),
+                );
+            }
+            PropRequirement::Equals(_) => {
+                score.add(1, format!("depends on the exact value of `{}`", name));
+            }
+            PropRequirement::Contains(_) | PropRequirement::Compare(..) => {
+                score.add(1, format!("depends on the value of `{}`", name));
+            }
+            PropRequirement::Exists => {}
+        }
+    }
+}
+
+/// Scores a selector, which is `scope_depth` TRAVERSEs deep.
+pub fn score_tree(tree: &[NodeSelector], scope_depth: usize) -> SelectorScore {
+    let mut score = SelectorScore::default();
+    for (index, node) in tree.iter().enumerate() {
+        // There is only one root object per file - its type is enough to select it.
+        if scope_depth == 0 && index == 0 && node.is_simple() {
+            continue;
+        }
+        score_node(node, &mut score);
+    }
+    // A tree starting with an id only (`#volumeSlider`) is found wherever it is - the path
+    // starts from it.
+    let depth = match tree.first().and_then(|e| e.standalone_id()) {
+        Some(_) => tree.len(),
+        None => scope_depth + tree.len(),
+    };
+    if depth > MAX_ROBUST_DEPTH {
+        score.add(
+            (depth - MAX_ROBUST_DEPTH) as u32,
+            format!("follows a fixed path {} levels deep", depth),
+        );
+        score.suggest(
+            "TRAVERSE into the nearest ancestor with an id instead of spelling out the whole path"
+                .to_string(),
+        );
+    }
+    score
+}
+
+fn score_rebuild_instructions(instructions: &[RebuildInstruction], score: &mut SelectorScore) {
+    for instruction in instructions {
+        match instruction {
+            RebuildInstruction::InsertArgument(argument)
+            | RebuildInstruction::RemoveArgument(argument)
+            | RebuildInstruction::RenameArgument(argument, _) => score.add(
+                1,
+                format!(
+                    "assumes `{}` is argument #{}",
+                    argument.name, argument.position
+                ),
+            ),
+            RebuildInstruction::Locate(locate)
+                if matches!(locate.selector, LocateRebuildActionSelector::Stream(_)) =>
+            {
+                score.add(2, "locates an exact piece of code".to_string())
+            }
+            RebuildInstruction::Remove(
+                RemoveRebuildAction::Stream(_) | RemoveRebuildAction::UntilStream(_),
+            ) => score.add(2, "removes an exact piece of code".to_string()),
+            RebuildInstruction::Replace(replace)
+                if matches!(replace.what, ReplaceRebuildActionWhat::LiteralStream(_))
+                    || replace.until_stream.is_some() =>
+            {
+                score.add(2, "replaces an exact piece of code".to_string())
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Scores the fragility of every selector used by the change.
+pub fn lint_selectors(change: &Change) -> Vec<SelectorReport> {
+    let mut reports: Vec<SelectorReport> = Vec::new();
+    // For every open TRAVERSE / REPLICATE: the report of its selector, whether it was ASSERTed
+    // and how deep within the file it is.
+    let mut scopes: Vec<(Option<usize>, bool, usize)> = Vec::new();
+    let mut saved_scopes = Vec::new();
+
+    for action in &change.changes {
+        let depth = scopes.last().map_or(0, |e| e.2);
+        let (directive, score) = match action {
+            FileChangeAction::Traverse(tree) => {
+                scopes.push((Some(reports.len()), false, depth + 1));
+                (format!("TRAVERSE {}", tree), score_tree(tree, depth))
+            }
+            FileChangeAction::TraverseFromRoot(tree) => {
+                scopes.push((Some(reports.len()), false, 1));
+                (format!("TRAVERSE ROOT {}", tree), score_tree(tree, 0))
+            }
+            FileChangeAction::Replicate(tree) => {
+                scopes.push((None, false, depth + 1));
+                (format!("REPLICATE {}", tree), score_tree(tree, depth))
+            }
+            FileChangeAction::ReplicateFromRoot(tree) => {
+                scopes.push((None, false, 1));
+                (format!("REPLICATE ROOT {}", tree), score_tree(tree, 0))
+            }
+            FileChangeAction::End(Keyword::Traverse | Keyword::Replicate) => {
+                if let Some((Some(index), false, _)) = scopes.pop() {
+                    let report = &mut reports[index];
+                    if report.score.score > 0 {
+                        report.score.suggest(
+                            "ASSERT the contents of the traversed object, so that a wrong match is caught"
+                                .to_string(),
+                        );
+                    }
+                }
+                continue;
+            }
+            FileChangeAction::PushScope => {
+                saved_scopes.push(scopes.clone());
+                continue;
+            }
+            FileChangeAction::PopScope => {
+                if let Some(saved) = saved_scopes.pop() {
+                    scopes = saved;
+                }
+                continue;
+            }
+            // An ASSERT only makes the change fail instead of being misapplied - it is not scored.
+            FileChangeAction::Assert(_) => {
+                if let Some(scope) = scopes.last_mut() {
+                    scope.1 = true;
+                }
+                continue;
+            }
+            FileChangeAction::Locate(locate) => match &locate.selector {
+                // Anchors are declared by diffs, so they can't be broken by an update.
+                LocationSelector::All | LocationSelector::Anchor(_) => continue,
+                LocationSelector::Tree(tree) => {
+                    let mut score = score_tree(tree, depth);
+                    score.add(
+                        1,
+                        format!("assumes `{}` stays next to the insertion point", tree),
+                    );
+                    (locate.to_string(), score)
+                }
+                // Functions, signals and properties are unique within their parent.
+                LocationSelector::Member(_, name) => {
+                    let mut score = SelectorScore::default();
+                    score.add(
+                        1,
+                        format!("assumes `{}` stays next to the insertion point", name),
+                    );
+                    (locate.to_string(), score)
+                }
+            },
+            FileChangeAction::Remove(node) => (
+                format!("REMOVE {}", node),
+                score_tree(std::slice::from_ref(node), depth),
+            ),
+            FileChangeAction::Replace(replace) => (
+                format!("REPLACE {}", replace.selector),
+                score_tree(&replace.selector, depth),
+            ),
+            FileChangeAction::Rename(rename) => (
+                format!("RENAME {}", rename.selector),
+                score_tree(&rename.selector, depth),
+            ),
+            FileChangeAction::Transform(transform) => (
+                format!("TRANSFORM {}", transform.selector),
+                score_tree(&transform.selector, depth),
+            ),
+            FileChangeAction::Rebuild(rebuild) => {
+                let mut score = score_tree(std::slice::from_ref(&rebuild.selector), depth);
+                score_rebuild_instructions(&rebuild.actions, &mut score);
+                (
+                    format!(
+                        "{} {}",
+                        if rebuild.redefine {
+                            "REDEFINE"
+                        } else {
+                            "REBUILD"
+                        },
+                        rebuild.selector
+                    ),
+                    score,
+                )
+            }
+            _ => continue,
+        };
+        reports.push(SelectorReport {
+            source: change.source.to_string(),
+            destination: change.destination.to_string(),
+            directive,
+            score,
+        });
+    }
+
+    reports
+}
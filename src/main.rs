@@ -1,22 +1,69 @@
 #![allow(dead_code)]
-use std::fs::{create_dir, remove_dir_all};
+extern crate alloc;
 
+use std::{
+    fs::{create_dir, remove_dir_all},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Error, Result};
 use clap::{Parser, Subcommand};
-use cli_util::{apply_changes, build_change_structures, process_diff_tree, start_hashmap_build};
+use cli_util::{
+    apply_changes, apply_changes_to_archive, build_change_structures, check_determinism,
+    compact_diff_directory, compare_qml_files, diff_pack_versions, dump_qml_tree,
+    explain_hash_rules, extend_supplementary_hashtab, initial_slots, lint_diffs, process_diff_tree,
+    process_single_file, prune_hashtab, run_diff_tests, start_hashmap_build, write_corpus,
+    write_error_report,
+};
+use corpus::CorpusConfig;
 use hash::hash;
 use hashrules::HashRules;
-use hashtab::{merge_hash_file, serialize_hashtab, HashTab, InvHashTab};
-use slots::Slots;
+use hashtab::{
+    compress_hashtab, merge_hash_file, merge_hash_file_with_sources, serialize_hashtab,
+    serialize_hashtab_with_sources, write_hashtab_file, HashTab, HashTabCompression,
+    HashTabSources, InvHashTab,
+};
+use id_check::IdCheck;
+use import_check::{ImportCheck, ImportMap, MissingImports};
+use journal::MutationJournal;
+use limits::Limits;
+use lsp::LanguageServer;
+use parser::{
+    diff::parser::Defines,
+    qml::emitter::{EmitterConfig, EmitterFormat},
+};
+use processor::RebuildOrder;
+use undo::build_reverse_diff;
+use util::{
+    common_util::{error_details, FailurePolicy},
+    output::{info, set_verbosity, warning, Verbosity},
+};
 
+#[path = "util/archive_util.rs"]
+mod archive_util;
 #[path = "util/cli_util.rs"]
 mod cli_util;
+mod corpus;
+mod diff_tests;
 mod hash;
 mod hashrules;
 mod hashtab;
+mod id_check;
+mod import_check;
+mod journal;
+mod limits;
+mod lint;
+#[path = "util/lsp.rs"]
+mod lsp;
+mod outline;
+mod pack_diff;
 mod parser;
 mod processor;
+mod qml_compare;
 mod refcell_translation;
 mod slots;
+mod translation;
+mod undo;
 mod util;
 
 /// qmldiff
@@ -31,6 +78,25 @@ mod util;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    /// Write a JSON report of the errors and of every file which could not be processed to this file
+    #[arg(long, global = true)]
+    error_report: Option<String>,
+    /// Only print errors (and the data the command outputs)
+    #[arg(short, long, global = true, action = clap::ArgAction::SetTrue)]
+    quiet: bool,
+    /// Also print every file being read, hashed or recursed into
+    #[arg(short, long, global = true, conflicts_with = "quiet", action = clap::ArgAction::SetTrue)]
+    verbose: bool,
+}
+
+impl Cli {
+    fn verbosity(&self) -> Verbosity {
+        match (self.quiet, self.verbose) {
+            (true, _) => Verbosity::Quiet,
+            (_, true) => Verbosity::Verbose,
+            _ => Verbosity::Normal,
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -45,13 +111,20 @@ enum Commands {
         /// The version of the QML environment to encode in hashtab
         #[arg(default_value = None, required = false, long)]
         version: Option<String>,
+        /// Compress the hashtab (gzip or zstd). It is decompressed transparently when loaded
+        #[arg(long, num_args = 0..=1, default_missing_value = "zstd")]
+        compress: Option<HashTabCompression>,
+        /// Also record which QML file every entry has been found in, for dump-hashtab --verbose
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        sources: bool,
         /// The name of the hashtab to create
         #[arg(default_value = "hashtab")]
         hashtab_name: String,
     },
     /// Dump the contents of a hashtab in a human-readable form
     DumpHashtab {
-        /// The path to the hashtab
+        /// The path to the hashtab. With --verbose, the QML file every entry has been found in is
+        /// also shown, if the hashtab has been created with --sources
         hashtab: String,
     },
     /// Hash a string
@@ -69,12 +142,15 @@ enum Commands {
         /// Instead, revert the hashing operation
         #[arg(short, long, action = clap::ArgAction::SetTrue)]
         revert: bool,
+        /// Also hash the new identifiers the diffs' QML code introduces, adding them to this supplementary hashtab
+        #[arg(long)]
+        supplementary: Option<String>,
     },
     /// Apply the diffs for a given hashtab and QML root path
     ApplyDiffs {
-        /// The hashtab to use
+        /// The hashtab to use. Can be given multiple times, to also load supplementary hashtabs
         #[arg(long)]
-        hashtab: Option<String>,
+        hashtab: Vec<String>,
         /// The root path of the QML tree
         qml_root_path: String,
         /// The destination root path
@@ -93,42 +169,347 @@ enum Commands {
         /// The QML environment version
         #[arg(default_value = None, required = false, long)]
         version: Option<String>,
+        /// Write a JSON lines journal of every mutation performed to this file
+        #[arg(default_value = None, required = false, long)]
+        journal: Option<String>,
+        /// Write a diff which reverts all the applied changes to this file
+        #[arg(default_value = None, required = false, long)]
+        reverse_diff: Option<String>,
+        /// Set a define checked by REQUIRE statements (NAME or NAME=VALUE). Can be repeated
+        #[arg(short = 'D', long = "define")]
+        defines: Vec<String>,
+        /// Reject diffs containing plain values which exist in the hashtab and should have been hashed
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        require_hashed: bool,
+        /// Read the QML tree from a tar / zip archive and write the patched tree into another archive, without unpacking anything to the disk
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "flatten")]
+        archive: bool,
+        /// Apply the diffs twice first, and fail if the results are not identical
+        #[arg(long, action = clap::ArgAction::SetTrue, conflicts_with = "archive")]
+        determinism_check: bool,
+        /// The amount of spaces per indentation level of the emitted QML
+        #[arg(long, default_value_t = 4)]
+        indent: usize,
+        /// Indent the emitted QML with tabs instead of spaces
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        tabs: bool,
+        /// End the lines of the emitted QML with CRLF. Files which use CRLF keep it regardless
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        crlf: bool,
+        /// Emit objects of at most this many tokens on a single line (0 disables it)
+        #[arg(long, default_value_t = 0)]
+        max_inline_tokens: usize,
+        /// Break the lines of bindings longer than this many columns after commas and operators (0 disables it)
+        #[arg(long, default_value_t = 0)]
+        max_line_width: usize,
+        /// Make the emitted QML as small as possible - no indentation, no blank lines and collapsed whitespace in the code
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        minify: bool,
+        /// The output format of the emitted QML - pretty, compact or canonical
+        #[arg(long, default_value = "pretty")]
+        format: EmitterFormat,
+        /// What to do when some files cannot be processed - strict writes nothing and fails listing all the errors, lenient writes every file which could be processed
+        #[arg(long, default_value = "strict")]
+        on_error: FailurePolicy,
+        /// What to do when the diffs make a file assign the same id to several objects - off, warn or error
+        #[arg(long, default_value = "warn")]
+        id_check: IdCheck,
+        /// A file mapping type names to the modules they are imported from (`<type> <module> [version]` per line), used to find the imports the patched files are missing
+        #[arg(long)]
+        import_map: Option<String>,
+        /// What to do when the diffs make a file use types whose modules it doesn't import - warn or add
+        #[arg(long, default_value = "warn", requires = "import_map")]
+        missing_imports: MissingImports,
+        /// When the AFFECT REBUILD changes of files also changed by AFFECT are applied - first (before the AFFECT changes) or strict (in the order of the changes)
+        #[arg(long, default_value = "first")]
+        rebuild_order: RebuildOrder,
+        /// How deep the LOADs of the diffs can be nested (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_load_depth)]
+        max_load_depth: usize,
+        /// How deep the slots can be nested - a slot inserting a slot which inserts another... (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_slot_depth)]
+        max_slot_depth: usize,
+        /// How many tokens a slot or a template can expand into (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_expansion_tokens)]
+        max_expansion_tokens: usize,
+        /// How many tokens a single diff file (or a file it LOADs) can consist of (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_diff_tokens)]
+        max_diff_tokens: usize,
+        /// Start out with the slots and templates exported by an earlier run
+        #[arg(long)]
+        import_slots: Option<String>,
+        /// Write the slots and templates into this file once the diffs are applied, so that a later run can import them
+        #[arg(long)]
+        export_slots: Option<String>,
+    },
+    /// Apply the diffs to a single QML file, and print the result to stdout
+    ProcessFile {
+        /// The hashtab to use
+        hashtab: String,
+        /// The QML file to process
+        qml_file: String,
+        /// The list of diff files or directories
+        #[arg(required = true)]
+        diff_list: Vec<String>,
+        /// The name the diffs refer to the file by (its file name by default)
+        #[arg(default_value = None, required = false, long = "as")]
+        name: Option<String>,
+        /// The QML environment version
+        #[arg(default_value = None, required = false, long)]
+        version: Option<String>,
+        /// Set a define checked by REQUIRE statements (NAME or NAME=VALUE). Can be repeated
+        #[arg(short = 'D', long = "define")]
+        defines: Vec<String>,
+        /// The output format of the emitted QML - pretty, compact or canonical
+        #[arg(long, default_value = "pretty")]
+        format: EmitterFormat,
+        /// What to do when the diffs make the file assign the same id to several objects - off, warn or error
+        #[arg(long, default_value = "warn")]
+        id_check: IdCheck,
+        /// A file mapping type names to the modules they are imported from (`<type> <module> [version]` per line), used to find the imports the patched file is missing
+        #[arg(long)]
+        import_map: Option<String>,
+        /// What to do when the diffs make the file use types whose modules it doesn't import - warn or add
+        #[arg(long, default_value = "warn", requires = "import_map")]
+        missing_imports: MissingImports,
+        /// When the AFFECT REBUILD changes of a file also changed by AFFECT are applied - first (before the AFFECT changes) or strict (in the order of the changes)
+        #[arg(long, default_value = "first")]
+        rebuild_order: RebuildOrder,
+        /// How deep the LOADs of the diffs can be nested (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_load_depth)]
+        max_load_depth: usize,
+        /// How deep the slots can be nested - a slot inserting a slot which inserts another... (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_slot_depth)]
+        max_slot_depth: usize,
+        /// How many tokens a slot or a template can expand into (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_expansion_tokens)]
+        max_expansion_tokens: usize,
+        /// How many tokens a single diff file (or a file it LOADs) can consist of (0 disables the limit)
+        #[arg(long, default_value_t = Limits::default().max_diff_tokens)]
+        max_diff_tokens: usize,
+        /// Print the changes affecting the file to stderr, the way the parser understood them
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        explain: bool,
     },
     /// Create the greatest-common-divisor of a list of hashtabs
     GCDHashtab {
         output_hashtab: String,
         hashtabs: Vec<String>,
     },
+    /// Create a hashtab containing only the entries referenced by the given diffs
+    PruneHashtab {
+        /// The hashtab to prune
+        hashtab: String,
+        /// The list of diff files or directories
+        #[arg(required = true)]
+        diff_list: Vec<String>,
+        /// Where to write the pruned hashtab
+        #[arg(short, long, required = true)]
+        output: String,
+        /// The hashtab rules file used to build the hashtab
+        #[arg(long)]
+        rules: Option<String>,
+        /// Compress the pruned hashtab. Uses zstd by default
+        #[arg(long, num_args = 0..=1, default_missing_value = "zstd")]
+        compress: Option<HashTabCompression>,
+    },
+    /// Report the selectors of the diffs which are likely to break when the QML tree is updated
+    LintDiff {
+        /// The hashtab to use
+        #[arg(long)]
+        hashtab: Option<String>,
+        /// The list of diff files or directories
+        #[arg(required = true)]
+        diff_list: Vec<String>,
+        /// Only report the selectors with at least this fragility score
+        #[arg(long, default_value_t = 1)]
+        min_score: u32,
+    },
+    /// Merge the diffs of a directory affecting the same files into consolidated files
+    Compact {
+        /// The directory containing the diffs
+        diff_directory: String,
+        /// The directory to write the compacted diffs to
+        output_directory: String,
+        /// Drop the diffs which cannot be applied to any of these versions. Can be repeated
+        #[arg(long = "version")]
+        versions: Vec<String>,
+    },
+    /// Show what every hashtab rule would match and derive, without writing anything
+    ExplainRules {
+        /// The hashtab to run the rules on
+        hashtab: String,
+        /// The hashtab rules file
+        rules: String,
+    },
+    /// Compare two versions of a diff pack directive by directive
+    DiffPacks {
+        /// The hashtab to use
+        #[arg(long)]
+        hashtab: Option<String>,
+        /// The diff files or directories of the old version of the pack
+        #[arg(required = true)]
+        old: Vec<String>,
+        /// The diff files or directories of the new version of the pack, given after `--`
+        #[arg(last = true, required = true)]
+        new: Vec<String>,
+    },
+    /// Run the regression tests of diffs described by test manifests
+    Test {
+        /// The test manifests
+        #[arg(required = true)]
+        manifests: Vec<String>,
+    },
+    /// Check whether two QML files are semantically equivalent, ignoring their formatting and comments
+    Compare {
+        /// The first QML file
+        first: String,
+        /// The second QML file
+        second: String,
+    },
+    /// Print an outline of a QML file - its objects, their ids and the members they declare
+    DumpTree {
+        /// The QML file
+        file: String,
+        /// Print the outline as JSON
+        #[arg(long, action = clap::ArgAction::SetTrue)]
+        json: bool,
+        /// Replace all the names with their hashes from this hashtab, so that the outline can be shared
+        #[arg(long, value_name = "HASHTAB")]
+        hashed: Option<String>,
+    },
+    /// Generate pseudo-QML files for benchmarks and fuzzing
+    GenCorpus {
+        /// The directory to write the files to
+        output_directory: String,
+        /// The amount of files to generate
+        #[arg(long, default_value_t = 10)]
+        files: usize,
+        /// How many levels of child objects every file has
+        #[arg(long, default_value_t = 4)]
+        depth: usize,
+        /// The amount of child objects of every object
+        #[arg(long, default_value_t = 3)]
+        width: usize,
+        /// The amount of statements in every function
+        #[arg(long, default_value_t = 8)]
+        function_lines: usize,
+        /// The same seed always generates the same files
+        #[arg(long, default_value_t = 0)]
+        seed: u64,
+    },
+    /// Run a language server for the diffs over stdio - reporting their errors and completing
+    /// keywords and slot names in editors
+    Lsp {
+        /// The hashtab to use. Can be given multiple times, to also load supplementary hashtabs
+        #[arg(long)]
+        hashtab: Vec<String>,
+        /// Resolve the selectors of the open diffs against the QML files of this root
+        #[arg(long)]
+        qml_root: Option<String>,
+    },
 }
 
+fn parse_defines(defines: &[String]) -> Defines {
+    defines
+        .iter()
+        .map(|e| match e.split_once('=') {
+            Some((name, value)) => (name.to_string(), value.to_string()),
+            None => (e.clone(), String::new()),
+        })
+        .collect()
+}
+
+fn load_import_check(
+    import_map: &Option<String>,
+    mode: MissingImports,
+) -> Result<Option<ImportCheck>> {
+    import_map
+        .as_ref()
+        .map(|path| ImportMap::load(Path::new(path)).map(|map| ImportCheck { map, mode }))
+        .transpose()
+}
+
+/// Rust ignores SIGPIPE, so printing into a closed pipe (`qmldiff dump-hashtab ht | head`)
+/// would panic. Restore the default of exiting silently.
+#[cfg(unix)]
+fn reset_sigpipe() {
+    unsafe {
+        libc::signal(libc::SIGPIPE, libc::SIG_DFL);
+    }
+}
+
+#[cfg(not(unix))]
+fn reset_sigpipe() {}
+
 fn main() {
+    reset_sigpipe();
     let cli = Cli::parse();
+    set_verbosity(cli.verbosity());
+    let mut failures = Vec::new();
+    let result = run(&cli, &mut failures);
+    if let Some(error_report) = &cli.error_report {
+        if let Err(error) = write_error_report(error_report, result.as_ref().err(), &failures) {
+            eprintln!("Error: cannot write the error report: {}", error);
+        }
+    }
+    if let Err(error) = result {
+        eprintln!("Error: {:#}", error);
+        std::process::exit(error_details(&error).kind.exit_code());
+    }
+}
 
+/// Runs the command. The files which could not be processed, but didn't stop the command,
+/// are put into `failures`.
+fn run(cli: &Cli, failures: &mut Vec<(String, Error)>) -> Result<()> {
     match &cli.command {
         Commands::CreateHashtab {
             qml_root_path,
             hashtab_name,
             hashrules_name,
             version,
+            compress,
+            sources,
         } => {
-            let mut hashtab = start_hashmap_build(qml_root_path);
+            let mut hashtab_sources = HashTabSources::default();
+            let mut hashtab =
+                start_hashmap_build(qml_root_path, sources.then_some(&mut hashtab_sources));
             if let Some(hashrules) = hashrules_name {
-                println!(
+                info!(
                     "Started processing hashtab rules from file {}...",
                     hashrules
                 );
-                let rules =
-                    HashRules::compile(&std::fs::read_to_string(hashrules).unwrap()).unwrap();
+                let rules = HashRules::compile(&std::fs::read_to_string(hashrules)?)?;
                 rules.process(&mut hashtab);
             }
-            let hashtab_data = serialize_hashtab(&hashtab, version.clone());
-            std::fs::write(hashtab_name, hashtab_data).unwrap()
+            let mut hashtab_data = serialize_hashtab_with_sources(
+                &hashtab,
+                version.clone(),
+                sources.then_some(&hashtab_sources),
+            );
+            if let Some(compression) = compress {
+                hashtab_data = compress_hashtab(&hashtab_data, *compression)?;
+            }
+            write_hashtab_file(hashtab_name, &hashtab_data)?;
         }
         Commands::DumpHashtab { hashtab } => {
+            let verbose = cli.verbosity() == Verbosity::Verbose;
             let mut tab = HashTab::new();
-            merge_hash_file(hashtab, &mut tab, None, None).unwrap();
+            let mut sources = HashTabSources::default();
+            merge_hash_file_with_sources(hashtab, &mut tab, &mut sources)?;
+            if verbose {
+                if sources.files.is_empty() {
+                    warning!("The hashtab does not record where its entries have been found - create it with --sources.");
+                }
+                for file in &sources.files {
+                    println!("File {} (contents hash {})", file.path, file.contents_hash);
+                }
+            }
             for (i, v) in tab {
-                println!("{} = {}", v, i);
+                match sources.source_of(i).filter(|_| verbose) {
+                    Some(file) => println!("{} = {} (from {})", v, i, file.path),
+                    None => println!("{} = {}", v, i),
+                }
             }
         }
         Commands::HashString { string } => {
@@ -138,10 +519,23 @@ fn main() {
             hashtab,
             diff_list,
             revert,
+            supplementary,
         } => {
             let mut hashtab_value = HashTab::new();
             let mut inv_hashtab = InvHashTab::new();
-            merge_hash_file(hashtab, &mut hashtab_value, None, Some(&mut inv_hashtab)).unwrap();
+            merge_hash_file(hashtab, &mut hashtab_value, None, Some(&mut inv_hashtab))?;
+            if let Some(supplementary) = supplementary {
+                if *revert {
+                    merge_hash_file(supplementary, &mut hashtab_value, None, None)?;
+                } else {
+                    extend_supplementary_hashtab(
+                        supplementary,
+                        diff_list,
+                        &mut hashtab_value,
+                        &mut inv_hashtab,
+                    )?;
+                }
+            }
             process_diff_tree(diff_list, &hashtab_value, &inv_hashtab, !*revert);
         }
         Commands::ApplyDiffs {
@@ -152,31 +546,128 @@ fn main() {
             flatten,
             clean,
             version,
+            journal,
+            reverse_diff,
+            defines,
+            require_hashed,
+            archive,
+            determinism_check,
+            indent,
+            tabs,
+            crlf,
+            max_inline_tokens,
+            max_line_width,
+            minify,
+            format,
+            on_error,
+            id_check,
+            import_map,
+            missing_imports,
+            rebuild_order,
+            max_load_depth,
+            max_slot_depth,
+            max_expansion_tokens,
+            max_diff_tokens,
+            import_slots,
+            export_slots,
         } => {
+            let import_check = load_import_check(import_map, *missing_imports)?;
+            let emitter = format.emitter(EmitterConfig {
+                indent_width: *indent,
+                use_tabs: *tabs,
+                crlf: *crlf,
+                max_inline_tokens: *max_inline_tokens,
+                max_line_width: *max_line_width,
+                minify: *minify,
+                ..EmitterConfig::default()
+            });
             let mut hashtab_value = HashTab::new();
-            if let Some(hashtab) = hashtab {
-                merge_hash_file(hashtab, &mut hashtab_value, None, None).unwrap();
+            for hashtab in hashtab {
+                merge_hash_file(hashtab, &mut hashtab_value, None, None)?;
+            }
+            let defines = parse_defines(defines);
+            let limits = Limits {
+                max_load_depth: *max_load_depth,
+                max_slot_depth: *max_slot_depth,
+                max_expansion_tokens: *max_expansion_tokens,
+                max_diff_tokens: *max_diff_tokens,
+            };
+            if *determinism_check {
+                check_determinism(
+                    qml_root_path,
+                    diff_list,
+                    &hashtab_value,
+                    version.clone(),
+                    &defines,
+                    *require_hashed,
+                    emitter.as_ref(),
+                    import_slots.as_deref(),
+                    &limits,
+                )?;
             }
-            if *clean {
+            // The destination archive is always overwritten.
+            if *clean && !*archive {
                 // Ignore result
                 {
                     let _ = remove_dir_all(qml_destination_path);
                 }
             }
-            let _ = create_dir(qml_destination_path);
-            let mut slots = Slots::new();
-            let mut changes =
-                build_change_structures(diff_list, &hashtab_value, &mut slots, version.clone())
-                    .unwrap();
-            slots.process_slots(&mut changes);
-            apply_changes(
-                qml_root_path,
-                qml_destination_path,
-                *flatten,
+            if !*archive {
+                let _ = create_dir(qml_destination_path);
+            }
+            let mut slots = initial_slots(import_slots.as_deref(), limits)?;
+            let mut changes = build_change_structures(
+                diff_list,
+                &hashtab_value,
                 &mut slots,
-                &changes,
-            )
-            .unwrap();
+                version.clone(),
+                &defines,
+                *require_hashed,
+                &limits,
+            )?;
+            slots.process_slots(&mut changes)?;
+            let mut mutation_journal = MutationJournal::new();
+            let journal_ref =
+                (journal.is_some() || reverse_diff.is_some()).then_some(&mut mutation_journal);
+            let applied = if *archive {
+                apply_changes_to_archive(
+                    qml_root_path,
+                    qml_destination_path,
+                    &mut slots,
+                    &changes,
+                    journal_ref,
+                    emitter.as_ref(),
+                    *on_error,
+                    *id_check,
+                    import_check.as_ref(),
+                    *rebuild_order,
+                )
+            } else {
+                apply_changes(
+                    qml_root_path,
+                    qml_destination_path,
+                    *flatten,
+                    &mut slots,
+                    &changes,
+                    journal_ref,
+                    emitter.as_ref(),
+                    *on_error,
+                    *id_check,
+                    import_check.as_ref(),
+                    *rebuild_order,
+                )
+            };
+            let applied = applied?;
+            failures.extend(applied.failures);
+            if let Some(journal) = journal {
+                std::fs::write(journal, mutation_journal.to_jsonl())?;
+            }
+            if let Some(reverse_diff) = reverse_diff {
+                std::fs::write(reverse_diff, build_reverse_diff(&mutation_journal))?;
+            }
+            if let Some(export_slots) = export_slots {
+                std::fs::write(export_slots, slots.export())?;
+            }
             let not_read_slots: Vec<&String> = slots
                 .0
                 .iter()
@@ -189,15 +680,74 @@ fn main() {
                 })
                 .collect();
             if !not_read_slots.is_empty() {
-                println!(
+                warning!(
                     "Warning! {} slots have been written to, but never read from:",
                     not_read_slots.len(),
                 );
                 for slot in not_read_slots {
-                    println!("- {}", slot);
+                    warning!("- {}", slot);
+                }
+            }
+            // Usually caused by the affected file being renamed or removed upstream.
+            if !applied.unapplied.is_empty() {
+                warning!(
+                    "Warning! {} changes have not been applied to any file:",
+                    applied.unapplied.len(),
+                );
+                for change in applied.unapplied {
+                    warning!("- {} (from {})", change.destination, change.source);
                 }
             }
         }
+        Commands::ProcessFile {
+            hashtab,
+            qml_file,
+            diff_list,
+            name,
+            version,
+            defines,
+            format,
+            id_check,
+            import_map,
+            missing_imports,
+            rebuild_order,
+            max_load_depth,
+            max_slot_depth,
+            max_expansion_tokens,
+            max_diff_tokens,
+            explain,
+        } => {
+            let mut hashtab_value = HashTab::new();
+            merge_hash_file(hashtab, &mut hashtab_value, None, None)?;
+            let name = match name {
+                Some(name) => name.clone(),
+                None => Path::new(qml_file)
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy()
+                    .to_string(),
+            };
+            let processed = process_single_file(
+                qml_file,
+                &name,
+                diff_list,
+                &hashtab_value,
+                version.clone(),
+                &parse_defines(defines),
+                format.emitter(EmitterConfig::default()).as_ref(),
+                *id_check,
+                load_import_check(import_map, *missing_imports)?.as_ref(),
+                *rebuild_order,
+                &Limits {
+                    max_load_depth: *max_load_depth,
+                    max_slot_depth: *max_slot_depth,
+                    max_expansion_tokens: *max_expansion_tokens,
+                    max_diff_tokens: *max_diff_tokens,
+                },
+                *explain,
+            )?;
+            print!("{}", processed);
+        }
         Commands::GCDHashtab {
             output_hashtab,
             hashtabs,
@@ -205,14 +755,113 @@ fn main() {
             let mut out = HashTab::new();
             for file in hashtabs {
                 let mut val = HashTab::new();
-                merge_hash_file(file, &mut val, None, None).unwrap();
+                merge_hash_file(file, &mut val, None, None)?;
                 if out.is_empty() {
                     out = val;
                 } else {
                     out.retain(|key, _value| val.contains_key(key));
                 }
             }
-            std::fs::write(output_hashtab, serialize_hashtab(&out, None)).unwrap();
+            write_hashtab_file(output_hashtab, &serialize_hashtab(&out, None))?;
+        }
+        Commands::PruneHashtab {
+            hashtab,
+            diff_list,
+            output,
+            rules,
+            compress,
+        } => {
+            let mut hashtab_value = HashTab::new();
+            merge_hash_file(hashtab, &mut hashtab_value, None, None)?;
+            let rules = rules
+                .as_ref()
+                .map(|e| HashRules::compile(&std::fs::read_to_string(e)?))
+                .transpose()?;
+            let pruned = prune_hashtab(&hashtab_value, diff_list, rules.as_ref())?;
+            let mut hashtab_data = serialize_hashtab(&pruned, None);
+            if let Some(compression) = compress {
+                hashtab_data = compress_hashtab(&hashtab_data, *compression)?;
+            }
+            write_hashtab_file(output, &hashtab_data)?;
+        }
+        Commands::LintDiff {
+            hashtab,
+            diff_list,
+            min_score,
+        } => {
+            let mut hashtab_value = HashTab::new();
+            if let Some(hashtab) = hashtab {
+                merge_hash_file(hashtab, &mut hashtab_value, None, None)?;
+            }
+            lint_diffs(diff_list, &hashtab_value, *min_score)?;
+        }
+        Commands::Compact {
+            diff_directory,
+            output_directory,
+            versions,
+        } => {
+            compact_diff_directory(diff_directory, output_directory, versions)?;
+        }
+        Commands::ExplainRules { hashtab, rules } => {
+            let mut hashtab_value = HashTab::new();
+            merge_hash_file(hashtab, &mut hashtab_value, None, None)?;
+            let rules = HashRules::compile(&std::fs::read_to_string(rules)?)?;
+            explain_hash_rules(&hashtab_value, &rules);
+        }
+        Commands::DiffPacks { hashtab, old, new } => {
+            let mut hashtab_value = HashTab::new();
+            if let Some(hashtab) = hashtab {
+                merge_hash_file(hashtab, &mut hashtab_value, None, None)?;
+            }
+            diff_pack_versions(old, new, &hashtab_value)?;
+        }
+        Commands::Test { manifests } => {
+            if run_diff_tests(manifests)? != 0 {
+                std::process::exit(1);
+            }
+        }
+        Commands::Compare { first, second } => {
+            if compare_qml_files(first, second)? != 0 {
+                std::process::exit(1);
+            }
+        }
+        Commands::DumpTree { file, json, hashed } => {
+            let inv_hashtab = match hashed {
+                Some(hashtab) => {
+                    let mut hashtab_value = HashTab::new();
+                    let mut inv_hashtab = InvHashTab::new();
+                    merge_hash_file(hashtab, &mut hashtab_value, None, Some(&mut inv_hashtab))?;
+                    Some(inv_hashtab)
+                }
+                None => None,
+            };
+            dump_qml_tree(file, *json, inv_hashtab.as_ref())?;
+        }
+        Commands::GenCorpus {
+            output_directory,
+            files,
+            depth,
+            width,
+            function_lines,
+            seed,
+        } => {
+            let config = CorpusConfig {
+                files: *files,
+                depth: *depth,
+                width: *width,
+                function_lines: *function_lines,
+                seed: *seed,
+            };
+            write_corpus(output_directory, &config)?;
+        }
+        Commands::Lsp { hashtab, qml_root } => {
+            let mut hashtab_value = HashTab::new();
+            for hashtab in hashtab {
+                merge_hash_file(hashtab, &mut hashtab_value, None, None)?;
+            }
+            LanguageServer::new(hashtab_value, qml_root.as_ref().map(PathBuf::from))
+                .run(&mut std::io::stdin().lock(), &mut std::io::stdout())?;
         }
     }
+    Ok(())
 }
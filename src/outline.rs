@@ -0,0 +1,245 @@
+//! An outline of a QML file - its objects, their ids and the names of the members they declare -
+//! so that the selectors of diffs can be written without reading through the whole file.
+
+use std::{collections::BTreeSet, fmt::Display};
+
+use crate::{
+    hashtab::InvHashTab,
+    journal::escape_json,
+    parser::qml::parser::{AssignmentChildValue, Object, ObjectChild, QMLTree, TreeElement},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberKind {
+    Property,
+    Assignment,
+    Function,
+    Signal,
+    Enum,
+}
+
+impl MemberKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Property => "property",
+            Self::Assignment => "assignment",
+            Self::Function => "function",
+            Self::Signal => "signal",
+            Self::Enum => "enum",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineMember {
+    pub kind: MemberKind,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OutlineObject {
+    /// The property or assignment holding the object, if it isn't a plain child of its parent -
+    /// `contentItem` for `contentItem: Rectangle {}`.
+    pub holder: Option<String>,
+    /// The name of the component the object defines - `component Badge: Rectangle {}`.
+    pub component: Option<String>,
+    pub name: String,
+    pub id: Option<String>,
+    pub members: Vec<OutlineMember>,
+    pub children: Vec<OutlineObject>,
+}
+
+fn outline_object(
+    object: &Object,
+    holder: Option<String>,
+    component: Option<String>,
+) -> OutlineObject {
+    let mut id = None;
+    let mut members = Vec::new();
+    let mut member = |kind, name: &String| {
+        members.push(OutlineMember {
+            kind,
+            name: name.clone(),
+        })
+    };
+    let mut children = Vec::new();
+    for child in &object.children {
+        match child {
+            ObjectChild::Assignment(assignment) if assignment.name == "id" => {
+                id = child.get_str_value();
+            }
+            ObjectChild::Assignment(assignment) => match &assignment.value {
+                AssignmentChildValue::Object(object) => {
+                    children.push(outline_object(object, Some(assignment.name.clone()), None))
+                }
+                AssignmentChildValue::Other(_) => member(MemberKind::Assignment, &assignment.name),
+            },
+            ObjectChild::Property(prop) => {
+                member(MemberKind::Property, &prop.name);
+                if let Some(AssignmentChildValue::Object(object)) = &prop.default_value {
+                    children.push(outline_object(object, Some(prop.name.clone()), None));
+                }
+            }
+            ObjectChild::ObjectProperty(prop) => {
+                member(MemberKind::Property, &prop.name);
+                children.push(outline_object(
+                    &prop.default_value,
+                    Some(prop.name.clone()),
+                    None,
+                ));
+            }
+            ObjectChild::ObjectAssignment(assignment) => {
+                children.push(outline_object(
+                    &assignment.value,
+                    Some(assignment.name.clone()),
+                    None,
+                ));
+            }
+            ObjectChild::ObjectListAssignment(list) => {
+                for object in &list.values {
+                    children.push(outline_object(object, Some(list.name.clone()), None));
+                }
+            }
+            ObjectChild::Function(function) => member(MemberKind::Function, &function.name),
+            ObjectChild::Signal(signal) => member(MemberKind::Signal, &signal.name),
+            ObjectChild::Enum(r#enum) => member(MemberKind::Enum, &r#enum.name),
+            ObjectChild::Object(object) => children.push(outline_object(object, None, None)),
+            ObjectChild::Component(component) => children.push(outline_object(
+                &component.object,
+                None,
+                Some(component.name.clone()),
+            )),
+            ObjectChild::Anchor(_) => {}
+        }
+    }
+    OutlineObject {
+        holder,
+        component,
+        name: object.name.clone(),
+        id,
+        members,
+        children,
+    }
+}
+
+/// The outline of every object at the top level of the file.
+pub fn outline_tree(tree: &QMLTree) -> Vec<OutlineObject> {
+    tree.iter()
+        .filter_map(|element| match element {
+            TreeElement::Object(object) => Some(outline_object(object, None, None)),
+            TreeElement::Component(component) => Some(outline_object(
+                &component.object,
+                None,
+                Some(component.name.clone()),
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// The name written the way `hash-diffs` writes it - `[[1234]]`, or `[[1234.5678]]` for a dotted
+/// name. `None` if it's not in the hashtab.
+fn hash_name(name: &str, inv_hashtab: &InvHashTab) -> Option<String> {
+    let parts: Option<Vec<String>> = name
+        .split('.')
+        .map(|e| inv_hashtab.get(e).map(|e| e.to_string()))
+        .collect();
+    parts
+        .map(|e| format!("[[{}]]", e.join(".")))
+        .or_else(|| inv_hashtab.get(name).map(|e| format!("[[{}]]", e)))
+}
+
+impl OutlineObject {
+    /// A copy of the outline with all the names replaced by their hashes - a skeleton of the
+    /// file which can be shared without its strings. The names missing from the hashtab are
+    /// kept as they are, and put into `missing`.
+    pub fn hashed(&self, inv_hashtab: &InvHashTab, missing: &mut BTreeSet<String>) -> Self {
+        let mut hash = |name: &String| {
+            hash_name(name, inv_hashtab).unwrap_or_else(|| {
+                missing.insert(name.clone());
+                name.clone()
+            })
+        };
+        let holder = self.holder.as_ref().map(&mut hash);
+        let component = self.component.as_ref().map(&mut hash);
+        let name = hash(&self.name);
+        let id = self.id.as_ref().map(&mut hash);
+        let members = self
+            .members
+            .iter()
+            .map(|e| OutlineMember {
+                kind: e.kind,
+                name: hash(&e.name),
+            })
+            .collect();
+        OutlineObject {
+            holder,
+            component,
+            name,
+            id,
+            members,
+            children: self
+                .children
+                .iter()
+                .map(|e| e.hashed(inv_hashtab, missing))
+                .collect(),
+        }
+    }
+
+    /// The object as written in selectors - `Rectangle#background`.
+    pub fn selector(&self) -> String {
+        match &self.id {
+            Some(id) => format!("{}#{}", self.name, id),
+            None => self.name.clone(),
+        }
+    }
+
+    fn write_indented(&self, f: &mut std::fmt::Formatter<'_>, depth: usize) -> std::fmt::Result {
+        let indent = "    ".repeat(depth);
+        match (&self.holder, &self.component) {
+            (Some(holder), _) => writeln!(f, "{}{}: {}", indent, holder, self.selector())?,
+            (_, Some(component)) => {
+                writeln!(f, "{}component {}: {}", indent, component, self.selector())?
+            }
+            (None, None) => writeln!(f, "{}{}", indent, self.selector())?,
+        }
+        for member in &self.members {
+            writeln!(f, "{}    {} {}", indent, member.kind.name(), member.name)?;
+        }
+        for child in &self.children {
+            child.write_indented(f, depth + 1)?;
+        }
+        Ok(())
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"type\":{},\"id\":{},\"holder\":{},\"component\":{},\"members\":[{}],\"children\":[{}]}}",
+            escape_json(&self.name),
+            self.id.as_deref().map_or("null".into(), escape_json),
+            self.holder.as_deref().map_or("null".into(), escape_json),
+            self.component.as_deref().map_or("null".into(), escape_json),
+            self.members
+                .iter()
+                .map(|e| format!(
+                    "{{\"kind\":{},\"name\":{}}}",
+                    escape_json(e.kind.name()),
+                    escape_json(&e.name)
+                ))
+                .collect::<Vec<_>>()
+                .join(","),
+            self.children
+                .iter()
+                .map(|e| e.to_json())
+                .collect::<Vec<_>>()
+                .join(",")
+        )
+    }
+}
+
+/// One line per object, followed by its members - indented under its parent.
+impl Display for OutlineObject {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_indented(f, 0)
+    }
+}
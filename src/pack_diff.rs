@@ -0,0 +1,263 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use crate::parser::{
+    diff::parser::{
+        Change, FileChangeAction, Insertable, LocateRebuildActionSelector, RebuildInstruction,
+        RemoveRebuildAction, ReplaceRebuildActionWhat,
+    },
+    qml::lexer::TokenType,
+};
+
+/// QML code without its formatting and comments, so that reformatting a diff is not reported
+/// as a change.
+fn normalize_code(stream: &[TokenType]) -> String {
+    let tokens = stream
+        .iter()
+        .filter(|e| {
+            !matches!(
+                e,
+                TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_)
+            )
+        })
+        .map(|e| e.to_string())
+        .collect::<Vec<_>>();
+    if tokens.is_empty() {
+        "{}".to_string()
+    } else {
+        format!("{{ {} }}", tokens.join(" "))
+    }
+}
+
+fn describe_insertable(insertable: &Insertable) -> String {
+    match insertable {
+        Insertable::Code(stream) => normalize_code(stream),
+        Insertable::Slot(name) => format!("SLOT {}", name),
+        Insertable::Template(name, stream) => {
+            format!("TEMPLATE {} {}", name, normalize_code(stream))
+        }
+    }
+}
+
+fn describe_rebuild_instruction(instruction: &RebuildInstruction) -> String {
+    match instruction {
+        RebuildInstruction::Locate(locate) => match &locate.selector {
+            LocateRebuildActionSelector::All => format!("LOCATE {} ALL", locate.location),
+            LocateRebuildActionSelector::Stream(stream) => {
+                format!("LOCATE {} {}", locate.location, normalize_code(stream))
+            }
+        },
+        RebuildInstruction::Insert(stream) => format!("INSERT {}", normalize_code(stream)),
+        RebuildInstruction::Remove(RemoveRebuildAction::Stream(stream)) => {
+            format!("REMOVE {}", normalize_code(stream))
+        }
+        RebuildInstruction::Remove(RemoveRebuildAction::UntilStream(stream)) => {
+            format!("REMOVE UNTIL {}", normalize_code(stream))
+        }
+        RebuildInstruction::Replace(replace) => {
+            let mut line = match &replace.what {
+                ReplaceRebuildActionWhat::Located => "REPLACE LOCATED".to_string(),
+                ReplaceRebuildActionWhat::LiteralStream(stream) => {
+                    format!("REPLACE {}", normalize_code(stream))
+                }
+            };
+            if let Some(until) = &replace.until_stream {
+                line += &format!(" UNTIL {}", normalize_code(until));
+            }
+            line + &format!(" WITH {}", normalize_code(&replace.new_contents))
+        }
+        other => other.to_string(),
+    }
+}
+
+fn describe_action(action: &FileChangeAction) -> String {
+    match action {
+        FileChangeAction::Insert(insertable) => {
+            format!("INSERT {}", describe_insertable(insertable))
+        }
+        FileChangeAction::InsertSorted(sorted) => format!(
+            "INSERT SORTED BY {} {}",
+            sorted.property,
+            normalize_code(&sorted.code)
+        ),
+        FileChangeAction::Replace(replace) => format!(
+            "REPLACE {} WITH {}",
+            replace.selector,
+            describe_insertable(&replace.content)
+        ),
+        // The contents of the blocks are described line by line by `describe_change`.
+        FileChangeAction::Rebuild(rebuild) => format!(
+            "{} {}",
+            if rebuild.redefine {
+                "REDEFINE"
+            } else {
+                "REBUILD"
+            },
+            rebuild.selector
+        ),
+        FileChangeAction::LocalSlot(name, _) => format!("LOCAL SLOT {}", name),
+        other => other.to_string(),
+    }
+}
+
+/// The directives of a change, one per line, in a form which does not depend on the formatting,
+/// comments or hashing of the diff. Directives within TRAVERSE / REPLICATE / REBUILD blocks
+/// are indented.
+pub fn describe_change(change: &Change) -> Vec<String> {
+    let mut lines = Vec::new();
+    for version in change.versions_allowed.iter().flatten() {
+        lines.push(format!("VERSION {}", version));
+    }
+    for requirement in &change.requirements {
+        lines.push(requirement.to_string());
+    }
+
+    let mut depth = 0usize;
+    let mut saved_depths = Vec::new();
+    for action in &change.changes {
+        match action {
+            FileChangeAction::End(_) => depth = depth.saturating_sub(1),
+            FileChangeAction::PopScope => depth = saved_depths.pop().unwrap_or_default(),
+            _ => {}
+        }
+        let indent = "    ".repeat(depth);
+        lines.push(format!("{}{}", indent, describe_action(action)));
+        match action {
+            FileChangeAction::Traverse(_)
+            | FileChangeAction::TraverseFromRoot(_)
+            | FileChangeAction::Replicate(_)
+            | FileChangeAction::ReplicateFromRoot(_) => depth += 1,
+            FileChangeAction::PushScope => saved_depths.push(depth),
+            FileChangeAction::Rebuild(rebuild) => {
+                for instruction in &rebuild.actions {
+                    lines.push(format!(
+                        "{}    {}",
+                        indent,
+                        describe_rebuild_instruction(instruction)
+                    ));
+                }
+                lines.push(format!("{}END REBUILD", indent));
+            }
+            FileChangeAction::LocalSlot(_, contents) => {
+                for content in contents {
+                    lines.push(format!("{}    {}", indent, describe_action(content)));
+                }
+                lines.push(format!("{}END SLOT", indent));
+            }
+            _ => {}
+        }
+    }
+    lines
+}
+
+/// Everything a diff pack does, keyed by the object being changed (`AFFECT <file>`,
+/// `SLOT <name>`...). Changes of the same object coming from different files are joined in
+/// the order they are loaded in, so moving them between files is not a change.
+#[derive(Debug, Default)]
+pub struct PackModel(pub BTreeMap<String, Vec<String>>);
+
+impl PackModel {
+    pub fn new(changes: &[Change]) -> Self {
+        let mut model = BTreeMap::<String, Vec<String>>::new();
+        for change in changes {
+            model
+                .entry(change.destination.to_string())
+                .or_default()
+                .extend(describe_change(change));
+        }
+        Self(model)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum PackDifference {
+    Added(String, Vec<String>),
+    Removed(String, Vec<String>),
+    /// The lines of the directives - prefixed with `+`, `-`, or ` ` for the unchanged blocks
+    /// the changed directives are within.
+    Modified(String, Vec<String>),
+}
+
+impl Display for PackDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (header, prefix, lines) = match self {
+            Self::Added(object, lines) => (format!("Added {}", object), "+ ", lines),
+            Self::Removed(object, lines) => (format!("Removed {}", object), "- ", lines),
+            Self::Modified(object, lines) => (format!("Modified {}", object), "", lines),
+        };
+        write!(f, "{}:", header)?;
+        for line in lines {
+            write!(f, "\n    {}{}", prefix, line)?;
+        }
+        Ok(())
+    }
+}
+
+fn depth_of(line: &str) -> usize {
+    (line.len() - line.trim_start_matches(' ').len()) / 4
+}
+
+/// Compares the directives using their longest common subsequence. Only the changed
+/// directives, and the unchanged ones opening the blocks they are in, are returned.
+fn diff_directives(old: &[String], new: &[String]) -> Vec<String> {
+    let mut common = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            common[i][j] = if old[i] == new[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut output = Vec::new();
+    // The unchanged lines enclosing the current position, and whether they were output already.
+    let mut scope: Vec<(&str, bool)> = Vec::new();
+    let mut changed = |prefix: char, line: &str, scope: &mut Vec<(&str, bool)>| {
+        scope.retain(|e| depth_of(e.0) < depth_of(line));
+        for (context, written) in scope.iter_mut().filter(|e| !e.1) {
+            output.push(format!("  {}", context));
+            *written = true;
+        }
+        output.push(format!("{} {}", prefix, line));
+    };
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            let depth = depth_of(&old[i]);
+            scope.retain(|e| depth_of(e.0) < depth);
+            scope.push((&old[i], false));
+            i += 1;
+            j += 1;
+        } else if i < old.len() && (j == new.len() || common[i + 1][j] >= common[i][j + 1]) {
+            changed('-', &old[i], &mut scope);
+            i += 1;
+        } else {
+            changed('+', &new[j], &mut scope);
+            j += 1;
+        }
+    }
+    output
+}
+
+/// Compares two versions of a pack. Returns the differences, and the amount of unchanged objects.
+pub fn diff_packs(old: &PackModel, new: &PackModel) -> (Vec<PackDifference>, usize) {
+    let mut differences = Vec::new();
+    let mut unchanged = 0;
+    for (object, old_lines) in &old.0 {
+        match new.0.get(object) {
+            None => differences.push(PackDifference::Removed(object.clone(), old_lines.clone())),
+            Some(new_lines) if new_lines == old_lines => unchanged += 1,
+            Some(new_lines) => differences.push(PackDifference::Modified(
+                object.clone(),
+                diff_directives(old_lines, new_lines),
+            )),
+        }
+    }
+    for (object, new_lines) in &new.0 {
+        if !old.0.contains_key(object) {
+            differences.push(PackDifference::Added(object.clone(), new_lines.clone()));
+        }
+    }
+    (differences, unchanged)
+}
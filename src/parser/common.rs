@@ -1,3 +1,4 @@
+use alloc::{boxed::Box, string::String, vec, vec::Vec};
 use anyhow::Error;
 
 #[macro_export]
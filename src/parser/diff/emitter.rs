@@ -1,3 +1,10 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
 use crate::parser::qml::{self, emitter::flatten_lines};
 
 use super::lexer::{HashedValue, TokenType};
@@ -14,7 +21,7 @@ pub fn token_stream_into_vec(
     }
 }
 
-fn hash_list_to_str(hl: &Vec<u64>) -> String {
+fn hash_list_to_str(hl: &[u64]) -> String {
     hl.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(".")
 }
 
@@ -1,72 +1,284 @@
-use std::sync::Arc;
+use std::{collections::BTreeSet, sync::Arc};
 
 use anyhow::{Error, Result};
 
 use crate::{
+    hash::hash,
     hashtab::HashTab,
     parser::{
         common::{ChainIteratorRemapper, IteratorRemapper},
-        qml::hash_extension::qml_hash_remap,
+        qml::{self, hash_extension::qml_hash_remap, lexer::QMLExtensionToken},
     },
+    util::common_util::{kind_error, ErrorKind},
 };
 
-use super::lexer::{HashedValue, TokenType};
+use super::{
+    lexer::{HashedValue, Keyword, TokenType},
+    parser::{Change, ObjectToChange},
+};
 
 pub struct DiffHashRemapper<'a> {
     hashtab: &'a HashTab,
 }
 
-fn resolve_hashed_ids(hashtab: &HashTab, source_name: &str, id: &Vec<u64>) -> Result<String> {
+/// Resolves all the parts of a hashed identifier. The parts missing from the hashtab are added
+/// to `missing`, and `None` is returned.
+fn resolve_hashed_ids(
+    hashtab: &HashTab,
+    id: &Vec<u64>,
+    missing: &mut BTreeSet<u64>,
+) -> Option<String> {
     let mut out_id = String::new();
+    let mut resolved = true;
     for id in id {
-        if out_id != "" { out_id += "." }
-        out_id += 
-        hashtab
-            .get(&id)
-            .ok_or(Error::msg(format!(
-                "Couldn't resolve the hashed identifier {} required by {}",
-                id, source_name
-            )))?;
+        if !out_id.is_empty() {
+            out_id += "."
+        }
+        match hashtab.get(id) {
+            Some(value) => out_id += value,
+            None => {
+                missing.insert(*id);
+                resolved = false;
+            }
+        }
     }
 
-    Ok(out_id)
+    resolved.then_some(out_id)
 }
 
-
-pub fn diff_hash_remapper(
+/// Unhashes the token. Hashes missing from the hashtab are added to `missing`, and the values
+/// referring to them are left hashed.
+fn remap_collecting_missing(
     hashtab: &HashTab,
     value: TokenType,
-    source_name: &str,
-) -> Result<TokenType> {
+    missing: &mut BTreeSet<u64>,
+) -> TokenType {
     match value {
-        TokenType::HashedValue(HashedValue::HashedIdentifier(id)) => Ok(TokenType::Identifier(resolve_hashed_ids(hashtab, source_name, &id)?)),
-        TokenType::HashedValue(HashedValue::HashedString(q, id)) => {
-            let unwrapped = resolve_hashed_ids(hashtab, source_name, &id)?;
-            Ok(TokenType::String(if q != '`' {
-                format!("{}{}{}", q, unwrapped, q)
-            } else {
-                unwrapped.clone()
-            }))
+        TokenType::HashedValue(HashedValue::HashedIdentifier(ref id)) => {
+            match resolve_hashed_ids(hashtab, id, missing) {
+                Some(unwrapped) => TokenType::Identifier(unwrapped),
+                None => value,
+            }
+        }
+        TokenType::HashedValue(HashedValue::HashedString(q, ref id)) => {
+            match resolve_hashed_ids(hashtab, id, missing) {
+                Some(unwrapped) => TokenType::String(if q != '`' {
+                    format!("{}{}{}", q, unwrapped, q)
+                } else {
+                    unwrapped
+                }),
+                None => value,
+            }
         }
         TokenType::QMLCode {
             qml_code,
             stream_character: is_stream,
-        } => {
-            Ok(TokenType::QMLCode {
-                qml_code: qml_code
-                    .into_iter()
-                    .map(|e| match qml_hash_remap(hashtab, e, source_name) {
-                        Ok(v) => v,
-                        Err(e) => {
-                            panic!("{:?}", e); // temporary solution.
+        } => TokenType::QMLCode {
+            qml_code: qml_code
+                .into_iter()
+                .map(|e| match e {
+                    qml::lexer::TokenType::Extension(
+                        QMLExtensionToken::HashedIdentifier(id)
+                        | QMLExtensionToken::HashedString(_, id),
+                    ) if hashtab.get(&id).is_none() => {
+                        missing.insert(id);
+                        e
+                    }
+                    // Only fails on missing hashes.
+                    e => qml_hash_remap(hashtab, e, "").unwrap(),
+                })
+                .collect(),
+            stream_character: is_stream,
+        },
+        other => other,
+    }
+}
+
+fn missing_hashes_error(source_name: &str, missing: &BTreeSet<u64>) -> Error {
+    kind_error(
+        ErrorKind::Hash,
+        format!(
+            "The diff {} refers to hashes missing from the hashtab: {}",
+            source_name,
+            missing
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    )
+}
+
+pub fn diff_hash_remapper(
+    hashtab: &HashTab,
+    value: TokenType,
+    source_name: &str,
+) -> Result<TokenType> {
+    let mut missing = BTreeSet::new();
+    let value = remap_collecting_missing(hashtab, value, &mut missing);
+    if missing.is_empty() {
+        Ok(value)
+    } else {
+        Err(missing_hashes_error(source_name, &missing))
+    }
+}
+
+/// Unhashes all the tokens of a diff. Fails listing every hash missing from the hashtab, instead
+/// of stopping at the first one. With `defer_hashed_files`, the names of the `AFFECT`ed files
+/// missing from the hashtab are left hashed - they're resolved later, by `resolve_hashed_files`.
+pub fn resolve_diff_hashes(
+    hashtab: &HashTab,
+    tokens: Vec<TokenType>,
+    source_name: &str,
+    defer_hashed_files: bool,
+) -> Result<Vec<TokenType>> {
+    let mut missing = BTreeSet::new();
+    let mut after_affect = false;
+    let tokens = tokens
+        .into_iter()
+        .map(|e| {
+            let mut token_missing = BTreeSet::new();
+            let token = remap_collecting_missing(hashtab, e, &mut token_missing);
+            let deferred =
+                defer_hashed_files && after_affect && matches!(token, TokenType::HashedValue(_));
+            if !deferred {
+                missing.extend(token_missing);
+            }
+            match token {
+                TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_) => {}
+                _ => after_affect = token == TokenType::Keyword(Keyword::Affect),
+            }
+            token
+        })
+        .collect();
+    if missing.is_empty() {
+        Ok(tokens)
+    } else {
+        Err(missing_hashes_error(source_name, &missing))
+    }
+}
+
+fn is_in_hashtab(hashtab: &HashTab, value: &str) -> bool {
+    hashtab.get(&hash(value)).is_some_and(|e| e == value)
+}
+
+fn unquoted(string: &str) -> Option<&str> {
+    (string.len() > 2).then(|| &string[1..string.len() - 1])
+}
+
+/// Finds all the plain identifiers and strings which `hash-diffs` would have hashed,
+/// i.e. the ones present in the hashtab.
+pub fn find_unhashed_values(tokens: &[TokenType], hashtab: &HashTab) -> BTreeSet<String> {
+    let is_unhashed_string =
+        |string: &str| unquoted(string).is_some_and(|e| is_in_hashtab(hashtab, e));
+    let mut unhashed = BTreeSet::new();
+    for token in tokens {
+        match token {
+            TokenType::Identifier(id)
+                if is_in_hashtab(hashtab, id)
+                    || id
+                        .strip_prefix('/')
+                        .unwrap_or(id)
+                        .split('.')
+                        .all(|e| is_in_hashtab(hashtab, e)) =>
+            {
+                unhashed.insert(id.clone());
+            }
+            TokenType::String(string) if is_unhashed_string(string) => {
+                unhashed.insert(string.clone());
+            }
+            TokenType::QMLCode { qml_code, .. } => {
+                for token in qml_code {
+                    match token {
+                        qml::lexer::TokenType::Identifier(id) if is_in_hashtab(hashtab, id) => {
+                            unhashed.insert(id.clone());
+                        }
+                        qml::lexer::TokenType::String(string) if is_unhashed_string(string) => {
+                            unhashed.insert(string.clone());
                         }
-                    })
-                    .collect(),
-                stream_character: is_stream,
-            })
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    unhashed
+}
+
+/// Collects the hashes the diff refers to, i.e. the values hashed by `hash-diffs`.
+pub fn find_hash_references(tokens: &[TokenType]) -> BTreeSet<u64> {
+    let mut references = BTreeSet::new();
+    for token in tokens {
+        match token {
+            TokenType::HashedValue(
+                HashedValue::HashedIdentifier(ids) | HashedValue::HashedString(_, ids),
+            ) => references.extend(ids),
+            TokenType::QMLCode { qml_code, .. } => {
+                for token in qml_code {
+                    if let qml::lexer::TokenType::Extension(
+                        QMLExtensionToken::HashedIdentifier(id)
+                        | QMLExtensionToken::HashedString(_, id),
+                    ) = token
+                    {
+                        references.insert(*id);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    references
+}
+
+/// Fails if the diff contains values which should have been hashed. Used to make sure
+/// that distributed diffs do not depend on the names of the current QML tree.
+pub fn ensure_hashed(tokens: &[TokenType], hashtab: &HashTab, source_name: &str) -> Result<()> {
+    let unhashed = find_unhashed_values(tokens, hashtab);
+    if unhashed.is_empty() {
+        return Ok(());
+    }
+    Err(kind_error(
+        ErrorKind::Hash,
+        format!(
+            "The diff {} contains values which should have been hashed: {}",
+            source_name,
+            unhashed.into_iter().collect::<Vec<_>>().join(", ")
+        ),
+    ))
+}
+
+/// Resolves the names of the files deferred by `resolve_diff_hashes`, now that the hashtab may
+/// have them. Returns the names which have been resolved.
+pub fn resolve_hashed_files(changes: &mut [Change], hashtab: &HashTab) -> Vec<String> {
+    let mut resolved = Vec::new();
+    for change in changes {
+        if let ObjectToChange::HashedFile(hash) = &change.destination {
+            if let Some(name) = resolve_hashed_ids(hashtab, hash, &mut BTreeSet::new()) {
+                resolved.push(name.clone());
+                change.destination = ObjectToChange::File(name);
+            }
+        }
+    }
+    resolved
+}
+
+/// Resolves the deferred files named by the hash of `file_name` - as a whole, or part by part
+/// (`hash-diffs` hashes `Page.qml` as `[[hash(Page).hash(qml)]]` if both parts are known). This
+/// doesn't need the hashtab at all. Returns whether any file has been resolved.
+pub fn resolve_hashed_file_name(changes: &mut [Change], file_name: &str) -> bool {
+    let whole = [hash(file_name)];
+    let parts: Vec<u64> = file_name.split('.').map(hash).collect();
+    let mut resolved = false;
+    for change in changes {
+        if let ObjectToChange::HashedFile(file_hash) = &change.destination {
+            if file_hash[..] == whole || *file_hash == parts {
+                change.destination = ObjectToChange::File(file_name.to_string());
+                resolved = true;
+            }
         }
-        other => Ok(other),
     }
+    resolved
 }
 
 impl IteratorRemapper<TokenType, Arc<String>> for DiffHashRemapper<'_> {
@@ -1,4 +1,5 @@
-use std::{fmt::Display, mem::take};
+use alloc::{format, string::String, vec::Vec};
+use core::{fmt::Display, mem::take};
 
 use anyhow::{Error, bail};
 
@@ -7,6 +8,10 @@ use crate::parser::{
     qml,
 };
 
+/// How deep `{` blocks can be nested within QML code. Real QML never gets close - reaching it
+/// almost always means that a closing `}` is missing.
+const MAX_QML_CODE_DEPTH: u32 = 256;
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Keyword {
     Affect,
@@ -15,23 +20,57 @@ pub enum Keyword {
     Assert,
     Locate,
     Replace,
+    String,
     Template,
     Remove,
     Import,
+    Pragma,
     Multiple,
     Replicate,
     Rename,
+    Transform,
+    Translate,
+    Using,
     End,
     Slot,
     Load,
     External,
     Version,
+    Require,
+    Meta,
+    Priority,
+    Env,
+    Define,
+    Create,
+    Redirect,
+    Push,
+    Pop,
+    Scope,
+    Local,
+    Sorted,
+    By,
+
+    // qmldir keywords:
+    Add,
+    Singleton,
+    Plugin,
+    Entry,
 
+    Suffix,
+    Propagate,
+    Value,
     With,
     To,
     All,
     After,
     Before,
+    Anchor,
+    Function,
+    Signal,
+    Property,
+    Root,
+    Not,
+    Else,
 
     // Stream editing keywords:
     Until,
@@ -43,23 +82,35 @@ pub enum Keyword {
 }
 
 impl Display for Keyword {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.write_str(&String::from(match self {
             Self::Affect => "AFFECT",
             Self::After => "AFTER",
             Self::All => "ALL",
             Self::Assert => "ASSERT",
             Self::Before => "BEFORE",
+            Self::Anchor => "ANCHOR",
+            Self::Function => "FUNCTION",
+            Self::Signal => "SIGNAL",
+            Self::Property => "PROPERTY",
+            Self::Root => "ROOT",
+            Self::Not => "NOT",
+            Self::Else => "ELSE",
             Self::Rename => "RENAME",
+            Self::Transform => "TRANSFORM",
+            Self::Translate => "TRANSLATE",
+            Self::Using => "USING",
             Self::Load => "LOAD",
             Self::External => "EXTERNAL",
             Self::End => "END",
             Self::Import => "IMPORT",
+            Self::Pragma => "PRAGMA",
             Self::Insert => "INSERT",
             Self::Locate => "LOCATE",
             Self::Multiple => "MULTIPLE",
             Self::Remove => "REMOVE",
             Self::Replace => "REPLACE",
+            Self::String => "STRING",
             Self::Replicate => "REPLICATE",
             Self::Slot => "SLOT",
             Self::Template => "TEMPLATE",
@@ -67,6 +118,26 @@ impl Display for Keyword {
             Self::With => "WITH",
             Self::To => "TO",
             Self::Version => "VERSION",
+            Self::Require => "REQUIRE",
+            Self::Meta => "META",
+            Self::Priority => "PRIORITY",
+            Self::Env => "ENV",
+            Self::Define => "DEFINE",
+            Self::Create => "CREATE",
+            Self::Redirect => "REDIRECT",
+            Self::Push => "PUSH",
+            Self::Pop => "POP",
+            Self::Scope => "SCOPE",
+            Self::Local => "LOCAL",
+            Self::Sorted => "SORTED",
+            Self::By => "BY",
+            Self::Add => "ADD",
+            Self::Singleton => "SINGLETON",
+            Self::Plugin => "PLUGIN",
+            Self::Entry => "ENTRY",
+            Self::Suffix => "SUFFIX",
+            Self::Propagate => "PROPAGATE",
+            Self::Value => "VALUE",
 
             Self::Until => "UNTIL",
             Self::Argument => "ARGUMENT",
@@ -91,20 +162,52 @@ impl TryFrom<&str> for Keyword {
             "TEMPLATE" => Ok(Self::Template),
             "LOCATE" => Ok(Self::Locate),
             "IMPORT" => Ok(Self::Import),
+            "PRAGMA" => Ok(Self::Pragma),
             "RENAME" => Ok(Self::Rename),
+            "TRANSFORM" => Ok(Self::Transform),
+            "TRANSLATE" => Ok(Self::Translate),
+            "USING" => Ok(Self::Using),
             "LOAD" => Ok(Self::Load),
             "EXTERNAL" => Ok(Self::External),
             "ALL" => Ok(Self::All),
             "BEFORE" => Ok(Self::Before),
             "AFTER" => Ok(Self::After),
+            "ANCHOR" => Ok(Self::Anchor),
+            "FUNCTION" => Ok(Self::Function),
+            "SIGNAL" => Ok(Self::Signal),
+            "PROPERTY" => Ok(Self::Property),
+            "ROOT" => Ok(Self::Root),
+            "NOT" => Ok(Self::Not),
+            "ELSE" => Ok(Self::Else),
             "REMOVE" => Ok(Self::Remove),
             "REPLICATE" => Ok(Self::Replicate),
             "MULTIPLE" => Ok(Self::Multiple),
             "REPLACE" => Ok(Self::Replace),
+            "STRING" => Ok(Self::String),
             "WITH" => Ok(Self::With),
             "TO" => Ok(Self::To),
             "END" => Ok(Self::End),
             "VERSION" => Ok(Self::Version),
+            "REQUIRE" => Ok(Self::Require),
+            "META" => Ok(Self::Meta),
+            "PRIORITY" => Ok(Self::Priority),
+            "ENV" => Ok(Self::Env),
+            "DEFINE" => Ok(Self::Define),
+            "CREATE" => Ok(Self::Create),
+            "REDIRECT" => Ok(Self::Redirect),
+            "PUSH" => Ok(Self::Push),
+            "POP" => Ok(Self::Pop),
+            "SCOPE" => Ok(Self::Scope),
+            "LOCAL" => Ok(Self::Local),
+            "SORTED" => Ok(Self::Sorted),
+            "BY" => Ok(Self::By),
+            "ADD" => Ok(Self::Add),
+            "SINGLETON" => Ok(Self::Singleton),
+            "PLUGIN" => Ok(Self::Plugin),
+            "ENTRY" => Ok(Self::Entry),
+            "SUFFIX" => Ok(Self::Suffix),
+            "PROPAGATE" => Ok(Self::Propagate),
+            "VALUE" => Ok(Self::Value),
 
             "UNTIL" => Ok(Self::Until),
             "ARGUMENT" => Ok(Self::Argument),
@@ -144,6 +247,8 @@ pub enum TokenType {
 pub struct Lexer {
     pub stream: StringCharacterTokenizer,
     pub line_pos: usize, // Current position within a line [unused.]
+    /// The error which ended the iteration over the tokens.
+    error: Option<Error>,
 }
 
 impl Lexer {
@@ -151,8 +256,15 @@ impl Lexer {
         Self {
             stream: input,
             line_pos: 0,
+            error: None,
         }
     }
+
+    /// The error which ended the iteration over the tokens, if any. `tokenize` returns it
+    /// directly.
+    pub fn take_error(&mut self) -> Option<Error> {
+        self.error.take()
+    }
     pub fn next_token(&mut self) -> Result<TokenType, Error> {
         if let Some(c) = self.stream.peek() {
             match c {
@@ -174,6 +286,7 @@ impl Lexer {
                 }
 
                 '"' | '\'' | '`' => {
+                    let start_line = self.line_pos + 1;
                     let quote = self.stream.advance().unwrap();
                     let mut is_quoted = false;
                     let string = self.stream.collect_while(move |_, c| {
@@ -191,7 +304,9 @@ impl Lexer {
                         CollectionType::Include
                     });
 
-                    self.stream.advance(); // Consume closing quote
+                    if self.stream.advance().is_none() {
+                        bail!("Unterminated string starting on line {} - expected a closing {}", start_line, quote);
+                    }
                     Ok(TokenType::String(if quote == '`' {
                         string
                     } else {
@@ -217,7 +332,11 @@ impl Lexer {
                         _ => return Err(Error::msg("Invalid hash!")),
                     }
                     self.stream.advance();
-                    let hash = hash.split('.').map(|x| x.parse::<u64>().unwrap()).collect();
+                    let hash = hash
+                        .split('.')
+                        .map(|x| x.parse::<u64>())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| Error::msg("Invalid hash!"))?;
                     Ok(TokenType::HashedValue(match string_quote {
                         None => HashedValue::HashedIdentifier(hash),
                         Some(q) => HashedValue::HashedString(q, hash)
@@ -230,20 +349,28 @@ impl Lexer {
                     if let Ok(keyword) = Keyword::try_from(ident.as_str()) {
                         Ok(TokenType::Keyword(keyword))
                     } else if ident == "STREAM" {
+                        let start_line = self.line_pos + 1;
                         self.stream.collect_while(|_, c| c.is_whitespace().into());
                         // Start processing as a QML token stream, until met with the same token as the one that follows
                         // this keyword
                         let mut qml_lexer = qml::lexer::Lexer::new(take(&mut self.stream));
                         let mut qml_code = Vec::new();
                         let initial_token = qml_lexer.next_token()?;
+                        if initial_token == qml::lexer::TokenType::EndOfStream {
+                            bail!("Expected the ending token after STREAM");
+                        }
                         loop {
                             let token = qml_lexer.next_token()?;
                             if token == initial_token {
                                 break;
                             }
+                            if token == qml::lexer::TokenType::EndOfStream {
+                                bail!("Unterminated STREAM starting on line {} - expected the ending token {}", start_line, initial_token);
+                            }
                             qml_code.push(token);
                         }
                         self.stream = take(&mut qml_lexer.stream);
+                        self.line_pos += qml_lexer.line_pos;
                         Ok(TokenType::QMLCode {
                             qml_code,
                             stream_character: Some(initial_token),
@@ -255,6 +382,7 @@ impl Lexer {
 
                 '{' => {
                     // This is the start of QML code.
+                    let start_line = self.line_pos + 1;
                     self.stream.advance();
                     let mut qml_lexer = qml::lexer::Lexer::new(take(&mut self.stream));
                     let mut qml_code = Vec::new();
@@ -262,9 +390,14 @@ impl Lexer {
                     loop {
                         let token = qml_lexer.next_token()?;
                         match token {
-                            qml::lexer::TokenType::Symbol('{') => depth += 1,
+                            qml::lexer::TokenType::Symbol('{') => {
+                                depth += 1;
+                                if depth > MAX_QML_CODE_DEPTH {
+                                    bail!("The QML code block starting on line {} is nested more than {} levels deep - is a closing }} missing?", start_line, MAX_QML_CODE_DEPTH);
+                                }
+                            }
                             qml::lexer::TokenType::Symbol('}') => depth -= 1,
-                            qml::lexer::TokenType::EndOfStream => bail!("Unexpected End-Of-Stream reached!"),
+                            qml::lexer::TokenType::EndOfStream => bail!("Unterminated QML code block starting on line {} - expected a closing }}", start_line),
                             _ => {}
                         }
                         if depth == 0 {
@@ -274,15 +407,16 @@ impl Lexer {
                         }
                     }
                     self.stream = take(&mut qml_lexer.stream);
+                    self.line_pos += qml_lexer.line_pos;
                     Ok(TokenType::QMLCode {
                         qml_code,
                         stream_character: None,
                     })
                 }
 
-                //       Child-of    Prop.EQ        ID      p.named | Others
-                // Prop.v      Contains    Traversal     Name       |
-                '[' | ']' | '>' | '~' | '=' | '/' | '#' | ':' | '!' | '.' => {
+                //       Child-of    Prop.EQ        ID      p.named  Alternative | Others
+                // Prop.v      Contains    Traversal     Name                   |
+                '[' | ']' | '>' | '<' | '~' | '=' | '/' | '#' | ':' | '!' | '.' | '|' => {
                     let symbol = self.stream.advance().unwrap();
                     Ok(TokenType::Symbol(symbol))
                 }
@@ -296,22 +430,42 @@ impl Lexer {
             Ok(TokenType::EndOfStream)
         }
     }
+
+    /// Reads all the tokens, failing on the first one which cannot be read.
+    pub fn tokenize(self) -> Result<Vec<TokenType>, Error> {
+        self.tokenize_with(|_| Ok(()))
+    }
+
+    /// Like `tokenize`, but `check` is called with every token as soon as it's read. The first
+    /// error it returns stops the lexer - the rest of the input is never read.
+    pub fn tokenize_with(
+        mut self,
+        mut check: impl FnMut(&TokenType) -> Result<(), Error>,
+    ) -> Result<Vec<TokenType>, Error> {
+        let mut tokens = Vec::new();
+        while self.stream.position < self.stream.input.len() {
+            let token = self.next_token()?;
+            check(&token)?;
+            tokens.push(token);
+        }
+        Ok(tokens)
+    }
 }
 
 impl Iterator for Lexer {
     type Item = TokenType;
 
     fn next(&mut self) -> Option<Self::Item> {
-        loop {
-            if self.stream.position >= self.stream.input.len() {
-                return None;
-            }
-            match self.next_token() {
-                Ok(token) => return Some(token),
-                Err(e) => {
-                    // TODO: handle this
-                    panic!("Error while reading token: {e:?}");
-                }
+        if self.stream.position >= self.stream.input.len() {
+            return None;
+        }
+        match self.next_token() {
+            Ok(token) => Some(token),
+            Err(error) => {
+                // An iterator can't fail - the tokens end at the first one which cannot be read.
+                self.stream.position = self.stream.input.len();
+                self.error = Some(error);
+                None
             }
         }
     }
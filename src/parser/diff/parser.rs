@@ -1,6 +1,7 @@
 use std::{
     cell::RefCell,
     collections::HashMap,
+    fmt::Display,
     iter::Peekable,
     mem::take,
     path::{Path, PathBuf},
@@ -11,36 +12,138 @@ use std::{
 use crate::{
     error_received_expected,
     hashtab::HashTab,
-    parser::{common::StringCharacterTokenizer, diff::hash_processor::diff_hash_remapper, qml},
+    parser::{
+        common::StringCharacterTokenizer,
+        diff::hash_processor::{ensure_hashed, resolve_diff_hashes},
+        qml::{
+            self,
+            emitter::emit_simple_token_stream,
+            template_expression::{parse_script, ScriptAssignment},
+        },
+    },
 };
 use anyhow::{bail, Error, Result};
 
-use super::lexer::{Keyword, Lexer, TokenType};
+use super::lexer::{HashedValue, Keyword, Lexer, TokenType};
+use crate::limits::Limits;
+use crate::parser::qmldir::QmlDirEntry;
+use crate::translation::TranslationCatalog;
 
 pub trait ExternalLoader {
     fn load_external(&mut self, file: &str);
+    /// Provides the contents of a file requested by a `LOAD` directive.
+    /// Returning `None` makes the parser fall back to reading the file from disk.
+    fn load_contents(&mut self, _file: &str) -> Option<String> {
+        None
+    }
+    /// Checks a file read by a `LOAD` directive before it's parsed. Returning an error rejects
+    /// the file.
+    fn verify_contents(&mut self, _file: &str, _contents: &str) -> Result<()> {
+        Ok(())
+    }
 }
 
 pub struct Parser<'a> {
     source_name: Arc<String>,
     stream: Peekable<Box<dyn Iterator<Item = TokenType>>>,
     root_path: Option<String>,
+    /// The root path of the top-level file. `LOAD` falls back to it if a file cannot be found
+    /// relative to the file being parsed.
+    pack_root_path: Option<String>,
+    /// The files currently being `LOAD`ed, starting with the top-level file.
+    load_chain: Vec<String>,
     hashtab: Option<&'a HashTab>,
     external_loader: Option<Rc<RefCell<Box<dyn ExternalLoader>>>>,
+    /// Reject `LOAD`ed files with values which should have been hashed.
+    pub require_hashed: bool,
+    /// Keep the `AFFECT` statements naming files by hashes missing from the hashtab, instead of
+    /// failing. See `ObjectToChange::HashedFile`.
+    pub defer_hashed_files: bool,
+    pub limits: Limits,
+    /// The metadata of the file which `LOAD`ed this one.
+    metadata: Arc<DiffMetadata>,
+    /// The ordering of the file which `LOAD`ed this one.
+    ordering: Arc<DiffOrdering>,
+    /// The selectors defined by `DEFINE SELECTOR`, referred to as `@name`. `LOAD`ed files can use
+    /// the aliases of the file loading them.
+    selector_aliases: HashMap<String, NodeTree>,
+}
+
+/// Paths which can only be resolved by the external loader.
+/// Strips the quotes the lexer keeps around '...' and "..." strings.
+fn unquote(string: &str) -> &str {
+    string
+        .strip_prefix(['"', '\''])
+        .and_then(|e| e.strip_suffix(['"', '\'']))
+        .unwrap_or(string)
+}
+
+fn is_qrc_path(path: &str) -> bool {
+    path.starts_with("qrc:") || path.starts_with(":/")
+}
+
+/// Turns a path into the form used to detect `LOAD` cycles.
+fn load_chain_key(path: &Path) -> String {
+    match std::fs::canonicalize(path) {
+        Ok(e) => e.to_string_lossy().to_string(),
+        Err(_) => path
+            .components()
+            .collect::<PathBuf>()
+            .to_string_lossy()
+            .to_string(),
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum PropRequirement {
     Exists,
     Equals(String),
     Contains(String),
+    /// The value has to be a number, for which the comparison holds.
+    Compare(Comparison, f64),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct NodeSelector {
+    /// Empty for selectors made of an id only (`#volumeSlider`), which match objects of any type.
     pub object_name: String,
     pub named: Option<String>,
     pub props: HashMap<String, PropRequirement>,
+    /// The selectors tried in order if this one doesn't match anything - `Column#main | ColumnLayout#main`.
+    pub alternatives: Vec<NodeSelector>,
+}
+
+/// Whether the diff lexer reads `value` back as a single identifier.
+fn is_plain_identifier(value: &str) -> bool {
+    value.starts_with(|c: char| c.is_alphabetic() || c.is_ascii_digit() || c == '_' || c == '-')
+        && value
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | '/'))
+        && Keyword::try_from(value).is_err()
+        && value != "STREAM"
+}
+
+/// Writes a value of a selector so that it's read back as it is - as an identifier if it's one,
+/// as a quoted string otherwise. The lexer keeps the quotes of `"..."` and `'...'` strings, so
+/// values quoted this way are written with their own quotes, and anything else within backticks.
+fn write_selector_value(f: &mut std::fmt::Formatter<'_>, value: &str) -> std::fmt::Result {
+    if is_plain_identifier(value) {
+        return f.write_str(value);
+    }
+    let (quote, contents) = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) if value.len() >= 2 && value.ends_with(quote) => {
+            (quote, &value[1..value.len() - 1])
+        }
+        _ => ('`', value),
+    };
+    write!(f, "{}", quote)?;
+    for chr in contents.chars() {
+        if chr == quote || chr == '\\' {
+            write!(f, "\\")?;
+        }
+        write!(f, "{}", chr)?;
+    }
+    write!(f, "{}", quote)
 }
 
 impl std::fmt::Display for NodeSelector {
@@ -49,24 +152,42 @@ impl std::fmt::Display for NodeSelector {
         if let Some(name) = &self.named {
             write!(f, ":{}", name)?;
         }
-        if let Some(PropRequirement::Equals(id)) = self.props.get("id") {
+        // Ids which aren't identifiers are written as any other property.
+        let short_id = match self.props.get("id") {
+            Some(PropRequirement::Equals(id)) if is_plain_identifier(id) => Some(id),
+            _ => None,
+        };
+        if let Some(id) = short_id {
             write!(f, "#{}", id)?;
         }
-        for (name, replacement) in &self.props {
-            if name != "id" || !matches!(replacement, PropRequirement::Equals(_)) {
+        // Sort the properties, so that the output is stable
+        let mut props = self.props.iter().collect::<Vec<_>>();
+        props.sort_by_key(|e| e.0);
+        for (name, replacement) in props {
+            if name != "id" || short_id.is_none() {
                 match replacement {
                     PropRequirement::Exists => {
                         write!(f, "[!{}]", name)?;
                     }
                     PropRequirement::Equals(val) => {
-                        write!(f, "[.{}={}]", name, val)?;
+                        write!(f, "[.{}=", name)?;
+                        write_selector_value(f, val)?;
+                        write!(f, "]")?;
                     }
                     PropRequirement::Contains(val) => {
-                        write!(f, "[.{}~{}]", name, val)?;
+                        write!(f, "[.{}~", name)?;
+                        write_selector_value(f, val)?;
+                        write!(f, "]")?;
+                    }
+                    PropRequirement::Compare(comparison, val) => {
+                        write!(f, "[.{}{}{}]", name, comparison, val)?;
                     }
                 }
             }
         }
+        for alternative in &self.alternatives {
+            write!(f, " | {}", alternative)?;
+        }
         Ok(())
     }
 }
@@ -77,120 +198,747 @@ impl NodeSelector {
             object_name: name,
             named: None,
             props: HashMap::new(),
+            alternatives: Vec::new(),
         }
     }
 
     pub fn is_simple(&self) -> bool {
         self.props.is_empty() && self.named.is_none()
     }
+
+    /// The id a selector made of an id only (`#volumeSlider`) looks for. At the start of a
+    /// `TRAVERSE`, such a selector finds the object with the id wherever it is in the file.
+    pub fn standalone_id(&self) -> Option<&str> {
+        if !self.object_name.is_empty() || self.named.is_some() || self.props.len() != 1 {
+            return None;
+        }
+        match self.props.get("id") {
+            Some(PropRequirement::Equals(id)) => Some(id),
+            _ => None,
+        }
+    }
+
+    /// This selector, followed by its alternatives - in the order they should be tried in.
+    pub fn candidates(&self) -> impl Iterator<Item = &NodeSelector> {
+        std::iter::once(self).chain(self.alternatives.iter())
+    }
+
+    /// Parses a single node selector, like `Button#ok[.text~Save]`.
+    pub fn parse(selector: &str) -> Result<Self> {
+        parse_selector(selector, Parser::read_node)
+    }
 }
 
-pub type NodeTree = Vec<NodeSelector>;
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeTree(pub Vec<NodeSelector>);
+
+impl std::ops::Deref for NodeTree {
+    type Target = Vec<NodeSelector>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
 
-#[derive(Debug, Clone)]
+impl std::ops::DerefMut for NodeTree {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl Display for NodeTree {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, node) in self.0.iter().enumerate() {
+            if i != 0 {
+                f.write_str(" > ")?;
+            }
+            write!(f, "{}", node)?;
+        }
+        Ok(())
+    }
+}
+
+impl NodeTree {
+    /// Parses a tree selector, like `Rectangle > Item[.color="\"red\""]`.
+    pub fn parse(selector: &str) -> Result<Self> {
+        parse_selector(selector, Parser::read_tree)
+    }
+}
+
+/// Parses a selector outside of a diff, using the same grammar as the diff parser.
+/// The whole string has to be consumed.
+fn parse_selector<T>(selector: &str, read: fn(&mut Parser<'static>) -> Result<T>) -> Result<T> {
+    let tokens = Lexer::new(StringCharacterTokenizer::new(selector.to_string())).tokenize()?;
+    let mut parser = Parser::new(
+        Box::new(tokens.into_iter()),
+        None,
+        Arc::new("<selector>".to_string()),
+        None,
+        None,
+    );
+    let value = read(&mut parser)?;
+    parser.discard_whitespace();
+    match parser.stream.next() {
+        None | Some(TokenType::EndOfStream) => Ok(value),
+        Some(next) => error_received_expected!(next, "end of selector"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Location {
     Before,
     After,
 }
 
-#[derive(Debug, Clone)]
+impl Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Before => "BEFORE",
+            Self::After => "AFTER",
+        })
+    }
+}
+
+/// The kinds of members which aren't objects, located by their name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemberKind {
+    Function,
+    Signal,
+    /// Properties declared by the object (`property int count`), not assignments.
+    Property,
+}
+
+impl Display for MemberKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Function => "FUNCTION",
+            Self::Signal => "SIGNAL",
+            Self::Property => "PROPERTY",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum LocationSelector {
     All,
     Tree(NodeTree),
+    /// An anchor declared by previously inserted code (`~#name~`).
+    Anchor(String),
+    /// `FUNCTION <name>`, `SIGNAL <name>` or `PROPERTY <name>`.
+    Member(MemberKind, String),
 }
 
-#[derive(Debug, Clone)]
+impl Display for LocationSelector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::All => f.write_str("ALL"),
+            Self::Tree(tree) => write!(f, "{}", tree),
+            Self::Anchor(name) => write!(f, "ANCHOR {}", name),
+            Self::Member(kind, name) => write!(f, "{} {}", kind, name),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct LocateAction {
     pub selector: LocationSelector,
     pub location: Location,
 }
 
-#[derive(Debug, Clone)]
+impl Display for LocateAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "LOCATE {} {}", self.location, self.selector)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AssertAction {
+    pub tree: NodeTree,
+    /// `ASSERT NOT` - only keep the roots the tree does not match.
+    pub negated: bool,
+    /// The explanation given with `ELSE "..."`, shown when the assertion fails.
+    pub message: Option<String>,
+}
+
+impl Display for AssertAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ASSERT ")?;
+        if self.negated {
+            f.write_str("NOT ")?;
+        }
+        write!(f, "{}", self.tree)?;
+        if let Some(message) = &self.message {
+            write!(f, " ELSE {:?}", message)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ReplaceAction {
     pub selector: NodeTree,
     pub content: Insertable, // QML / SLOT / TEMPLATE
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Insertable {
     Code(Vec<crate::parser::qml::lexer::TokenType>),
     Slot(String),
     Template(String, Vec<crate::parser::qml::lexer::TokenType>),
 }
 
-#[derive(Debug, Clone)]
+impl Display for Insertable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Code(stream) => f.write_str(&qml_stream_to_string(stream)),
+            Self::Slot(name) => write!(f, "SLOT {}", name),
+            Self::Template(name, stream) => {
+                write!(f, "TEMPLATE {} {}", name, qml_stream_to_string(stream))
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct ImportAction {
     pub name: String,
     pub version: String,
     pub alias: Option<String>,
 }
 
-#[derive(Debug, Clone)]
+/// `PRAGMA <name> [value]` - adds `pragma <name>` (or `pragma <name>: <value>`) to the file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PragmaAction {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// `INSERT SORTED BY <property> { QML }` - inserts the code among the children ordered by the
+/// value of `property`, instead of at the cursor.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InsertSortedAction {
+    pub property: String,
+    pub code: Vec<qml::lexer::TokenType>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct RenameAction {
     pub selector: NodeTree,
     pub name_to: String,
+    /// `RENAME <node> TO <name> PROPAGATE` - also rewrites the references to the old name within
+    /// the bindings and functions of the object.
+    pub propagate: bool,
+}
+
+impl Display for RenameAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RENAME {} TO {}", self.selector, self.name_to)?;
+        if self.propagate {
+            f.write_str(" PROPAGATE")?;
+        }
+        Ok(())
+    }
+}
+
+/// The directives editing the values of the enum the change has traversed into.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnumValueAction {
+    /// `INSERT VALUE <name> [= <value>]` - inserted at the cursor, or after all the other values
+    /// if there is none.
+    Insert(String, Option<String>),
+    /// `REMOVE VALUE <name>`
+    Remove(String),
+    /// `RENAME VALUE <name> TO <new name>`
+    Rename(String, String),
+}
+
+impl Display for EnumValueAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Insert(name, Some(value)) => write!(f, "INSERT VALUE {} = {}", name, value),
+            Self::Insert(name, None) => write!(f, "INSERT VALUE {}", name),
+            Self::Remove(name) => write!(f, "REMOVE VALUE {}", name),
+            Self::Rename(name, new_name) => write!(f, "RENAME VALUE {} TO {}", name, new_name),
+        }
+    }
+}
+
+/// `TRANSFORM <tree> WITH { script }` - computes new values of the properties of every object
+/// matched by the tree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TransformAction {
+    pub selector: NodeTree,
+    pub script: Vec<qml::lexer::TokenType>,
+    pub assignments: Vec<ScriptAssignment>,
 }
 
-#[derive(Debug, Clone)]
+impl Display for TransformAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "TRANSFORM {} WITH {}",
+            self.selector,
+            qml_stream_to_string(&self.script)
+        )
+    }
+}
+
+/// `REPLACE STRING "<from>" WITH "<to>" [ALL]` - replaces the string literals of the whole file
+/// which are exactly `from`. With `ALL`, every occurrence of `from` within a string literal is
+/// replaced instead.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplaceStringAction {
+    pub from: String,
+    pub to: String,
+    pub all: bool,
+}
+
+impl Display for ReplaceStringAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let quote = |e: &str| format!("\"{}\"", e.replace('\\', "\\\\").replace('"', "\\\""));
+        write!(
+            f,
+            "REPLACE STRING {} WITH {}",
+            quote(&self.from),
+            quote(&self.to)
+        )?;
+        if self.all {
+            f.write_str(" ALL")?;
+        }
+        Ok(())
+    }
+}
+
+/// `TRANSLATE USING <catalog>` - translates the texts passed to `qsTr` within the whole file. The
+/// catalog is read when the diff is parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TranslateAction {
+    /// The path of the catalog, the way the diff refers to it.
+    pub path: String,
+    pub catalog: Arc<TranslationCatalog>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct RebuildAction {
     pub selector: NodeSelector,
     pub actions: Vec<RebuildInstruction>,
     pub redefine: bool,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum FileChangeAction {
     Traverse(NodeTree),
-    Assert(NodeTree),
+    /// `TRAVERSE ROOT <tree>` - locates the tree starting from the root of the file, no matter
+    /// how deep the current scope is. Ended by `END TRAVERSE`, like any other `TRAVERSE`.
+    TraverseFromRoot(NodeTree),
+    Assert(AssertAction),
     Locate(LocateAction),
     Remove(NodeSelector),
     Rename(RenameAction),
+    EnumValue(EnumValueAction),
+    Transform(TransformAction),
+    Translate(TranslateAction),
     Insert(
         Insertable, /*The QML Code as a string, for the QML parser to work on, or a slot*/
     ),
+    InsertSorted(InsertSortedAction),
     Replace(ReplaceAction),
+    ReplaceString(ReplaceStringAction),
     End(Keyword),
     AllowMultiple,
     AddImport(ImportAction),
+    AddPragma(PragmaAction),
     Rebuild(RebuildAction),
     Replicate(NodeTree),
+    ReplicateFromRoot(NodeTree),
+    /// `PUSH SCOPE` - saves the current root, cursor and the whole stack of the roots leading to
+    /// it, so that `POP SCOPE` can return to them.
+    PushScope,
+    PopScope,
+    /// `LOCAL SLOT <name> ... END SLOT` - a slot only visible within the change defining it.
+    /// Resolved by `Slots::process_slots`.
+    LocalSlot(String, Vec<FileChangeAction>),
+
+    // qmldir files only:
+    AddQmlDirEntry(QmlDirEntry),
+    RemoveQmlDirEntry(String),
+}
+
+/// Writes a directive the way it would appear in a diff. The directives nested within `REBUILD`
+/// and `LOCAL SLOT` blocks are put on their own lines, prefixed with `indent` and indented one
+/// level further.
+fn write_action(
+    f: &mut std::fmt::Formatter<'_>,
+    action: &FileChangeAction,
+    indent: &str,
+) -> std::fmt::Result {
+    match action {
+        FileChangeAction::Traverse(tree) => write!(f, "TRAVERSE {}", tree),
+        FileChangeAction::TraverseFromRoot(tree) => write!(f, "TRAVERSE ROOT {}", tree),
+        FileChangeAction::Assert(assert) => write!(f, "{}", assert),
+        FileChangeAction::Locate(locate) => write!(f, "{}", locate),
+        FileChangeAction::Remove(node) => write!(f, "REMOVE {}", node),
+        FileChangeAction::Rename(rename) => write!(f, "{}", rename),
+        FileChangeAction::EnumValue(action) => write!(f, "{}", action),
+        FileChangeAction::Transform(transform) => write!(f, "{}", transform),
+        FileChangeAction::Translate(translate) => write!(f, "TRANSLATE USING {}", translate.path),
+        FileChangeAction::Insert(insertable) => write!(f, "INSERT {}", insertable),
+        FileChangeAction::InsertSorted(sorted) => write!(
+            f,
+            "INSERT SORTED BY {} {}",
+            sorted.property,
+            qml_stream_to_string(&sorted.code)
+        ),
+        FileChangeAction::Replace(replace) => {
+            write!(f, "REPLACE {} WITH {}", replace.selector, replace.content)
+        }
+        FileChangeAction::ReplaceString(replace) => write!(f, "{}", replace),
+        FileChangeAction::End(keyword) => write!(f, "END {}", keyword),
+        FileChangeAction::AllowMultiple => f.write_str("MULTIPLE"),
+        FileChangeAction::AddImport(import) => {
+            write!(f, "IMPORT {} {}", import.name, import.version)?;
+            if let Some(alias) = &import.alias {
+                write!(f, " {}", alias)?;
+            }
+            Ok(())
+        }
+        FileChangeAction::AddPragma(pragma) => {
+            write!(f, "PRAGMA {}", pragma.name)?;
+            if let Some(value) = &pragma.value {
+                write!(f, " {}", value)?;
+            }
+            Ok(())
+        }
+        FileChangeAction::Rebuild(rebuild) => {
+            let keyword = if rebuild.redefine {
+                Keyword::Redefine
+            } else {
+                Keyword::Rebuild
+            };
+            write!(f, "{} {}", keyword, rebuild.selector)?;
+            for instruction in &rebuild.actions {
+                write!(f, "\n{}    {}", indent, instruction)?;
+            }
+            write!(f, "\n{}END {}", indent, keyword)
+        }
+        FileChangeAction::Replicate(tree) => write!(f, "REPLICATE {}", tree),
+        FileChangeAction::ReplicateFromRoot(tree) => write!(f, "REPLICATE ROOT {}", tree),
+        FileChangeAction::PushScope => f.write_str("PUSH SCOPE"),
+        FileChangeAction::PopScope => f.write_str("POP SCOPE"),
+        FileChangeAction::LocalSlot(name, contents) => {
+            write!(f, "LOCAL SLOT {}", name)?;
+            let nested = format!("{}    ", indent);
+            for content in contents {
+                write!(f, "\n{}", nested)?;
+                write_action(f, content, &nested)?;
+            }
+            write!(f, "\n{}END SLOT", indent)
+        }
+        FileChangeAction::AddQmlDirEntry(QmlDirEntry::Singleton {
+            name,
+            version,
+            file,
+        }) => write!(f, "ADD SINGLETON {} {} {}", name, version, file),
+        FileChangeAction::AddQmlDirEntry(QmlDirEntry::Plugin { name, path }) => {
+            write!(f, "ADD PLUGIN {}", name)?;
+            if let Some(path) = path {
+                write!(f, " {}", path)?;
+            }
+            Ok(())
+        }
+        FileChangeAction::AddQmlDirEntry(entry) => write!(f, "ADD {}", entry),
+        FileChangeAction::RemoveQmlDirEntry(name) => write!(f, "REMOVE ENTRY {}", name),
+    }
+}
+
+impl Display for FileChangeAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write_action(f, self, "")
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum ObjectToChange {
     FileTokenStream(String),
     File(String),
+    /// A file matched by the end of its path, like `ui/Page.qml` matching `qrc:/ui/Page.qml`.
+    /// Created by an `AFFECT SUFFIX` statement.
+    FileSuffix(String),
     Template(String),
     Slot(String),
+    /// A file which doesn't exist in the QML tree, created by a `CREATE` statement.
+    NewFile(String),
+    /// A file which doesn't exist in the QML tree, starting out as a copy of the original
+    /// contents of another file. Created by a `REDIRECT` statement.
+    Redirect {
+        original: String,
+        redirected: String,
+    },
+    /// A file named by a hash missing from the hashtab (`AFFECT [[1234]]`). Only created when
+    /// the hashed files are deferred - its directives don't apply to any file until the name is
+    /// resolved, see `resolve_hashed_files`.
+    HashedFile(Vec<u64>),
+}
+
+impl Display for ObjectToChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FileTokenStream(file) => write!(f, "AFFECT REBUILD {}", file),
+            Self::File(file) => write!(f, "AFFECT {}", file),
+            Self::FileSuffix(suffix) => write!(f, "AFFECT SUFFIX {}", suffix),
+            Self::Template(name) => write!(f, "TEMPLATE {}", name),
+            Self::Slot(name) => write!(f, "SLOT {}", name),
+            Self::NewFile(file) => write!(f, "CREATE {}", file),
+            Self::Redirect {
+                original,
+                redirected,
+            } => write!(f, "REDIRECT {} TO {}", original, redirected),
+            Self::HashedFile(hash) => write!(
+                f,
+                "AFFECT [[{}]]",
+                hash.iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(".")
+            ),
+        }
+    }
+}
+
+impl ObjectToChange {
+    /// Whether the directives of an `AFFECT` (or `AFFECT SUFFIX`) statement apply to the file.
+    pub fn affects_file(&self, file_name: &str) -> bool {
+        match self {
+            Self::File(file) => file == file_name,
+            Self::FileSuffix(suffix) => has_path_suffix(file_name, suffix),
+            _ => false,
+        }
+    }
+}
+
+/// Whether the path ends with the suffix. Only whole path segments are matched - `ui/Page.qml`
+/// matches `/usr/share/ui/Page.qml` and `qrc:/ui/Page.qml`, but not `/ui/MainPage.qml`.
+pub fn has_path_suffix(path: &str, suffix: &str) -> bool {
+    let suffix = suffix.trim_start_matches('/');
+    match path.strip_suffix(suffix) {
+        Some(prefix) => !suffix.is_empty() && (prefix.is_empty() || prefix.ends_with('/')),
+        None => false,
+    }
 }
 
-#[derive(Debug, Clone)]
+/// Values of the defines set by the host (through FFI or CLI flags), checked by `REQUIRE`.
+pub type Defines = HashMap<String, String>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+impl Display for Comparison {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Equal => "==",
+            Self::NotEqual => "!=",
+            Self::Greater => ">",
+            Self::GreaterOrEqual => ">=",
+            Self::Less => "<",
+            Self::LessOrEqual => "<=",
+        })
+    }
+}
+
+impl Comparison {
+    /// Compares two values. If both are dot-separated numbers (like versions), they're
+    /// compared numerically, part by part. Otherwise they're compared as strings.
+    pub fn compare(&self, left: &str, right: &str) -> bool {
+        fn as_version(value: &str) -> Option<Vec<u64>> {
+            value.split('.').map(|e| e.parse::<u64>().ok()).collect()
+        }
+        let ordering = match (as_version(left), as_version(right)) {
+            (Some(mut left), Some(mut right)) => {
+                let len = left.len().max(right.len());
+                left.resize(len, 0);
+                right.resize(len, 0);
+                left.cmp(&right)
+            }
+            _ => left.cmp(right),
+        };
+        self.holds(ordering)
+    }
+
+    /// Compares two numbers, like the value of a property with the one in a selector.
+    pub fn compare_numbers(&self, left: f64, right: f64) -> bool {
+        left.partial_cmp(&right)
+            .is_some_and(|ordering| self.holds(ordering))
+    }
+
+    fn holds(&self, ordering: std::cmp::Ordering) -> bool {
+        match self {
+            Self::Equal => ordering.is_eq(),
+            Self::NotEqual => ordering.is_ne(),
+            Self::Greater => ordering.is_gt(),
+            Self::GreaterOrEqual => ordering.is_ge(),
+            Self::Less => ordering.is_lt(),
+            Self::LessOrEqual => ordering.is_le(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Requirement {
+    Define(String),
+    Env {
+        name: String,
+        comparison: Comparison,
+        value: String,
+    },
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Define(name) => write!(f, "REQUIRE DEFINE {}", name),
+            Self::Env {
+                name,
+                comparison,
+                value,
+            } => write!(f, "REQUIRE ENV {} {} {}", name, comparison, value),
+        }
+    }
+}
+
+impl Requirement {
+    /// `ENV` requirements check the defines first, then the process' environment.
+    pub fn is_met(&self, defines: &Defines) -> bool {
+        match self {
+            Self::Define(name) => defines.contains_key(name),
+            Self::Env {
+                name,
+                comparison,
+                value,
+            } => match defines
+                .get(name)
+                .cloned()
+                .or_else(|| std::env::var(name).ok())
+            {
+                Some(current) => comparison.compare(&current, value),
+                None => false,
+            },
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Change {
     pub source: Arc<String>,
     pub destination: ObjectToChange,
     pub changes: Vec<FileChangeAction>,
     pub versions_allowed: Option<Vec<String>>,
+    pub requirements: Vec<Requirement>,
+    pub metadata: Arc<DiffMetadata>,
+    pub ordering: Arc<DiffOrdering>,
+}
+
+/// The information about a diff set by the `META` statements at the beginning of its file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffMetadata {
+    pub name: Option<String>,
+    pub author: Option<String>,
+    pub version: Option<String>,
+}
+
+/// When a diff should be applied relative to the other diffs, set by the `PRIORITY` and
+/// `AFTER DIFF` statements at the beginning of its file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiffOrdering {
+    pub priority: i64,
+    /// The names or the paths of the diffs which have to be applied before this one.
+    pub after: Vec<String>,
+}
+
+/// `{}` describes the change in a single line. `{:#}` prints it back as diff syntax - the
+/// `VERSION` and `REQUIRE` statements it depends on, followed by its block, with the directives
+/// within `TRAVERSE` / `REPLICATE` / `PUSH SCOPE` blocks indented.
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !f.alternate() {
+            return write!(f, "{} (defined by '{}')", self.destination, self.source);
+        }
+        for version in self.versions_allowed.iter().flatten() {
+            writeln!(f, "VERSION {}", version)?;
+        }
+        for requirement in &self.requirements {
+            writeln!(f, "{}", requirement)?;
+        }
+        let end = match (&self.destination, self.changes.as_slice()) {
+            (ObjectToChange::FileTokenStream(file), [FileChangeAction::Rebuild(rebuild)]) => {
+                write!(f, "AFFECT REBUILD {}", file)?;
+                for instruction in &rebuild.actions {
+                    write!(f, "\n    {}", instruction)?;
+                }
+                return f.write_str("\nEND REBUILD");
+            }
+            (
+                ObjectToChange::Template(name),
+                [FileChangeAction::Insert(Insertable::Code(code))],
+            ) => return write!(f, "TEMPLATE {} {}", name, qml_stream_to_string(code)),
+            (ObjectToChange::NewFile(file), [FileChangeAction::Insert(Insertable::Code(code))]) => {
+                return write!(f, "CREATE {} {}", file, qml_stream_to_string(code))
+            }
+            (ObjectToChange::Redirect { .. }, _) => return write!(f, "{}", self.destination),
+            (ObjectToChange::Slot(_), _) => "END SLOT",
+            _ => "END AFFECT",
+        };
+
+        write!(f, "{}", self.destination)?;
+        let mut depth = 1usize;
+        let mut saved_depths = Vec::new();
+        for action in &self.changes {
+            match action {
+                FileChangeAction::End(_) => depth = depth.saturating_sub(1).max(1),
+                FileChangeAction::PopScope => depth = saved_depths.pop().unwrap_or(1),
+                _ => {}
+            }
+            let indent = "    ".repeat(depth);
+            write!(f, "\n{}", indent)?;
+            write_action(f, action, &indent)?;
+            match action {
+                FileChangeAction::Traverse(_)
+                | FileChangeAction::TraverseFromRoot(_)
+                | FileChangeAction::Replicate(_)
+                | FileChangeAction::ReplicateFromRoot(_) => depth += 1,
+                FileChangeAction::PushScope => saved_depths.push(depth),
+                _ => {}
+            }
+        }
+        write!(f, "\n{}", end)
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct RebuildArgumentReference {
     pub position: usize,
     pub name: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum LocateRebuildActionSelector {
     All,
     Stream(Vec<qml::lexer::TokenType>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct LocateRebuildAction {
     pub location: Location,
     pub selector: LocateRebuildActionSelector,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RemoveRebuildAction {
     Located,
     Stream(Vec<qml::lexer::TokenType>),
@@ -198,20 +946,20 @@ pub enum RemoveRebuildAction {
     UntilEnd,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ReplaceRebuildActionWhat {
     LiteralStream(Vec<qml::lexer::TokenType>),
     Located,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct ReplaceRebuildAction {
     pub what: ReplaceRebuildActionWhat,
     pub new_contents: Vec<qml::lexer::TokenType>,
     pub until_stream: Option<Vec<qml::lexer::TokenType>>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum RebuildInstruction {
     InsertArgument(RebuildArgumentReference),
     RemoveArgument(RebuildArgumentReference),
@@ -223,6 +971,72 @@ pub enum RebuildInstruction {
     Replace(ReplaceRebuildAction),
 }
 
+/// The code within braces, the way it's written in a diff. The code keeps its own formatting, it's
+/// only separated from the braces if it isn't already.
+pub fn qml_stream_to_string(stream: &Vec<qml::lexer::TokenType>) -> String {
+    let code = emit_simple_token_stream(stream);
+    if code.is_empty() {
+        return "{}".to_string();
+    }
+    let open = if code.starts_with(char::is_whitespace) {
+        "{"
+    } else {
+        "{ "
+    };
+    let close = if code.ends_with(char::is_whitespace) {
+        "}"
+    } else {
+        " }"
+    };
+    format!("{}{}{}", open, code, close)
+}
+
+impl Display for RebuildArgumentReference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} AT {}", self.name, self.position)
+    }
+}
+
+impl Display for RebuildInstruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InsertArgument(arg) => write!(f, "INSERT ARGUMENT {}", arg),
+            Self::RemoveArgument(arg) => write!(f, "REMOVE ARGUMENT {}", arg),
+            Self::RenameArgument(arg, name) => write!(f, "RENAME ARGUMENT {} TO {}", arg, name),
+            Self::Locate(locate) => match &locate.selector {
+                LocateRebuildActionSelector::All => write!(f, "LOCATE {} ALL", locate.location),
+                LocateRebuildActionSelector::Stream(stream) => write!(
+                    f,
+                    "LOCATE {} {}",
+                    locate.location,
+                    qml_stream_to_string(stream)
+                ),
+            },
+            Self::Insert(stream) => write!(f, "INSERT {}", qml_stream_to_string(stream)),
+            Self::Remove(RemoveRebuildAction::Located) => f.write_str("REMOVE LOCATED"),
+            Self::Remove(RemoveRebuildAction::Stream(stream)) => {
+                write!(f, "REMOVE {}", qml_stream_to_string(stream))
+            }
+            Self::Remove(RemoveRebuildAction::UntilStream(stream)) => {
+                write!(f, "REMOVE UNTIL {}", qml_stream_to_string(stream))
+            }
+            Self::Remove(RemoveRebuildAction::UntilEnd) => f.write_str("REMOVE UNTIL END"),
+            Self::Replace(replace) => {
+                match &replace.what {
+                    ReplaceRebuildActionWhat::Located => f.write_str("REPLACE LOCATED")?,
+                    ReplaceRebuildActionWhat::LiteralStream(stream) => {
+                        write!(f, "REPLACE {}", qml_stream_to_string(stream))?
+                    }
+                }
+                if let Some(until) = &replace.until_stream {
+                    write!(f, " UNTIL {}", qml_stream_to_string(until))?;
+                }
+                write!(f, " WITH {}", qml_stream_to_string(&replace.new_contents))
+            }
+        }
+    }
+}
+
 fn trim_token_stream(token_stream: &mut Vec<qml::lexer::TokenType>) {
     while let Some(qml::lexer::TokenType::Whitespace(_)) = token_stream.first() {
         token_stream.remove(0);
@@ -259,6 +1073,42 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Reads the name of an enum value - QML requires them to start with an uppercase letter.
+    fn next_enum_value_name(&mut self) -> Result<String> {
+        let name = self.next_id()?;
+        if !name.starts_with(|c: char| c.is_ascii_uppercase()) {
+            return Err(Error::msg(format!(
+                "Enum value {} has to start with an uppercase letter",
+                name
+            )));
+        }
+        Ok(name)
+    }
+
+    fn next_string(&mut self) -> Result<String> {
+        let next = self.next_lex()?;
+        match next {
+            TokenType::String(s) => Ok(unquote(&s).to_string()),
+            next => error_received_expected!(next, "String"),
+        }
+    }
+
+    /// Consumes the hashed name of an `AFFECT`ed file, if the hashed files are deferred.
+    fn next_hashed_file(&mut self) -> Option<Vec<u64>> {
+        if !self.defer_hashed_files {
+            return None;
+        }
+        let next = self
+            .stream
+            .next_if(|e| matches!(e, TokenType::HashedValue(_)))?;
+        match next {
+            TokenType::HashedValue(
+                HashedValue::HashedIdentifier(hash) | HashedValue::HashedString(_, hash),
+            ) => Some(hash),
+            _ => None,
+        }
+    }
+
     fn discard_whitespace(&mut self) {
         loop {
             match self.stream.peek() {
@@ -287,11 +1137,112 @@ impl<'a> Parser<'a> {
         }
     }
 
+    fn read_comparison(&mut self) -> Result<Comparison> {
+        self.discard_whitespace();
+        let first = self.next_lex()?;
+        self.read_comparison_from(first)
+    }
+
+    /// Reads the rest of a comparison, which starts with the `first` symbol.
+    fn read_comparison_from(&mut self, first: TokenType) -> Result<Comparison> {
+        let followed_by_equals = matches!(self.stream.peek(), Some(TokenType::Symbol('=')));
+        let comparison = match (&first, followed_by_equals) {
+            (TokenType::Symbol('='), true) => Comparison::Equal,
+            (TokenType::Symbol('!'), true) => Comparison::NotEqual,
+            (TokenType::Symbol('>'), true) => Comparison::GreaterOrEqual,
+            (TokenType::Symbol('<'), true) => Comparison::LessOrEqual,
+            (TokenType::Symbol('>'), false) => return Ok(Comparison::Greater),
+            (TokenType::Symbol('<'), false) => return Ok(Comparison::Less),
+            _ => return error_received_expected!(first, "Comparison (== / != / > / >= / < / <=)"),
+        };
+        self.stream.next();
+        Ok(comparison)
+    }
+
+    fn read_requirement(&mut self) -> Result<Requirement> {
+        let next = self.next_lex()?;
+        match next {
+            TokenType::Keyword(Keyword::Define) => Ok(Requirement::Define(self.next_id()?)),
+            TokenType::Keyword(Keyword::Env) => {
+                let name = self.next_id()?;
+                let comparison = self.read_comparison()?;
+                let value = self
+                    .next_string_or_id()?
+                    .trim_matches(['"', '\'', '`'])
+                    .into();
+                Ok(Requirement::Env {
+                    name,
+                    comparison,
+                    value,
+                })
+            }
+            _ => error_received_expected!(next, "DEFINE / ENV"),
+        }
+    }
+
+    fn read_metadata(&mut self, metadata: &mut DiffMetadata) -> Result<()> {
+        let key = self.next_lex()?;
+        let field = match &key {
+            TokenType::Identifier(name) if name == "NAME" => &mut metadata.name,
+            TokenType::Identifier(name) if name == "AUTHOR" => &mut metadata.author,
+            TokenType::Keyword(Keyword::Version) => &mut metadata.version,
+            _ => return error_received_expected!(key, "NAME / AUTHOR / VERSION"),
+        };
+        let value = match self.next_string_or_id() {
+            Err(x) => return error_received_expected!(x, "String"),
+            Ok(e) => e,
+        };
+        *field = Some(value.trim_matches(['"', '\'', '`']).into());
+        Ok(())
+    }
+
+    /// Reads a `@name` reference to a selector alias, if there's one.
+    fn read_selector_alias(&mut self) -> Result<Option<NodeTree>> {
+        self.discard_whitespace();
+        if !matches!(self.stream.peek(), Some(TokenType::Unknown('@'))) {
+            return Ok(None);
+        }
+        self.stream.next();
+        let name = self.next_id()?;
+        match self.selector_aliases.get(&name) {
+            Some(tree) => Ok(Some(tree.clone())),
+            None => bail!("Error while parsing: Unknown selector alias @{}", name),
+        }
+    }
+
     pub fn read_node(&mut self) -> Result<NodeSelector> {
+        if let Some(mut tree) = self.read_selector_alias()? {
+            if tree.len() != 1 {
+                bail!(
+                    "Error while parsing: Expected a single node, got the selector alias {}",
+                    tree
+                );
+            }
+            return Ok(tree.0.remove(0));
+        }
+        // Node | Node - the alternatives are tried in order
+        let mut node = self.read_single_node()?;
+        self.discard_whitespace();
+        while let Some(TokenType::Symbol('|')) = self.stream.peek() {
+            self.stream.next();
+            node.alternatives.push(self.read_single_node()?);
+            self.discard_whitespace();
+        }
+
+        Ok(node)
+    }
+
+    fn read_single_node(&mut self) -> Result<NodeSelector> {
         //                         /------------------------------\ /----------------------------------------------------\
         // ObjectName : named # id = property_name = property_value = property name ~ "property value contains this value"
+        // Numeric values can be compared with property_name > value (or >=, <, <=, !=).
         // [...] can be used for grouping.
-        let name = self.next_id()?;
+        // The object name can be left out of selectors made of an id only - `#id`.
+        self.discard_whitespace();
+        let name = match self.stream.peek() {
+            Some(TokenType::Symbol('#')) => String::new(),
+            _ => self.next_id()?,
+        };
         let mut object = NodeSelector::new(name);
         while let Some(TokenType::Symbol(symbol)) = self.stream.peek() {
             match symbol {
@@ -319,7 +1270,7 @@ impl<'a> Parser<'a> {
                     self.stream.next();
                     // Next is the property name
                     let prop_name = self.next_id()?;
-                    // Next should be a symbol - '=' or '~'
+                    // Next should be a symbol - '=', '~' or a numeric comparison
                     let next = self.next_lex()?;
                     match next {
                         TokenType::Symbol('~') => {
@@ -335,10 +1286,25 @@ impl<'a> Parser<'a> {
                             let id = self.next_string_or_id()?;
                             object.props.insert(prop_name, PropRequirement::Equals(id));
                         }
+                        TokenType::Symbol('>' | '<' | '!') => {
+                            let comparison = self.read_comparison_from(next)?;
+                            let value = self.next_string_or_id()?;
+                            let Some(number) = value.parse().ok().filter(|e: &f64| e.is_finite())
+                            else {
+                                bail!(
+                                    "Property {} can only be compared with a number, got {}",
+                                    prop_name,
+                                    value
+                                );
+                            };
+                            object
+                                .props
+                                .insert(prop_name, PropRequirement::Compare(comparison, number));
+                        }
                         _ => return error_received_expected!(next, "Property value condition"),
                     }
                 }
-                '>' => break, // Tree.
+                '>' | '|' => break, // Tree / alternative.
                 _ => return error_received_expected!(self.stream.peek(), "Property match symbol"),
             }
         }
@@ -346,17 +1312,49 @@ impl<'a> Parser<'a> {
         Ok(object)
     }
 
+    /// Consumes the `ROOT` keyword, or the leading `/` of an absolute selector (`/Rectangle > Item`),
+    /// which make the selector resolve from the root of the file instead of the current root.
+    fn read_root_marker(&mut self) -> bool {
+        self.discard_whitespace();
+        match self.stream.peek_mut() {
+            Some(TokenType::Keyword(Keyword::Root)) => {
+                self.stream.next();
+                true
+            }
+            // The lexer reads `/` as a part of the identifier following it, unless it's hashed.
+            Some(TokenType::Identifier(id)) if id == "/" => {
+                self.stream.next();
+                true
+            }
+            Some(TokenType::Identifier(id)) if id.starts_with('/') => {
+                id.remove(0);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reads a node, or all the nodes of a `@name` selector alias.
+    fn read_tree_segment(&mut self, nodes: &mut Vec<NodeSelector>) -> Result<()> {
+        match self.read_selector_alias()? {
+            Some(tree) => nodes.extend(tree.0),
+            None => nodes.push(self.read_node()?),
+        }
+        Ok(())
+    }
+
     pub fn read_tree(&mut self) -> Result<NodeTree> {
         // Node > Node
-        let mut nodes = vec![self.read_node()?];
+        let mut nodes = Vec::new();
+        self.read_tree_segment(&mut nodes)?;
         self.discard_whitespace();
         while let Some(TokenType::Symbol('>')) = self.stream.peek() {
             self.stream.next();
-            nodes.push(self.read_node()?);
+            self.read_tree_segment(&mut nodes)?;
             self.discard_whitespace();
         }
 
-        Ok(nodes)
+        Ok(NodeTree(nodes))
     }
 
     fn read_argument_reference(&mut self) -> Result<RebuildArgumentReference> {
@@ -403,9 +1401,14 @@ impl<'a> Parser<'a> {
 
                     Keyword::Affect
                     | Keyword::Traverse
+                    | Keyword::Transform
+                    | Keyword::Translate
+                    | Keyword::Using
+                    | Keyword::String
                     | Keyword::Assert
                     | Keyword::Template
                     | Keyword::Import
+                    | Keyword::Pragma
                     | Keyword::Multiple
                     | Keyword::Slot
                     | Keyword::Load
@@ -415,6 +1418,13 @@ impl<'a> Parser<'a> {
                     | Keyword::All
                     | Keyword::After
                     | Keyword::Before
+                    | Keyword::Anchor
+                    | Keyword::Function
+                    | Keyword::Signal
+                    | Keyword::Property
+                    | Keyword::Root
+                    | Keyword::Not
+                    | Keyword::Else
                     | Keyword::Until
                     | Keyword::Argument
                     | Keyword::At
@@ -422,6 +1432,26 @@ impl<'a> Parser<'a> {
                     | Keyword::Rebuild
                     | Keyword::Replicate
                     | Keyword::Version
+                    | Keyword::Require
+                    | Keyword::Meta
+                    | Keyword::Priority
+                    | Keyword::Env
+                    | Keyword::Define
+                    | Keyword::Create
+                    | Keyword::Redirect
+                    | Keyword::Push
+                    | Keyword::Pop
+                    | Keyword::Scope
+                    | Keyword::Local
+                    | Keyword::Sorted
+                    | Keyword::By
+                    | Keyword::Add
+                    | Keyword::Singleton
+                    | Keyword::Plugin
+                    | Keyword::Entry
+                    | Keyword::Suffix
+                    | Keyword::Propagate
+                    | Keyword::Value
                     | Keyword::Redefine => {
                         return error_received_expected!(kw, "Rebuild directive keyword");
                     }
@@ -652,7 +1682,33 @@ impl<'a> Parser<'a> {
                         alias,
                     }))
                 }
+                Keyword::Pragma => {
+                    let name = self.next_id()?;
+                    self.discard_whitespace();
+                    let value = match self.stream.peek() {
+                        Some(TokenType::Identifier(id)) => Some(id.clone()),
+                        _ => None,
+                    };
+                    if value.is_some() {
+                        self.stream.next();
+                    }
+                    Ok(FileChangeAction::AddPragma(PragmaAction { name, value }))
+                }
                 Keyword::Rename => {
+                    self.discard_whitespace();
+                    if let Some(TokenType::Keyword(Keyword::Value)) = self.stream.peek() {
+                        // RENAME VALUE <name> TO <new name>
+                        self.stream.next();
+                        let name = self.next_enum_value_name()?;
+                        let next = self.next_lex()?;
+                        if next != TokenType::Keyword(Keyword::To) {
+                            return error_received_expected!(next, "TO");
+                        }
+                        return Ok(FileChangeAction::EnumValue(EnumValueAction::Rename(
+                            name,
+                            self.next_enum_value_name()?,
+                        )));
+                    }
                     let node = self.read_tree()?;
                     self.discard_whitespace();
                     let next = self.next_lex()?;
@@ -661,9 +1717,59 @@ impl<'a> Parser<'a> {
                         _ => return error_received_expected!(next, "TO"),
                     }
                     let name = self.next_string_or_id()?;
+                    self.discard_whitespace();
+                    let propagate = matches!(
+                        self.stream.peek(),
+                        Some(TokenType::Keyword(Keyword::Propagate))
+                    );
+                    if propagate {
+                        self.stream.next();
+                    }
                     Ok(FileChangeAction::Rename(RenameAction {
                         name_to: name,
                         selector: node,
+                        propagate,
+                    }))
+                }
+                Keyword::Transform => {
+                    // TRANSFORM <tree> WITH { <property> = <expression>; ... }
+                    let selector = self.read_tree()?;
+                    let next = self.next_lex()?;
+                    if next != TokenType::Keyword(Keyword::With) {
+                        return error_received_expected!(next, "WITH");
+                    }
+                    match self.next_lex()? {
+                        TokenType::QMLCode {
+                            qml_code: script,
+                            stream_character: None,
+                        } => Ok(FileChangeAction::Transform(TransformAction {
+                            selector,
+                            assignments: parse_script(&script).map_err(|error| {
+                                Error::msg(format!("Invalid TRANSFORM script: {}", error))
+                            })?,
+                            script,
+                        })),
+                        next => error_received_expected!(next, "{ script }"),
+                    }
+                }
+                Keyword::Translate => {
+                    // TRANSLATE USING <catalog>
+                    let next = self.next_lex()?;
+                    if next != TokenType::Keyword(Keyword::Using) {
+                        return error_received_expected!(next, "USING");
+                    }
+                    let path = unquote(&self.read_path()?).to_string();
+                    let (full_path, _, contents) = self.read_loaded_file(&path)?;
+                    let catalog = TranslationCatalog::parse(&path, &contents).map_err(|error| {
+                        Error::msg(format!(
+                            "Cannot read the translation catalog {}: {}",
+                            full_path.display(),
+                            error
+                        ))
+                    })?;
+                    Ok(FileChangeAction::Translate(TranslateAction {
+                        path,
+                        catalog: Arc::new(catalog),
                     }))
                 }
                 Keyword::Insert => {
@@ -691,6 +1797,50 @@ impl<'a> Parser<'a> {
                         TokenType::Keyword(Keyword::Slot) => {
                             Ok(FileChangeAction::Insert(Insertable::Slot(self.next_id()?)))
                         }
+                        // Slots can only hold QML code.
+                        TokenType::Keyword(Keyword::Value) if !in_slot => {
+                            // INSERT VALUE <name> [= <value>]
+                            let name = self.next_enum_value_name()?;
+                            self.discard_whitespace();
+                            let value = if let Some(TokenType::Symbol('=')) = self.stream.peek() {
+                                self.stream.next();
+                                let value = self.next_id()?;
+                                let number = value.strip_prefix("0x").map_or_else(
+                                    || value.parse::<i64>(),
+                                    |e| i64::from_str_radix(e, 16),
+                                );
+                                if number.is_err() {
+                                    return Err(Error::msg(format!(
+                                        "The value of {} has to be an integer, got {}",
+                                        name, value
+                                    )));
+                                }
+                                Some(value)
+                            } else {
+                                None
+                            };
+                            Ok(FileChangeAction::EnumValue(EnumValueAction::Insert(
+                                name, value,
+                            )))
+                        }
+                        TokenType::Keyword(Keyword::Sorted) => {
+                            // INSERT SORTED BY <property> { QML }
+                            let next = self.next_lex()?;
+                            if next != TokenType::Keyword(Keyword::By) {
+                                return error_received_expected!(next, "BY");
+                            }
+                            let property = self.next_id()?;
+                            match self.next_lex()? {
+                                TokenType::QMLCode {
+                                    qml_code: code,
+                                    stream_character: _,
+                                } => Ok(FileChangeAction::InsertSorted(InsertSortedAction {
+                                    property,
+                                    code,
+                                })),
+                                next => error_received_expected!(next, "QML code"),
+                            }
+                        }
                         TokenType::QMLCode {
                             qml_code: code,
                             stream_character: _,
@@ -700,11 +1850,43 @@ impl<'a> Parser<'a> {
                 }
                 _ if in_slot => error_received_expected!(kw, "INSERT"),
 
+                Keyword::Local => {
+                    // LOCAL SLOT <name> ... END SLOT
+                    let next = self.next_lex()?;
+                    if next != TokenType::Keyword(Keyword::Slot) {
+                        return error_received_expected!(next, "SLOT");
+                    }
+                    let name = self.next_id()?;
+                    let mut contents = Vec::new();
+                    loop {
+                        self.discard_whitespace();
+                        if let Some(TokenType::Keyword(Keyword::End)) = self.stream.peek() {
+                            self.stream.next();
+                            let next = self.next_lex()?;
+                            if next != TokenType::Keyword(Keyword::Slot) {
+                                return error_received_expected!(next, "END SLOT");
+                            }
+                            break;
+                        }
+                        contents.push(self.read_next_instruction(true)?);
+                    }
+                    Ok(FileChangeAction::LocalSlot(name, contents))
+                }
+
                 Keyword::Affect
                 | Keyword::After
                 | Keyword::All
                 | Keyword::Template
+                | Keyword::String
+                | Keyword::Using
                 | Keyword::Before
+                | Keyword::Anchor
+                | Keyword::Function
+                | Keyword::Signal
+                | Keyword::Property
+                | Keyword::Root
+                | Keyword::Not
+                | Keyword::Else
                 | Keyword::Load
                 | Keyword::External
                 | Keyword::To
@@ -714,9 +1896,47 @@ impl<'a> Parser<'a> {
                 | Keyword::Until
                 | Keyword::Located
                 | Keyword::Version
+                | Keyword::Require
+                | Keyword::Meta
+                | Keyword::Priority
+                | Keyword::Env
+                | Keyword::Define
+                | Keyword::Create
+                | Keyword::Redirect
+                | Keyword::Scope
+                | Keyword::Sorted
+                | Keyword::By
+                | Keyword::Singleton
+                | Keyword::Plugin
+                | Keyword::Entry
+                | Keyword::Suffix
+                | Keyword::Propagate
+                | Keyword::Value
                 | Keyword::At => error_received_expected!(kw, "Directive keyword"),
 
-                Keyword::Assert => Ok(FileChangeAction::Assert(self.read_tree()?)),
+                Keyword::Assert => {
+                    // ASSERT [NOT] <tree> [ELSE "message"]
+                    self.discard_whitespace();
+                    let negated =
+                        matches!(self.stream.peek(), Some(TokenType::Keyword(Keyword::Not)));
+                    if negated {
+                        self.stream.next();
+                    }
+                    let tree = self.read_tree()?;
+                    self.discard_whitespace();
+                    let message =
+                        if let Some(TokenType::Keyword(Keyword::Else)) = self.stream.peek() {
+                            self.stream.next();
+                            Some(unquote(&self.next_string_or_id()?).to_string())
+                        } else {
+                            None
+                        };
+                    Ok(FileChangeAction::Assert(AssertAction {
+                        tree,
+                        negated,
+                        message,
+                    }))
+                }
                 Keyword::End => {
                     let next = self.next_lex()?;
                     match next {
@@ -734,6 +1954,8 @@ impl<'a> Parser<'a> {
                     // LOCATE AFTER ALL
                     // LOCATE BEFORE ALL
                     // LOCATE BEFORE <Selector>
+                    // LOCATE AFTER ANCHOR <name>
+                    // LOCATE AFTER FUNCTION / SIGNAL / PROPERTY <name>
                     let next = self.next_lex()?;
                     let location = match next {
                         TokenType::Keyword(Keyword::After) => Location::After,
@@ -743,21 +1965,119 @@ impl<'a> Parser<'a> {
                     self.discard_whitespace();
                     let peek = self.stream.peek();
                     let selector = match peek {
-                        Some(TokenType::Identifier(_)) => LocationSelector::Tree(self.read_tree()?),
+                        Some(TokenType::Identifier(_) | TokenType::Unknown('@')) => {
+                            LocationSelector::Tree(self.read_tree()?)
+                        }
                         Some(TokenType::Keyword(Keyword::All)) => {
                             self.stream.next();
                             LocationSelector::All
                         }
-                        _ => return error_received_expected!(peek, "ALL / tree"),
+                        Some(TokenType::Keyword(Keyword::Anchor)) => {
+                            self.stream.next();
+                            LocationSelector::Anchor(self.next_id()?)
+                        }
+                        Some(TokenType::Keyword(
+                            keyword @ (Keyword::Function | Keyword::Signal | Keyword::Property),
+                        )) => {
+                            let kind = match keyword {
+                                Keyword::Function => MemberKind::Function,
+                                Keyword::Signal => MemberKind::Signal,
+                                _ => MemberKind::Property,
+                            };
+                            self.stream.next();
+                            LocationSelector::Member(kind, self.next_id()?)
+                        }
+                        _ => {
+                            return error_received_expected!(
+                                peek,
+                                "ALL / ANCHOR / FUNCTION / SIGNAL / PROPERTY / tree"
+                            )
+                        }
                     };
                     Ok(FileChangeAction::Locate(LocateAction {
                         location,
                         selector,
                     }))
                 }
-                Keyword::Remove => Ok(FileChangeAction::Remove(self.read_node()?)),
+                Keyword::Remove => {
+                    self.discard_whitespace();
+                    if let Some(TokenType::Keyword(Keyword::Entry)) = self.stream.peek() {
+                        self.stream.next();
+                        Ok(FileChangeAction::RemoveQmlDirEntry(self.next_id()?))
+                    } else if let Some(TokenType::Keyword(Keyword::Value)) = self.stream.peek() {
+                        self.stream.next();
+                        Ok(FileChangeAction::EnumValue(EnumValueAction::Remove(
+                            self.next_enum_value_name()?,
+                        )))
+                    } else {
+                        Ok(FileChangeAction::Remove(self.read_node()?))
+                    }
+                }
+                Keyword::Add => {
+                    // ADD SINGLETON <name> <version> <file>
+                    // ADD PLUGIN <name> [path]
+                    let next = self.next_lex()?;
+                    match next {
+                        TokenType::Keyword(Keyword::Singleton) => {
+                            Ok(FileChangeAction::AddQmlDirEntry(QmlDirEntry::Singleton {
+                                name: self.next_id()?,
+                                version: self.next_id()?,
+                                file: self.read_path()?,
+                            }))
+                        }
+                        TokenType::Keyword(Keyword::Plugin) => {
+                            let name = self.next_id()?;
+                            self.discard_whitespace();
+                            let path = match self.stream.peek() {
+                                Some(TokenType::Identifier(_)) | Some(TokenType::String(_)) => {
+                                    Some(self.read_path()?)
+                                }
+                                _ => None,
+                            };
+                            Ok(FileChangeAction::AddQmlDirEntry(QmlDirEntry::Plugin {
+                                name,
+                                path,
+                            }))
+                        }
+                        _ => error_received_expected!(next, "SINGLETON / PLUGIN"),
+                    }
+                }
                 Keyword::Multiple => Ok(FileChangeAction::AllowMultiple),
+                Keyword::Push | Keyword::Pop => {
+                    let next = self.next_lex()?;
+                    match next {
+                        TokenType::Keyword(Keyword::Scope) if kw == Keyword::Push => {
+                            Ok(FileChangeAction::PushScope)
+                        }
+                        TokenType::Keyword(Keyword::Scope) => Ok(FileChangeAction::PopScope),
+                        _ => error_received_expected!(next, "SCOPE"),
+                    }
+                }
                 Keyword::Replace => {
+                    self.discard_whitespace();
+                    if let Some(TokenType::Keyword(Keyword::String)) = self.stream.peek() {
+                        // REPLACE STRING "<from>" WITH "<to>" [ALL]
+                        self.stream.next();
+                        let from = self.next_string()?;
+                        let next = self.next_lex()?;
+                        if next != TokenType::Keyword(Keyword::With) {
+                            return error_received_expected!(next, "WITH");
+                        }
+                        let to = self.next_string()?;
+                        if from.is_empty() {
+                            return Err(Error::msg("Cannot REPLACE STRING an empty string"));
+                        }
+                        self.discard_whitespace();
+                        let all = self.stream.peek() == Some(&TokenType::Keyword(Keyword::All));
+                        if all {
+                            self.stream.next();
+                        }
+                        return Ok(FileChangeAction::ReplaceString(ReplaceStringAction {
+                            from,
+                            to,
+                            all,
+                        }));
+                    }
                     let node = self.read_tree()?;
                     self.discard_whitespace();
                     let next = self.next_lex()?;
@@ -783,8 +2103,20 @@ impl<'a> Parser<'a> {
                         _ => error_received_expected!(next, "QML code / SLOT <slot>"),
                     }
                 }
-                Keyword::Traverse => Ok(FileChangeAction::Traverse(self.read_tree()?)),
-                Keyword::Replicate => Ok(FileChangeAction::Replicate(self.read_tree()?)),
+                Keyword::Traverse => {
+                    if self.read_root_marker() {
+                        Ok(FileChangeAction::TraverseFromRoot(self.read_tree()?))
+                    } else {
+                        Ok(FileChangeAction::Traverse(self.read_tree()?))
+                    }
+                }
+                Keyword::Replicate => {
+                    if self.read_root_marker() {
+                        Ok(FileChangeAction::ReplicateFromRoot(self.read_tree()?))
+                    } else {
+                        Ok(FileChangeAction::Replicate(self.read_tree()?))
+                    }
+                }
             }
         } else {
             error_received_expected!(next, "Directive keyword")
@@ -819,57 +2151,154 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Lists the paths a `LOAD`ed file can be found at, along with the root paths
+    /// files loaded from them should use - first relative to the current file, then
+    /// relative to the top-level file.
+    fn load_candidates(&self, file: &str) -> Result<Vec<(PathBuf, Option<String>)>> {
+        if is_qrc_path(file) {
+            if self.external_loader.is_none() {
+                bail!("Cannot load {} - no external loader supported!", file);
+            }
+            return Ok(vec![(PathBuf::from(file), None)]);
+        }
+        let new_path = Path::new(file);
+        if new_path.is_absolute() {
+            bail!("Cannot load files using absolute paths!");
+        }
+        let mut candidates = Vec::new();
+        for root in [&self.root_path, &self.pack_root_path]
+            .into_iter()
+            .flatten()
+        {
+            let full_path = Path::new(root).join(new_path);
+            if candidates.iter().any(|(e, _)| e == &full_path) {
+                continue;
+            }
+            let moved_root = full_path
+                .parent()
+                .map(|e| e.to_string_lossy().to_string())
+                .unwrap_or_else(|| root.clone());
+            candidates.push((full_path, Some(moved_root)));
+        }
+        if candidates.is_empty() {
+            // Without a root path, only the external loader can provide the file.
+            if self.external_loader.is_none() {
+                bail!("Cannot load a file if no root path set!");
+            }
+            candidates.push((PathBuf::from(file), None));
+        }
+        Ok(candidates)
+    }
+
+    fn read_load_candidate(&self, full_path: &Path) -> Option<String> {
+        let provided_contents = self.external_loader.as_ref().and_then(|loader| {
+            loader
+                .borrow_mut()
+                .load_contents(&full_path.to_string_lossy())
+        });
+        provided_contents.or_else(|| std::fs::read_to_string(full_path).ok())
+    }
+
+    /// Reads a file referred to by a diff the way `LOAD` does. Returns its full path, the root path
+    /// the files it loads should use, and its contents.
+    fn read_loaded_file(&self, file: &str) -> Result<(PathBuf, Option<String>, String)> {
+        let candidates = self.load_candidates(file)?;
+        let found = candidates.iter().find_map(|(full_path, moved_root)| {
+            self.read_load_candidate(full_path)
+                .map(|contents| (full_path.clone(), moved_root.clone(), contents))
+        });
+        let Some(found) = found else {
+            bail!(
+                "Cannot read file {} (tried {})",
+                file,
+                candidates
+                    .iter()
+                    .map(|e| e.0.to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        };
+        Ok(found)
+    }
+
     fn load_from(
         &mut self,
         file: &str,
         output: &mut Vec<Change>,
         versions_allowed: Option<Vec<String>>,
+        requirements: Vec<Requirement>,
+        metadata: Arc<DiffMetadata>,
+        ordering: Arc<DiffOrdering>,
     ) -> Result<()> {
-        let (root, full_path) = self.get_full_path_and_root_of(file)?;
-        let file_contents = match std::fs::read_to_string(&full_path) {
-            Ok(e) => e,
-            Err(_) => {
-                return Err(Error::msg(format!(
-                    "Cannot read file {}",
-                    full_path.to_string_lossy()
-                )))
+        let (full_path, moved_root, file_contents) = self.read_loaded_file(file)?;
+        if let Some(loader) = &self.external_loader {
+            loader
+                .borrow_mut()
+                .verify_contents(&full_path.to_string_lossy(), &file_contents)?;
+        }
+        let key = load_chain_key(&full_path);
+        if self.load_chain.contains(&key) {
+            bail!(
+                "LOAD cycle detected: {} -> {}",
+                self.load_chain.join(" -> "),
+                key
+            );
+        }
+        let mut load_chain = self.load_chain.clone();
+        load_chain.push(key);
+        self.limits.check_load_depth(&load_chain)?;
+        let tokens = self
+            .limits
+            .tokenize_diff(file_contents, &full_path.to_string_lossy())?;
+        let tokens = if let Some(hashtab) = self.hashtab {
+            if self.require_hashed {
+                ensure_hashed(&tokens, hashtab, &full_path.to_string_lossy())?;
             }
-        };
-        let moved_root = if let Some(e) = Path::new(file).parent() {
-            String::from(Path::new(root).join(e).to_string_lossy())
+            resolve_diff_hashes(
+                hashtab,
+                tokens,
+                &full_path.to_string_lossy(),
+                self.defer_hashed_files,
+            )?
         } else {
-            root.to_string()
+            tokens
         };
         let mut parser = Self::new(
-            Box::new({
-                if let Some(hashtab) = self.hashtab {
-                    Lexer::new(StringCharacterTokenizer::new(file_contents))
-                        .map(|e| {
-                            diff_hash_remapper(hashtab, e, &full_path.to_string_lossy()).unwrap()
-                        })
-                        .collect::<Vec<TokenType>>()
-                } else {
-                    Lexer::new(StringCharacterTokenizer::new(file_contents))
-                        .collect::<Vec<TokenType>>()
-                }
-                .into_iter()
-            }),
-            Some(moved_root),
+            Box::new(tokens.into_iter()),
+            moved_root.clone(),
             Arc::from(full_path.to_string_lossy().to_string()),
             self.hashtab,
             self.external_loader.clone(),
         );
-        output.extend(parser.parse(versions_allowed.clone())?);
+        parser.pack_root_path = self.pack_root_path.clone();
+        parser.require_hashed = self.require_hashed;
+        parser.defer_hashed_files = self.defer_hashed_files;
+        parser.limits = self.limits;
+        parser.load_chain = load_chain;
+        parser.metadata = metadata;
+        parser.ordering = ordering;
+        parser.selector_aliases = self.selector_aliases.clone();
+        output.extend(parser.parse(versions_allowed, requirements)?);
         Ok(())
     }
 
-    pub fn parse(&mut self, parent_versions_allowed: Option<Vec<String>>) -> Result<Vec<Change>> {
+    pub fn parse(
+        &mut self,
+        parent_versions_allowed: Option<Vec<String>>,
+        parent_requirements: Vec<Requirement>,
+    ) -> Result<Vec<Change>> {
         let mut output = Vec::default();
         // If we're loading a file using the `LOAD` keyword, the allowed versions should be propagated from the
         // parent ONLY. Do not allow defining more allowed versions in non-root files.
         // (Unless the root file does not provide any supported versions' list. Then allow it.)
         let allow_new_version_definitions = parent_versions_allowed.is_none();
         let mut versions_allowed = parent_versions_allowed;
+        // Unlike versions, requirements can only make the set of allowed environments narrower, so
+        // LOADed files are free to add their own.
+        let mut requirements = parent_requirements;
+        // LOADed files share the metadata of their parent, but can override it for their own changes.
+        let mut metadata = self.metadata.clone();
+        let mut ordering = self.ordering.clone();
 
         let mut current_working_file: Option<ObjectToChange> = None;
         let mut current_instructions = Vec::new();
@@ -914,6 +2343,9 @@ impl<'a> Parser<'a> {
                             changes: take(&mut current_instructions),
                             destination: current_working_file.take().unwrap(),
                             versions_allowed: versions_allowed.clone(),
+                            requirements: requirements.clone(),
+                            metadata: metadata.clone(),
+                            ordering: ordering.clone(),
                         });
                     }
                     _ => current_instructions.push(self.read_next_instruction(in_slot)?),
@@ -947,6 +2379,61 @@ impl<'a> Parser<'a> {
                             }
                         };
                     }
+                    TokenType::Keyword(Keyword::Require) if has_seen_non_version_statements => {
+                        return error_received_expected!(next, "AFFECT / SLOT / TEMPLATE statement (REQUIRE statements only allowed at the beginning of file!)");
+                    }
+                    TokenType::Keyword(Keyword::Require) => {
+                        requirements.push(self.read_requirement()?);
+                    }
+                    TokenType::Keyword(Keyword::Meta) if has_seen_non_version_statements => {
+                        return error_received_expected!(next, "AFFECT / SLOT / TEMPLATE statement (META statements only allowed at the beginning of file!)");
+                    }
+                    TokenType::Keyword(Keyword::Meta) => {
+                        self.read_metadata(Arc::make_mut(&mut metadata))?;
+                    }
+                    TokenType::Keyword(Keyword::Priority) | TokenType::Keyword(Keyword::After)
+                        if has_seen_non_version_statements =>
+                    {
+                        return error_received_expected!(next, "AFFECT / SLOT / TEMPLATE statement (PRIORITY and AFTER DIFF statements only allowed at the beginning of file!)");
+                    }
+                    TokenType::Keyword(Keyword::Priority) => {
+                        let priority = self.next_string_or_id()?;
+                        Arc::make_mut(&mut ordering).priority = priority
+                            .trim_matches(['"', '\'', '`'])
+                            .parse()
+                            .map_err(|_| {
+                                Error::msg(format!(
+                                    "Error while parsing: Invalid priority {} - expected an integer",
+                                    priority
+                                ))
+                            })?;
+                    }
+                    TokenType::Keyword(Keyword::After) => {
+                        match self.next_lex()? {
+                            TokenType::Identifier(id) if id == "DIFF" => {}
+                            next => return error_received_expected!(next, "AFTER DIFF <name / path>"),
+                        }
+                        let diff = self.next_string_or_id()?;
+                        Arc::make_mut(&mut ordering)
+                            .after
+                            .push(diff.trim_matches(['"', '\'', '`']).into());
+                    }
+                    TokenType::Keyword(Keyword::Define) => {
+                        // DEFINE SELECTOR <name> = <tree>
+                        match self.next_lex()? {
+                            TokenType::Identifier(id) if id == "SELECTOR" => {}
+                            next => return error_received_expected!(next, "DEFINE SELECTOR <name> = <tree>"),
+                        }
+                        let name = self.next_id()?;
+                        match self.next_lex()? {
+                            TokenType::Symbol('=') => {}
+                            next => return error_received_expected!(next, "="),
+                        }
+                        let tree = self.read_tree()?;
+                        if self.selector_aliases.insert(name.clone(), tree).is_some() {
+                            bail!("Error while parsing: The selector alias @{} is already defined", name);
+                        }
+                    }
                     TokenType::Keyword(Keyword::Affect) => {
                         has_seen_non_version_statements = true;
                         self.discard_whitespace();
@@ -956,14 +2443,23 @@ impl<'a> Parser<'a> {
                             output.push(Change {
                                 source: self.source_name.clone(),
                                 changes: vec![FileChangeAction::Rebuild(RebuildAction {
-                                    selector: NodeSelector { object_name: "root".to_string(), named: None, props: Default::default() },
+                                    selector: NodeSelector::new("root".to_string()),
                                     redefine: false,
                                     actions: self.read_rebuild_instructions(false)?,
                                 })],
                                 destination: file_to_change,
-                                versions_allowed: versions_allowed.clone()
+                                versions_allowed: versions_allowed.clone(),
+                                requirements: requirements.clone(),
+                                metadata: metadata.clone(),
+                                ordering: ordering.clone(),
                             });
                             continue;
+                        } else if let Some(TokenType::Keyword(Keyword::Suffix)) = self.stream.peek() {
+                            let _ = self.next_lex();
+                            current_working_file =
+                                Some(ObjectToChange::FileSuffix(self.next_string_or_id()?));
+                        } else if let Some(hash) = self.next_hashed_file() {
+                            current_working_file = Some(ObjectToChange::HashedFile(hash));
                         } else {
                             current_working_file =
                                 Some(ObjectToChange::File(self.next_string_or_id()?));
@@ -985,6 +2481,50 @@ impl<'a> Parser<'a> {
                             destination: ObjectToChange::Template(name),
                             changes: vec![FileChangeAction::Insert(Insertable::Code(data))],
                             versions_allowed: versions_allowed.clone(),
+                            requirements: requirements.clone(),
+                            metadata: metadata.clone(),
+                            ordering: ordering.clone(),
+                        });
+                    }
+                    TokenType::Keyword(Keyword::Create) => {
+                        has_seen_non_version_statements = true;
+                        let file = self.next_string_or_id()?;
+                        let data = match self.next_lex()? {
+                            TokenType::QMLCode {
+                                qml_code,
+                                stream_character: _,
+                            } => qml_code,
+                            next => return error_received_expected!(next, "CREATE <path> { QML }"),
+                        };
+                        output.push(Change {
+                            source: self.source_name.clone(),
+                            destination: ObjectToChange::NewFile(file),
+                            changes: vec![FileChangeAction::Insert(Insertable::Code(data))],
+                            versions_allowed: versions_allowed.clone(),
+                            requirements: requirements.clone(),
+                            metadata: metadata.clone(),
+                            ordering: ordering.clone(),
+                        });
+                    }
+                    TokenType::Keyword(Keyword::Redirect) => {
+                        has_seen_non_version_statements = true;
+                        let original = self.next_string_or_id()?;
+                        match self.next_lex()? {
+                            TokenType::Keyword(Keyword::To) => {}
+                            next => return error_received_expected!(next, "REDIRECT <path> TO <path>"),
+                        }
+                        let redirected = self.next_string_or_id()?;
+                        output.push(Change {
+                            source: self.source_name.clone(),
+                            destination: ObjectToChange::Redirect {
+                                original,
+                                redirected,
+                            },
+                            changes: Vec::new(),
+                            versions_allowed: versions_allowed.clone(),
+                            requirements: requirements.clone(),
+                            metadata: metadata.clone(),
+                            ordering: ordering.clone(),
                         });
                     }
                     TokenType::Keyword(Keyword::Slot) => {
@@ -1006,14 +2546,21 @@ impl<'a> Parser<'a> {
                             self.load_external(&path)?;
                         } else {
                             let path = self.read_path()?;
-                            self.load_from(&path, &mut output, versions_allowed.clone())?;
+                            self.load_from(
+                                &path,
+                                &mut output,
+                                versions_allowed.clone(),
+                                requirements.clone(),
+                                metadata.clone(),
+                                ordering.clone(),
+                            )?;
                         }
                     }
 
                     _ => {
                         return error_received_expected!(
                             next,
-                            "AFFECT / SLOT / VERSION / TEMPLATE statement"
+                            "AFFECT / SLOT / VERSION / REQUIRE / META / PRIORITY / DEFINE SELECTOR / TEMPLATE / CREATE / REDIRECT statement"
                         )
                     }
                 }
@@ -1026,6 +2573,9 @@ impl<'a> Parser<'a> {
                 destination: current_working_file.take().unwrap(),
                 changes: std::mem::take(&mut current_instructions),
                 versions_allowed: versions_allowed.clone(),
+                requirements: requirements.clone(),
+                metadata: metadata.clone(),
+                ordering: ordering.clone(),
             });
         }
 
@@ -1039,12 +2589,590 @@ impl<'a> Parser<'a> {
         hashtab: Option<&'a HashTab>,
         external_loader: Option<Rc<RefCell<Box<dyn ExternalLoader>>>>,
     ) -> Parser<'a> {
+        let load_chain = vec![load_chain_key(Path::new(source_name.as_str()))];
         Parser {
             source_name,
             stream: token_stream.peekable(),
+            pack_root_path: root_path.clone(),
             root_path,
+            load_chain,
             hashtab,
             external_loader,
+            require_hashed: false,
+            defer_hashed_files: false,
+            limits: Limits::default(),
+            metadata: Arc::default(),
+            ordering: Arc::default(),
+            selector_aliases: HashMap::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use super::{
+        has_path_suffix, Comparison, Defines, ExternalLoader, FileChangeAction,
+        LocateRebuildActionSelector, LocationSelector, MemberKind, NodeSelector, NodeTree,
+        ObjectToChange, Parser, PropRequirement, RebuildInstruction, Requirement,
+    };
+    use crate::parser::{
+        common::StringCharacterTokenizer,
+        diff::{
+            emitter::emit_token_stream,
+            lexer::{Lexer, TokenType},
+        },
+        qml,
+    };
+    use crate::{
+        hash::hash,
+        hashtab::HashTab,
+        limits::Limits,
+        parser::diff::hash_processor::{resolve_hashed_file_name, resolve_hashed_files},
+        slots::Slots,
+        util::common_util::{error_details, parse_diff, ErrorKind},
+    };
+
+    #[test]
+    fn test_node_selector_parsing() {
+        let node = NodeSelector::parse("Button#ok[.text~Save]").unwrap();
+        assert_eq!(node.object_name, "Button");
+        assert!(matches!(node.props.get("id"), Some(PropRequirement::Equals(id)) if id == "ok"));
+        assert!(
+            matches!(node.props.get("text"), Some(PropRequirement::Contains(text)) if text == "Save")
+        );
+
+        let node = NodeSelector::parse("Item:content[!visible].color=red").unwrap();
+        assert_eq!(node.named.as_deref(), Some("content"));
+        assert!(matches!(
+            node.props.get("visible"),
+            Some(PropRequirement::Exists)
+        ));
+        assert_eq!(node.to_string(), "Item:content[.color=red][!visible]");
+
+        let node = NodeSelector::parse("Item[.width>=100][.opacity<0.5]").unwrap();
+        assert!(matches!(
+            node.props.get("width"),
+            Some(PropRequirement::Compare(Comparison::GreaterOrEqual, width)) if *width == 100.0
+        ));
+        assert_eq!(node.to_string(), "Item[.opacity<0.5][.width>=100]");
+        assert!(NodeSelector::parse("Item[.width>wide]").is_err());
+
+        assert!(NodeSelector::parse("Rectangle > Item").is_err());
+        assert!(NodeSelector::parse("Item[.color]").is_err());
+    }
+
+    #[test]
+    fn test_node_tree_parsing() {
+        let tree = NodeTree::parse("Rectangle > Item#content > Text[.text~Hello]").unwrap();
+        assert_eq!(tree.len(), 3);
+        assert_eq!(
+            tree.to_string(),
+            "Rectangle > Item#content > Text[.text~Hello]"
+        );
+        // Re-parsing the displayed form gives the same selector.
+        assert_eq!(
+            NodeTree::parse(&tree.to_string()).unwrap().to_string(),
+            tree.to_string()
+        );
+
+        let tree = NodeTree::parse("Rectangle > ColumnLayout#main|Column#main > Text").unwrap();
+        assert_eq!(tree.len(), 3);
+        assert_eq!(tree[1].alternatives.len(), 1);
+        assert_eq!(tree[1].alternatives[0].object_name, "Column");
+        assert_eq!(
+            tree.to_string(),
+            "Rectangle > ColumnLayout#main | Column#main > Text"
+        );
+
+        let tree = NodeTree::parse("#volumeSlider > Text").unwrap();
+        assert_eq!(tree[0].standalone_id(), Some("volumeSlider"));
+        assert_eq!(tree[1].standalone_id(), None);
+        assert_eq!(tree.to_string(), "#volumeSlider > Text");
+        assert_eq!(
+            NodeTree::parse("Slider#volumeSlider").unwrap()[0].standalone_id(),
+            None
+        );
+
+        assert!(NodeTree::parse("Rectangle >").is_err());
+        assert!(NodeTree::parse("Rectangle Item").is_err());
+        assert!(NodeTree::parse("Rectangle |").is_err());
+        // Malformed selectors are errors, not panics.
+        assert!(NodeTree::parse("Item {").is_err());
+        assert!(NodeTree::parse("Item[.text=\"Save]").is_err());
+        assert!(NodeSelector::parse("Item[.text='Save").is_err());
+        assert!(NodeTree::parse("[[12.]]").is_err());
+    }
+
+    #[test]
+    fn test_path_suffix() {
+        assert!(has_path_suffix("qrc:/ui/Page.qml", "ui/Page.qml"));
+        assert!(has_path_suffix("/usr/share/ui/Page.qml", "/ui/Page.qml"));
+        assert!(has_path_suffix("ui/Page.qml", "ui/Page.qml"));
+        assert!(!has_path_suffix("/ui/MainPage.qml", "Page.qml"));
+        assert!(!has_path_suffix("/ui/Page.qml", ""));
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        let source = "AFFECT /Main.qml\n    REBUILD text\n        LOCATE AFTER STREAM / } else { /\n        INSERT STREAM |\n            foo(\"a\"); // comment\n        |\n    END REBUILD\nEND AFFECT\n";
+        let tokens = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+            .tokenize()
+            .unwrap();
+        assert!(tokens.iter().any(|e| matches!(
+            e,
+            TokenType::QMLCode {
+                stream_character: Some(qml::lexer::TokenType::Symbol('|')),
+                ..
+            }
+        )));
+        // The emitter reproduces the STREAM blocks exactly.
+        assert_eq!(emit_token_stream(tokens.clone()), source);
+
+        let changes = Parser::new(
+            Box::new(tokens.into_iter()),
+            None,
+            Arc::new("test".to_string()),
+            None,
+            None,
+        )
+        .parse(None, Vec::new())
+        .unwrap();
+        let FileChangeAction::Rebuild(rebuild) = &changes[0].changes[0] else {
+            panic!("Expected REBUILD");
+        };
+        let emit = |code: &Vec<qml::lexer::TokenType>| {
+            code.iter().map(|e| e.to_string()).collect::<String>()
+        };
+        assert!(matches!(
+            &rebuild.actions[0],
+            RebuildInstruction::Locate(locate)
+                if matches!(&locate.selector, LocateRebuildActionSelector::Stream(code) if emit(code) == "} else {")
+        ));
+        assert!(matches!(
+            &rebuild.actions[1],
+            RebuildInstruction::Insert(code) if emit(code).trim() == "foo(\"a\"); // comment"
+        ));
+    }
+
+    #[test]
+    fn test_change_display() {
+        let source = "VERSION 1.0\nAFFECT /Main.qml\n    TRAVERSE Item > Rectangle#r\n        LOCATE AFTER ALL\n        INSERT {\n            Text { text: \"hi\" }\n        }\n        REBUILD onClicked\n            INSERT { foo(); }\n        END REBUILD\n    END TRAVERSE\n    IMPORT QtQuick 2.0 Q\n    RENAME Text TO Label PROPAGATE\nEND AFFECT";
+        let tokens = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+            .tokenize()
+            .unwrap();
+        let changes = Parser::new(
+            Box::new(tokens.into_iter()),
+            None,
+            Arc::new("test".to_string()),
+            None,
+            None,
+        )
+        .parse(None, Vec::new())
+        .unwrap();
+        assert_eq!(
+            changes[0].to_string(),
+            "AFFECT /Main.qml (defined by 'test')"
+        );
+        assert_eq!(format!("{:#}", changes[0]), source);
+    }
+
+    #[test]
+    fn test_requirements() {
+        let source = "REQUIRE DEFINE sailfish\nREQUIRE ENV QT_VERSION >= 6.6\nREQUIRE ENV QMLDIFF_TEST_THEME == dark\nAFFECT /Main.qml\nEND AFFECT";
+        let tokens = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+            .tokenize()
+            .unwrap();
+        let changes = Parser::new(
+            Box::new(tokens.into_iter()),
+            None,
+            Arc::new("test".to_string()),
+            None,
+            None,
+        )
+        .parse(None, Vec::new())
+        .unwrap();
+        let requirements = &changes[0].requirements;
+        assert_eq!(
+            requirements,
+            &[
+                Requirement::Define("sailfish".to_string()),
+                Requirement::Env {
+                    name: "QT_VERSION".to_string(),
+                    comparison: Comparison::GreaterOrEqual,
+                    value: "6.6".to_string(),
+                },
+                Requirement::Env {
+                    name: "QMLDIFF_TEST_THEME".to_string(),
+                    comparison: Comparison::Equal,
+                    value: "dark".to_string(),
+                },
+            ]
+        );
+
+        // `-D NAME=VALUE` defines are checked before the environment.
+        std::env::set_var("QMLDIFF_TEST_THEME", "dark");
+        let defines = |entries: &[(&str, &str)]| {
+            entries
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect::<Defines>()
+        };
+        let met = |defines: &Defines| requirements.iter().all(|e| e.is_met(defines));
+        assert!(met(&defines(&[("sailfish", ""), ("QT_VERSION", "6.10")])));
+        assert!(!met(&defines(&[("QT_VERSION", "6.10")])));
+        assert!(!met(&defines(&[("sailfish", ""), ("QT_VERSION", "6.5.3")])));
+        assert!(!met(&defines(&[
+            ("sailfish", ""),
+            ("QT_VERSION", "6.10"),
+            ("QMLDIFF_TEST_THEME", "light")
+        ])));
+        std::env::remove_var("QMLDIFF_TEST_THEME");
+        assert!(!met(&defines(&[("sailfish", ""), ("QT_VERSION", "6.10")])));
+    }
+
+    #[test]
+    fn test_selector_value_display() {
+        let source = r#"AFFECT /Main.qml
+    TRAVERSE Item#ok > State[.name="on"] > Text[.text~'it\'s'][.font=`a "b" \` c`]
+        REMOVE Button[.id=`not an id`][.label="\"quoted\""][.role=`ALL`][.path=a/b.c]
+    END TRAVERSE
+END AFFECT"#;
+        let parse = |source: &str| {
+            let tokens = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+                .tokenize()
+                .unwrap();
+            Parser::new(
+                Box::new(tokens.into_iter()),
+                None,
+                Arc::new("test".to_string()),
+                None,
+                None,
+            )
+            .parse(None, Vec::new())
+            .unwrap()
+        };
+        let changes = parse(source);
+        let FileChangeAction::Traverse(tree) = &changes[0].changes[0] else {
+            panic!("Expected TRAVERSE");
+        };
+        assert_eq!(
+            tree.to_string(),
+            r#"Item#ok > State[.name="on"] > Text[.font=`a "b" \` c`][.text~'it\'s']"#
+        );
+        // The printed diff is read back as the same change.
+        let printed = format!("{:#}", changes[0]);
+        assert!(printed
+            .contains(r#"Button[.id=`not an id`][.label="\"quoted\""][.path=a/b.c][.role=`ALL`]"#));
+        assert_eq!(parse(&printed), changes);
+    }
+
+    #[test]
+    fn test_locate_member() {
+        let source = "AFFECT /Main.qml\n    TRAVERSE Item\n        LOCATE AFTER SIGNAL clicked\n        LOCATE BEFORE PROPERTY count\n    END TRAVERSE\nEND AFFECT\n";
+        let tokens = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+            .tokenize()
+            .unwrap();
+        let changes = Parser::new(
+            Box::new(tokens.into_iter()),
+            None,
+            Arc::new("test".to_string()),
+            None,
+            None,
+        )
+        .parse(None, Vec::new())
+        .unwrap();
+        assert!(matches!(
+            &changes[0].changes[1],
+            FileChangeAction::Locate(locate)
+                if matches!(&locate.selector, LocationSelector::Member(MemberKind::Signal, name) if name == "clicked")
+        ));
+        assert_eq!(
+            changes[0].changes[2].to_string(),
+            "LOCATE BEFORE PROPERTY count"
+        );
+    }
+
+    #[test]
+    fn test_replace_string() {
+        let source = "AFFECT /Main.qml\n    REPLACE STRING \"Acme \\\"Reader\\\"\" WITH 'Foo' ALL\n    REPLACE STRING \"a\" WITH \"\"\nEND AFFECT\n";
+        let tokens = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+            .tokenize()
+            .unwrap();
+        let changes = Parser::new(
+            Box::new(tokens.into_iter()),
+            None,
+            Arc::new("test".to_string()),
+            None,
+            None,
+        )
+        .parse(None, Vec::new())
+        .unwrap();
+        assert!(matches!(
+            &changes[0].changes[0],
+            FileChangeAction::ReplaceString(replace)
+                if replace.from == "Acme \"Reader\"" && replace.to == "Foo" && replace.all
+        ));
+        assert_eq!(
+            changes[0].changes[0].to_string(),
+            "REPLACE STRING \"Acme \\\"Reader\\\"\" WITH \"Foo\" ALL"
+        );
+        assert_eq!(
+            changes[0].changes[1].to_string(),
+            "REPLACE STRING \"a\" WITH \"\""
+        );
+    }
+
+    #[test]
+    fn test_deferred_hashed_files() {
+        let source = format!(
+            "AFFECT [[{}]]\n    TRAVERSE Item\n    END TRAVERSE\nEND AFFECT\nAFFECT [[{}]]\nEND AFFECT\n",
+            hash("Main.qml"),
+            hash("ui/Page.qml")
+        );
+        let hashtab = HashTab::new();
+        let parse = |defer| {
+            parse_diff(
+                None,
+                source.clone(),
+                "test",
+                &hashtab,
+                None,
+                false,
+                defer,
+                &Limits::default(),
+            )
+        };
+        assert!(parse(false).is_err());
+
+        let mut changes = parse(true).unwrap();
+        assert!(matches!(
+            &changes[0].destination,
+            ObjectToChange::HashedFile(_)
+        ));
+        assert!(!changes[0].destination.affects_file("Main.qml"));
+        assert!(resolve_hashed_file_name(&mut changes, "Main.qml"));
+        assert!(changes[0].destination.affects_file("Main.qml"));
+        assert!(!resolve_hashed_file_name(&mut changes, "Other.qml"));
+
+        let hashtab = HashTab::from([(hash("ui/Page.qml"), "ui/Page.qml".to_string())]);
+        assert_eq!(
+            resolve_hashed_files(&mut changes, &hashtab),
+            vec!["ui/Page.qml"]
+        );
+        assert!(changes[1].destination.affects_file("ui/Page.qml"));
+    }
+
+    /// Provides an endless chain of diffs - `N.qmd` loads `N+1.qmd`.
+    struct EndlessLoader;
+    impl ExternalLoader for EndlessLoader {
+        fn load_external(&mut self, _: &str) {}
+        fn load_contents(&mut self, file: &str) -> Option<String> {
+            let index: usize = file
+                .strip_prefix("/packs/")?
+                .strip_suffix(".qmd")?
+                .parse()
+                .ok()?;
+            Some(format!("LOAD {}.qmd\n", index + 1))
         }
     }
+
+    /// Provides the same contents for every loaded file.
+    struct StaticLoader(String);
+    impl ExternalLoader for StaticLoader {
+        fn load_external(&mut self, _: &str) {}
+        fn load_contents(&mut self, _: &str) -> Option<String> {
+            Some(self.0.clone())
+        }
+    }
+
+    #[test]
+    fn test_malformed_loaded_diff() {
+        let parse = |loaded: &str| {
+            parse_diff(
+                Some("/packs".to_string()),
+                "LOAD broken.qmd\n".to_string(),
+                "/packs/main.qmd",
+                &HashTab::new(),
+                Some(Box::new(StaticLoader(loaded.to_string()))),
+                false,
+                false,
+                &Limits::default(),
+            )
+        };
+        let error = parse("AFFECT Main.qml\n    TRAVERSE Item\n        INSERT {\n").unwrap_err();
+        assert!(error.to_string().contains("/packs/broken.qmd"));
+        assert!(format!("{:#}", error).contains("Unterminated QML code block"));
+        let error = parse("AFFECT Main.qml\n    RENAME \"Item TO Text\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("Unterminated string"));
+    }
+
+    #[test]
+    fn test_absolute_load_path() {
+        // Even a loader which could provide the file mustn't be asked for it.
+        let error = parse_diff(
+            Some("/packs".to_string()),
+            "LOAD /packs/other.qmd\n".to_string(),
+            "/packs/main.qmd",
+            &HashTab::new(),
+            Some(Box::new(StaticLoader(String::new()))),
+            false,
+            false,
+            &Limits::default(),
+        )
+        .unwrap_err();
+        assert!(format!("{:#}", error).contains("Cannot load files using absolute paths!"));
+    }
+
+    #[test]
+    fn test_limits() {
+        let parse = |source: &str, limits: &Limits| {
+            parse_diff(
+                Some("/packs".to_string()),
+                source.to_string(),
+                "/packs/0.qmd",
+                &HashTab::new(),
+                Some(Box::new(EndlessLoader)),
+                false,
+                false,
+                limits,
+            )
+        };
+        let error = parse("LOAD 1.qmd\n", &Limits::default()).unwrap_err();
+        assert_eq!(error_details(&error).kind, ErrorKind::Limit);
+        assert!(error.to_string().contains("/packs/32.qmd -> /packs/33.qmd"));
+
+        let source = "AFFECT Main.qml\nEND AFFECT\n";
+        let limits = Limits {
+            max_diff_tokens: 4,
+            ..Limits::default()
+        };
+        assert!(parse(source, &Limits::default()).is_ok());
+        assert!(parse(source, &limits).is_err());
+
+        // The loaded files have to fit within the limit too - the tokens of their QML code count.
+        let limits = Limits {
+            max_diff_tokens: 100,
+            ..Limits::default()
+        };
+        let loaded = format!(
+            "AFFECT Main.qml\nTRAVERSE Item\nLOCATE AFTER ALL\nINSERT {{\n{}}}\nEND TRAVERSE\nEND AFFECT\n",
+            "Item {}\n".repeat(100)
+        );
+        let error = parse_diff(
+            Some("/packs".to_string()),
+            "LOAD big.qmd\n".to_string(),
+            "/packs/main.qmd",
+            &HashTab::new(),
+            Some(Box::new(StaticLoader(loaded))),
+            false,
+            false,
+            &limits,
+        )
+        .unwrap_err();
+        assert_eq!(error_details(&error).kind, ErrorKind::Limit);
+        assert!(error.to_string().contains("/packs/big.qmd"));
+
+        // Slots inserting each other would never stop expanding.
+        let mut changes = parse(
+            "SLOT a\nINSERT SLOT b\nEND SLOT\nSLOT b\nINSERT SLOT a\nEND SLOT\n\
+             AFFECT Main.qml\nTRAVERSE Item\nLOCATE AFTER ALL\nINSERT SLOT a\nEND TRAVERSE\nEND AFFECT\n",
+            &Limits::default(),
+        )
+        .unwrap();
+        let mut slots = Slots::new();
+        slots.update_slots(&mut changes);
+        let error = slots.process_slots(&mut changes).unwrap_err();
+        assert_eq!(error_details(&error).kind, ErrorKind::Limit);
+
+        // Every slot doubles the code of the one it inserts.
+        let mut source = "SLOT s0\nINSERT { x: 1 }\nEND SLOT\n".to_string();
+        for i in 1..24 {
+            source += &format!(
+                "SLOT s{}\nINSERT SLOT s{}\nINSERT SLOT s{}\nEND SLOT\n",
+                i,
+                i - 1,
+                i - 1
+            );
+        }
+        source += "AFFECT Main.qml\nTRAVERSE Item\nLOCATE AFTER ALL\nINSERT SLOT s23\nEND TRAVERSE\nEND AFFECT\n";
+        let mut changes = parse(&source, &Limits::default()).unwrap();
+        let mut slots = Slots::new();
+        slots.update_slots(&mut changes);
+        let error = slots.process_slots(&mut changes).unwrap_err();
+        assert!(error.to_string().contains("expands into more than"));
+    }
+
+    #[test]
+    fn test_unterminated_stream() {
+        let mut lexer = Lexer::new(StringCharacterTokenizer::new(
+            "INSERT STREAM | foo\nEND REBUILD".to_string(),
+        ));
+        let result = loop {
+            match lexer.next_token() {
+                Ok(TokenType::EndOfStream) => break Ok(()),
+                Ok(_) => {}
+                Err(error) => break Err(error),
+            }
+        };
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_selector_aliases() {
+        let parse = |source: &str| {
+            let tokens =
+                Lexer::new(StringCharacterTokenizer::new(source.to_string())).tokenize()?;
+            Parser::new(
+                Box::new(tokens.into_iter()),
+                None,
+                Arc::new("test".to_string()),
+                None,
+                None,
+            )
+            .parse(None, Vec::new())
+        };
+        let changes = parse("DEFINE SELECTOR row = ColumnLayout#root > RowLayout:header\nAFFECT /Main.qml\n    TRAVERSE @row > Text\n        REMOVE @row\n    END TRAVERSE\nEND AFFECT\n");
+        assert!(changes.is_err());
+
+        let changes = parse("DEFINE SELECTOR row = ColumnLayout#root > RowLayout:header\nDEFINE SELECTOR text = Text#title\nAFFECT /Main.qml\n    TRAVERSE @row > @text\n        REMOVE @text\n    END TRAVERSE\nEND AFFECT\n").unwrap();
+        let FileChangeAction::Traverse(tree) = &changes[0].changes[0] else {
+            panic!("Expected TRAVERSE");
+        };
+        assert_eq!(
+            tree.to_string(),
+            "ColumnLayout#root > RowLayout:header > Text#title"
+        );
+        assert!(
+            matches!(&changes[0].changes[1], FileChangeAction::Remove(node) if node.to_string() == "Text#title")
+        );
+
+        assert!(
+            parse("AFFECT /Main.qml\n    TRAVERSE @missing\n    END TRAVERSE\nEND AFFECT\n")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_unterminated_qml_code() {
+        let source = "AFFECT /Main.qml\n    TRAVERSE Item\n        INSERT {\n            Item {\n        }\n    END TRAVERSE\nEND AFFECT\n";
+        let error = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+            .tokenize()
+            .unwrap_err();
+        assert!(error.to_string().contains("starting on line 3"));
+
+        let source = format!("INSERT {}", "{".repeat(300));
+        let error = Lexer::new(StringCharacterTokenizer::new(source))
+            .tokenize()
+            .unwrap_err();
+        assert!(error.to_string().contains("nested more than"));
+
+        // Lines are still counted after a terminated block.
+        let source = "INSERT {\n    Item {}\n}\nINSERT {";
+        let error = Lexer::new(StringCharacterTokenizer::new(source.to_string()))
+            .tokenize()
+            .unwrap_err();
+        assert!(error.to_string().contains("starting on line 4"));
+    }
 }
@@ -1,3 +1,4 @@
 pub mod common;
 pub mod diff;
 pub mod qml;
+pub mod qmldir;
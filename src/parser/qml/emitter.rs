@@ -1,11 +1,21 @@
-use std::fmt::Display;
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{fmt::Display, str::FromStr};
+
+use anyhow::{Error, Result};
 
 use crate::parser::qml::lexer::Keyword;
 
 use super::{
-    lexer::TokenType,
+    lexer::{QMLExtensionToken, TokenType},
     parser::{
-        AssignmentChildValue, Import, Object, ObjectChild, Pragma, PropertyChild, TreeElement,
+        AssignmentChildValue, Import, Object, ObjectChild, Pragma, PropertyChild, QMLTree,
+        TreeElement,
     },
 };
 
@@ -15,12 +25,276 @@ pub struct Line {
     pub indent: usize,
 }
 
-const INDENT_DEPTH: usize = 4;
+/// Controls the layout of the emitted QML, so that it can match the conventions of the original files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EmitterConfig {
+    /// The amount of spaces per indentation level. Ignored when indenting with tabs.
+    pub indent_width: usize,
+    pub use_tabs: bool,
+    /// End the lines with `\r\n` instead of `\n`.
+    pub crlf: bool,
+    /// Objects made of at most this many tokens are emitted on a single line
+    /// (`Text { text: "a"; color: "red" }`). 0 always breaks objects into multiple lines.
+    pub max_inline_tokens: usize,
+    /// The lines of bindings longer than this many columns are broken after commas and operators.
+    /// Tabs count as `indent_width` columns. 0 never breaks them.
+    pub max_line_width: usize,
+    /// Put an empty line after every child of an object.
+    pub separate_children: bool,
+    /// Make the files as small as possible - no indentation, no empty lines, and every run of
+    /// whitespace in the code collapsed into a single space or line break. Comments are kept.
+    pub minify: bool,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        Self {
+            indent_width: 4,
+            use_tabs: false,
+            crlf: false,
+            max_inline_tokens: 0,
+            max_line_width: 0,
+            separate_children: true,
+            minify: false,
+        }
+    }
+}
+
+/// Turns a processed QML tree back into text. `EmitterConfig` implements the default
+/// pretty-printer - other implementations can be passed to `find_and_process` instead.
+pub trait Emitter {
+    fn emit_tree(&self, objects: &QMLTree) -> String;
+
+    /// `AFFECT REBUILD`ed files are never parsed - only their token stream can be emitted.
+    fn emit_tokens(&self, stream: &[TokenType]) -> String {
+        flatten_lines(&emit_token_stream(stream, 0))
+    }
+}
+
+impl Emitter for EmitterConfig {
+    fn emit_tree(&self, objects: &QMLTree) -> String {
+        emit_string(objects, self)
+    }
+
+    fn emit_tokens(&self, stream: &[TokenType]) -> String {
+        if self.minify {
+            let mut stream = stream.to_vec();
+            collapse_token_stream(&mut stream, true);
+            return flatten_lines_with(&emit_token_stream(&stream, 0), self);
+        }
+        flatten_lines_with(&emit_token_stream(stream, 0), self)
+    }
+}
+
+/// Emits the tree without comments and with all the code formatted the same way, no matter how
+/// it was originally written - so that two trees can be compared semantically.
+pub struct CanonicalEmitter;
+
+impl Emitter for CanonicalEmitter {
+    fn emit_tree(&self, objects: &QMLTree) -> String {
+        emit_string(&canonicalize_tree(objects), &EmitterConfig::default())
+    }
+
+    fn emit_tokens(&self, stream: &[TokenType]) -> String {
+        let mut stream = stream.to_vec();
+        collapse_token_stream(&mut stream, false);
+        flatten_lines(&emit_token_stream(&stream, 0))
+    }
+}
+
+/// The output formats selectable from the command line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EmitterFormat {
+    /// The layout is controlled by the `EmitterConfig`.
+    Pretty,
+    /// As few lines as possible - every object which can be is emitted on a single line.
+    Compact,
+    Canonical,
+}
+
+impl FromStr for EmitterFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pretty" => Ok(Self::Pretty),
+            "compact" => Ok(Self::Compact),
+            "canonical" => Ok(Self::Canonical),
+            _ => Err(Error::msg(format!(
+                "Unknown format {} - expected pretty, compact or canonical",
+                s
+            ))),
+        }
+    }
+}
+
+impl EmitterFormat {
+    /// The emitter for this format. Only the line endings of `config` apply to the compact format,
+    /// and none of it to the canonical one.
+    pub fn emitter(self, config: EmitterConfig) -> Box<dyn Emitter> {
+        match self {
+            Self::Pretty => Box::new(config),
+            Self::Compact => Box::new(EmitterConfig {
+                indent_width: 0,
+                use_tabs: false,
+                max_inline_tokens: usize::MAX,
+                max_line_width: 0,
+                separate_children: false,
+                ..config
+            }),
+            Self::Canonical => Box::new(CanonicalEmitter),
+        }
+    }
+}
+
+/// Drops the comments, collapses every run of whitespace into a single space and removes the
+/// indentation, the empty lines and the trailing whitespace.
+/// A copy of the tree without comments and with the whitespace of all the code collapsed.
+pub fn canonicalize_tree(objects: &QMLTree) -> QMLTree {
+    collapse_tree(objects, false)
+}
+
+/// A copy of the tree with the whitespace of all the code collapsed, keeping the comments.
+pub fn minify_tree(objects: &QMLTree) -> QMLTree {
+    collapse_tree(objects, true)
+}
+
+fn collapse_tree(objects: &QMLTree, keep_comments: bool) -> QMLTree {
+    let mut objects = objects.clone();
+    for element in &mut objects {
+        match element {
+            TreeElement::Object(object) => collapse_object(object, keep_comments),
+            TreeElement::Component(component) => {
+                collapse_object(&mut component.object, keep_comments)
+            }
+            _ => {}
+        }
+    }
+    objects
+}
+
+fn collapse_token_stream(stream: &mut Vec<TokenType>, keep_comments: bool) {
+    let mut canonical: Vec<TokenType> = Vec::with_capacity(stream.len());
+    for token in stream.drain(..) {
+        // The lexer folds line breaks following other whitespace into a single token.
+        let token = match token {
+            TokenType::Whitespace(ws) if ws.contains('\n') => TokenType::NewLine(0),
+            token => token,
+        };
+        match token {
+            TokenType::Comment(_) if keep_comments => canonical.push(token),
+            // A comment can be the only thing separating two tokens.
+            TokenType::Whitespace(_) | TokenType::Comment(_) => {
+                if !matches!(
+                    canonical.last(),
+                    Some(TokenType::Whitespace(_)) | Some(TokenType::NewLine(_))
+                ) {
+                    canonical.push(TokenType::Whitespace(" ".into()));
+                }
+            }
+            TokenType::NewLine(line) => {
+                if let Some(TokenType::Whitespace(_)) = canonical.last() {
+                    canonical.pop();
+                }
+                if !matches!(canonical.last(), None | Some(TokenType::NewLine(_))) {
+                    canonical.push(TokenType::NewLine(line));
+                }
+            }
+            token => canonical.push(token),
+        }
+    }
+    while let Some(TokenType::Whitespace(_)) | Some(TokenType::NewLine(_)) = canonical.last() {
+        canonical.pop();
+    }
+    *stream = canonical;
+}
+
+fn collapse_value(value: &mut AssignmentChildValue, keep_comments: bool) {
+    match value {
+        AssignmentChildValue::Object(object) => collapse_object(object, keep_comments),
+        AssignmentChildValue::Other(stream) => collapse_token_stream(stream, keep_comments),
+    }
+}
+
+fn collapse_object(object: &mut Object, keep_comments: bool) {
+    for child in &mut object.children {
+        match child {
+            ObjectChild::Signal(signal) => {
+                if let Some(arguments) = &mut signal.arguments {
+                    collapse_token_stream(arguments, keep_comments);
+                }
+            }
+            ObjectChild::Property(prop) => {
+                if let Some(value) = &mut prop.default_value {
+                    collapse_value(value, keep_comments);
+                }
+            }
+            ObjectChild::ObjectProperty(prop) => {
+                collapse_object(&mut prop.default_value, keep_comments)
+            }
+            ObjectChild::Assignment(assignment) => {
+                collapse_value(&mut assignment.value, keep_comments)
+            }
+            ObjectChild::ObjectAssignment(assignment) => {
+                collapse_object(&mut assignment.value, keep_comments)
+            }
+            ObjectChild::ObjectListAssignment(list) => list
+                .values
+                .iter_mut()
+                .for_each(|e| collapse_object(e, keep_comments)),
+            ObjectChild::Function(function) => {
+                // The body directly follows the arguments - the space between them has to stay.
+                let separated = matches!(
+                    function.arguments.last(),
+                    Some(TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_))
+                );
+                collapse_token_stream(&mut function.arguments, keep_comments);
+                if separated {
+                    function.arguments.push(TokenType::Whitespace(" ".into()));
+                }
+                collapse_token_stream(&mut function.body, keep_comments);
+            }
+            ObjectChild::Object(object) => collapse_object(object, keep_comments),
+            ObjectChild::Component(component) => {
+                collapse_object(&mut component.object, keep_comments)
+            }
+            ObjectChild::Enum(_) | ObjectChild::Anchor(_) => {}
+        }
+    }
+}
+
+impl EmitterConfig {
+    fn indentation(&self, depth: usize) -> String {
+        if self.minify {
+            String::new()
+        } else if self.use_tabs {
+            "\t".repeat(depth)
+        } else {
+            " ".repeat(self.indent_width * depth)
+        }
+    }
+
+    /// The width of the indentation, in columns.
+    fn columns(&self, depth: usize) -> usize {
+        if self.minify {
+            0
+        } else {
+            self.indent_width * depth
+        }
+    }
+
+    fn newline(&self) -> &'static str {
+        if self.crlf {
+            "\r\n"
+        } else {
+            "\n"
+        }
+    }
+}
 
 impl Display for Line {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&String::from(' ').repeat(INDENT_DEPTH * self.indent))?;
-        f.write_str(&self.text)
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.render(&EmitterConfig::default()))
     }
 }
 
@@ -48,6 +322,16 @@ impl Line {
             indent: 0,
         }
     }
+
+    pub fn render(&self, config: &EmitterConfig) -> String {
+        // Code copied verbatim from a CRLF file keeps its carriage returns.
+        let text = if config.crlf {
+            self.text.trim_end_matches('\r')
+        } else {
+            &self.text
+        };
+        config.indentation(self.indent) + text
+    }
 }
 
 fn emit_import(import: &Import) -> Line {
@@ -87,25 +371,150 @@ pub fn emit_simple_token_stream(stream: &Vec<TokenType>) -> String {
     string
 }
 
-pub fn emit_token_stream(stream: &Vec<TokenType>, indent: usize) -> Vec<Line> {
+/// Like `emit_simple_token_stream`, but without the whitespace and comments, so that values can
+/// be compared no matter how they are formatted.
+pub fn emit_significant_token_stream(stream: &[TokenType]) -> String {
+    stream
+        .iter()
+        .filter(|e| {
+            !matches!(
+                e,
+                TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_)
+            )
+        })
+        .map(|e| e.to_string())
+        .collect()
+}
+
+fn token_text(stream: &[TokenType], i: usize) -> String {
+    match &stream[i] {
+        // A line comment would swallow the code following it on the same line.
+        TokenType::Comment(comment)
+            if comment.starts_with("//")
+                && !matches!(stream.get(i + 1), None | Some(TokenType::NewLine(_))) =>
+        {
+            format!("/*{}*/", &comment[2..])
+        }
+        token => token.to_string(),
+    }
+}
+
+fn push_text(lines: &mut Vec<Line>, text: &str, indent: usize) {
+    let next = Line::linearize(text, indent, None, None);
+    lines.last_mut().unwrap().text.push_str(&next[0].text);
+    lines.extend_from_slice(&next[1..]);
+}
+
+pub fn emit_token_stream(stream: &[TokenType], indent: usize) -> Vec<Line> {
     let mut lines = vec![Line {
         text: String::new(),
         indent,
     }];
-    for token in stream {
-        let last = &mut lines.last_mut().unwrap().text;
-        let next = Line::linearize(&token.to_string(), indent, None, None);
-        last.push_str(&next[0].text);
-        lines.extend_from_slice(&next[1..]);
+    for i in 0..stream.len() {
+        push_text(&mut lines, &token_text(stream, i), indent);
+    }
+
+    lines
+}
+
+/// Whether a line can be broken after the `i`th token - a comma, or an operator followed by a
+/// space. Operators made of several symbols (`&&`, `=>`) are never split that way, and a line
+/// can't end with a postfix `++` / `--` - JS would end the statement there.
+/// Most of the operators are lexed as `Unknown` symbols.
+fn is_break_point(stream: &[TokenType], i: usize) -> bool {
+    let Some(TokenType::Symbol(symbol) | TokenType::Unknown(symbol)) = stream.get(i) else {
+        return false;
+    };
+    match stream.get(i + 1) {
+        None | Some(TokenType::NewLine(_)) => false,
+        Some(TokenType::Whitespace(space)) if space.contains('\n') => false,
+        Some(TokenType::Whitespace(_)) => {
+            let postfix = matches!(symbol, '+' | '-')
+                && i > 0
+                && matches!(stream[i - 1], TokenType::Symbol(c) | TokenType::Unknown(c) if c == *symbol);
+            ",+-*%=<>&|?:^".contains(*symbol) && !postfix
+        }
+        Some(_) => *symbol == ',',
+    }
+}
+
+/// The width of the code from the `start`th token up to the next place its line can be broken at.
+fn segment_width(stream: &[TokenType], texts: &[String], start: usize) -> usize {
+    let mut width = 0;
+    for (i, text) in texts.iter().enumerate().skip(start) {
+        if let Some(newline) = text.find('\n') {
+            return width + text[..newline].chars().count();
+        }
+        width += text.chars().count();
+        if is_break_point(stream, i) {
+            break;
+        }
+    }
+    width
+}
+
+/// Like `emit_token_stream(stream, 0)`, but breaks the lines longer than
+/// `config.max_line_width` after commas and operators. The stream starts at `column` of a line
+/// indented `depth` levels deep - the parts broken off of it are indented one level deeper.
+pub fn emit_wrapped_token_stream(
+    stream: &[TokenType],
+    depth: usize,
+    column: usize,
+    config: &EmitterConfig,
+) -> Vec<Line> {
+    if config.max_line_width == 0 {
+        return emit_token_stream(stream, 0);
+    }
+    let texts: Vec<String> = (0..stream.len()).map(|i| token_text(stream, i)).collect();
+    let mut lines = vec![Line {
+        text: String::new(),
+        indent: 0,
+    }];
+    let mut column = column;
+    let mut i = 0;
+    while i < stream.len() {
+        push_text(&mut lines, &texts[i], 0);
+        column = match texts[i].rfind('\n') {
+            Some(newline) => texts[i][newline + 1..].chars().count(),
+            None => column + texts[i].chars().count(),
+        };
+        if is_break_point(stream, i) {
+            // The space after the operator isn't needed at the end of the line.
+            let next = match stream[i + 1] {
+                TokenType::Whitespace(_) => i + 2,
+                _ => i + 1,
+            };
+            if next < stream.len()
+                && column + segment_width(stream, &texts, i + 1) > config.max_line_width
+            {
+                lines.push(Line {
+                    text: String::new(),
+                    indent: depth + 1,
+                });
+                column = config.columns(depth + 1);
+                i = next;
+                continue;
+            }
+        }
+        i += 1;
     }
 
     lines
 }
 
-fn emit_assignment_child_value(value: &AssignmentChildValue, indent: usize) -> Vec<Line> {
+/// `column` is where the value starts on the line of the binding.
+fn emit_assignment_child_value(
+    value: &AssignmentChildValue,
+    indent: usize,
+    column: usize,
+    config: &EmitterConfig,
+) -> Vec<Line> {
     match value {
-        AssignmentChildValue::Other(stream) => emit_token_stream(stream, indent),
-        AssignmentChildValue::Object(object) => emit_object(object, indent),
+        // The lines following the first one keep their original indentation, like function bodies.
+        AssignmentChildValue::Other(stream) => {
+            emit_wrapped_token_stream(stream, indent, column, config)
+        }
+        AssignmentChildValue::Object(object) => emit_object(object, indent, config),
         // AssignmentChildValue::List(list) => {
         //     let mut temporary_lines = vec![Line {
         //         text: String::from("["),
@@ -166,6 +575,11 @@ fn _emit_object_to_token_stream(object: &Object, stream: &mut Vec<TokenType>, on
                 add!(TokenType::Symbol(':'));
                 _emit_object_to_token_stream(&assignment.value, stream, false);
             }
+            ObjectChild::ObjectListAssignment(list) => {
+                id!(list.name.clone());
+                add!(TokenType::Symbol(':'));
+                _emit_object_list_to_token_stream(&list.values, stream);
+            }
             ObjectChild::Assignment(assignment) => {
                 // HACK: See comment in parser:
                 if assignment.name.contains(" ") {
@@ -183,6 +597,11 @@ fn _emit_object_to_token_stream(object: &Object, stream: &mut Vec<TokenType>, on
                     AssignmentChildValue::Other(other) => stream.extend_from_slice(other),
                 }
             }
+            ObjectChild::Anchor(name) => {
+                add!(TokenType::Extension(QMLExtensionToken::Anchor(
+                    name.clone()
+                )));
+            }
             ObjectChild::Enum(r#enum) => {
                 add!(TokenType::Keyword(Keyword::Enum));
                 id!(r#enum.name.clone());
@@ -246,6 +665,24 @@ fn _emit_object_to_token_stream(object: &Object, stream: &mut Vec<TokenType>, on
     }
 }
 
+fn _emit_object_list_to_token_stream(values: &[Object], stream: &mut Vec<TokenType>) {
+    stream.push(TokenType::Symbol('['));
+    for (i, object) in values.iter().enumerate() {
+        if i != 0 {
+            stream.push(TokenType::Symbol(','));
+        }
+        stream.push(TokenType::Whitespace(" ".into()));
+        _emit_object_to_token_stream(object, stream, false);
+    }
+    stream.push(TokenType::Symbol(']'));
+}
+
+pub fn emit_object_list_to_token_stream(values: &[Object]) -> Vec<TokenType> {
+    let mut stream = vec![];
+    _emit_object_list_to_token_stream(values, &mut stream);
+    stream
+}
+
 pub fn emit_object_to_token_stream(object: &Object, only_body: bool) -> Vec<TokenType> {
     let mut stream = vec![];
     _emit_object_to_token_stream(object, &mut stream, only_body);
@@ -253,30 +690,77 @@ pub fn emit_object_to_token_stream(object: &Object, only_body: bool) -> Vec<Toke
 }
 
 fn emit_property_prologue<T: Clone>(prop: &PropertyChild<T>) -> String {
-    let modifiers: String = prop
+    let mut words: Vec<String> = prop
         .modifiers
         .iter()
         .map(|k| Into::<String>::into(k.clone()))
-        .fold(String::new(), |a, b| a + &b + " ");
+        .collect();
     if let Some(r#type) = &prop.r#type {
-        format!("{} {} {}", modifiers, r#type, prop.name)
-    } else {
-        format!("{} {}", modifiers, prop.name)
+        words.push(r#type.clone());
     }
+    words.push(prop.name.clone());
+    words.join(" ")
 }
 
-pub fn emit_object(object: &Object, indent: usize) -> Vec<Line> {
-    let root_line = Line {
+/// Emits the object on one line, if it's short enough and all of its children fit on one line.
+fn emit_object_inline(object: &Object, indent: usize, config: &EmitterConfig) -> Option<Line> {
+    let token_count = emit_object_to_token_stream(object, false)
+        .iter()
+        .filter(|e| !matches!(e, TokenType::Whitespace(_) | TokenType::NewLine(_)))
+        .count();
+    if token_count > config.max_inline_tokens {
+        return None;
+    }
+    let mut children = Vec::new();
+    let separated = EmitterConfig {
+        separate_children: true,
+        ..*config
+    };
+    for child in &object.children {
+        let lines = emit_object_children(core::slice::from_ref(child), 0, &separated);
+        match lines.as_slice() {
+            // Anchors are not emitted at all.
+            [] => {}
+            // The line of the child, and the empty line following it.
+            [line, _] => children.push(line.text.clone()),
+            _ => return None,
+        }
+    }
+    let text = if children.is_empty() {
+        format!("{} {{}}", object.name)
+    } else {
+        format!("{} {{ {} }}", object.name, children.join("; "))
+    };
+    Some(Line { text, indent })
+}
+
+pub fn emit_object(object: &Object, indent: usize, config: &EmitterConfig) -> Vec<Line> {
+    if let Some(line) = emit_object_inline(object, indent, config) {
+        return vec![line];
+    }
+    let mut lines = vec![Line {
         text: format!("{} {{", object.name),
         indent,
-    };
-    let indent = indent + 1;
-    let mut lines = vec![root_line];
+    }];
+    lines.extend(emit_object_children(&object.children, indent + 1, config));
+    lines.push(Line {
+        text: "}".into(),
+        indent,
+    });
 
-    for child in &object.children {
+    lines
+}
+
+pub fn emit_object_children(
+    children: &[ObjectChild],
+    indent: usize,
+    config: &EmitterConfig,
+) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for child in children {
         match child {
             ObjectChild::ObjectAssignment(assignment) => {
-                let value_emited = emit_object(&assignment.value, indent);
+                let value_emited = emit_object(&assignment.value, indent, config);
                 let new_first_line = Line {
                     text: format!(
                         "{}: {}",
@@ -289,7 +773,9 @@ pub fn emit_object(object: &Object, indent: usize) -> Vec<Line> {
                 lines.extend_from_slice(&value_emited[1..]);
             }
             ObjectChild::Assignment(assignment) => {
-                let value_emited = emit_assignment_child_value(&assignment.value, indent);
+                let column = config.columns(indent) + assignment.name.len() + 2;
+                let value_emited =
+                    emit_assignment_child_value(&assignment.value, indent, column, config);
                 let new_first_line = Line {
                     text: format!(
                         "{}: {}",
@@ -301,6 +787,25 @@ pub fn emit_object(object: &Object, indent: usize) -> Vec<Line> {
                 lines.push(new_first_line);
                 lines.extend_from_slice(&value_emited[1..]);
             }
+            ObjectChild::ObjectListAssignment(list) => {
+                lines.push(Line {
+                    text: format!("{}: [", list.name),
+                    indent,
+                });
+                for (i, object) in list.values.iter().enumerate() {
+                    let mut value_emited = emit_object(object, indent + 1, config);
+                    if i < list.values.len() - 1 {
+                        value_emited.last_mut().unwrap().text.push(',');
+                    }
+                    lines.extend(value_emited);
+                }
+                lines.push(Line {
+                    text: String::from("]"),
+                    indent,
+                });
+            }
+            // Anchors only exist while the diffs are being applied.
+            ObjectChild::Anchor(_) => continue,
             ObjectChild::Enum(r#enum) => {
                 lines.push(Line {
                     indent,
@@ -333,7 +838,7 @@ pub fn emit_object(object: &Object, indent: usize) -> Vec<Line> {
                     text: format!("function {}", function.name),
                     indent,
                 }];
-                let arg_stream = emit_token_stream(&function.arguments, indent + 1);
+                let arg_stream = emit_token_stream(&function.arguments, 0);
                 sub_lines.last_mut().unwrap().text += &arg_stream[0].text;
                 sub_lines.extend_from_slice(&arg_stream[1..]);
                 let func_stream = emit_token_stream(&function.body, 0);
@@ -342,13 +847,14 @@ pub fn emit_object(object: &Object, indent: usize) -> Vec<Line> {
                 lines.extend(sub_lines);
             }
             ObjectChild::Object(object) => {
-                lines.extend(emit_object(object, indent));
+                lines.extend(emit_object(object, indent, config));
             }
             ObjectChild::Property(prop) => {
                 let mut line = emit_property_prologue(prop);
                 if let Some(default) = &prop.default_value {
-                    let new_lines = emit_assignment_child_value(default, indent);
                     line += ": ";
+                    let column = config.columns(indent) + line.len();
+                    let new_lines = emit_assignment_child_value(default, indent, column, config);
                     line += &new_lines[0].text;
                     lines.push(Line { text: line, indent });
                     lines.extend_from_slice(&new_lines[1..]);
@@ -358,7 +864,7 @@ pub fn emit_object(object: &Object, indent: usize) -> Vec<Line> {
             }
             ObjectChild::ObjectProperty(prop) => {
                 let mut line = emit_property_prologue(prop);
-                let new_lines = emit_object(&prop.default_value, indent);
+                let new_lines = emit_object(&prop.default_value, indent, config);
                 line += ": ";
                 line += &new_lines[0].text;
                 lines.push(Line { text: line, indent });
@@ -380,31 +886,40 @@ pub fn emit_object(object: &Object, indent: usize) -> Vec<Line> {
                     text: format!("component {}: ", comp.name),
                     indent,
                 }];
-                let arg_stream = emit_object(&comp.object, indent + 1);
+                let arg_stream = emit_object(&comp.object, indent + 1, config);
                 sub_lines.last_mut().unwrap().text += &arg_stream[0].text;
                 sub_lines.extend_from_slice(&arg_stream[1..]);
                 lines.extend(sub_lines);
             }
         }
 
-        lines.push(Line::empty());
+        if config.separate_children {
+            lines.push(Line::empty());
+        }
     }
 
-    lines.push(Line {
-        text: "}".into(),
-        indent: indent - 1,
-    });
-
     lines
 }
 
-pub fn emit(objects: &Vec<TreeElement>) -> Vec<Line> {
+pub fn emit(objects: &Vec<TreeElement>, config: &EmitterConfig) -> Vec<Line> {
+    let minified;
+    let objects = if config.minify {
+        minified = minify_tree(objects);
+        &minified
+    } else {
+        objects
+    };
     let mut lines = Vec::default();
     for obj in objects {
         match obj {
             TreeElement::Import(import) => lines.push(emit_import(import)),
             TreeElement::Pragma(pragma) => lines.push(emit_pragma(pragma)),
-            TreeElement::Object(obj) => lines.extend(emit_object(obj, 0)),
+            TreeElement::Object(obj) => lines.extend(emit_object(obj, 0, config)),
+            TreeElement::Component(comp) => {
+                let mut object_lines = emit_object(&comp.object, 0, config);
+                object_lines[0].text = format!("component {}: {}", comp.name, object_lines[0].text);
+                lines.extend(object_lines);
+            }
         }
     }
 
@@ -412,13 +927,18 @@ pub fn emit(objects: &Vec<TreeElement>) -> Vec<Line> {
 }
 
 pub fn flatten_lines(lines: &[Line]) -> String {
+    flatten_lines_with(lines, &EmitterConfig::default())
+}
+
+pub fn flatten_lines_with(lines: &[Line], config: &EmitterConfig) -> String {
     lines
         .iter()
+        .filter(|l| !(config.minify && l.text.trim().is_empty()))
         .enumerate()
-        .map(|(i, l)| (if i == 0 { "" } else { "\n" }).to_string() + &l.to_string())
+        .map(|(i, l)| (if i == 0 { "" } else { config.newline() }).to_string() + &l.render(config))
         .collect()
 }
 
-pub fn emit_string(objects: &Vec<TreeElement>) -> String {
-    flatten_lines(&emit(objects))
+pub fn emit_string(objects: &Vec<TreeElement>, config: &EmitterConfig) -> String {
+    flatten_lines_with(&emit(objects, config), config)
 }
@@ -1,4 +1,9 @@
-use std::fmt::Display;
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Display;
 
 use anyhow::Error;
 
@@ -90,7 +95,7 @@ impl From<Keyword> for String {
 }
 
 impl Display for TokenType {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.write_str(&match self {
             TokenType::String(k) => k.clone(),
             TokenType::Identifier(k) => k.clone(),
@@ -100,7 +105,7 @@ impl Display for TokenType {
             TokenType::Symbol(k) | TokenType::Unknown(k) => String::from(*k),
             TokenType::Whitespace(s) => s.clone(),
             TokenType::NewLine(_) => String::from("\n"),
-            TokenType::Comment(comment) => format!("/*{}*/", comment),
+            TokenType::Comment(comment) => comment.clone(),
             TokenType::EndOfStream => String::from("<<End of Stream>>"),
             TokenType::Extension(ext) => format!("{}", ext),
         })
@@ -108,11 +113,12 @@ impl Display for TokenType {
 }
 
 impl Display for QMLExtensionToken {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             Self::HashedIdentifier(hash) => write!(f, "~&{}&~", hash),
             Self::HashedString(quote, hash) => write!(f, "~&{}{}&~", quote, hash),
             Self::Slot(slot) => write!(f, "~{{{}}}~", slot),
+            Self::Anchor(anchor) => write!(f, "~#{}~", anchor),
         }
     }
 }
@@ -122,6 +128,7 @@ pub enum QMLExtensionToken {
     HashedIdentifier(u64),
     HashedString(char, u64),
     Slot(String),
+    Anchor(String),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -132,6 +139,7 @@ pub enum TokenType {
     Number(String), // Numbers are stored as strings, so as to avoid any possible loss of precision when dealing with parsing / reemission.
     String(String),
     Symbol(char),
+    /// The comment with its delimiters - `// ...` or `/* ... */`.
     Comment(String),
     NewLine(usize),
     Whitespace(String),
@@ -140,16 +148,53 @@ pub enum TokenType {
     Extension(QMLExtensionToken),
 }
 
+/// The extension tokens the lexer recognizes. The QML code of the diffs uses them, but plain QML
+/// files are better off without - `~{` and `~#` can start valid (if weird) JS.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QMLDiffExtensions {
+    /// Hashed identifiers and strings - `~&1234&~`, `~&'1234&~`.
+    pub hashes: bool,
+    /// Slots - `~{name}~`.
+    pub slots: bool,
+    /// Anchors - `~#name~`.
+    pub anchors: bool,
+}
+
+impl QMLDiffExtensions {
+    /// For the QML code of the diffs.
+    pub const ALL: Self = Self {
+        hashes: true,
+        slots: true,
+        anchors: true,
+    };
+    /// For the QML files the diffs are applied to.
+    pub const NONE: Self = Self {
+        hashes: false,
+        slots: false,
+        anchors: false,
+    };
+}
+
 pub struct Lexer {
     pub stream: StringCharacterTokenizer,
-    line_pos: usize, // Current position within a line [unused.]
+    pub line_pos: usize, // Current position within a line [unused.]
+    pub extensions: QMLDiffExtensions,
 }
 
 impl Lexer {
+    /// A lexer recognizing all the extension tokens.
     pub fn new(stream: StringCharacterTokenizer) -> Self {
+        Self::with_extensions(stream, QMLDiffExtensions::ALL)
+    }
+
+    pub fn with_extensions(
+        stream: StringCharacterTokenizer,
+        extensions: QMLDiffExtensions,
+    ) -> Self {
         Self {
             stream,
             line_pos: 0,
+            extensions,
         }
     }
 
@@ -192,7 +237,7 @@ impl Lexer {
                 // For hashed string: ~&[q]hash&~
                 // where [q] is one of `, ', "
                 // Example: ~&'1234&~
-                '~' if self.stream.peek_offset(1) == Some('&') => {
+                '~' if self.extensions.hashes && self.stream.peek_offset(1) == Some('&') => {
                     // HASH!
                     self.stream.advance();
                     self.stream.advance();
@@ -214,7 +259,7 @@ impl Lexer {
                         None => QMLExtensionToken::HashedIdentifier(hashed_value),
                     }))
                 }
-                '~' if self.stream.peek_offset(1) == Some('{') => {
+                '~' if self.extensions.slots && self.stream.peek_offset(1) == Some('{') => {
                     // Slot
                     self.stream.advance();
                     self.stream.advance();
@@ -226,6 +271,15 @@ impl Lexer {
 
                     Ok(TokenType::Extension(QMLExtensionToken::Slot(slot_name)))
                 }
+                '~' if self.extensions.anchors && self.stream.peek_offset(1) == Some('#') => {
+                    // Anchor - ~#name~
+                    self.stream.advance();
+                    self.stream.advance();
+                    let anchor_name = self.stream.collect_while(|_, c| (c != '~').into());
+                    self.stream.advance(); // Remove ~
+
+                    Ok(TokenType::Extension(QMLExtensionToken::Anchor(anchor_name)))
+                }
                 '\n' => {
                     self.stream.advance();
                     self.line_pos += 1;
@@ -241,7 +295,7 @@ impl Lexer {
                     self.stream.advance();
                     self.stream.advance();
                     let comment = self.stream.collect_while(|_, c| (c != '\n').into());
-                    Ok(TokenType::Comment(comment))
+                    Ok(TokenType::Comment(format!("//{}", comment)))
                 }
 
                 '/' if self.stream.input[self.stream.position..].starts_with("/*") => {
@@ -252,7 +306,7 @@ impl Lexer {
                         .collect_while(|s, _c| (!s.input[s.position..].starts_with("*/")).into());
                     self.stream.advance(); // Consume '*'
                     self.stream.advance(); // Consume '/'
-                    Ok(TokenType::Comment(comment))
+                    Ok(TokenType::Comment(format!("/*{}*/", comment)))
                 }
 
                 '"' | '\'' | '`' => {
@@ -314,6 +368,18 @@ impl Lexer {
     }
 }
 
+impl Lexer {
+    /// Reads all the tokens, failing on the first one which cannot be read - unlike the iterator,
+    /// which skips them.
+    pub fn tokenize(mut self) -> Result<Vec<TokenType>, Error> {
+        let mut tokens = Vec::new();
+        while self.stream.position < self.stream.input.len() {
+            tokens.push(self.next_token()?);
+        }
+        Ok(tokens)
+    }
+}
+
 impl Iterator for Lexer {
     type Item = TokenType;
 
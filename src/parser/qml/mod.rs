@@ -3,6 +3,7 @@ pub mod hash_extension;
 pub mod lexer;
 pub mod parser;
 pub mod slot_extensions;
+pub mod template_expression;
 
 #[cfg(test)]
 mod test;
@@ -1,17 +1,23 @@
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
 use anyhow::{Error, Result};
-use std::{
+use core::{
     iter::Peekable,
-    mem::{discriminant, Discriminant},
+    mem::{discriminant, take, Discriminant},
 };
 
 use super::{
-    emitter::emit_simple_token_stream,
-    lexer::{Keyword, TokenType},
+    emitter::emit_significant_token_stream,
+    lexer::{Keyword, QMLExtensionToken, TokenType},
 };
 
 pub type QMLTree = Vec<TreeElement>;
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Import {
     pub object_name: String,
     pub version: Option<String>,
@@ -51,6 +57,13 @@ pub struct ObjectAssignmentChild {
     pub value: Object,
 }
 
+/// `name: [Object {}, Object {}]` - a list made up only of objects.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ObjectListAssignmentChild {
+    pub name: String,
+    pub values: Vec<Object>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct FunctionChild {
     pub name: String,
@@ -64,7 +77,7 @@ pub struct EnumChild {
     pub values: Vec<(String, Option<String>)>,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Pragma {
     pub pragma: String,
     pub value: Option<String>,
@@ -83,10 +96,13 @@ pub enum ObjectChild {
     ObjectProperty(PropertyChild<Object>),
     Assignment(AssignmentChild),
     ObjectAssignment(ObjectAssignmentChild),
+    ObjectListAssignment(ObjectListAssignmentChild),
     Function(FunctionChild),
     Object(Object),
     Enum(EnumChild),
     Component(ComponentDefinition),
+    /// A named position among the children, declared by `~#name~`. It is never emitted.
+    Anchor(String),
 }
 
 impl<'a> ObjectChild {
@@ -94,6 +110,7 @@ impl<'a> ObjectChild {
         match self {
             ObjectChild::Assignment(assi) => Some(&assi.name),
             ObjectChild::ObjectAssignment(assi) => Some(&assi.name),
+            ObjectChild::ObjectListAssignment(assi) => Some(&assi.name),
             ObjectChild::Component(cmp) => Some(&cmp.name),
             ObjectChild::Enum(e) => Some(&e.name),
             ObjectChild::Function(fnc) => Some(&fnc.name),
@@ -101,6 +118,7 @@ impl<'a> ObjectChild {
             ObjectChild::Property(prop) => Some(&prop.name),
             ObjectChild::ObjectProperty(prop) => Some(&prop.name),
             ObjectChild::Signal(signal) => Some(&signal.name),
+            ObjectChild::Anchor(_) => None,
         }
     }
 
@@ -108,23 +126,25 @@ impl<'a> ObjectChild {
         match self {
             ObjectChild::Assignment(assigned) => match &assigned.value {
                 AssignmentChildValue::Other(generic_value) => {
-                    Some(emit_simple_token_stream(generic_value))
+                    Some(emit_significant_token_stream(generic_value))
                 }
                 _ => None,
             },
             ObjectChild::ObjectAssignment(_) => None,
+            ObjectChild::ObjectListAssignment(_) => None,
             ObjectChild::Component(_) => None,
             ObjectChild::Enum(_) => None,
             ObjectChild::Function(_) => None,
             ObjectChild::Object(_) => None,
             ObjectChild::Property(prop) => match &prop.default_value {
                 Some(AssignmentChildValue::Other(generic_value)) => {
-                    Some(emit_simple_token_stream(generic_value))
+                    Some(emit_significant_token_stream(generic_value))
                 }
                 _ => None,
             },
             ObjectChild::ObjectProperty(_) => None,
             ObjectChild::Signal(_) => None,
+            ObjectChild::Anchor(_) => None,
         }
     }
 }
@@ -135,11 +155,13 @@ impl PartialEq for ObjectChild {
             (ObjectChild::Signal(a), ObjectChild::Signal(b)) => a == b,
             (ObjectChild::Property(a), ObjectChild::Property(b)) => a == b,
             (ObjectChild::ObjectAssignment(a), ObjectChild::ObjectAssignment(b)) => a == b,
+            (ObjectChild::ObjectListAssignment(a), ObjectChild::ObjectListAssignment(b)) => a == b,
             (ObjectChild::Assignment(a), ObjectChild::Assignment(b)) => a == b,
             (ObjectChild::Function(a), ObjectChild::Function(b)) => a == b,
             (ObjectChild::Object(a), ObjectChild::Object(b)) => a == b,
             (ObjectChild::Enum(a), ObjectChild::Enum(b)) => a == b,
             (ObjectChild::Component(a), ObjectChild::Component(b)) => a == b,
+            (ObjectChild::Anchor(a), ObjectChild::Anchor(b)) => a == b,
             _ => false,
         }
     }
@@ -155,11 +177,13 @@ pub struct Object {
     pub full_name: String,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TreeElement {
     Import(Import),
     Object(Object),
     Pragma(Pragma),
+    /// `component Name: Object {}` next to the root object.
+    Component(ComponentDefinition),
 }
 
 pub struct Parser {
@@ -190,7 +214,7 @@ impl Parser {
     ) -> Result<String> {
         let mut final_string = String::default();
         let mut next_delim = next_delim;
-        const WEAK_KEYWORDS: &'static [Keyword] = &[Keyword::Component];
+        const WEAK_KEYWORDS: &[Keyword] = &[Keyword::Component];
         loop {
             let token = self.stream.peek();
             match token {
@@ -305,6 +329,18 @@ impl Parser {
         }
     }
 
+    /// Like `discard_whitespace`, but returns the skipped tokens, so that the original
+    /// spacing of code can be kept.
+    fn take_whitespace(&mut self) -> Vec<TokenType> {
+        let mut taken = Vec::new();
+        while let Some(TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_)) =
+            self.stream.peek()
+        {
+            taken.push(self.stream.next().unwrap());
+        }
+        taken
+    }
+
     fn parse_pragma_statement(&mut self) -> Result<Pragma> {
         self.discard_whitespace();
         let id = self.next_id(false)?;
@@ -390,6 +426,19 @@ impl Parser {
                 TokenType::Keyword(Keyword::Pragma) => {
                     elements.push(TreeElement::Pragma(self.parse_pragma_statement()?));
                 }
+                TokenType::Keyword(Keyword::Component) => {
+                    let name = self.next_id(true)?;
+                    self.discard_whitespace();
+                    let next_token = self.next_lex()?;
+                    if let TokenType::Symbol(':') = next_token {
+                        let comp_name = self.next_id(true)?;
+                        let object =
+                            self.parse_object(comp_name, false, String::from("<root> > ") + &name)?;
+                        elements.push(TreeElement::Component(ComponentDefinition { name, object }));
+                    } else {
+                        return error_received_expected!(next_token, ":");
+                    }
+                }
 
                 TokenType::Identifier(object) => {
                     let name = self.reread_as_compound_name(object)?;
@@ -458,6 +507,9 @@ impl Parser {
     fn read_value(&mut self, parent_name: String) -> Result<AssignmentChildValue> {
         // Read until two identifiers / identifier and keyword is detected
         let mut value = Vec::default();
+        // The whitespace after the last token of the value. It only becomes part of the value
+        // once another token follows.
+        let mut spacing = Vec::default();
 
         self.discard_whitespace();
         match self.stream.peek() {
@@ -468,9 +520,12 @@ impl Parser {
                 // Skip past the name we just peeked
                 let name = name.clone();
                 let _next = self.next_lex().unwrap();
-                self.discard_whitespace();
+                spacing = self.take_whitespace();
+                if let Some(TokenType::Symbol('.')) = self.stream.peek() {
+                    spacing.clear();
+                }
                 let name = self.reread_as_compound_name(name)?;
-                self.discard_whitespace();
+                spacing.extend(self.take_whitespace());
                 // Read next to check if it's an object
                 if let Some(TokenType::Symbol('{')) = self.stream.peek() {
                     // It is
@@ -485,13 +540,14 @@ impl Parser {
             }
             Some(TokenType::Symbol('(')) => {
                 value.extend_from_slice(&self.read_until_depth_runs_out('(', ')')?);
-                self.discard_whitespace();
+                spacing = self.take_whitespace();
                 if let Some(TokenType::Unknown('=')) = self.stream.peek() {
+                    value.append(&mut spacing);
                     value.push(self.stream.next().unwrap());
                     let next_lex = self.next_lex()?;
                     if let TokenType::Unknown('>') = next_lex {
                         value.push(next_lex);
-                        self.discard_whitespace();
+                        value.extend(self.take_whitespace());
                         //value.extend_from_slice(&self.read_until_depth_runs_out('{', '}')?);
                         let read_value = self.read_value(parent_name)?;
                         if let AssignmentChildValue::Other(tokens) = read_value {
@@ -518,7 +574,7 @@ impl Parser {
         let mut last_important = value.last().cloned();
 
         loop {
-            self.discard_whitespace();
+            spacing.extend(self.take_whitespace());
             // println!("Next is {:?}", self.stream.peek());
             match self.stream.peek() {
                 Some(TokenType::Keyword(_))
@@ -548,16 +604,19 @@ impl Parser {
                     // println!("Prevented.");
                 }
                 Some(TokenType::Symbol('[')) => {
+                    value.append(&mut spacing);
                     value.extend_from_slice(&self.read_until_depth_runs_out('[', ']')?);
                     last_important = Some(value.last().unwrap().clone());
                     continue;
                 }
                 Some(TokenType::Symbol('(')) => {
+                    value.append(&mut spacing);
                     value.extend_from_slice(&self.read_until_depth_runs_out('(', ')')?);
                     last_important = Some(value.last().unwrap().clone());
                     continue;
                 }
                 Some(TokenType::Symbol('{')) => {
+                    value.append(&mut spacing);
                     value.extend_from_slice(&self.read_until_depth_runs_out('{', '}')?);
                     last_important = Some(value.last().unwrap().clone());
                     continue;
@@ -571,6 +630,7 @@ impl Parser {
                 _ => last_important = Some(token.clone()),
             }
             // println!("Token recvd for value: {:?}", &token);
+            value.append(&mut spacing);
             value.push(token);
         }
     }
@@ -625,8 +685,9 @@ impl Parser {
                             Keyword::Function => {
                                 let name = self.next_id(true)?;
                                 self.discard_whitespace();
-                                let arguments = self.read_until_depth_runs_out('(', ')')?;
-                                self.discard_whitespace();
+                                let mut arguments = self.read_until_depth_runs_out('(', ')')?;
+                                // Keep the spacing between the arguments and the body.
+                                arguments.extend(self.take_whitespace());
                                 let body = self.read_until_depth_runs_out('{', '}')?;
                                 object.children.push(ObjectChild::Function(FunctionChild {
                                     arguments,
@@ -774,6 +835,9 @@ impl Parser {
                             full_tree_name.clone() + " > " + &id,
                         )?);
                     }
+                    TokenType::Extension(QMLExtensionToken::Anchor(name)) => {
+                        object.children.push(ObjectChild::Anchor(name));
+                    }
                     _ => {
                         return error_received_expected!(token, "Valid property starter token");
                     }
@@ -802,7 +866,7 @@ impl Parser {
             Some(TokenType::Symbol(':')) => {
                 // Simple property assignment
                 self.stream.next();
-                let value = self.read_value(parent_name)?;
+                let value = self.read_value(parent_name.clone())?;
                 match value {
                     AssignmentChildValue::Object(obj) => {
                         Ok(ObjectChild::ObjectAssignment(ObjectAssignmentChild {
@@ -810,10 +874,17 @@ impl Parser {
                             value: obj,
                         }))
                     }
-                    val => Ok(ObjectChild::Assignment(AssignmentChild {
-                        name: id,
-                        value: val,
-                    })),
+                    AssignmentChildValue::Other(stream) => {
+                        match parse_object_list(&stream, &parent_name) {
+                            Some(values) => Ok(ObjectChild::ObjectListAssignment(
+                                ObjectListAssignmentChild { name: id, values },
+                            )),
+                            None => Ok(ObjectChild::Assignment(AssignmentChild {
+                                name: id,
+                                value: AssignmentChildValue::Other(stream),
+                            })),
+                        }
+                    }
                 }
             }
             Some(TokenType::Symbol('{')) => {
@@ -832,3 +903,63 @@ impl Parser {
         self.parse_global_scope()
     }
 }
+
+fn is_insignificant(token: &TokenType) -> bool {
+    matches!(
+        token,
+        TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_)
+    )
+}
+
+fn parse_object_list_element(tokens: Vec<TokenType>, parent_name: &str) -> Option<Object> {
+    let mut parser = Parser::new(Box::new(tokens.into_iter()));
+    let name = parser.next_id(true).ok()?;
+    let full_name = format!("{}>{}", parent_name, name);
+    let object = parser.parse_object(name, false, full_name).ok()?;
+    parser.discard_whitespace();
+    match parser.stream.peek() {
+        None | Some(TokenType::EndOfStream) => Some(object),
+        _ => None,
+    }
+}
+
+/// Parses the value of an assignment which is a list made up only of objects -
+/// `[State {}, State {}]`. Returns None if the value is anything else.
+pub fn parse_object_list(stream: &[TokenType], parent_name: &str) -> Option<Vec<Object>> {
+    let first = stream.iter().position(|e| !is_insignificant(e))?;
+    let last = stream.iter().rposition(|e| !is_insignificant(e))?;
+    if first == last
+        || stream[first] != TokenType::Symbol('[')
+        || stream[last] != TokenType::Symbol(']')
+    {
+        return None;
+    }
+
+    let mut values = Vec::new();
+    let mut element = Vec::new();
+    let mut depth = 0usize;
+    for token in &stream[first + 1..last] {
+        match token {
+            TokenType::Symbol('[' | '{' | '(') => depth += 1,
+            // The opening bracket got closed before the end - `[a][0]`.
+            TokenType::Symbol(']' | '}' | ')') if depth == 0 => return None,
+            TokenType::Symbol(']' | '}' | ')') => depth -= 1,
+            TokenType::Symbol(',') if depth == 0 => {
+                values.push(parse_object_list_element(take(&mut element), parent_name)?);
+                continue;
+            }
+            _ => {}
+        }
+        element.push(token.clone());
+    }
+    // A trailing comma is allowed.
+    if element.iter().any(|e| !is_insignificant(e)) {
+        values.push(parse_object_list_element(element, parent_name)?);
+    }
+
+    if values.is_empty() {
+        None
+    } else {
+        Some(values)
+    }
+}
@@ -29,12 +29,10 @@ impl IteratorRemapper<TokenType, &str> for QMLSlotRemapper<'_> {
                             id
                         )))
                     } else {
-                        ChainIteratorRemapper::Link(Box::new(
-                            self.slots
-                                .resolve_slot_final_state(&id)
-                                .unwrap()
-                                .into_iter(),
-                        ))
+                        match self.slots.resolve_slot_final_state(&id) {
+                            Ok(tokens) => ChainIteratorRemapper::Link(Box::new(tokens.into_iter())),
+                            Err(error) => ChainIteratorRemapper::Error(error),
+                        }
                     }
                 } else {
                     ChainIteratorRemapper::Skip
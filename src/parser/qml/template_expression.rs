@@ -0,0 +1,305 @@
+use anyhow::{bail, Error, Result};
+
+use crate::parser::common::StringCharacterTokenizer;
+
+use super::lexer::{Lexer, TokenType};
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Number(f64),
+    /// The contents of the string, without the quotes.
+    String(String),
+}
+
+impl Value {
+    fn to_token(&self) -> TokenType {
+        match self {
+            Self::Number(number) => TokenType::Number(format_number(*number)),
+            Self::String(string) => TokenType::String(format!("\"{}\"", string)),
+        }
+    }
+
+    fn as_string(&self) -> String {
+        match self {
+            Self::Number(number) => format_number(*number),
+            Self::String(string) => string.clone(),
+        }
+    }
+}
+
+impl core::fmt::Display for Value {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.to_token())
+    }
+}
+
+fn format_number(number: f64) -> String {
+    if number.fract() == 0.0 && number.abs() < 1e15 {
+        format!("{}", number as i64)
+    } else {
+        format!("{}", number)
+    }
+}
+
+/// Whether the `~{...}~` reference is an expression, rather than the name of a single value.
+pub fn is_expression(reference: &str) -> bool {
+    !reference
+        .trim()
+        .chars()
+        .all(|c| c.is_alphanumeric() || c == '_')
+}
+
+struct Evaluator<'a> {
+    tokens: Vec<TokenType>,
+    position: usize,
+    resolve: &'a mut dyn FnMut(&str) -> Result<Vec<TokenType>>,
+}
+
+impl Evaluator<'_> {
+    fn peek(&self) -> Option<&TokenType> {
+        self.tokens.get(self.position)
+    }
+
+    fn peek_operator(&self, operators: &[char]) -> Option<char> {
+        match self.peek() {
+            Some(TokenType::Unknown(c) | TokenType::Symbol(c)) if operators.contains(c) => Some(*c),
+            _ => None,
+        }
+    }
+
+    fn numbers(operator: char, left: Value, right: Value) -> Result<(f64, f64)> {
+        match (left, right) {
+            (Value::Number(left), Value::Number(right)) => Ok((left, right)),
+            (left, right) => bail!(
+                "Cannot use {} on {} and {} - only numbers are supported",
+                operator,
+                left,
+                right
+            ),
+        }
+    }
+
+    // expression := term (('+' | '-') term)*
+    fn expression(&mut self) -> Result<Value> {
+        let mut value = self.term()?;
+        while let Some(operator) = self.peek_operator(&['+', '-']) {
+            self.position += 1;
+            let right = self.term()?;
+            value = match (operator, value, right) {
+                ('+', left @ Value::String(_), right) | ('+', left, right @ Value::String(_)) => {
+                    Value::String(left.as_string() + &right.as_string())
+                }
+                (operator, left, right) => {
+                    let (left, right) = Self::numbers(operator, left, right)?;
+                    Value::Number(if operator == '+' {
+                        left + right
+                    } else {
+                        left - right
+                    })
+                }
+            };
+        }
+        Ok(value)
+    }
+
+    // term := unary (('*' | '/' | '%') unary)*
+    fn term(&mut self) -> Result<Value> {
+        let mut value = self.unary()?;
+        while let Some(operator) = self.peek_operator(&['*', '/', '%']) {
+            self.position += 1;
+            let right = self.unary()?;
+            let (left, right) = Self::numbers(operator, value, right)?;
+            if operator != '*' && right == 0.0 {
+                bail!("Division by zero");
+            }
+            value = Value::Number(match operator {
+                '*' => left * right,
+                '/' => left / right,
+                _ => left % right,
+            });
+        }
+        Ok(value)
+    }
+
+    // unary := '-' unary | primary
+    fn unary(&mut self) -> Result<Value> {
+        if self.peek_operator(&['-']).is_some() {
+            self.position += 1;
+            return match self.unary()? {
+                Value::Number(number) => Ok(Value::Number(-number)),
+                value => bail!("Cannot negate {}", value),
+            };
+        }
+        self.primary()
+    }
+
+    // primary := number | string | value name | '(' expression ')'
+    fn primary(&mut self) -> Result<Value> {
+        let token = self.peek().cloned();
+        self.position += 1;
+        match token {
+            Some(TokenType::Number(number)) => number
+                .parse()
+                .map(Value::Number)
+                .map_err(|_| Error::msg(format!("Invalid number {}", number))),
+            Some(TokenType::String(string)) => {
+                Ok(Value::String(string[1..string.len() - 1].to_string()))
+            }
+            Some(TokenType::Identifier(name)) => {
+                let tokens = (self.resolve)(&name)?;
+                evaluate_tokens(tokens, &mut |_| {
+                    bail!("Values cannot refer to other values")
+                })
+                .map_err(|error| {
+                    Error::msg(format!(
+                        "The value {} cannot be used in an expression: {}",
+                        name, error
+                    ))
+                })
+            }
+            Some(TokenType::Symbol('(')) => {
+                let value = self.expression()?;
+                match self.peek() {
+                    Some(TokenType::Symbol(')')) => {
+                        self.position += 1;
+                        Ok(value)
+                    }
+                    other => bail!("Expected ), got {:?}", other),
+                }
+            }
+            other => bail!(
+                "Expected a number, a string or a value name, got {:?}",
+                other
+            ),
+        }
+    }
+}
+
+fn evaluate_tokens(
+    tokens: Vec<TokenType>,
+    resolve: &mut dyn FnMut(&str) -> Result<Vec<TokenType>>,
+) -> Result<Value> {
+    let tokens = tokens
+        .into_iter()
+        .filter(|e| {
+            !matches!(
+                e,
+                TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_)
+            )
+        })
+        .collect();
+    let mut evaluator = Evaluator {
+        tokens,
+        position: 0,
+        resolve,
+    };
+    let value = evaluator.expression()?;
+    if let Some(token) = evaluator.peek() {
+        bail!("Unexpected {}", token);
+    }
+    Ok(value)
+}
+
+/// Evaluates an expression which has already been lexed, like the value of an assignment of a
+/// `TRANSFORM` script. The expression language is the one of the templates.
+pub fn evaluate_token_expression(
+    tokens: Vec<TokenType>,
+    resolve: &mut dyn FnMut(&str) -> Result<Vec<TokenType>>,
+) -> Result<TokenType> {
+    evaluate_tokens(tokens, resolve).map(|value| value.to_token())
+}
+
+/// An assignment of a `TRANSFORM` script - `property = expression`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScriptAssignment {
+    pub property: String,
+    pub expression: Vec<TokenType>,
+}
+
+/// Joins dotted names (`font.pixelSize`) into single identifiers, and drops the tokens which
+/// aren't significant.
+fn join_dotted_names(tokens: &[TokenType]) -> Vec<TokenType> {
+    let mut joined: Vec<TokenType> = Vec::new();
+    for token in tokens {
+        match (token, joined.as_mut_slice()) {
+            (TokenType::Whitespace(_) | TokenType::Comment(_), _) => {}
+            (
+                TokenType::Identifier(name),
+                [.., TokenType::Identifier(previous), TokenType::Symbol('.')],
+            ) => {
+                *previous = format!("{}.{}", previous, name);
+                joined.pop();
+            }
+            _ => joined.push(token.clone()),
+        }
+    }
+    joined
+}
+
+/// Parses a script made of assignments (`font.pixelSize = font.pixelSize * 1.5`), separated by
+/// semicolons or new lines. The assigned values are expressions, which can refer to the values
+/// of other properties by their names.
+pub fn parse_script(code: &[TokenType]) -> Result<Vec<ScriptAssignment>> {
+    let mut statements = vec![Vec::new()];
+    let mut depth = 0usize;
+    for token in join_dotted_names(code) {
+        match token {
+            TokenType::Symbol('(') => depth += 1,
+            TokenType::Symbol(')') => depth = depth.saturating_sub(1),
+            TokenType::Symbol(';') | TokenType::NewLine(_) if depth == 0 => {
+                statements.push(Vec::new());
+                continue;
+            }
+            TokenType::NewLine(_) => continue,
+            _ => {}
+        }
+        statements.last_mut().unwrap().push(token);
+    }
+
+    let mut assignments = Vec::new();
+    for statement in statements.into_iter().filter(|e| !e.is_empty()) {
+        let assignment = match statement.as_slice() {
+            [TokenType::Identifier(property), TokenType::Unknown('='), expression @ ..]
+                if !expression.is_empty() && expression[0] != TokenType::Unknown('=') =>
+            {
+                ScriptAssignment {
+                    property: property.clone(),
+                    expression: expression.to_vec(),
+                }
+            }
+            _ => {
+                let statement: Vec<String> = statement.iter().map(|e| e.to_string()).collect();
+                bail!(
+                    "Expected <property> = <expression>, got {}",
+                    statement.join(" ")
+                );
+            }
+        };
+        assignments.push(assignment);
+    }
+    if assignments.is_empty() {
+        bail!("The script doesn't assign anything");
+    }
+    Ok(assignments)
+}
+
+/// Evaluates an expression used in a template, like `~{width / 2}~` or `~{"Item " + name}~`.
+/// Numbers support `+`, `-`, `*`, `/` and `%`, strings can be joined with `+`. Names refer to the
+/// values of the template invocation, which are resolved with `resolve` - they have to be numbers
+/// or strings themselves.
+pub fn evaluate_expression(
+    expression: &str,
+    resolve: &mut dyn FnMut(&str) -> Result<Vec<TokenType>>,
+) -> Result<TokenType> {
+    Lexer::new(StringCharacterTokenizer::new(expression.to_string()))
+        .tokenize()
+        .and_then(|tokens| evaluate_tokens(tokens, resolve))
+        .map(|value| value.to_token())
+        .map_err(|error| {
+            Error::msg(format!(
+                "Cannot evaluate the template expression {}: {}",
+                expression.trim(),
+                error
+            ))
+        })
+}
@@ -1,14 +1,34 @@
+#[cfg(feature = "reporting")]
+use std::collections::BTreeSet;
 use std::{
     fs::{read_dir, read_to_string, write},
     path::Path,
 };
 
+#[cfg(feature = "reporting")]
 use crate::{
-    parser::qml::emitter::{emit, flatten_lines, Line},
-    util::common_util::parse_qml,
+    corpus::{generate_corpus, CorpusConfig},
+    hash::hash,
+    hashtab::InvHashTab,
+    outline::outline_tree,
+    qml_compare::{compare_trees, QMLDifference},
+};
+use crate::{
+    parser::{
+        common::StringCharacterTokenizer,
+        diff::parser::NodeTree,
+        qml::{
+            emitter::{emit, flatten_lines, CanonicalEmitter, Emitter, EmitterConfig, Line},
+            lexer::{Lexer, QMLDiffExtensions, QMLExtensionToken, TokenType},
+            parser::{ObjectChild, TreeElement},
+            template_expression::{evaluate_expression, evaluate_token_expression, parse_script},
+        },
+    },
+    refcell_translation::{translate_from_root, untranslate_from_root},
+    util::common_util::{parse_qml, SourceFormat},
 };
 
-fn destroy_indents(lines: &mut Vec<Line>) {
+fn destroy_indents(lines: &mut [Line]) {
     lines.iter_mut().for_each(|e| e.indent = 0);
 }
 
@@ -20,15 +40,25 @@ fn destroy_indents(lines: &mut Vec<Line>) {
 fn test_qml_parser_on_file(file: &Path) {
     let contents = read_to_string(file).unwrap();
     print!("Testing the qml parser on file: {}... ", file.display());
-    let ast_first_pass = parse_qml(contents, file.to_str().unwrap(), None, None).unwrap();
-    let mut lines_first_emit = emit(&ast_first_pass);
+    test_qml_parser_on_string(contents, file.to_str().unwrap());
+}
+
+fn test_qml_parser_on_string(contents: String, name: &str) {
+    let ast_first_pass = parse_qml(contents, name, None, None, QMLDiffExtensions::NONE).unwrap();
+    let mut lines_first_emit = emit(&ast_first_pass, &EmitterConfig::default());
     destroy_indents(&mut lines_first_emit);
     let emit_first_pass = flatten_lines(&lines_first_emit)
         .replace(" instanceof ", "instanceof")
         .replace(" new ", "new");
-    let ast_second_pass =
-        parse_qml(emit_first_pass.clone(), file.to_str().unwrap(), None, None).unwrap();
-    let mut lines_second_emit = emit(&ast_second_pass);
+    let ast_second_pass = parse_qml(
+        emit_first_pass.clone(),
+        name,
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    let mut lines_second_emit = emit(&ast_second_pass, &EmitterConfig::default());
     destroy_indents(&mut lines_second_emit);
     let emit_second_pass = flatten_lines(&lines_second_emit)
         .replace(" instanceof ", "instanceof")
@@ -45,8 +75,8 @@ fn test_qml_parser_on_file(file: &Path) {
     println!("OK!");
 }
 
-const TEST_DIR: &'static str = "/ram/test_qml_root";
-const OUTPUT_DIR: &'static str = "/ram/";
+const TEST_DIR: &str = "/ram/test_qml_root";
+const OUTPUT_DIR: &str = "/ram/";
 
 fn test_recursively(dir: &Path) {
     println!("Recursing into {}...", dir.display());
@@ -70,3 +100,477 @@ fn test_recursively(dir: &Path) {
 fn test_qml_parser_recursively() {
     test_recursively(Path::new(TEST_DIR));
 }
+
+#[cfg(feature = "reporting")]
+#[test]
+fn test_qml_parser_on_generated_corpus() {
+    let config = CorpusConfig {
+        files: 5,
+        ..CorpusConfig::default()
+    };
+    for (name, contents) in generate_corpus(&config) {
+        print!("Testing the qml parser on generated file: {}... ", name);
+        test_qml_parser_on_string(contents, &name);
+    }
+}
+
+const FIRST_FORMATTING: &str = "Item {\n    id: root // the root\n    function foo(a,   b) {\n        return a +   b;\n\n    }\n}\n";
+const SECOND_FORMATTING: &str =
+    "Item { id: root\nfunction foo(a, b)   { /* sum */\n  return a + b;  \n}\n}";
+
+#[test]
+fn test_canonical_emitter_ignores_formatting() {
+    let first = parse_qml(
+        FIRST_FORMATTING.into(),
+        "first.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    let second = parse_qml(
+        SECOND_FORMATTING.into(),
+        "second.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    assert_eq!(
+        CanonicalEmitter.emit_tree(&first),
+        CanonicalEmitter.emit_tree(&second)
+    );
+}
+
+#[test]
+fn test_template_expressions() {
+    let evaluate = |expression: &str| {
+        evaluate_expression(expression, &mut |name| {
+            let value = match name {
+                "width" => "300",
+                "name" => "'Box'",
+                _ => anyhow::bail!("Unknown value {}", name),
+            };
+            Lexer::new(StringCharacterTokenizer::new(value.to_string())).tokenize()
+        })
+    };
+    let number = |value: &str| Some(TokenType::Number(value.to_string()));
+    assert_eq!(evaluate("width / 2").ok(), number("150"));
+    assert_eq!(evaluate("(width - 100) * -1.5 + 1").ok(), number("-299"));
+    assert_eq!(evaluate("7 % 4").ok(), number("3"));
+    assert_eq!(
+        evaluate("name + \"_\" + width / 4").ok(),
+        Some(TokenType::String("\"Box_75\"".to_string()))
+    );
+
+    assert!(evaluate("width / 0").is_err());
+    assert!(evaluate("name * 2").is_err());
+    assert!(evaluate("height + 1").is_err());
+    assert!(evaluate("(width").is_err());
+}
+
+#[test]
+fn test_transform_scripts() {
+    let lex = |code: &str| -> Vec<TokenType> {
+        Lexer::new(StringCharacterTokenizer::new(code.to_string()))
+            .tokenize()
+            .unwrap()
+    };
+    let script = parse_script(&lex(
+        "font.pixelSize = font.pixelSize * (1 +\n 0.5) // bigger\nwidth = 3; text = \"a\"",
+    ))
+    .unwrap();
+    let properties: Vec<_> = script.iter().map(|e| e.property.as_str()).collect();
+    assert_eq!(properties, ["font.pixelSize", "width", "text"]);
+    let value = evaluate_token_expression(script[0].expression.clone(), &mut |name| {
+        assert_eq!(name, "font.pixelSize");
+        Ok(lex("12"))
+    });
+    assert_eq!(value.ok(), Some(TokenType::Number("18".to_string())));
+
+    assert!(parse_script(&lex("width == 3")).is_err());
+    assert!(parse_script(&lex("width")).is_err());
+    assert!(parse_script(&lex("// nothing")).is_err());
+}
+
+#[test]
+fn test_translated_tree_lookup() {
+    let source = "Item {\n    id: root\n    Text { id: title; text: \"Hello\" }\n    Column {\n        Text { text: 'a' + 'b' }\n    }\n    property int count: 3 /* three */\n}\n";
+    let tree = translate_from_root(
+        parse_qml(
+            source.into(),
+            "Main.qml",
+            None,
+            None,
+            QMLDiffExtensions::NONE,
+        )
+        .unwrap(),
+    );
+    let texts = tree.find_all(&NodeTree::parse("Item > Text").unwrap());
+    assert_eq!(texts.len(), 1);
+    assert_eq!(texts[0].borrow().id().as_deref(), Some("title"));
+    assert_eq!(
+        texts[0].borrow().get_property_value("text").as_deref(),
+        Some("\"Hello\"")
+    );
+    let nested = tree.find_all(&NodeTree::parse("Item#root > Column > Text").unwrap());
+    assert_eq!(
+        nested[0].borrow().get_property_value("text").as_deref(),
+        Some("'a'+'b'")
+    );
+
+    let root = tree.find_all(&NodeTree::parse("Item").unwrap());
+    let root = root[0].borrow();
+    assert_eq!(root.get_property_value("count").as_deref(), Some("3"));
+    assert_eq!(root.children_named("count").count(), 1);
+    assert!(root.get_property_value("width").is_none());
+}
+
+#[test]
+fn test_top_level_components() {
+    let source = "pragma Singleton\nimport QtQuick 2.15\nItem {\n    id: root\n}\ncomponent Badge: Rectangle {\n    radius: 4\n}\n";
+    test_qml_parser_on_string(source.to_string(), "Main.qml");
+    let tree = parse_qml(
+        source.into(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    assert!(matches!(
+        &tree[3],
+        TreeElement::Component(component) if component.name == "Badge" && component.object.name == "Rectangle"
+    ));
+    // The components next to the root object survive the translation.
+    assert_eq!(
+        untranslate_from_root(translate_from_root(tree.clone())),
+        tree
+    );
+}
+
+#[test]
+fn test_line_wrapping() {
+    let source = "Item {\n    visible: root.width > 100 && root.height > 100 && settings.enabled && !root.locked\n    property string label: qsTr(\"%1 of %2\").arg(count,total) + suffix\n    x: offsetOfTheFirstVisibleItemInList++ + m\n}\n";
+    let tree = parse_qml(
+        source.into(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    let config = EmitterConfig {
+        max_line_width: 40,
+        ..EmitterConfig::default()
+    };
+    let wrapped = config.emit_tree(&tree);
+    assert_eq!(
+        wrapped.lines().take(4).collect::<Vec<_>>(),
+        [
+            "Item {",
+            "    visible: root.width > 100 &&",
+            "        root.height > 100 &&",
+            "        settings.enabled && !root.locked",
+        ]
+    );
+    assert!(wrapped.contains("qsTr(\"%1 of %2\").arg(count,\n        total) + suffix\n"));
+    // A postfix `++` can't end a line.
+    assert!(wrapped.contains("    x: offsetOfTheFirstVisibleItemInList++ +\n        m\n"));
+    // Only the whitespace changes.
+    let unwrapped = EmitterConfig::default().emit_tree(&tree);
+    assert_eq!(
+        wrapped
+            .replace(",\n        ", ",")
+            .replace("\n        ", " "),
+        unwrapped
+    );
+}
+
+#[test]
+fn test_minify() {
+    let source = "Item {\n    // The size\n    width:   100 *  2\n\n    function toggle(a,  b) {\n        if (a)    {\n\n            b = 1 // Set\n        }\n    }\n    Rectangle {\n        color: \"a  b\"\n    }\n}\n";
+    let tree = parse_qml(
+        source.into(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    let config = EmitterConfig {
+        minify: true,
+        ..EmitterConfig::default()
+    };
+    let minified = config.emit_tree(&tree);
+    // The strings and the line breaks of the code stay as they are.
+    assert_eq!(
+        minified,
+        "Item {\nwidth: 100 * 2\nfunction toggle(a, b) {\nif (a) {\nb = 1 // Set\n}\n}\nRectangle {\ncolor: \"a  b\"\n}\n}"
+    );
+    let reparsed = parse_qml(
+        minified.clone(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    assert_eq!(config.emit_tree(&reparsed), minified);
+}
+
+#[test]
+fn test_strict_extensions() {
+    let code = "text: ~{label}~";
+    let lex = |extensions| {
+        Lexer::with_extensions(StringCharacterTokenizer::new(code.into()), extensions)
+            .collect::<Vec<_>>()
+    };
+    assert!(
+        lex(QMLDiffExtensions::ALL).contains(&TokenType::Extension(QMLExtensionToken::Slot(
+            "label".into()
+        )))
+    );
+    // Vendor code is plain QML - the same text is just symbols and an identifier there.
+    let strict = lex(QMLDiffExtensions::NONE);
+    assert!(!strict.iter().any(|e| matches!(e, TokenType::Extension(_))));
+    assert_eq!(
+        strict.iter().map(|e| e.to_string()).collect::<String>(),
+        code
+    );
+}
+
+#[test]
+fn test_grouped_properties() {
+    let source = "Item {\n    Text {\n        anchors { left: parent.left; right: parent.right } // }\n        font {\n            // The size {\n            pixelSize: size > 2 ? 3 : { a: 1 }.a\n            family: \"a } b\" /* } */\n        }\n        border { width: 1 }\n        anchors.margins: 4\n    }\n}\n";
+    test_qml_parser_on_string(source.to_string(), "Main.qml");
+    let tree = translate_from_root(
+        parse_qml(
+            source.into(),
+            "Main.qml",
+            None,
+            None,
+            QMLDiffExtensions::NONE,
+        )
+        .unwrap(),
+    );
+    let group = |tree_selector: &str| {
+        let found = tree.find_all(&NodeTree::parse(tree_selector).unwrap());
+        assert_eq!(found.len(), 1, "{}", tree_selector);
+        found[0].clone()
+    };
+    let anchors = group("Item > Text > anchors");
+    assert_eq!(
+        anchors.borrow().get_property_value("left").as_deref(),
+        Some("parent.left")
+    );
+    assert_eq!(
+        anchors.borrow().get_property_value("right").as_deref(),
+        Some("parent.right")
+    );
+    let font = group("Item > Text > font");
+    assert_eq!(font.borrow().children.len(), 2);
+    assert_eq!(
+        font.borrow().get_property_value("family").as_deref(),
+        Some("\"a } b\"")
+    );
+    let border = group("Item > Text > border");
+    assert_eq!(
+        border.borrow().get_property_value("width").as_deref(),
+        Some("1")
+    );
+    let text = group("Item > Text");
+    assert_eq!(
+        text.borrow()
+            .get_property_value("anchors.margins")
+            .as_deref(),
+        Some("4")
+    );
+}
+
+#[test]
+fn test_object_lists() {
+    let source = "Item {\n    states: [\n        State { name: \"on\" },\n        State {\n            name: \"off\"\n            PropertyChanges { target: root }\n        },\n    ]\n    transitions: [Transition {}]\n    mixed: [State {}, 3]\n    first: [a][0]\n    nested: [[State {}]]\n    empty: []\n}\n";
+    test_qml_parser_on_string(source.to_string(), "Main.qml");
+    let parse = |source: String| {
+        let mut tree = parse_qml(source, "Main.qml", None, None, QMLDiffExtensions::NONE).unwrap();
+        let Some(TreeElement::Object(object)) = tree.pop() else {
+            panic!("Expected an object");
+        };
+        object.children
+    };
+    let lists = |children: &[ObjectChild]| {
+        children
+            .iter()
+            .map(|e| match e {
+                ObjectChild::ObjectListAssignment(list) => Some((
+                    list.name.clone(),
+                    list.values
+                        .iter()
+                        .map(|e| e.name.clone())
+                        .collect::<Vec<_>>(),
+                )),
+                ObjectChild::Assignment(_) => None,
+                other => panic!("Unexpected child {:?}", other),
+            })
+            .collect::<Vec<_>>()
+    };
+    let children = parse(source.into());
+    let expected = vec![
+        Some((
+            "states".to_string(),
+            vec!["State".to_string(), "State".into()],
+        )),
+        Some(("transitions".into(), vec!["Transition".into()])),
+        // Lists which aren't made up only of objects are kept as they are.
+        None,
+        None,
+        None,
+        None,
+    ];
+    assert_eq!(lists(&children), expected);
+    let ObjectChild::ObjectListAssignment(states) = &children[0] else {
+        unreachable!()
+    };
+    assert!(matches!(
+        &states.values[1].children[..],
+        [ObjectChild::Assignment(_), ObjectChild::Object(changes)] if changes.name == "PropertyChanges"
+    ));
+
+    // The emitted lists are parsed into lists of objects again.
+    let emitted = flatten_lines(&emit(
+        &parse_qml(
+            source.into(),
+            "Main.qml",
+            None,
+            None,
+            QMLDiffExtensions::NONE,
+        )
+        .unwrap(),
+        &EmitterConfig::default(),
+    ));
+    assert_eq!(lists(&parse(emitted)), expected);
+    // So are the lists which went through the translation.
+    let tree = parse_qml(
+        source.into(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    assert_eq!(
+        untranslate_from_root(translate_from_root(tree.clone())),
+        tree
+    );
+}
+
+#[test]
+fn test_source_format_is_preserved() {
+    let source = "\u{feff}Item {\r\n    function foo() {\r\n        return 1;\r\n    }\r\n}\r\n";
+    // The byte order mark is skipped even if the format isn't detected first.
+    assert!(parse_qml(
+        source.into(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE
+    )
+    .is_ok());
+
+    let (format, contents) = SourceFormat::detect(source);
+    assert!(format.bom && format.crlf);
+    let tree = parse_qml(
+        contents.into(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    let emitted = format.restore(EmitterConfig::default().emit_tree(&tree));
+    assert!(emitted.starts_with('\u{feff}'));
+    assert!(!emitted.replace("\r\n", "").contains(['\r', '\n']));
+
+    let (format, _) = SourceFormat::detect("Item {\n}\r\n");
+    assert_eq!(format, SourceFormat::default());
+}
+
+#[cfg(feature = "reporting")]
+#[test]
+fn test_compare_trees_ignores_formatting() {
+    let first = parse_qml(
+        FIRST_FORMATTING.into(),
+        "first.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    let second = parse_qml(
+        SECOND_FORMATTING.into(),
+        "second.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    assert!(compare_trees(&first, &second).is_empty());
+
+    let third = "Item {\n    id: root\n    function foo(a, b) {\n        return a - b;\n    }\n}\n";
+    let third = parse_qml(
+        third.into(),
+        "third.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    assert!(matches!(
+        compare_trees(&first, &third).as_slice(),
+        [QMLDifference::Changed(path, _, _)] if path == "Item#root > function foo"
+    ));
+}
+
+#[cfg(feature = "reporting")]
+#[test]
+fn test_outline() {
+    let source = "Item {\n    id: root\n    property bool checked: false\n    signal toggled()\n    function toggle() { checked = !checked }\n    Rectangle { id: background; color: \"red\" }\n    ListView { delegate: Text {} }\n}\n";
+    let tree = parse_qml(
+        source.into(),
+        "Main.qml",
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )
+    .unwrap();
+    let outline = outline_tree(&tree);
+    assert_eq!(
+        outline[0].to_string(),
+        "Item#root\n    property checked\n    signal toggled\n    function toggle\n    Rectangle#background\n        assignment color\n    ListView\n        delegate: Text\n"
+    );
+    assert_eq!(
+        outline[0].children[0].to_json(),
+        "{\"type\":\"Rectangle\",\"id\":\"background\",\"holder\":null,\"component\":null,\"members\":[{\"kind\":\"assignment\",\"name\":\"color\"}],\"children\":[]}"
+    );
+
+    // Names missing from the hashtab are kept.
+    let inv_hashtab: InvHashTab = ["Rectangle", "background", "color"]
+        .into_iter()
+        .map(|e| (e.to_string(), hash(e)))
+        .collect();
+    let mut missing = BTreeSet::new();
+    let hashed = outline[0].children[0].hashed(&inv_hashtab, &mut missing);
+    assert_eq!(
+        hashed.to_string(),
+        format!(
+            "[[{}]]#[[{}]]\n    assignment [[{}]]\n",
+            hash("Rectangle"),
+            hash("background"),
+            hash("color")
+        )
+    );
+    assert!(missing.is_empty());
+    outline[0].hashed(&inv_hashtab, &mut missing);
+    assert!(missing.contains("Item") && missing.contains("delegate"));
+}
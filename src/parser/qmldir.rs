@@ -0,0 +1,213 @@
+use alloc::{
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt::Display;
+
+/// Keywords which start qmldir lines that do not declare a type.
+const NON_TYPE_KEYWORDS: &[&str] = &[
+    "module",
+    "plugin",
+    "optional",
+    "classname",
+    "typeinfo",
+    "depends",
+    "import",
+    "designersupported",
+    "linktarget",
+    "prefer",
+    "default",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QmlDirEntry {
+    Singleton {
+        name: String,
+        version: String,
+        file: String,
+    },
+    Plugin {
+        name: String,
+        path: Option<String>,
+    },
+    /// Every other line (module declaration, types, comments...), kept verbatim.
+    Other(String),
+}
+
+impl Display for QmlDirEntry {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Self::Singleton {
+                name,
+                version,
+                file,
+            } => write!(f, "singleton {} {} {}", name, version, file),
+            Self::Plugin {
+                name,
+                path: Some(path),
+            } => write!(f, "plugin {} {}", name, path),
+            Self::Plugin { name, path: None } => write!(f, "plugin {}", name),
+            Self::Other(line) => f.write_str(line),
+        }
+    }
+}
+
+impl QmlDirEntry {
+    pub fn parse(line: &str) -> Self {
+        let words = line.split_whitespace().collect::<Vec<_>>();
+        match words.as_slice() {
+            ["singleton", name, version, file] => Self::Singleton {
+                name: name.to_string(),
+                version: version.to_string(),
+                file: file.to_string(),
+            },
+            ["plugin", name] => Self::Plugin {
+                name: name.to_string(),
+                path: None,
+            },
+            ["plugin", name, path] => Self::Plugin {
+                name: name.to_string(),
+                path: Some(path.to_string()),
+            },
+            _ => Self::Other(line.to_string()),
+        }
+    }
+
+    /// The name of the type or plugin declared by this entry, if any.
+    pub fn name(&self) -> Option<&str> {
+        match self {
+            Self::Singleton { name, .. } | Self::Plugin { name, .. } => Some(name),
+            Self::Other(line) => {
+                let words = line.split_whitespace().collect::<Vec<_>>();
+                match words.as_slice() {
+                    [first, ..] if first.starts_with('#') => None,
+                    ["internal", name, _] => Some(name),
+                    [first, _, _] if !NON_TYPE_KEYWORDS.contains(first) => Some(first),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct QmlDir(pub Vec<QmlDirEntry>);
+
+impl QmlDir {
+    pub fn parse(contents: &str) -> Self {
+        Self(contents.lines().map(QmlDirEntry::parse).collect())
+    }
+
+    /// Adds the entry, replacing any entry of the same kind which declares the same name.
+    pub fn add(&mut self, entry: QmlDirEntry) {
+        let existing = self.0.iter_mut().find(|e| {
+            core::mem::discriminant(*e) == core::mem::discriminant(&entry)
+                && e.name() == entry.name()
+        });
+        match existing {
+            Some(existing) => *existing = entry,
+            None => self.0.push(entry),
+        }
+    }
+
+    /// Removes all the entries declaring `name`, returning them.
+    pub fn remove(&mut self, name: &str) -> Vec<QmlDirEntry> {
+        let (removed, kept) = core::mem::take(&mut self.0)
+            .into_iter()
+            .partition(|e| e.name() == Some(name));
+        self.0 = kept;
+        removed
+    }
+}
+
+impl Display for QmlDir {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for entry in &self.0 {
+            writeln!(f, "{}", entry)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn is_qmldir(file_name: &str) -> bool {
+    file_name.ends_with("qmldir")
+}
+
+#[cfg(test)]
+mod test {
+    use alloc::{string::ToString, vec};
+
+    use super::{QmlDir, QmlDirEntry};
+
+    const QMLDIR: &str = "module Theme\n# The colors\nColors 1.0 Colors.qml\ninternal Helper Helper.qml\nsingleton Fonts 1.0 Fonts.qml\nplugin themeplugin\nplugin other lib\ntypeinfo plugins.qmltypes\n";
+
+    #[test]
+    fn test_qmldir_parsing() {
+        let qmldir = QmlDir::parse(QMLDIR);
+        assert_eq!(
+            qmldir.0[4],
+            QmlDirEntry::Singleton {
+                name: "Fonts".into(),
+                version: "1.0".into(),
+                file: "Fonts.qml".into(),
+            }
+        );
+        assert_eq!(
+            qmldir.0[5],
+            QmlDirEntry::Plugin {
+                name: "themeplugin".into(),
+                path: None,
+            }
+        );
+        assert_eq!(
+            qmldir.0[6],
+            QmlDirEntry::Plugin {
+                name: "other".into(),
+                path: Some("lib".into()),
+            }
+        );
+        assert_eq!(
+            qmldir.0.iter().map(|e| e.name()).collect::<vec::Vec<_>>(),
+            vec![
+                None,
+                None,
+                Some("Colors"),
+                Some("Helper"),
+                Some("Fonts"),
+                Some("themeplugin"),
+                Some("other"),
+                None
+            ]
+        );
+        // Emitting a file which wasn't changed gives it back as it was.
+        assert_eq!(qmldir.to_string(), QMLDIR);
+    }
+
+    #[test]
+    fn test_qmldir_add_and_remove() {
+        let mut qmldir = QmlDir::parse(QMLDIR);
+        qmldir.add(QmlDirEntry::parse("singleton Fonts 2.0 Fonts2.qml"));
+        qmldir.add(QmlDirEntry::parse("singleton Spacing 1.0 Spacing.qml"));
+        // A plugin doesn't replace a type of the same name.
+        qmldir.add(QmlDirEntry::parse("plugin Colors"));
+        assert_eq!(qmldir.0[4].to_string(), "singleton Fonts 2.0 Fonts2.qml");
+        assert_eq!(qmldir.0.len(), 10);
+
+        let removed = qmldir.remove("Colors");
+        assert_eq!(
+            removed,
+            vec![
+                QmlDirEntry::Other("Colors 1.0 Colors.qml".into()),
+                QmlDirEntry::Plugin {
+                    name: "Colors".into(),
+                    path: None,
+                },
+            ]
+        );
+        assert!(qmldir.remove("Missing").is_empty());
+        assert_eq!(
+            qmldir.to_string(),
+            "module Theme\n# The colors\ninternal Helper Helper.qml\nsingleton Fonts 2.0 Fonts2.qml\nplugin themeplugin\nplugin other lib\ntypeinfo plugins.qmltypes\nsingleton Spacing 1.0 Spacing.qml\n"
+        );
+    }
+}
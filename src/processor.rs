@@ -1,31 +1,52 @@
+use std::borrow::Cow;
 use std::cell::RefCell;
+use std::collections::BTreeSet;
 use std::mem::take;
+use std::ops::Range;
 use std::rc::Rc;
-use std::usize;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
-use crate::parser::common::IteratorPipeline;
+use crate::id_check::{IdCheck, IdTracker};
+use crate::import_check::{used_types, ImportCheck, MissingImports};
+use crate::journal::{ChangeTrace, DirectiveTrace, JournalEntry, MutationJournal};
+
+use crate::parser::common::{IteratorPipeline, StringCharacterTokenizer};
 use crate::parser::diff::lexer::Keyword;
 use crate::parser::diff::parser::{
-    FileChangeAction, Insertable, LocateRebuildActionSelector, Location, LocationSelector,
-    ObjectToChange, RebuildAction, RebuildInstruction, RemoveRebuildAction,
-    ReplaceRebuildActionWhat,
+    qml_stream_to_string, EnumValueAction, FileChangeAction, Insertable,
+    LocateRebuildActionSelector, Location, LocationSelector, MemberKind, ObjectToChange,
+    RebuildAction, RebuildInstruction, RemoveRebuildAction, ReplaceRebuildActionWhat,
+    ReplaceStringAction, TransformAction,
 };
 use crate::parser::diff::parser::{NodeSelector, NodeTree, PropRequirement};
 use crate::parser::qml::emitter::{
-    emit_object_to_token_stream, emit_string, emit_token_stream, flatten_lines,
+    emit_object_list_to_token_stream, emit_object_to_token_stream, Emitter,
+};
+use crate::parser::qml::lexer::{Lexer, TokenType};
+use crate::parser::qml::parser::{
+    parse_object_list, AssignmentChild, AssignmentChildValue, Import, Object, ObjectChild,
+    ObjectListAssignmentChild, Pragma, TreeElement,
 };
-use crate::parser::qml::lexer::TokenType;
-use crate::parser::qml::parser::{AssignmentChildValue, Import, Object, ObjectChild, TreeElement};
 use crate::parser::qml::slot_extensions::QMLSlotRemapper;
+use crate::parser::qml::template_expression::evaluate_token_expression;
+use crate::parser::qmldir::{QmlDir, QmlDirEntry};
 use crate::refcell_translation::{
     translate, translate_from_root, translate_object_child, untranslate, untranslate_from_root,
     untranslate_object_child, TranslatedEnumChild, TranslatedObject,
     TranslatedObjectAssignmentChild, TranslatedObjectChild, TranslatedObjectRef, TranslatedTree,
 };
 use crate::slots::Slots;
+use crate::translation::{escape_string_literal, unescape_string_literal, TranslationCatalog};
+use crate::undo::{
+    child_code, child_selector, reinsert_directives, replace_directive, traverse_directive,
+};
 use crate::util::common_util::{
-    add_error_source_if_needed, parse_qml_from_chain, parse_qml_into_simple_object,
+    add_directive_if_needed, add_error_source_if_needed, kind_error, parse_qml_from_chain,
+    parse_qml_into_simple_object, ErrorKind,
 };
+use crate::util::output::warning;
 
 use anyhow::{bail, Error, Result};
 
@@ -37,32 +58,103 @@ macro_rules! traverse_no_raw_children {
     };
 }
 
+/// When the `AFFECT REBUILD` changes of a file are applied, if it's changed by `AFFECT` too.
+/// `AFFECT REBUILD` edits the tokens of the file, so it cannot follow a change made to the parsed
+/// file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RebuildOrder {
+    /// Before all the `AFFECT` changes, no matter where they are in the order of the changes.
+    #[default]
+    First,
+    /// In the order of the changes - an `AFFECT REBUILD` following an `AFFECT` of the same file
+    /// fails.
+    Strict,
+}
+
+impl FromStr for RebuildOrder {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "first" => Ok(Self::First),
+            "strict" => Ok(Self::Strict),
+            _ => Err(Error::msg(format!(
+                "Unknown rebuild order {} - expected first or strict",
+                s
+            ))),
+        }
+    }
+}
+
+/// Applies the changes affecting `file_name` to the tokens of the file. Returns the new contents
+/// of the file - `None` if no change modified it - and the sources of the changes which modified
+/// it, one per change.
+#[allow(clippy::too_many_arguments)]
 pub fn find_and_process(
     file_name: &str,
     mut token_stream: Vec<TokenType>,
-    diffs: &Vec<Change>,
+    diffs: &[Change],
     slots: &mut Slots,
-) -> Result<(String, usize)> {
+    mut journal: Option<&mut MutationJournal>,
+    mut trace: Option<&mut Vec<ChangeTrace>>,
+    emitter: &dyn Emitter,
+    id_check: IdCheck,
+    import_check: Option<&ImportCheck>,
+    rebuild_order: RebuildOrder,
+) -> Result<(Option<String>, Vec<Arc<String>>)> {
     let mut qml: Option<TranslatedTree> = None;
-    let mut count = 0;
+    let mut ids: Option<IdTracker> = None;
+    let mut original_types = None;
+    let mut applied = Vec::new();
+    let diffs: Vec<&Change> = match rebuild_order {
+        RebuildOrder::First => {
+            let (rebuilds, others): (Vec<&Change>, Vec<&Change>) = diffs
+                .iter()
+                .partition(|e| matches!(e.destination, ObjectToChange::FileTokenStream(_)));
+            rebuilds.into_iter().chain(others).collect()
+        }
+        RebuildOrder::Strict => diffs.iter().collect(),
+    };
     for diff in diffs {
+        let start_time = Instant::now();
+        let mut directives = Vec::new();
         match &diff.destination {
-            ObjectToChange::File(f) if f == file_name => {
+            destination if destination.affects_file(file_name) => {
                 if qml.is_none() {
-                    qml = Some(translate_from_root(parse_qml_from_chain(take(
-                        &mut token_stream,
-                    ))?));
+                    let tree = translate_from_root(parse_qml_from_chain(take(&mut token_stream))?);
+                    if id_check != IdCheck::Off {
+                        ids = Some(IdTracker::new(&tree));
+                    }
+                    if import_check.is_some() {
+                        original_types = Some(used_types(&tree));
+                    }
+                    qml = Some(tree);
                 }
-                count += 1;
-                add_error_source_if_needed(
-                    process(qml.as_mut().unwrap(), diff, slots),
+                let mutations = add_error_source_if_needed(
+                    process(
+                        qml.as_mut().unwrap(),
+                        diff,
+                        file_name,
+                        slots,
+                        journal.as_deref_mut(),
+                        trace.is_some().then_some(&mut directives),
+                    ),
                     &diff.source,
-                )?
+                )?;
+                if mutations != 0 {
+                    applied.push(diff.source.clone());
+                }
+                if let Some(ids) = &mut ids {
+                    ids.update(qml.as_ref().unwrap(), &diff.source);
+                }
             }
             ObjectToChange::FileTokenStream(f) if f == file_name => {
-                count += 1;
                 if qml.is_some() {
-                    bail!("Cannot ALTER REBUILD a file which has been ALTERed before");
+                    bail!(
+                        "Cannot AFFECT REBUILD {} (defined by '{}') - it has been changed by AFFECT before",
+                        file_name,
+                        diff.source
+                    );
                 }
                 let rebuild_instructions = if let FileChangeAction::Rebuild(r) = &diff.changes[0] {
                     r
@@ -70,58 +162,327 @@ pub fn find_and_process(
                     unreachable!()
                 };
                 add_error_source_if_needed(
-                    execute_rebuild_steps(rebuild_instructions, &mut None, &mut token_stream),
+                    add_directive_if_needed(
+                        execute_rebuild_steps(rebuild_instructions, &mut None, &mut token_stream),
+                        || format!("REBUILD {}", file_name),
+                    ),
                     &diff.source,
                 )?;
+                applied.push(diff.source.clone());
+                if let Some(journal) = journal.as_deref_mut() {
+                    journal.record(JournalEntry {
+                        file: file_name.to_string(),
+                        source: diff.source.to_string(),
+                        action: "REBUILD",
+                        scope: Vec::new(),
+                        selector: None,
+                        cursor: None,
+                        inserted: Vec::new(),
+                        removed: Vec::new(),
+                        undo: vec![format!(
+                            "; The REBUILD of {} cannot be undone - restore the file manually.",
+                            file_name
+                        )],
+                    });
+                }
+                directives.push(DirectiveTrace {
+                    directive: format!("REBUILD {}", file_name),
+                    matched: Vec::new(),
+                });
             }
-            _ => {}
+            _ => continue,
+        }
+        if let Some(trace) = trace.as_deref_mut() {
+            trace.push(ChangeTrace {
+                source: diff.source.to_string(),
+                directives,
+                duration: start_time.elapsed(),
+            });
         }
     }
 
+    // Re-emitting a file nothing was changed in would only lose its formatting.
+    if applied.is_empty() {
+        return Ok((None, applied));
+    }
+    let duplicates = ids.map(|e| e.duplicates()).unwrap_or_default();
+    if !duplicates.is_empty() {
+        let message = format!(
+            "Duplicate ids in {}: {}",
+            file_name,
+            duplicates
+                .iter()
+                .map(|e| e.to_string())
+                .collect::<Vec<_>>()
+                .join("; ")
+        );
+        if id_check == IdCheck::Error {
+            let error = kind_error(ErrorKind::Validation, message);
+            return add_error_source_if_needed(Err(error), &duplicates[0].sources[0]);
+        }
+        warning!("Warning: {}", message);
+    }
+    if let (Some(check), Some(tree), Some(original_types)) =
+        (import_check, qml.as_mut(), &original_types)
+    {
+        let missing = check.missing_imports(tree, original_types);
+        if !missing.is_empty() && check.mode == MissingImports::Add {
+            ImportCheck::add_imports(tree, &missing);
+        } else if !missing.is_empty() {
+            warning!(
+                "Warning: {} uses types whose modules it doesn't import: {}",
+                file_name,
+                missing
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+    }
     if let Some(qml) = qml {
-        Ok((emit_string(&untranslate_from_root(qml)), count))
+        let emitted = emitter.emit_tree(&untranslate_from_root(qml));
+        Ok((Some(emitted), applied))
     } else {
-        Ok((flatten_lines(&emit_token_stream(&token_stream, 0)), count))
+        Ok((Some(emitter.emit_tokens(&token_stream)), applied))
     }
 }
 
+/// Builds the contents of a file created by a `CREATE` statement, or returns `None` if no
+/// change creates `file_name`. Slots and templates within the code are expanded.
+pub fn build_created_file(
+    file_name: &str,
+    diffs: &[Change],
+    slots: &mut Slots,
+    emitter: &dyn Emitter,
+) -> Result<Option<String>> {
+    let mut creating = diffs
+        .iter()
+        .filter(|e| matches!(&e.destination, ObjectToChange::NewFile(f) if f == file_name));
+    let Some(diff) = creating.next() else {
+        return Ok(None);
+    };
+    if let Some(other) = creating.next() {
+        bail!(
+            "Cannot CREATE {} twice (defined by '{}' and '{}')",
+            file_name,
+            diff.source,
+            other.source
+        );
+    }
+    let code = match &diff.changes[..] {
+        [FileChangeAction::Insert(Insertable::Code(code))] => code.clone(),
+        _ => unreachable!(),
+    };
+    let mut raw_qml = IteratorPipeline::new(Box::new(code.into_iter()), file_name);
+    let mut slot_resolver = QMLSlotRemapper::new(slots);
+    raw_qml.add_remapper(&mut slot_resolver);
+    let tree = add_error_source_if_needed(parse_qml_from_chain(raw_qml.collect()), &diff.source)?;
+    Ok(Some(emitter.emit_tree(&tree)))
+}
+
+/// Finds the file whose original contents `file_name` is a copy of, if it's the target of a
+/// `REDIRECT` statement.
+pub fn find_redirect_source(file_name: &str, diffs: &[Change]) -> Result<Option<String>> {
+    let mut redirects = diffs.iter().filter_map(|e| match &e.destination {
+        ObjectToChange::Redirect {
+            original,
+            redirected,
+        } if redirected == file_name => Some((original, &e.source)),
+        _ => None,
+    });
+    let Some((original, source)) = redirects.next() else {
+        return Ok(None);
+    };
+    if let Some((other, other_source)) = redirects.next() {
+        bail!(
+            "Cannot REDIRECT both {} and {} to {} (defined by '{}' and '{}')",
+            original,
+            other,
+            file_name,
+            source,
+            other_source
+        );
+    }
+    Ok(Some(original.clone()))
+}
+
+/// The files the changes `AFFECT`, sorted and without duplicates. Files created by `CREATE` and
+/// `REDIRECT` statements are not included, and neither are `AFFECT SUFFIX` statements - they
+/// don't name a single file.
+pub fn modified_files(diffs: &[Change]) -> impl Iterator<Item = &str> {
+    diffs
+        .iter()
+        .filter_map(|e| match &e.destination {
+            ObjectToChange::File(f) | ObjectToChange::FileTokenStream(f) => Some(f.as_str()),
+            _ => None,
+        })
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+}
+
+/// Applies the changes to a qmldir file. Unlike QML files, these are not tokenized.
+/// The file is only re-emitted if the changes actually modified it - like `find_and_process`, the
+/// sources of the changes which did are returned along with it.
+pub fn find_and_process_qmldir(
+    file_name: &str,
+    contents: &str,
+    diffs: &[Change],
+    mut journal: Option<&mut MutationJournal>,
+) -> Result<(Option<String>, Vec<Arc<String>>)> {
+    let mut qmldir = QmlDir::parse(contents);
+    let mut applied = Vec::new();
+    for diff in diffs {
+        match &diff.destination {
+            destination if destination.affects_file(file_name) => {
+                let mut mutations = 0;
+                for change in &diff.changes {
+                    let (action, selector, inserted, removed, undo) = match change {
+                        FileChangeAction::AddQmlDirEntry(entry) => {
+                            qmldir.add(entry.clone());
+                            let name = entry.name().unwrap_or_default().to_string();
+                            (
+                                "ADD",
+                                None,
+                                vec![entry.to_string()],
+                                Vec::new(),
+                                vec![format!("REMOVE ENTRY {}", name)],
+                            )
+                        }
+                        FileChangeAction::RemoveQmlDirEntry(name) => {
+                            let removed = qmldir.remove(name);
+                            let undo = removed
+                                .iter()
+                                .map(|e| match e {
+                                    QmlDirEntry::Singleton {
+                                        name,
+                                        version,
+                                        file,
+                                    } => format!("ADD SINGLETON {} {} {}", name, version, file),
+                                    QmlDirEntry::Plugin {
+                                        name,
+                                        path: Some(path),
+                                    } => format!("ADD PLUGIN {} {}", name, path),
+                                    QmlDirEntry::Plugin { name, path: None } => {
+                                        format!("ADD PLUGIN {}", name)
+                                    }
+                                    QmlDirEntry::Other(line) => {
+                                        format!(
+                                            "; The entry '{}' cannot be restored automatically.",
+                                            line
+                                        )
+                                    }
+                                })
+                                .collect();
+                            (
+                                "REMOVE ENTRY",
+                                Some(name.clone()),
+                                Vec::new(),
+                                removed.iter().map(|e| e.to_string()).collect(),
+                                undo,
+                            )
+                        }
+                        _ => {
+                            return add_error_source_if_needed(
+                                Err(Error::msg(
+                                    "Only ADD and REMOVE ENTRY can be used within qmldir files!",
+                                )),
+                                &diff.source,
+                            )
+                        }
+                    };
+                    if !inserted.is_empty() || !removed.is_empty() {
+                        mutations += 1;
+                    }
+                    if let Some(journal) = journal.as_deref_mut() {
+                        journal.record(JournalEntry {
+                            file: file_name.to_string(),
+                            source: diff.source.to_string(),
+                            action,
+                            scope: Vec::new(),
+                            selector,
+                            cursor: None,
+                            inserted,
+                            removed,
+                            undo,
+                        });
+                    }
+                }
+                if mutations != 0 {
+                    applied.push(diff.source.clone());
+                }
+            }
+            ObjectToChange::FileTokenStream(f) if f == file_name => {
+                bail!("Cannot AFFECT REBUILD a qmldir file");
+            }
+            _ => {}
+        }
+    }
+
+    Ok(((!applied.is_empty()).then(|| qmldir.to_string()), applied))
+}
+
+/// The value of the object's child called `name` - `None` if there's no such child, `Some(None)`
+/// if its value isn't a plain one. Dotted names (`anchors.fill`) are also looked up within grouped
+/// property bindings (`anchors { fill: parent }`).
+fn property_value(object: &TranslatedObject, name: &str) -> Option<Option<String>> {
+    if let Some(child) = object
+        .children
+        .iter()
+        .find(|e| e.get_name().is_some_and(|e| e == name))
+    {
+        return Some(child.get_str_value());
+    }
+    let (group, name) = name.split_once('.')?;
+    object.children.iter().find_map(|e| match e {
+        TranslatedObjectChild::Object(obj) if obj.borrow().name == group => {
+            property_value(&obj.borrow(), name)
+        }
+        _ => None,
+    })
+}
+
 fn does_match(
     object: &TranslatedObject,
     sel: &NodeSelector,
     object_named: Option<&String>,
 ) -> bool {
-    if sel.object_name != object.name {
+    if !sel.object_name.is_empty() && sel.object_name != object.name {
         return false;
     }
     if sel.named.is_some() && object_named != sel.named.as_ref() {
         return false;
     }
-    let children_names: Vec<Option<&String>> =
-        object.children.iter().map(|e| e.get_name()).collect();
 
     for (name, requirement) in &sel.props {
-        if let Some(index) = children_names.iter().position(|e| e == &Some(name)) {
-            match requirement {
-                PropRequirement::Exists => {} // Checked already.
-                PropRequirement::Equals(eq) => {
-                    let child = object.children.get(index).unwrap();
-                    if let Some(value) = child.get_str_value() {
-                        if value != *eq {
-                            return false;
-                        }
+        let Some(value) = property_value(object, name) else {
+            return false; // All conditions demand existence of the child.
+        };
+        match requirement {
+            PropRequirement::Exists => {} // Checked already.
+            PropRequirement::Equals(eq) => {
+                if let Some(value) = value {
+                    if value != *eq {
+                        return false;
                     }
                 }
-                PropRequirement::Contains(eq) => {
-                    let child = object.children.get(index).unwrap();
-                    if let Some(value) = child.get_str_value() {
-                        if !value.contains(eq) {
-                            return false;
-                        }
+            }
+            PropRequirement::Contains(eq) => {
+                if let Some(value) = value {
+                    if !value.contains(eq) {
+                        return false;
                     }
                 }
             }
-        } else {
-            return false; // All conditions demand existence of the child.
+            PropRequirement::Compare(comparison, compared_to) => {
+                // Values which aren't plain numbers (bindings, objects) never match.
+                let matches = value
+                    .and_then(|value| value.replace(' ', "").parse::<f64>().ok())
+                    .is_some_and(|value| comparison.compare_numbers(value, *compared_to));
+                if !matches {
+                    return false;
+                }
+            }
         }
     }
 
@@ -138,77 +499,130 @@ enum TreeRoot {
     },
 }
 
+/// The full name of a child object, or the summary of any other child.
+fn child_full_name(child: &TranslatedObjectChild) -> String {
+    let object = match child {
+        TranslatedObjectChild::Object(obj) => obj,
+        TranslatedObjectChild::ObjectAssignment(assigned) => &assigned.value,
+        TranslatedObjectChild::ObjectProperty(prop) => &prop.default_value,
+        _ => return child.summary(),
+    };
+    let object = object.borrow();
+    if object.full_name.is_empty() {
+        object.summary()
+    } else {
+        object.full_name.clone()
+    }
+}
+
+fn child_full_name_at(root: &TreeRoot, index: usize) -> String {
+    match root {
+        TreeRoot::Object(obj) => child_full_name(&obj.borrow().children[index]),
+        TreeRoot::Enum(r#enum) => r#enum.values.borrow()[index].0.clone(),
+        TreeRoot::Child {
+            parent: _,
+            child_index: _,
+        } => traverse_no_raw_children!(),
+    }
+}
+
+fn root_full_names(roots: &[TreeRoot]) -> Vec<String> {
+    roots
+        .iter()
+        .map(|root| match root {
+            TreeRoot::Object(obj) => {
+                let obj = obj.borrow();
+                if obj.full_name.is_empty() {
+                    obj.summary()
+                } else {
+                    obj.full_name.clone()
+                }
+            }
+            TreeRoot::Enum(r#enum) => format!("enum {}", r#enum.name),
+            TreeRoot::Child {
+                parent,
+                child_index,
+            } => child_full_name(&parent.borrow().children[*child_index]),
+        })
+        .collect()
+}
+
 fn locate_in_tree(
     roots: Vec<TreeRoot>,
-    tree: &NodeTree,
+    tree: &[NodeSelector],
     force_raw_children: bool,
 ) -> Vec<TreeRoot> {
     let mut potential_roots = roots; // Start with the initial root
-    for sel in tree {
+    for (depth, node) in tree.iter().enumerate() {
         let mut swap_root = Vec::new();
-        for (i, r) in potential_roots.iter().enumerate() {
-            let is_last = i == potential_roots.len() - 1;
-            // Borrow each potential root mutably for children traversal
-            if let TreeRoot::Object(r) = r {
-                for (i, child) in r.borrow().children.iter().enumerate() {
-                    let child_object = match child {
-                        TranslatedObjectChild::Object(obj) => {
-                            Some((None, TreeRoot::Object(obj.clone())))
-                        }
-                        TranslatedObjectChild::ObjectProperty(obj) => {
-                            Some((Some(&obj.name), TreeRoot::Object(obj.default_value.clone())))
-                        }
-                        TranslatedObjectChild::Component(asi)
-                        | TranslatedObjectChild::ObjectAssignment(asi) => {
-                            Some((Some(&asi.name), TreeRoot::Object(asi.value.clone())))
-                        }
-                        TranslatedObjectChild::Enum(enu) => {
-                            Some((Some(&enu.name), TreeRoot::Enum(enu.clone())))
-                        }
-                        _ if force_raw_children => Some((
-                            child.get_name(),
-                            TreeRoot::Child {
-                                child_index: i,
-                                parent: r.clone(),
-                            },
-                        )),
-                        _ => None,
-                    };
+        // Only the final selector's matches should be returned as raw children
+        let is_last = depth == tree.len() - 1;
+        // The alternatives are only tried if the previous ones didn't match anything.
+        for sel in node.candidates() {
+            for r in potential_roots.iter() {
+                // Borrow each potential root mutably for children traversal
+                if let TreeRoot::Object(r) = r {
+                    for (i, child) in r.borrow().children.iter().enumerate() {
+                        let child_object = match child {
+                            TranslatedObjectChild::Object(obj) => {
+                                Some((None, TreeRoot::Object(obj.clone())))
+                            }
+                            TranslatedObjectChild::ObjectProperty(obj) => {
+                                Some((Some(&obj.name), TreeRoot::Object(obj.default_value.clone())))
+                            }
+                            TranslatedObjectChild::Component(asi)
+                            | TranslatedObjectChild::ObjectAssignment(asi)
+                            | TranslatedObjectChild::ObjectListAssignment(asi) => {
+                                Some((Some(&asi.name), TreeRoot::Object(asi.value.clone())))
+                            }
+                            TranslatedObjectChild::Enum(enu) => {
+                                Some((Some(&enu.name), TreeRoot::Enum(enu.clone())))
+                            }
+                            _ if force_raw_children => Some((
+                                child.get_name(),
+                                TreeRoot::Child {
+                                    child_index: i,
+                                    parent: r.clone(),
+                                },
+                            )),
+                            _ => None,
+                        };
 
-                    if let Some((name, object)) = child_object {
-                        match &object {
-                            TreeRoot::Object(obj) => {
-                                if does_match(&obj.borrow(), sel, name) {
-                                    // Collect the matched child object
-                                    if force_raw_children && is_last {
-                                        swap_root.push(TreeRoot::Child {
-                                            parent: r.clone(),
-                                            child_index: i,
-                                        });
-                                    } else {
-                                        swap_root.push(object);
+                        if let Some((name, object)) = child_object {
+                            match &object {
+                                TreeRoot::Object(obj) => {
+                                    if does_match(&obj.borrow(), sel, name) {
+                                        // Collect the matched child object
+                                        if force_raw_children && is_last {
+                                            swap_root.push(TreeRoot::Child {
+                                                parent: r.clone(),
+                                                child_index: i,
+                                            });
+                                        } else {
+                                            swap_root.push(object);
+                                        }
                                     }
                                 }
-                            }
-                            TreeRoot::Enum(r#enum) => {
-                                if sel.is_simple() && sel.object_name == r#enum.name {
-                                    if force_raw_children && is_last {
-                                        swap_root.push(TreeRoot::Child {
-                                            parent: r.clone(),
-                                            child_index: i,
-                                        });
-                                    } else {
-                                        swap_root.push(object);
+                                TreeRoot::Enum(r#enum) => {
+                                    if sel.is_simple() && sel.object_name == r#enum.name {
+                                        if force_raw_children && is_last {
+                                            swap_root.push(TreeRoot::Child {
+                                                parent: r.clone(),
+                                                child_index: i,
+                                            });
+                                        } else {
+                                            swap_root.push(object);
+                                        }
                                     }
                                 }
-                            }
-                            TreeRoot::Child {
-                                parent: _,
-                                child_index: _,
-                            } => {
-                                if let Some(name) = name {
-                                    if sel.is_simple() && sel.object_name == *name {
-                                        swap_root.push(object);
+                                TreeRoot::Child {
+                                    parent: _,
+                                    child_index: _,
+                                } => {
+                                    if let Some(name) = name {
+                                        if sel.is_simple() && sel.object_name == *name {
+                                            swap_root.push(object);
+                                        }
                                     }
                                 }
                             }
@@ -216,6 +630,9 @@ fn locate_in_tree(
                     }
                 }
             }
+            if !swap_root.is_empty() {
+                break;
+            }
         }
         potential_roots = swap_root; // Update the list of potential roots for the next iteration
     }
@@ -223,6 +640,34 @@ fn locate_in_tree(
     potential_roots
 }
 
+/// Like `locate_in_tree`, but a tree starting with an id only (`#volumeSlider > Text`) starts
+/// from the object of the file with that id, instead of the children of `roots`.
+fn locate_in_file(file: &TranslatedTree, roots: Vec<TreeRoot>, tree: &NodeTree) -> Vec<TreeRoot> {
+    if tree[0].candidates().all(|e| e.standalone_id().is_none()) {
+        return locate_in_tree(roots, tree, false);
+    }
+    for candidate in tree[0].candidates() {
+        let found = match candidate.standalone_id() {
+            Some(id) => match file.find_by_id(id) {
+                Some(object) => locate_in_tree(vec![TreeRoot::Object(object)], &tree[1..], false),
+                None => Vec::new(),
+            },
+            None => {
+                let mut single = tree.clone();
+                single[0] = NodeSelector {
+                    alternatives: Vec::new(),
+                    ..candidate.clone()
+                };
+                locate_in_tree(roots.clone(), &single, false)
+            }
+        };
+        if !found.is_empty() {
+            return found;
+        }
+    }
+    Vec::new()
+}
+
 #[derive(Clone, Debug)]
 struct RootReference {
     pub root: Vec<TreeRoot>,
@@ -230,14 +675,89 @@ struct RootReference {
     pub is_replicating: bool,
 }
 
-fn tree_to_string(tree: &NodeTree) -> String {
-    tree.iter()
-        .map(|e| e.to_string())
-        .collect::<Vec<String>>()
-        .join(" > ")
+/// Whether `tree` matches anything within `root`.
+fn root_contains(root: &TreeRoot, tree: &NodeTree) -> bool {
+    // Is the tree selector simple
+    if tree.len() == 1 {
+        for sel in tree[0].candidates().filter(|e| e.is_simple()) {
+            match root {
+                TreeRoot::Object(e) => {
+                    for child_object in &e.borrow().children {
+                        // Yes, and it matches
+                        if child_object.get_name() == Some(&sel.object_name) {
+                            return true;
+                        }
+                    }
+                }
+                TreeRoot::Enum(e) => {
+                    for value in e.values.borrow().iter() {
+                        if value.0 == sel.object_name {
+                            return true;
+                        }
+                    }
+                }
+                TreeRoot::Child {
+                    parent: _,
+                    child_index: _,
+                } => traverse_no_raw_children!(),
+            }
+        }
+    }
+    !locate_in_tree(vec![root.clone()], tree, false).is_empty()
+}
+
+/// Counts the objects of a QML file matched by `tree` - the ones a `TRAVERSE` at the start of a
+/// change would enter. Nothing is modified.
+pub fn count_matches(token_stream: Vec<TokenType>, tree: &NodeTree) -> Result<usize> {
+    let qml = translate_from_root(parse_qml_from_chain(token_stream)?);
+    Ok(locate_in_file(&qml, vec![TreeRoot::Object(qml.root.clone())], tree).len())
+}
+
+/// The objects within `object` matched by `tree` (like `Rectangle > Item#toolbar`), the same way a
+/// `TRAVERSE` would find them. Enums matched by the tree are not included.
+pub fn find_all(object: &TranslatedObjectRef, tree: &NodeTree) -> Vec<TranslatedObjectRef> {
+    locate_in_tree(vec![TreeRoot::Object(object.clone())], tree, false)
+        .into_iter()
+        .filter_map(|e| match e {
+            TreeRoot::Object(object) => Some(object),
+            _ => None,
+        })
+        .collect()
+}
+
+impl TranslatedTree {
+    /// The objects of the file matched by `tree` - the ones a `TRAVERSE` at the start of a change
+    /// would enter.
+    pub fn find_all(&self, tree: &NodeTree) -> Vec<TranslatedObjectRef> {
+        locate_in_file(self, vec![TreeRoot::Object(self.root.clone())], tree)
+            .into_iter()
+            .filter_map(|e| match e {
+                TreeRoot::Object(object) => Some(object),
+                _ => None,
+            })
+            .collect()
+    }
 }
 
-fn find_first_matching_child(root: &TreeRoot, tree: &Vec<NodeSelector>) -> Result<usize> {
+fn find_first_matching_child(root: &TreeRoot, tree: &NodeTree) -> Result<usize> {
+    if !tree[0].alternatives.is_empty() {
+        // The first alternative which matches any child wins, even if a later one matches
+        // a preceding child.
+        for candidate in tree[0].candidates() {
+            let mut single = tree.clone();
+            single[0] = NodeSelector {
+                alternatives: Vec::new(),
+                ..candidate.clone()
+            };
+            if let Ok(index) = find_first_matching_child(root, &single) {
+                return Ok(index);
+            }
+        }
+        return Err(kind_error(
+            ErrorKind::Selector,
+            format!("Cannot LOCATE {}", tree),
+        ));
+    }
     macro_rules! make_tree_return_i {
         ($i: expr, $obj: expr, $name: expr) => {
             if !locate_in_tree(
@@ -274,7 +794,7 @@ fn find_first_matching_child(root: &TreeRoot, tree: &Vec<NodeSelector>) -> Resul
                 }
 
                 match child {
-                    TranslatedObjectChild::Object(obj) => {
+                    TranslatedObjectChild::Object(obj)
                         if !locate_in_tree(
                             vec![TreeRoot::Object(Rc::new(RefCell::new(TranslatedObject {
                                 name: String::default(),
@@ -284,13 +804,13 @@ fn find_first_matching_child(root: &TreeRoot, tree: &Vec<NodeSelector>) -> Resul
                             tree,
                             false,
                         )
-                        .is_empty()
-                        {
-                            return Ok(i);
-                        }
+                        .is_empty() =>
+                    {
+                        return Ok(i);
                     }
                     TranslatedObjectChild::Component(obj)
-                    | TranslatedObjectChild::ObjectAssignment(obj) => {
+                    | TranslatedObjectChild::ObjectAssignment(obj)
+                    | TranslatedObjectChild::ObjectListAssignment(obj) => {
                         make_tree_return_i!(i, obj.value, obj.name);
                     }
                     TranslatedObjectChild::ObjectProperty(obj) => {
@@ -310,7 +830,58 @@ fn find_first_matching_child(root: &TreeRoot, tree: &Vec<NodeSelector>) -> Resul
         _ => {}
     }
 
-    Err(Error::msg(format!("Cannot LOCATE {:?}", tree)))
+    Err(kind_error(
+        ErrorKind::Selector,
+        format!("Cannot LOCATE {}", tree),
+    ))
+}
+
+fn find_member(root: &TreeRoot, kind: MemberKind, name: &str) -> Result<usize> {
+    if let TreeRoot::Object(root) = root {
+        if let Some(index) = root
+            .borrow()
+            .children
+            .iter()
+            .position(|child| match (kind, child) {
+                (MemberKind::Function, TranslatedObjectChild::Function(function)) => {
+                    function.name == name
+                }
+                (MemberKind::Signal, TranslatedObjectChild::Signal(signal)) => signal.name == name,
+                (MemberKind::Property, TranslatedObjectChild::Property(property)) => {
+                    property.name == name
+                }
+                (MemberKind::Property, TranslatedObjectChild::ObjectProperty(property)) => {
+                    property.name == name
+                }
+                _ => false,
+            })
+        {
+            return Ok(index);
+        }
+    }
+
+    Err(kind_error(
+        ErrorKind::Selector,
+        format!("Cannot LOCATE {} {}", kind, name),
+    ))
+}
+
+fn find_anchor(root: &TreeRoot, name: &str) -> Result<usize> {
+    if let TreeRoot::Object(root) = root {
+        if let Some(index) = root
+            .borrow()
+            .children
+            .iter()
+            .position(|e| matches!(e, TranslatedObjectChild::Anchor(anchor) if anchor == name))
+        {
+            return Ok(index);
+        }
+    }
+
+    Err(kind_error(
+        ErrorKind::Selector,
+        format!("Cannot LOCATE ANCHOR {}", name),
+    ))
 }
 
 fn insert_into_root(
@@ -318,7 +889,7 @@ fn insert_into_root(
     root: &TreeRoot,
     code: &[TokenType],
     slots: &mut Slots,
-) -> Result<()> {
+) -> Result<Range<usize>> {
     let mut raw_qml = IteratorPipeline::new(
         Box::new(
             if matches!(root, TreeRoot::Object(_)) {
@@ -353,6 +924,7 @@ fn insert_into_root(
     // Start the QML parser...
     let tokens = raw_qml.collect();
     let mut qml_root = parse_qml_from_chain(tokens)?;
+    let start = *root_cursor;
     if let Some(TreeElement::Object(object)) = qml_root.pop() {
         match root {
             TreeRoot::Object(root) => {
@@ -369,10 +941,9 @@ fn insert_into_root(
                     return Err(Error::msg("Internal error"));
                 }
                 if let ObjectChild::Enum(enum_child) = &object.children[0] {
-                    r#enum
-                        .values
-                        .borrow_mut()
-                        .extend_from_slice(&enum_child.values);
+                    let mut values = r#enum.values.borrow_mut();
+                    values.splice(start..start, enum_child.values.iter().cloned());
+                    *root_cursor += enum_child.values.len();
                 }
             }
             TreeRoot::Child {
@@ -387,10 +958,273 @@ fn insert_into_root(
     } else {
         return Err(Error::msg("Internal parse error"));
     }
+    Ok(start..*root_cursor)
+}
+
+fn pragma_code(name: &str, value: &Option<String>) -> String {
+    match value {
+        Some(value) => format!("pragma {}: {}", name, value),
+        None => format!("pragma {}", name),
+    }
+}
+
+/// Only objects and inline components can be inserted next to the root object of the file -
+/// anything else would be lost when the file is emitted.
+fn check_top_level_children(
+    file: &TranslatedTree,
+    root: &TreeRoot,
+    inserted: Range<usize>,
+) -> Result<()> {
+    let TreeRoot::Object(root) = root else {
+        return Ok(());
+    };
+    if !Rc::ptr_eq(root, &file.root) {
+        return Ok(());
+    }
+    for child in &root.borrow().children[inserted] {
+        if !matches!(
+            child,
+            TranslatedObjectChild::Object(_) | TranslatedObjectChild::Component(_)
+        ) {
+            return Err(Error::msg(format!(
+                "Cannot INSERT {} at the top level of the file - only objects and components can be inserted next to the root object",
+                child.summary()
+            )));
+        }
+    }
     Ok(())
 }
 
-fn parse_argument_stream(stream: &Vec<TokenType>) -> Result<(Vec<String>, usize)> {
+/// The name of the list of objects whose children are the children of `list`, if `list` is one.
+fn list_name(object: &TranslatedObjectRef, list: &TranslatedObjectRef) -> Option<String> {
+    for child in &object.borrow().children {
+        let value = match child {
+            TranslatedObjectChild::ObjectListAssignment(assigned) => {
+                if Rc::ptr_eq(&assigned.value, list) {
+                    return Some(assigned.name.clone());
+                }
+                &assigned.value
+            }
+            TranslatedObjectChild::Object(obj) => obj,
+            TranslatedObjectChild::ObjectAssignment(assigned)
+            | TranslatedObjectChild::Component(assigned) => &assigned.value,
+            TranslatedObjectChild::ObjectProperty(prop) => &prop.default_value,
+            _ => continue,
+        };
+        if let Some(name) = list_name(value, list) {
+            return Some(name);
+        }
+    }
+    None
+}
+
+/// Lists of objects can only hold objects - anything else would be lost when emitting the list.
+fn check_list_children(
+    file: &TranslatedTree,
+    root: &TreeRoot,
+    inserted: Range<usize>,
+) -> Result<()> {
+    let TreeRoot::Object(root) = root else {
+        return Ok(());
+    };
+    let Some(name) = list_name(&file.root, root) else {
+        return Ok(());
+    };
+    for child in &root.borrow().children[inserted] {
+        if !matches!(child, TranslatedObjectChild::Object(_)) {
+            return Err(kind_error(
+                ErrorKind::Validation,
+                format!(
+                    "Cannot INSERT {} into the list {} - only objects can be inserted into lists of objects",
+                    child.summary(),
+                    name
+                ),
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn enum_value_code(value: &(String, Option<String>)) -> String {
+    match &value.1 {
+        Some(v) => format!("{} = {}", value.0, v),
+        None => value.0.clone(),
+    }
+}
+
+/// The code of the children within `range`, as emitted - used to compare them.
+fn children_code(root: &TreeRoot, range: Range<usize>) -> String {
+    match root {
+        TreeRoot::Object(obj) => obj.borrow().children[range]
+            .iter()
+            .map(child_code)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        TreeRoot::Enum(r#enum) => r#enum.values.borrow()[range]
+            .iter()
+            .map(enum_value_code)
+            .collect::<Vec<_>>()
+            .join("\n"),
+        TreeRoot::Child {
+            parent: _,
+            child_index: _,
+        } => traverse_no_raw_children!(),
+    }
+}
+
+/// The selector of the child preceding `index` - used to put removed children back in place.
+/// Anchors are skipped, as they are not present in the patched file.
+fn anchor_of(root: &TreeRoot, index: usize) -> Option<String> {
+    match root {
+        TreeRoot::Object(obj) => obj.borrow().children[..index]
+            .iter()
+            .rev()
+            .find(|e| !matches!(e, TranslatedObjectChild::Anchor(_)))
+            .map(child_selector),
+        TreeRoot::Enum(r#enum) => index
+            .checked_sub(1)
+            .map(|i| r#enum.values.borrow()[i].0.clone()),
+        _ => None,
+    }
+}
+
+/// The directives which undo the insertion of the children in `range`.
+fn undo_insertion(root: &TreeRoot, range: Range<usize>) -> Vec<String> {
+    match root {
+        TreeRoot::Object(obj) => obj.borrow().children[range]
+            .iter()
+            .filter(|e| !matches!(e, TranslatedObjectChild::Anchor(_)))
+            .map(|e| format!("REMOVE {}", child_selector(e)))
+            .collect(),
+        TreeRoot::Enum(r#enum) => r#enum.values.borrow()[range]
+            .iter()
+            .map(|e| format!("REMOVE {}", e.0))
+            .collect(),
+        TreeRoot::Child {
+            parent: _,
+            child_index: _,
+        } => traverse_no_raw_children!(),
+    }
+}
+
+/// What a `TRANSFORM` has done to an object - the summaries of the children it has set and of
+/// the ones they've replaced, and the directives which undo it from within the object.
+struct Transformed {
+    inserted: Vec<String>,
+    removed: Vec<String>,
+    undo: Vec<String>,
+}
+
+/// Runs the script of a `TRANSFORM` on an object. The assignments are run in order - later ones
+/// see the values set by the previous ones. Properties the object doesn't have are assigned.
+fn transform_object(
+    object: &TranslatedObjectRef,
+    transform: &TransformAction,
+) -> Result<Transformed> {
+    fn find_property(object: &TranslatedObject, name: &str) -> Option<usize> {
+        object.children.iter().position(|e| match e {
+            TranslatedObjectChild::Assignment(assignment) => assignment.name == name,
+            TranslatedObjectChild::Property(property) => property.name == name,
+            _ => false,
+        })
+    }
+
+    // The children the script has changed, as they were before - `None` for the added ones.
+    let mut originals: Vec<(String, Option<TranslatedObjectChild>)> = Vec::new();
+    let mut resolve = |name: &str| match object.borrow().get_property_value(name) {
+        Some(value) => Lexer::new(StringCharacterTokenizer::new(value)).tokenize(),
+        None => bail!("The object has no property {}", name),
+    };
+    for assignment in &transform.assignments {
+        let value = evaluate_token_expression(assignment.expression.clone(), &mut resolve)
+            .map_err(|error| {
+                Error::msg(format!(
+                    "Cannot set {} of {}: {}",
+                    assignment.property,
+                    object.borrow().summary(),
+                    error
+                ))
+            })?;
+
+        let mut object = object.borrow_mut();
+        let index = find_property(&object, &assignment.property);
+        if !originals.iter().any(|e| e.0 == assignment.property) {
+            let original = index.map(|i| object.children[i].deep_clone());
+            originals.push((assignment.property.clone(), original));
+        }
+        let value = AssignmentChildValue::Other(vec![value]);
+        match index.map(|i| &mut object.children[i]) {
+            Some(TranslatedObjectChild::Assignment(child)) => child.value = value,
+            Some(TranslatedObjectChild::Property(child)) => child.default_value = Some(value),
+            _ => object
+                .children
+                .push(TranslatedObjectChild::Assignment(AssignmentChild {
+                    name: assignment.property.clone(),
+                    value,
+                })),
+        }
+    }
+
+    let object = object.borrow();
+    let mut transformed = Transformed {
+        inserted: Vec::new(),
+        removed: Vec::new(),
+        undo: Vec::new(),
+    };
+    for (name, original) in originals {
+        if let Some(index) = find_property(&object, &name) {
+            transformed.inserted.push(object.children[index].summary());
+        }
+        transformed.undo.push(match original {
+            Some(original) => {
+                transformed.removed.push(original.summary());
+                replace_directive(&name, &child_code(&original))
+            }
+            None => format!("REMOVE {}", name),
+        });
+    }
+    Ok(transformed)
+}
+
+/// The value of `property` assigned directly within an object child - what INSERT SORTED orders by.
+fn sort_key(child: &TranslatedObjectChild, property: &str) -> Option<String> {
+    match child {
+        TranslatedObjectChild::Object(obj) => obj
+            .borrow()
+            .children
+            .iter()
+            .find(|e| e.get_name().is_some_and(|name| name == property))
+            .and_then(|e| e.get_str_value()),
+        _ => None,
+    }
+}
+
+/// Numeric values are compared as numbers, everything else as strings.
+fn compare_sort_keys(a: &str, b: &str) -> std::cmp::Ordering {
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(a), Ok(b)) => a.total_cmp(&b),
+        _ => a.cmp(b),
+    }
+}
+
+fn summarize_children(root: &TreeRoot, range: Range<usize>) -> Vec<String> {
+    match root {
+        TreeRoot::Object(obj) => obj.borrow().children[range]
+            .iter()
+            .map(|e| e.summary())
+            .collect(),
+        TreeRoot::Enum(r#enum) => r#enum.values.borrow()[range]
+            .iter()
+            .map(|e| e.0.clone())
+            .collect(),
+        TreeRoot::Child {
+            parent: _,
+            child_index: _,
+        } => traverse_no_raw_children!(),
+    }
+}
+
+fn parse_argument_stream(stream: &[TokenType]) -> Result<(Vec<String>, usize)> {
     let mut pos = 0;
     let mut args = Vec::new();
     let mut requires_close = false;
@@ -399,7 +1233,7 @@ fn parse_argument_stream(stream: &Vec<TokenType>) -> Result<(Vec<String>, usize)
         let token = &stream[pos];
         pos += 1;
         match token {
-            TokenType::Whitespace(_) => {}
+            TokenType::Whitespace(_) | TokenType::NewLine(_) | TokenType::Comment(_) => {}
             TokenType::Symbol('(') => {
                 requires_close = true;
             }
@@ -440,7 +1274,7 @@ fn parse_argument_stream(stream: &Vec<TokenType>) -> Result<(Vec<String>, usize)
     Ok((args, pos))
 }
 
-fn find_beginning_of_function(stream: &Vec<TokenType>, mut start: usize) -> usize {
+fn find_beginning_of_function(stream: &[TokenType], mut start: usize) -> usize {
     while start < stream.len() {
         match stream[start] {
             TokenType::Symbol('{') => {
@@ -464,6 +1298,7 @@ fn build_arguments_token_stream(args: Vec<String>) -> Vec<TokenType> {
         tokens.push(TokenType::Identifier(arg));
         if i != len - 1 {
             tokens.push(TokenType::Symbol(','));
+            tokens.push(TokenType::Whitespace(" ".into()));
         }
     }
     tokens.push(TokenType::Symbol(')'));
@@ -471,14 +1306,25 @@ fn build_arguments_token_stream(args: Vec<String>) -> Vec<TokenType> {
     tokens
 }
 
+/// `prefix` is everything preceding the body of the original function (`(a, b) => `). It is
+/// kept as-is if the arguments were not changed.
 fn build_arrow_func(
     arguments: Vec<String>,
+    original_arguments: &Option<Vec<String>>,
+    prefix: Vec<TokenType>,
     body: Vec<TokenType>,
     enclosed: bool,
 ) -> Vec<TokenType> {
-    let mut base = build_arguments_token_stream(arguments);
-    base.push(TokenType::Unknown('='));
-    base.push(TokenType::Unknown('>'));
+    let mut base = if original_arguments.as_ref() == Some(&arguments) {
+        prefix
+    } else {
+        let mut base = build_arguments_token_stream(arguments);
+        base.push(TokenType::Whitespace(" ".into()));
+        base.push(TokenType::Unknown('='));
+        base.push(TokenType::Unknown('>'));
+        base.push(TokenType::Whitespace(" ".into()));
+        base
+    };
     if enclosed {
         base.push(TokenType::Symbol('{'));
     }
@@ -490,15 +1336,12 @@ fn build_arrow_func(
 }
 
 fn is_whitespace(x: &TokenType) -> bool {
-    match x {
-        TokenType::NewLine(_) | TokenType::Whitespace(_) => true,
-        _ => false,
-    }
+    matches!(x, TokenType::NewLine(_) | TokenType::Whitespace(_))
 }
 
 fn find_substream_in_stream(
-    haystack: &Vec<TokenType>,
-    needle: &Vec<TokenType>,
+    haystack: &[TokenType],
+    needle: &[TokenType],
     mut start: usize,
     glob_whitespace_before: bool,
 ) -> Option<(usize, usize)> {
@@ -565,7 +1408,7 @@ fn execute_rebuild_steps(
             () => {
                 if position == usize::MAX {
                     return Err(Error::msg(format!(
-                        "In order to apply {:?}, the position must be unambiguous! Please use LOCATE first.",
+                        "In order to apply {}, the position must be unambiguous! Please use LOCATE first.",
                         instr
                     )));
                 }
@@ -637,10 +1480,14 @@ fn execute_rebuild_steps(
                     ) {
                         Some(n) => n,
                         None => {
-                            return Err(Error::msg(format!(
-                                "Cannot locate the substream [{:?}] in [{:?}]",
-                                stream, &main_body_stream
-                            )));
+                            return Err(kind_error(
+                                ErrorKind::Selector,
+                                format!(
+                                    "Cannot locate the substream {} in {}",
+                                    qml_stream_to_string(stream),
+                                    qml_stream_to_string(main_body_stream)
+                                ),
+                            ));
                         }
                     };
                     located = Some(stream.clone());
@@ -687,7 +1534,8 @@ fn execute_rebuild_steps(
                                 // We're OK - remove
                                 main_body_stream.splice(position..position + length, vec![]);
                             } else {
-                                return Err(Error::msg(
+                                return Err(kind_error(
+                                    ErrorKind::Selector,
                                     "Requested substream to REMOVE could not be found",
                                 ));
                             }
@@ -708,7 +1556,8 @@ fn execute_rebuild_steps(
                         {
                             main_body_stream.splice(position..until_stream_location, vec![]);
                         } else {
-                            return Err(Error::msg(
+                            return Err(kind_error(
+                                ErrorKind::Selector,
                                 "Requested substream to REMOVE UNTIL not found in stream",
                             ));
                         }
@@ -735,10 +1584,13 @@ fn execute_rebuild_steps(
                         match find_substream_in_stream(main_body_stream, stream, position, false) {
                             Some((pos, _len)) => pos,
                             None => {
-                                return Err(Error::msg(format!(
-                                    "Could not locate substream [{:?}] in stream!",
-                                    stream
-                                )));
+                                return Err(kind_error(
+                                    ErrorKind::Selector,
+                                    format!(
+                                        "Could not locate substream {} in stream!",
+                                        qml_stream_to_string(stream)
+                                    ),
+                                ));
                             }
                         }
                     }
@@ -766,10 +1618,13 @@ fn execute_rebuild_steps(
                     counter += 1;
                 }
                 if counter == 0 {
-                    return Err(Error::msg(format!(
-                        "Cannot replace substream {:?} - not found!",
-                        source_stream
-                    )));
+                    return Err(kind_error(
+                        ErrorKind::Selector,
+                        format!(
+                            "Cannot replace substream {} - not found!",
+                            qml_stream_to_string(&source_stream)
+                        ),
+                    ));
                 }
             }
         }
@@ -808,6 +1663,17 @@ fn rebuild_child(
     rebuild_instructions: &RebuildAction,
     child: &mut TranslatedObjectChild,
 ) -> Result<()> {
+    // Lists of objects are rebuilt as the code they were parsed from.
+    if let TranslatedObjectChild::ObjectListAssignment(_) = child {
+        if let ObjectChild::ObjectListAssignment(list) =
+            untranslate_object_child(child.deep_clone())
+        {
+            *child = TranslatedObjectChild::Assignment(AssignmentChild {
+                name: list.name,
+                value: AssignmentChildValue::Other(emit_object_list_to_token_stream(&list.values)),
+            });
+        }
+    }
     let mut arguments_token_length = 0;
     let mut arguments = None;
     match child {
@@ -837,6 +1703,8 @@ fn rebuild_child(
         }
     }
 
+    let original_arguments = arguments.clone();
+    let mut arrow_prefix = Vec::new();
     let (mut main_body_stream, is_enclosed, is_object) = if arguments.is_some() {
         match child {
             TranslatedObjectChild::Function(func) => {
@@ -847,6 +1715,7 @@ fn rebuild_child(
             TranslatedObjectChild::Assignment(assign) => match assign.value {
                 AssignmentChildValue::Other(ref mut stream) => {
                     let mut begin = find_beginning_of_function(stream, arguments_token_length);
+                    arrow_prefix = Vec::from(&stream[..begin]);
                     let mut end = stream.len();
                     let enclosed = stream[begin] == TokenType::Symbol('{');
                     if enclosed {
@@ -860,6 +1729,7 @@ fn rebuild_child(
             TranslatedObjectChild::Property(prop) => match prop.default_value {
                 Some(AssignmentChildValue::Other(ref mut stream)) => {
                     let mut begin = find_beginning_of_function(stream, arguments_token_length);
+                    arrow_prefix = Vec::from(&stream[..begin]);
                     let mut end = stream.len();
                     let enclosed = stream[begin] == TokenType::Symbol('{');
                     if enclosed {
@@ -918,7 +1788,18 @@ fn rebuild_child(
                 main_body_stream.push(TokenType::Symbol('}'));
             }
             func.body = main_body_stream;
-            func.arguments = build_arguments_token_stream(arguments.unwrap());
+            let arguments = arguments.unwrap();
+            if Some(&arguments) != original_arguments.as_ref() {
+                // Keep the spacing between the arguments and the body.
+                let spacing_start = func
+                    .arguments
+                    .iter()
+                    .rposition(|e| !is_whitespace(e) && !matches!(e, TokenType::Comment(_)))
+                    .map_or(0, |e| e + 1);
+                let spacing = func.arguments.split_off(spacing_start);
+                func.arguments = build_arguments_token_stream(arguments);
+                func.arguments.extend(spacing);
+            }
         }
         TranslatedObjectChild::Assignment(assign) => {
             if is_object {
@@ -929,6 +1810,8 @@ fn rebuild_child(
                 // This used to be a function. Regenerate fully.
                 assign.value = AssignmentChildValue::Other(build_arrow_func(
                     arguments,
+                    &original_arguments,
+                    take(&mut arrow_prefix),
                     main_body_stream,
                     is_enclosed,
                 ));
@@ -946,6 +1829,8 @@ fn rebuild_child(
                 // This used to be a function. Regenerate fully.
                 prop.default_value = Some(AssignmentChildValue::Other(build_arrow_func(
                     arguments,
+                    &original_arguments,
+                    take(&mut arrow_prefix),
                     main_body_stream,
                     is_enclosed,
                 )));
@@ -965,29 +1850,430 @@ fn rebuild_child(
         }
         _ => unreachable!(),
     }
+    // The objects of a rebuilt list can be traversed into again.
+    if let TranslatedObjectChild::Assignment(AssignmentChild {
+        name,
+        value: AssignmentChildValue::Other(stream),
+    }) = child
+    {
+        if let Some(values) = parse_object_list(stream, "") {
+            *child = translate_object_child(ObjectChild::ObjectListAssignment(
+                ObjectListAssignmentChild {
+                    name: take(name),
+                    values,
+                },
+            ));
+        }
+    }
     Ok(())
 }
 
-fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots) -> Result<()> {
+/// A child renamed by `RENAME ... PROPAGATE`, whose references have to follow it.
+struct PropagatedRename<'a> {
+    from: &'a str,
+    to: &'a str,
+    /// The id of the object the child belongs to - `id.from` refers to the child too.
+    own_id: Option<String>,
+}
+
+/// Rewrites the references to the renamed child within the tokens. A plain `from` is only a
+/// reference if `unqualified` is set - it isn't when something else of the same name shadows the
+/// child. Members of other objects (`other.from`) are left alone.
+fn propagate_rename_in_tokens(
+    tokens: &mut [TokenType],
+    rename: &PropagatedRename,
+    unqualified: bool,
+) {
+    let is_own_id = |token: Option<&TokenType>| match token {
+        Some(TokenType::Identifier(id)) => rename.own_id.as_ref() == Some(id),
+        _ => false,
+    };
+    for i in 0..tokens.len() {
+        let TokenType::Identifier(name) = &tokens[i] else {
+            continue;
+        };
+        // Values starting with a name are read as a single compound identifier - `id.from`.
+        let mut segments: Vec<&str> = name.split('.').collect();
+        if !segments.contains(&rename.from) {
+            continue;
+        }
+        let mut previous = tokens[..i].iter().rev().filter(|e| !is_whitespace(e));
+        let first_is_reference = match previous.next() {
+            Some(TokenType::Symbol('.')) => is_own_id(previous.next()),
+            _ => unqualified,
+        };
+        let own_id_segment = rename.own_id.as_deref() == Some(segments[0]);
+        for (index, segment) in segments.iter_mut().enumerate() {
+            let is_reference = match index {
+                0 => first_is_reference,
+                1 => own_id_segment,
+                _ => false,
+            };
+            if is_reference && *segment == rename.from {
+                *segment = rename.to;
+            }
+        }
+        tokens[i] = TokenType::Identifier(segments.join("."));
+    }
+}
+
+/// Whether the code declares a local variable called `name` - `var name`, `let name` or
+/// `const name`, anywhere within it.
+fn declares_local(tokens: &[TokenType], name: &str) -> bool {
+    let tokens: Vec<&TokenType> = tokens.iter().filter(|e| !is_whitespace(e)).collect();
+    tokens.windows(2).any(|pair| match pair {
+        [TokenType::Identifier(keyword), TokenType::Identifier(declared)] => {
+            matches!(keyword.as_str(), "var" | "let" | "const") && declared == name
+        }
+        _ => false,
+    })
+}
+
+fn propagate_rename_in_value(
+    value: &mut AssignmentChildValue,
+    rename: &PropagatedRename,
+    unqualified: bool,
+) {
+    match value {
+        AssignmentChildValue::Other(tokens) => {
+            let shadowed = declares_local(tokens, rename.from);
+            propagate_rename_in_tokens(tokens, rename, unqualified && !shadowed)
+        }
+        AssignmentChildValue::Object(object) => {
+            let translated = translate(object.clone());
+            propagate_rename(&translated, rename, unqualified);
+            *object = untranslate(translated);
+        }
+    }
+}
+
+/// Rewrites the references to the renamed child within the bindings and functions of the
+/// object and of its child objects.
+fn propagate_rename(object: &TranslatedObjectRef, rename: &PropagatedRename, unqualified: bool) {
+    let mut object = object.borrow_mut();
+    // An object declaring something of the same name shadows the renamed child.
+    let unqualified = unqualified
+        && !object
+            .children
+            .iter()
+            .any(|e| e.get_name().is_some_and(|e| e == rename.from));
+    for child in object.children.iter_mut() {
+        match child {
+            TranslatedObjectChild::Assignment(assignment) => {
+                propagate_rename_in_value(&mut assignment.value, rename, unqualified)
+            }
+            TranslatedObjectChild::Property(prop) => {
+                if let Some(value) = &mut prop.default_value {
+                    propagate_rename_in_value(value, rename, unqualified);
+                }
+            }
+            TranslatedObjectChild::Function(function) => {
+                let shadowed = function
+                    .arguments
+                    .iter()
+                    .any(|e| matches!(e, TokenType::Identifier(name) if name == rename.from))
+                    || declares_local(&function.body, rename.from);
+                propagate_rename_in_tokens(&mut function.body, rename, unqualified && !shadowed)
+            }
+            TranslatedObjectChild::Object(object) => propagate_rename(object, rename, unqualified),
+            TranslatedObjectChild::ObjectAssignment(assignment)
+            | TranslatedObjectChild::ObjectListAssignment(assignment) => {
+                propagate_rename(&assignment.value, rename, unqualified)
+            }
+            TranslatedObjectChild::ObjectProperty(prop) => {
+                propagate_rename(&prop.default_value, rename, unqualified)
+            }
+            // Components are separate scopes.
+            TranslatedObjectChild::Component(_)
+            | TranslatedObjectChild::Enum(_)
+            | TranslatedObjectChild::Signal(_)
+            | TranslatedObjectChild::Anchor(_) => {}
+        }
+    }
+}
+
+/// Escapes the quotes which would end a literal delimited by `quote`.
+fn escape_quotes(text: &str, quote: char) -> Cow<'_, str> {
+    if !text.contains(quote) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::new();
+    let mut previous = None;
+    for c in text.chars() {
+        if c == quote && previous != Some('\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+        previous = Some(c);
+    }
+    Cow::Owned(escaped)
+}
+
+/// Replaces the text of a `REPLACE STRING` within the string literals of the tokens. Returns
+/// whether any literal has changed.
+fn replace_string_in_tokens(tokens: &mut [TokenType], replace: &ReplaceStringAction) -> bool {
+    let mut replaced = false;
+    for token in tokens {
+        let TokenType::String(literal) = token else {
+            continue;
+        };
+        // The literals keep their quotes.
+        let Some(quote) = literal.chars().next() else {
+            continue;
+        };
+        let Some(contents) = literal[quote.len_utf8()..].strip_suffix(quote) else {
+            continue;
+        };
+        let (from, to) = (
+            escape_quotes(&replace.from, quote),
+            escape_quotes(&replace.to, quote),
+        );
+        let contents = if replace.all && contents.contains(from.as_ref()) {
+            contents.replace(from.as_ref(), &to)
+        } else if contents == from {
+            to.into_owned()
+        } else {
+            continue;
+        };
+        *token = TokenType::String(format!("{}{}{}", quote, contents, quote));
+        replaced = true;
+    }
+    replaced
+}
+
+/// The index of the first token from `start` on which isn't whitespace or a comment.
+fn next_significant(tokens: &[TokenType], start: usize) -> Option<usize> {
+    (start..tokens.len())
+        .find(|i| !is_whitespace(&tokens[*i]) && !matches!(tokens[*i], TokenType::Comment(_)))
+}
+
+/// Translates the texts passed to `qsTr` within the tokens. Only plain string literals are
+/// translated - `qsTr("a" + b)` is left alone. Returns whether any call has changed.
+fn translate_tokens(tokens: &mut [TokenType], catalog: &TranslationCatalog, context: &str) -> bool {
+    let literal = |token: &TokenType| match token {
+        TokenType::String(literal) if literal.len() >= 2 && !literal.starts_with('`') => {
+            let quote = literal.chars().next().unwrap();
+            literal[1..]
+                .strip_suffix(quote)
+                .map(|contents| (quote, contents.to_string()))
+        }
+        _ => None,
+    };
+    let mut translated = false;
+    for i in 0..tokens.len() {
+        if !matches!(&tokens[i], TokenType::Identifier(name) if name == "qsTr") {
+            continue;
+        }
+        // qsTr(<source> [, <disambiguation>] [, n])
+        let Some(open) = next_significant(tokens, i + 1) else {
+            continue;
+        };
+        if tokens[open] != TokenType::Symbol('(') {
+            continue;
+        }
+        let Some(source) = next_significant(tokens, open + 1) else {
+            continue;
+        };
+        let Some((quote, contents)) = literal(&tokens[source]) else {
+            continue;
+        };
+        let after = next_significant(tokens, source + 1);
+        let comment = match after.map(|e| &tokens[e]) {
+            Some(TokenType::Symbol(')')) => String::new(),
+            Some(TokenType::Symbol(',')) => {
+                let comment = next_significant(tokens, after.unwrap() + 1);
+                match comment.and_then(|e| literal(&tokens[e])) {
+                    Some((_, comment)) => unescape_string_literal(&comment),
+                    None => continue,
+                }
+            }
+            _ => continue,
+        };
+        let text = unescape_string_literal(&contents);
+        let Some(translation) = catalog.translate(context, &text, &comment) else {
+            continue;
+        };
+        if translation != text {
+            let contents = escape_string_literal(translation, quote);
+            tokens[source] = TokenType::String(format!("{}{}{}", quote, contents, quote));
+            translated = true;
+        }
+    }
+    translated
+}
+
+fn rewrite_tokens_in_value(
+    value: &mut AssignmentChildValue,
+    rewrite: &mut dyn FnMut(&mut [TokenType]) -> bool,
+    changed: &mut Vec<(String, String)>,
+) -> bool {
+    match value {
+        AssignmentChildValue::Other(tokens) => rewrite(tokens),
+        AssignmentChildValue::Object(object) => {
+            let translated = translate(object.clone());
+            rewrite_tokens(&translated, rewrite, changed);
+            *object = untranslate(translated);
+            false
+        }
+    }
+}
+
+/// Runs `rewrite` on the bindings and functions of the object and of all its child objects,
+/// components included. `rewrite` returns whether it has changed the tokens - the summaries of
+/// the changed children, before and after the change, are added to `changed`.
+fn rewrite_tokens(
+    object: &TranslatedObjectRef,
+    rewrite: &mut dyn FnMut(&mut [TokenType]) -> bool,
+    changed: &mut Vec<(String, String)>,
+) {
+    let mut object = object.borrow_mut();
+    for child in object.children.iter_mut() {
+        let before = child.summary();
+        let replaced = match child {
+            TranslatedObjectChild::Assignment(assignment) => {
+                rewrite_tokens_in_value(&mut assignment.value, rewrite, changed)
+            }
+            TranslatedObjectChild::Property(prop) => prop
+                .default_value
+                .as_mut()
+                .is_some_and(|value| rewrite_tokens_in_value(value, rewrite, changed)),
+            TranslatedObjectChild::Function(function) => rewrite(&mut function.body),
+            TranslatedObjectChild::Object(object) => {
+                rewrite_tokens(object, rewrite, changed);
+                false
+            }
+            TranslatedObjectChild::ObjectAssignment(assignment)
+            | TranslatedObjectChild::ObjectListAssignment(assignment)
+            | TranslatedObjectChild::Component(assignment) => {
+                rewrite_tokens(&assignment.value, rewrite, changed);
+                false
+            }
+            TranslatedObjectChild::ObjectProperty(prop) => {
+                rewrite_tokens(&prop.default_value, rewrite, changed);
+                false
+            }
+            TranslatedObjectChild::Enum(_)
+            | TranslatedObjectChild::Signal(_)
+            | TranslatedObjectChild::Anchor(_) => false,
+        };
+        if replaced {
+            changed.push((before, child.summary()));
+        }
+    }
+}
+
+/// Applies a change to the tree. Errors record the directive which failed.
+fn process(
+    absolute_root: &mut TranslatedTree,
+    diff: &Change,
+    file_name: &str,
+    slots: &mut Slots,
+    journal: Option<&mut MutationJournal>,
+    trace: Option<&mut Vec<DirectiveTrace>>,
+) -> Result<usize> {
+    let mut position = 0;
+    add_directive_if_needed(
+        process_directives(
+            absolute_root,
+            diff,
+            file_name,
+            slots,
+            journal,
+            trace,
+            &mut position,
+        ),
+        // Only the first line of the directive - without the code or the directives it contains.
+        || {
+            let directive = diff.changes[position].to_string();
+            directive.lines().next().unwrap_or_default().to_string()
+        },
+    )
+}
+
+fn process_directives(
+    absolute_root: &mut TranslatedTree,
+    diff: &Change,
+    file_name: &str,
+    slots: &mut Slots,
+    mut journal: Option<&mut MutationJournal>,
+    mut trace: Option<&mut Vec<DirectiveTrace>>,
+    position: &mut usize,
+) -> Result<usize> {
+    let recording = journal.is_some();
+    let tracing = trace.is_some();
     let mut root_stack: Vec<RootReference> = Vec::new();
+    // The directives which lead to the current root - used only by the journal.
+    let mut scope: Vec<String> = Vec::new();
     let mut current_root = RootReference {
         root: vec![TreeRoot::Object(absolute_root.root.clone())],
         cursor: None,
         is_replicating: false,
     }; // Start with root as the current root
 
+    // The states saved by PUSH SCOPE - the current root, the root stack and the journal's scope.
+    let mut saved_scopes: Vec<(RootReference, Vec<RootReference>, Vec<String>)> = Vec::new();
+    // How many directives actually inserted, removed or altered something.
+    let mut mutations = 0;
+
     macro_rules! unambiguous_root {
         () => {{
             if current_root.root.len() != 1 {
-                return Err(Error::msg(format!(
-                    "Root must be unambiguous! (Right now {} elements matched)",
-                    current_root.root.len()
-                )));
+                return Err(kind_error(
+                    ErrorKind::Selector,
+                    format!(
+                        "Root must be unambiguous! (Right now {} elements matched)",
+                        current_root.root.len()
+                    ),
+                ));
             }
             &current_root.root[0]
         }};
     }
 
+    macro_rules! journal {
+        ($action: expr, $selector: expr, $cursor: expr, $inserted: expr, $removed: expr, $undo: expr) => {
+            let (inserted, removed): (Vec<String>, Vec<String>) = ($inserted, $removed);
+            if !inserted.is_empty() || !removed.is_empty() {
+                mutations += 1;
+            }
+            if let Some(journal) = journal.as_deref_mut() {
+                journal.record(JournalEntry {
+                    file: file_name.to_string(),
+                    source: diff.source.to_string(),
+                    action: $action,
+                    scope: scope.clone(),
+                    selector: $selector,
+                    cursor: $cursor,
+                    inserted,
+                    removed,
+                    undo: $undo,
+                });
+            }
+        };
+    }
+
+    macro_rules! trace {
+        ($directive: expr, $matched: expr) => {
+            if let Some(trace) = trace.as_deref_mut() {
+                trace.push(DirectiveTrace {
+                    directive: $directive,
+                    matched: $matched,
+                });
+            }
+        };
+    }
+
+    // Drops the innermost TRAVERSE / REPLICATE directive and the ASSERTs within it.
+    macro_rules! pop_scope {
+        () => {
+            while let Some(directive) = scope.pop() {
+                if !directive.starts_with("ASSERT") {
+                    break;
+                }
+            }
+        };
+    }
+
     macro_rules! unambiguous_root_cursor_set {
         () => {{
             let reference = unambiguous_root!();
@@ -1001,12 +2287,14 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
         }};
     }
 
-    for change in &diff.changes {
+    for (i, change) in diff.changes.iter().enumerate() {
+        *position = i;
         match change {
             FileChangeAction::End(Keyword::Traverse) if !current_root.is_replicating => {
                 // Pop the last object from the stack to return to the previous root
                 if let Some(root) = root_stack.pop() {
                     current_root = root;
+                    pop_scope!();
                 } else {
                     return Err(Error::msg("Cannot END TRAVERSE - end of scope!"));
                 }
@@ -1022,13 +2310,23 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
                         }
                     };
                     current_root = previous_root;
+                    pop_scope!();
                     // Merge
                     let (root, cursor) = unambiguous_root_cursor_set!();
                     match root {
                         TreeRoot::Object(obj) => {
+                            let merged = cursor..cursor + children_to_merge.len();
                             obj.borrow_mut()
                                 .children
                                 .splice(cursor..cursor, children_to_merge);
+                            journal!(
+                                "REPLICATE",
+                                None,
+                                Some(cursor),
+                                summarize_children(root, merged.clone()),
+                                Vec::new(),
+                                undo_insertion(root, merged)
+                            );
                         }
                         _ => {
                             return Err(Error::msg(
@@ -1043,18 +2341,48 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
             FileChangeAction::End(_) => {
                 return Err(Error::msg("END TRAVERSE / END REPLICATE first!"));
             }
-            FileChangeAction::Replicate(tree) => {
-                let object = locate_in_tree(current_root.root.clone(), tree, true);
+            FileChangeAction::PushScope => {
+                // The fake root of a REPLICATE is gone once it's merged back, so there would be
+                // nothing to return to.
+                if current_root.is_replicating || root_stack.iter().any(|e| e.is_replicating) {
+                    return Err(Error::msg("Cannot PUSH SCOPE within REPLICATE!"));
+                }
+                saved_scopes.push((current_root.clone(), root_stack.clone(), scope.clone()));
+            }
+            FileChangeAction::PopScope => {
+                if current_root.is_replicating || root_stack.iter().any(|e| e.is_replicating) {
+                    return Err(Error::msg("END REPLICATE before POP SCOPE!"));
+                }
+                if let Some((root, stack, saved_scope)) = saved_scopes.pop() {
+                    current_root = root;
+                    root_stack = stack;
+                    scope = saved_scope;
+                } else {
+                    return Err(Error::msg("Cannot POP SCOPE - no scope has been pushed!"));
+                }
+            }
+            FileChangeAction::Replicate(tree) | FileChangeAction::ReplicateFromRoot(tree) => {
+                let (roots, directive) = match change {
+                    FileChangeAction::ReplicateFromRoot(_) => (
+                        vec![TreeRoot::Object(absolute_root.root.clone())],
+                        format!("REPLICATE ROOT {}", tree),
+                    ),
+                    _ => (current_root.root.clone(), format!("REPLICATE {}", tree)),
+                };
+                let object = locate_in_tree(roots, tree, true);
                 if object.len() != 1 {
-                    return Err(Error::msg(format!(
-                        "Cannot locate exactly one elemnt for replication: {}",
-                        tree_to_string(tree)
-                    )));
+                    return Err(kind_error(
+                        ErrorKind::Selector,
+                        format!("Cannot locate exactly one elemnt for replication: {}", tree),
+                    ));
                 }
 
+                trace!(directive.clone(), root_full_names(&object));
+
                 // Push the current root onto the stack and create a new root that will consist of the replicated object
 
                 root_stack.push(current_root);
+                scope.push(directive);
                 let element = match object.first().unwrap() {
                     TreeRoot::Child {
                         parent,
@@ -1072,56 +2400,56 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
                     is_replicating: true,
                 }
             }
-            FileChangeAction::Traverse(tree) => {
+            FileChangeAction::Traverse(tree) | FileChangeAction::TraverseFromRoot(tree) => {
+                let (roots, directive) = match change {
+                    FileChangeAction::TraverseFromRoot(_) => (
+                        vec![TreeRoot::Object(absolute_root.root.clone())],
+                        format!("TRAVERSE ROOT {}", tree),
+                    ),
+                    _ => (current_root.root.clone(), format!("TRAVERSE {}", tree)),
+                };
                 // Attempt to locate the child object in the current root
-                let object = locate_in_tree(current_root.root.clone(), tree, false);
+                let object = locate_in_file(absolute_root, roots, tree);
                 if object.is_empty() {
-                    return Err(Error::msg(format!(
-                        "Cannot locate element in tree: {}",
-                        tree_to_string(tree)
-                    )));
+                    return Err(kind_error(
+                        ErrorKind::Selector,
+                        format!("Cannot locate element in tree: {}", tree),
+                    ));
                 }
 
+                trace!(directive.clone(), root_full_names(&object));
+
                 // Push the current root onto the stack and set the new current root
                 root_stack.push(current_root);
+                scope.push(directive);
                 current_root = RootReference {
                     root: object,
                     cursor: None,
                     is_replicating: false,
                 };
             }
-            FileChangeAction::Assert(tree_selector) => {
-                current_root.root.retain(|e| {
-                    // Is the tree selector simple
-                    if tree_selector.len() == 1 && tree_selector[0].is_simple() {
-                        match &e {
-                            TreeRoot::Object(e) => {
-                                for child_object in &e.borrow().children {
-                                    // Yes, and it matches
-                                    if child_object.get_name()
-                                        == Some(&tree_selector[0].object_name)
-                                    {
-                                        return true;
-                                    }
-                                }
-                            }
-                            TreeRoot::Enum(e) => {
-                                for value in e.values.borrow().iter() {
-                                    if value.0 == tree_selector[0].object_name {
-                                        return true;
-                                    }
-                                }
-                            }
-                            TreeRoot::Child {
-                                parent: _,
-                                child_index: _,
-                            } => traverse_no_raw_children!(),
-                        }
-                    }
-                    !locate_in_tree(vec![e.clone()], tree_selector, false).is_empty()
-                });
+            FileChangeAction::Assert(assert) => {
+                current_root
+                    .root
+                    .retain(|e| root_contains(e, &assert.tree) != assert.negated);
                 if current_root.root.is_empty() {
-                    return Err(Error::msg("ASSERTed all objects out of existence"));
+                    let error = if assert.negated {
+                        format!("ASSERT NOT {} matched all objects", assert.tree)
+                    } else {
+                        "ASSERTed all objects out of existence".to_string()
+                    };
+                    return Err(kind_error(
+                        ErrorKind::Selector,
+                        match &assert.message {
+                            Some(message) => format!("{}: {}", error, message),
+                            None => error,
+                        },
+                    ));
+                }
+                trace!(assert.to_string(), root_full_names(&current_root.root));
+                // The negated ASSERTs might not hold anymore once the change is applied.
+                if !assert.negated {
+                    scope.push(assert.to_string());
                 }
             }
             FileChangeAction::Insert(insertable) => {
@@ -1136,10 +2464,79 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
                     }
                 } {
                     let (root, mut cursor) = unambiguous_root_cursor_set!();
-                    insert_into_root(&mut cursor, root, code, slots)?;
+                    let start = cursor;
+                    let inserted = insert_into_root(&mut cursor, root, code, slots)?;
+                    check_top_level_children(absolute_root, root, inserted.clone())?;
+                    check_list_children(absolute_root, root, inserted.clone())?;
+                    journal!(
+                        "INSERT",
+                        None,
+                        Some(start),
+                        summarize_children(root, inserted.clone()),
+                        Vec::new(),
+                        undo_insertion(root, inserted)
+                    );
                     current_root.cursor = Some(cursor);
                 }
             }
+            FileChangeAction::InsertSorted(sorted) => {
+                let root = unambiguous_root!();
+                let TreeRoot::Object(obj) = root else {
+                    return Err(Error::msg(format!(
+                        "Cannot INSERT SORTED BY {} - not within an object!",
+                        sorted.property
+                    )));
+                };
+                // Insert at the end first, then move the new children into place.
+                let mut end = obj.borrow().children.len();
+                let inserted = insert_into_root(&mut end, root, &sorted.code, slots)?;
+                let new_children: Vec<_> = obj.borrow_mut().children.drain(inserted).collect();
+                let key = match new_children
+                    .iter()
+                    .find_map(|e| sort_key(e, &sorted.property))
+                {
+                    Some(key) => key,
+                    None => {
+                        return Err(Error::msg(format!(
+                            "Cannot INSERT SORTED BY {} - the inserted code doesn't assign it!",
+                            sorted.property
+                        )))
+                    }
+                };
+                let start = {
+                    let children = &obj.borrow().children;
+                    let keys: Vec<_> = children
+                        .iter()
+                        .map(|e| sort_key(e, &sorted.property))
+                        .collect();
+                    // Before the first child which sorts after the new one, otherwise right
+                    // after the last sorted child.
+                    match keys.iter().position(|e| {
+                        e.as_ref().is_some_and(|e| {
+                            compare_sort_keys(e, &key) == std::cmp::Ordering::Greater
+                        })
+                    }) {
+                        Some(idx) => idx,
+                        None => keys
+                            .iter()
+                            .rposition(|e| e.is_some())
+                            .map_or(children.len(), |idx| idx + 1),
+                    }
+                };
+                let count = new_children.len();
+                obj.borrow_mut().children.splice(start..start, new_children);
+                let inserted = start..start + count;
+                check_list_children(absolute_root, root, inserted.clone())?;
+                journal!(
+                    "INSERT",
+                    Some(format!("SORTED BY {}", sorted.property)),
+                    Some(start),
+                    summarize_children(root, inserted.clone()),
+                    Vec::new(),
+                    undo_insertion(root, inserted.clone())
+                );
+                current_root.cursor = Some(inserted.end);
+            }
             FileChangeAction::Locate(location) => {
                 let root = unambiguous_root!();
                 current_root.cursor = Some(match &location.selector {
@@ -1156,30 +2553,64 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
                     },
                     LocationSelector::Tree(tree) => {
                         let element_idx = find_first_matching_child(root, tree)?;
+                        trace!(
+                            location.to_string(),
+                            vec![child_full_name_at(root, element_idx)]
+                        );
 
                         match location.location {
                             Location::After => element_idx + 1,
                             Location::Before => element_idx,
                         }
                     }
+                    LocationSelector::Member(kind, name) => {
+                        let element_idx = find_member(root, *kind, name)?;
+                        trace!(
+                            location.to_string(),
+                            vec![child_full_name_at(root, element_idx)]
+                        );
+
+                        match location.location {
+                            Location::After => element_idx + 1,
+                            Location::Before => element_idx,
+                        }
+                    }
+                    LocationSelector::Anchor(name) => {
+                        let element_idx = find_anchor(root, name)?;
+                        trace!(location.to_string(), vec![format!("anchor {}", name)]);
+
+                        // The anchor itself stays where it was declared.
+                        match location.location {
+                            Location::After => element_idx + 1,
+                            Location::Before => element_idx,
+                        }
+                    }
                 });
             }
             FileChangeAction::Replace(replacer) => {
                 let root = unambiguous_root!();
                 let mut element_idx = find_first_matching_child(root, &replacer.selector)?;
-                match root {
+                trace!(
+                    format!("REPLACE {}", replacer.selector),
+                    vec![child_full_name_at(root, element_idx)]
+                );
+                let start = element_idx;
+                let anchor = recording.then(|| anchor_of(root, element_idx)).flatten();
+                let (removed, removed_code) = match root {
                     TreeRoot::Object(obj) => {
-                        obj.borrow_mut().children.remove(element_idx);
+                        let child = obj.borrow_mut().children.remove(element_idx);
+                        (child.summary(), child_code(&child))
                     }
                     TreeRoot::Enum(r#enum) => {
-                        r#enum.values.borrow_mut().remove(element_idx);
+                        let value = r#enum.values.borrow_mut().remove(element_idx);
+                        (value.0.clone(), enum_value_code(&value))
                     }
                     TreeRoot::Child {
                         parent: _,
                         child_index: _,
                     } => traverse_no_raw_children!(),
                 };
-                insert_into_root(
+                let inserted = insert_into_root(
                     &mut element_idx,
                     root,
                     match &replacer.content {
@@ -1193,17 +2624,184 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
                     },
                     slots,
                 )?;
+                check_list_children(absolute_root, root, inserted.clone())?;
+                // Such a REPLACE is most likely a leftover, which still forces the file to be
+                // re-emitted.
+                if children_code(root, inserted.clone()) == removed_code {
+                    warning!(
+                        "Warning: REPLACE {} in {} (from {}) doesn't change anything - the new contents are the same as the replaced ones.",
+                        replacer.selector, file_name, diff.source
+                    );
+                }
+                journal!(
+                    "REPLACE",
+                    Some(replacer.selector.to_string()),
+                    Some(start),
+                    summarize_children(root, inserted.clone()),
+                    vec![removed],
+                    [
+                        undo_insertion(root, inserted),
+                        reinsert_directives(anchor, &removed_code),
+                    ]
+                    .concat()
+                );
                 current_root.cursor = Some(element_idx);
             }
+            FileChangeAction::Transform(transform) => {
+                let objects = locate_in_tree(current_root.root.clone(), &transform.selector, false);
+                if objects.is_empty() {
+                    return Err(kind_error(
+                        ErrorKind::Selector,
+                        format!("Cannot locate element in tree: {}", transform.selector),
+                    ));
+                }
+                trace!(
+                    format!("TRANSFORM {}", transform.selector),
+                    root_full_names(&objects)
+                );
+                // The undo directives find the objects again by their parents' selectors.
+                let parents: String = transform.selector[..transform.selector.len() - 1]
+                    .iter()
+                    .map(|e| format!("{} > ", e))
+                    .collect();
+                for object in objects {
+                    let TreeRoot::Object(object) = object else {
+                        return Err(Error::msg("Cannot TRANSFORM an enum!"));
+                    };
+                    let transformed = transform_object(&object, transform)?;
+                    let path = parents.clone()
+                        + &child_selector(&TranslatedObjectChild::Object(object.clone()));
+                    journal!(
+                        "TRANSFORM",
+                        Some(transform.selector.to_string()),
+                        None,
+                        transformed.inserted,
+                        transformed.removed,
+                        vec![traverse_directive(&path, &transformed.undo)]
+                    );
+                }
+            }
+            FileChangeAction::Translate(translate) => {
+                // Like qsTr, the context of a file is its name without the extension.
+                let context = std::path::Path::new(file_name)
+                    .file_stem()
+                    .map(|e| e.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                let mut changed = Vec::new();
+                rewrite_tokens(
+                    &absolute_root.root,
+                    &mut |tokens| translate_tokens(tokens, &translate.catalog, &context),
+                    &mut changed,
+                );
+                trace!(
+                    format!("TRANSLATE USING {}", translate.path),
+                    changed.iter().map(|e| e.1.clone()).collect()
+                );
+                let (removed, inserted) = changed.into_iter().unzip();
+                journal!(
+                    "TRANSLATE",
+                    Some(translate.path.clone()),
+                    None,
+                    inserted,
+                    removed,
+                    vec![format!(
+                        "; The TRANSLATE USING {} cannot be undone - restore the file manually.",
+                        translate.path
+                    )]
+                );
+            }
+            FileChangeAction::ReplaceString(replace) => {
+                // Not limited to the current scope - the strings of the whole file are replaced.
+                let mut changed = Vec::new();
+                rewrite_tokens(
+                    &absolute_root.root,
+                    &mut |tokens| replace_string_in_tokens(tokens, replace),
+                    &mut changed,
+                );
+                if changed.is_empty() {
+                    return Err(kind_error(
+                        ErrorKind::Selector,
+                        format!("Cannot find the string \"{}\" in the file", replace.from),
+                    ));
+                }
+                trace!(
+                    replace.to_string(),
+                    changed.iter().map(|e| e.1.clone()).collect()
+                );
+                let (removed, inserted) = changed.into_iter().unzip();
+                // Also replaces the literals which were already the new string before.
+                let undo = if replace.to.is_empty() {
+                    format!(
+                        "; The REPLACE STRING of \"{}\" cannot be undone - restore the file manually.",
+                        replace.from
+                    )
+                } else {
+                    ReplaceStringAction {
+                        from: replace.to.clone(),
+                        to: replace.from.clone(),
+                        all: replace.all,
+                    }
+                    .to_string()
+                };
+                journal!(
+                    "REPLACE STRING",
+                    Some(replace.from.clone()),
+                    None,
+                    inserted,
+                    removed,
+                    vec![undo]
+                );
+            }
             FileChangeAction::Rename(rename) => {
                 let root = unambiguous_root!();
                 let element_idx = find_first_matching_child(root, &rename.selector)?;
+                trace!(
+                    format!("RENAME {}", rename.selector),
+                    vec![child_full_name_at(root, element_idx)]
+                );
                 match root {
                     TreeRoot::Enum(_) => {
-                        return Err(Error::msg("Cannot RENAME a value within an enum!"))
+                        return Err(Error::msg(
+                            "Cannot RENAME a value within an enum! Use RENAME VALUE instead.",
+                        ))
                     }
                     TreeRoot::Object(obj) => {
-                        obj.borrow_mut().children[element_idx].set_name(rename.name_to.clone())?;
+                        let (old_name, old_summary, new_summary) = {
+                            let child = &mut obj.borrow_mut().children[element_idx];
+                            let old_summary = child.summary();
+                            let old_name = child.get_name().cloned().unwrap_or_default();
+                            child.set_name(rename.name_to.clone())?;
+                            (old_name, old_summary, child.summary())
+                        };
+                        let mut undo = format!("RENAME {} TO {}", rename.name_to, old_name);
+                        if rename.propagate {
+                            let own_id = obj.borrow().children.iter().find_map(|e| match e {
+                                TranslatedObjectChild::Assignment(assignment)
+                                    if assignment.name == "id" =>
+                                {
+                                    e.get_str_value()
+                                }
+                                _ => None,
+                            });
+                            propagate_rename(
+                                obj,
+                                &PropagatedRename {
+                                    from: &old_name,
+                                    to: &rename.name_to,
+                                    own_id,
+                                },
+                                true,
+                            );
+                            undo += " PROPAGATE";
+                        }
+                        journal!(
+                            "RENAME",
+                            Some(rename.selector.to_string()),
+                            Some(element_idx),
+                            vec![new_summary],
+                            vec![old_summary],
+                            vec![undo]
+                        );
                     }
                     TreeRoot::Child {
                         parent: _,
@@ -1212,44 +2810,191 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
                 }
                 current_root.cursor = Some(element_idx + 1);
             }
+            FileChangeAction::EnumValue(action) => {
+                let root = unambiguous_root!();
+                let TreeRoot::Enum(r#enum) = root else {
+                    return Err(Error::msg(format!(
+                        "Cannot {} - not within an enum!",
+                        action
+                    )));
+                };
+                let position = |name: &str| r#enum.values.borrow().iter().position(|e| e.0 == name);
+                let find = |name: &str| {
+                    position(name).ok_or_else(|| {
+                        kind_error(
+                            ErrorKind::Selector,
+                            format!("The enum {} has no value {}", r#enum.name, name),
+                        )
+                    })
+                };
+                let ensure_free = |name: &str| match position(name) {
+                    Some(_) => Err(Error::msg(format!(
+                        "The enum {} already has a value {}",
+                        r#enum.name, name
+                    ))),
+                    None => Ok(()),
+                };
+                match action {
+                    EnumValueAction::Insert(name, value) => {
+                        ensure_free(name)?;
+                        // Without a cursor, the value is inserted as if after LOCATE AFTER ALL.
+                        let index = current_root
+                            .cursor
+                            .unwrap_or_else(|| r#enum.values.borrow().len());
+                        r#enum
+                            .values
+                            .borrow_mut()
+                            .insert(index, (name.clone(), value.clone()));
+                        journal!(
+                            "INSERT VALUE",
+                            None,
+                            Some(index),
+                            vec![name.clone()],
+                            Vec::new(),
+                            vec![format!("REMOVE VALUE {}", name)]
+                        );
+                        current_root.cursor = Some(index + 1);
+                    }
+                    EnumValueAction::Remove(name) => {
+                        let index = find(name)?;
+                        trace!(action.to_string(), vec![name.clone()]);
+                        let anchor = anchor_of(root, index);
+                        let value = r#enum.values.borrow_mut().remove(index);
+                        journal!(
+                            "REMOVE VALUE",
+                            Some(name.clone()),
+                            None,
+                            Vec::new(),
+                            vec![name.clone()],
+                            vec![
+                                match anchor {
+                                    Some(anchor) => format!("LOCATE AFTER {}", anchor),
+                                    None => "LOCATE BEFORE ALL".to_string(),
+                                },
+                                format!("INSERT VALUE {}", enum_value_code(&value)),
+                            ]
+                        );
+                    }
+                    EnumValueAction::Rename(name, new_name) => {
+                        let index = find(name)?;
+                        ensure_free(new_name)?;
+                        trace!(action.to_string(), vec![name.clone()]);
+                        r#enum.values.borrow_mut()[index].0 = new_name.clone();
+                        journal!(
+                            "RENAME VALUE",
+                            Some(name.clone()),
+                            Some(index),
+                            vec![new_name.clone()],
+                            vec![name.clone()],
+                            vec![format!("RENAME VALUE {} TO {}", new_name, name)]
+                        );
+                        current_root.cursor = Some(index + 1);
+                    }
+                }
+            }
             FileChangeAction::Remove(selector) => {
                 // Root must be unambiguous
-                match unambiguous_root!() {
+                let root = unambiguous_root!();
+                let mut removed = Vec::new();
+                let mut matched = Vec::new();
+                // The removed children's code, along with the amount of children kept before them.
+                let mut removed_code: Vec<(usize, String)> = Vec::new();
+                let mut kept = 0;
+                match root {
                     TreeRoot::Object(obj) => {
-                        obj.borrow_mut().children.retain(|e| {
-                            if selector.is_simple() {
+                        let removes = |sel: &NodeSelector, e: &TranslatedObjectChild| {
+                            if sel.is_simple() {
                                 // Might be a generic prop.
-                                if e.get_name() == Some(&selector.object_name) {
-                                    return false;
+                                if e.get_name() == Some(&sel.object_name) {
+                                    return true;
                                 }
                             }
 
                             // Complex object. Delve deeper.
                             match e {
                                 TranslatedObjectChild::Object(e) => {
-                                    !does_match(&e.borrow(), selector, None)
+                                    does_match(&e.borrow(), sel, None)
                                 }
                                 TranslatedObjectChild::ObjectAssignment(e) => {
-                                    !does_match(&e.value.borrow(), selector, Some(&e.name))
+                                    does_match(&e.value.borrow(), sel, Some(&e.name))
+                                }
+                                TranslatedObjectChild::ObjectProperty(e) => {
+                                    does_match(&e.default_value.borrow(), sel, Some(&e.name))
+                                }
+                                _ => false, // Retain all else!
+                            }
+                        };
+                        // The alternatives are only used if the previous ones match nothing.
+                        let sel = selector
+                            .candidates()
+                            .find(|sel| obj.borrow().children.iter().any(|e| removes(sel, e)))
+                            .unwrap_or(selector);
+                        obj.borrow_mut().children.retain(|e| {
+                            let retain = !removes(sel, e);
+                            if retain {
+                                kept += 1;
+                            } else {
+                                removed.push(e.summary());
+                                if tracing {
+                                    matched.push(child_full_name(e));
+                                }
+                                if recording {
+                                    removed_code.push((kept, child_code(e)));
                                 }
-                                _ => true, // Retain all else!
                             }
+                            retain
                         });
                     }
                     TreeRoot::Enum(r#enum) => {
-                        if !selector.is_simple() {
+                        let sel = selector
+                            .candidates()
+                            .find(|sel| {
+                                r#enum
+                                    .values
+                                    .borrow()
+                                    .iter()
+                                    .any(|e| e.0 == sel.object_name)
+                            })
+                            .unwrap_or(selector);
+                        if !sel.is_simple() {
                             return Err(Error::msg("Cannot do precision removal in enum."));
                         }
-                        r#enum
-                            .values
-                            .borrow_mut()
-                            .retain(|e| e.0 != selector.object_name);
+                        r#enum.values.borrow_mut().retain(|e| {
+                            if e.0 == sel.object_name {
+                                removed.push(e.0.clone());
+                                matched.push(e.0.clone());
+                                removed_code.push((kept, enum_value_code(e)));
+                                false
+                            } else {
+                                kept += 1;
+                                true
+                            }
+                        });
                     }
                     TreeRoot::Child {
                         parent: _,
                         child_index: _,
                     } => traverse_no_raw_children!(),
                 }
+                // Children removed from between the same two siblings are put back together.
+                let mut undo = Vec::new();
+                for group in removed_code.chunk_by(|a, b| a.0 == b.0) {
+                    let code = group
+                        .iter()
+                        .map(|e| e.1.as_str())
+                        .collect::<Vec<_>>()
+                        .join("\n");
+                    undo.extend(reinsert_directives(anchor_of(root, group[0].0), &code));
+                }
+                trace!(format!("REMOVE {}", selector), matched);
+                journal!(
+                    "REMOVE",
+                    Some(selector.to_string()),
+                    None,
+                    Vec::new(),
+                    removed,
+                    undo
+                );
             }
             FileChangeAction::AddImport(import) => {
                 if !root_stack.is_empty() {
@@ -1285,26 +3030,111 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
                         object_name: import.name.clone(),
                         version: Some(import.version.clone()),
                     }));
+                    journal!(
+                        "IMPORT",
+                        None,
+                        None,
+                        vec![match &import.alias {
+                            Some(alias) =>
+                                format!("{} {} as {}", import.name, import.version, alias),
+                            None => format!("{} {}", import.name, import.version),
+                        }],
+                        Vec::new(),
+                        vec![format!(
+                            "; The IMPORT of {} cannot be undone - remove it manually if needed.",
+                            import.name
+                        )]
+                    );
+                }
+            }
+            FileChangeAction::AddPragma(pragma) => {
+                if !root_stack.is_empty() {
+                    return Err(Error::msg(
+                        "Cannot use pragma within TRAVERSE / SLOT statements!",
+                    ));
+                }
+                let existing = absolute_root.leftovers.iter().find_map(|e| match e {
+                    TreeElement::Pragma(e) if e.pragma == pragma.name => Some(e),
+                    _ => None,
+                });
+                match existing {
+                    // Already set. Do not duplicate it.
+                    Some(existing) if existing.value == pragma.value => {}
+                    Some(existing) => {
+                        return Err(Error::msg(format!(
+                            "Cannot add `{}` - the file already has `{}`",
+                            pragma_code(&pragma.name, &pragma.value),
+                            pragma_code(&existing.pragma, &existing.value)
+                        )));
+                    }
+                    None => {
+                        // The pragmas go first, before the imports.
+                        let position = absolute_root
+                            .leftovers
+                            .iter()
+                            .rposition(|e| matches!(e, TreeElement::Pragma(_)))
+                            .map_or(0, |e| e + 1);
+                        absolute_root.leftovers.insert(
+                            position,
+                            TreeElement::Pragma(Pragma {
+                                pragma: pragma.name.clone(),
+                                value: pragma.value.clone(),
+                            }),
+                        );
+                        journal!(
+                            "PRAGMA",
+                            None,
+                            None,
+                            vec![pragma_code(&pragma.name, &pragma.value)],
+                            Vec::new(),
+                            vec![format!(
+                                "; The PRAGMA {} cannot be undone - remove it manually if needed.",
+                                pragma.name
+                            )]
+                        );
+                    }
                 }
             }
             FileChangeAction::Rebuild(rebuild) => {
                 let root = unambiguous_root!();
-                let element_idx = find_first_matching_child(root, &vec![rebuild.selector.clone()])?;
+                let element_idx =
+                    find_first_matching_child(root, &NodeTree(vec![rebuild.selector.clone()]))?;
                 match root {
                     TreeRoot::Enum(_) => {
                         return Err(Error::msg("Cannot rebuild an enum!"));
                     }
                     TreeRoot::Object(obj) => {
-                        if rebuild.redefine {
+                        let anchor = recording.then(|| anchor_of(root, element_idx)).flatten();
+                        let (removed, removed_code) = {
+                            let child = &obj.borrow().children[element_idx];
+                            (child.summary(), recording.then(|| child_code(child)))
+                        };
+                        let new_count = if rebuild.redefine {
                             let child = obj.borrow_mut().children.remove(element_idx);
                             let new_children = redefine_child(rebuild, child)?;
+                            let new_count = new_children.len();
                             obj.borrow_mut()
                                 .children
-                                .splice(element_idx..element_idx, new_children.into_iter());
+                                .splice(element_idx..element_idx, new_children);
+                            new_count
                         } else {
                             let child_reference = &mut obj.borrow_mut().children[element_idx];
                             rebuild_child(rebuild, child_reference)?;
-                        }
+                            1
+                        };
+                        let inserted = element_idx..element_idx + new_count;
+                        journal!(
+                            "REBUILD",
+                            Some(rebuild.selector.to_string()),
+                            Some(element_idx),
+                            summarize_children(root, inserted.clone()),
+                            vec![removed],
+                            [
+                                undo_insertion(root, inserted),
+                                reinsert_directives(anchor, &removed_code.unwrap_or_default()),
+                            ]
+                            .concat()
+                        );
                     }
                     TreeRoot::Child {
                         parent: _,
@@ -1315,8 +3145,16 @@ fn process(absolute_root: &mut TranslatedTree, diff: &Change, slots: &mut Slots)
             FileChangeAction::AllowMultiple => {
                 return Err(Error::msg("Not supported yet!"));
             }
+            FileChangeAction::LocalSlot(..) => {
+                panic!("Cannot define local slot! Use `process_slots()` first!")
+            }
+            FileChangeAction::AddQmlDirEntry(_) | FileChangeAction::RemoveQmlDirEntry(_) => {
+                return Err(Error::msg(
+                    "ADD / REMOVE ENTRY can only be used within qmldir files!",
+                ));
+            }
         }
     }
 
-    Ok(())
+    Ok(mutations)
 }
@@ -0,0 +1,312 @@
+use std::{collections::HashMap, fmt::Display};
+
+use crate::parser::qml::{
+    emitter::{canonicalize_tree, emit, emit_object_children, EmitterConfig},
+    parser::{
+        AssignmentChildValue, Object, ObjectChild, ObjectListAssignmentChild, QMLTree, TreeElement,
+    },
+};
+
+const HEADER_PATH: &str = "imports and pragmas";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QMLDifference {
+    /// The path of the child, and its code
+    Added(String, Vec<String>),
+    Removed(String, Vec<String>),
+    /// The path of the child, its old and new code
+    Changed(String, Vec<String>, Vec<String>),
+    /// The path of the object, the old and new order of its child objects
+    Reordered(String, Vec<String>, Vec<String>),
+}
+
+impl Display for QMLDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (header, old, new) = match self {
+            Self::Added(path, lines) => (format!("Added {}", path), &vec![], lines),
+            Self::Removed(path, lines) => (format!("Removed {}", path), lines, &vec![]),
+            Self::Changed(path, old, new) => (format!("Changed {}", path), old, new),
+            Self::Reordered(path, old, new) => {
+                let what = match path.as_str() {
+                    "" => "the root objects".to_string(),
+                    HEADER_PATH => format!("the {}", HEADER_PATH),
+                    _ => format!("the children of {}", path),
+                };
+                return write!(
+                    f,
+                    "Reordered {}:\n    - {}\n    + {}",
+                    what,
+                    old.join(", "),
+                    new.join(", ")
+                );
+            }
+        };
+        write!(f, "{}:", header)?;
+        for line in old {
+            write!(f, "\n    - {}", line)?;
+        }
+        for line in new {
+            write!(f, "\n    + {}", line)?;
+        }
+        Ok(())
+    }
+}
+
+fn emit_config() -> EmitterConfig {
+    EmitterConfig {
+        separate_children: false,
+        ..EmitterConfig::default()
+    }
+}
+
+fn emit_child(child: &ObjectChild) -> Vec<String> {
+    emit_object_children(std::slice::from_ref(child), 0, &emit_config())
+        .iter()
+        .map(|e| e.to_string())
+        .collect()
+}
+
+fn nested_object(child: &ObjectChild) -> Option<&Object> {
+    match child {
+        ObjectChild::Object(object) => Some(object),
+        ObjectChild::ObjectAssignment(assignment) => Some(&assignment.value),
+        ObjectChild::ObjectProperty(prop) => Some(&prop.default_value),
+        ObjectChild::Component(component) => Some(&component.object),
+        ObjectChild::Assignment(assignment) => match &assignment.value {
+            AssignmentChildValue::Object(object) => Some(object),
+            AssignmentChildValue::Other(_) => None,
+        },
+        ObjectChild::Property(prop) => match &prop.default_value {
+            Some(AssignmentChildValue::Object(object)) => Some(object),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn nested_object_mut(child: &mut ObjectChild) -> Option<&mut Object> {
+    match child {
+        ObjectChild::Object(object) => Some(object),
+        ObjectChild::ObjectAssignment(assignment) => Some(&mut assignment.value),
+        ObjectChild::ObjectProperty(prop) => Some(&mut prop.default_value),
+        ObjectChild::Component(component) => Some(&mut component.object),
+        ObjectChild::Assignment(assignment) => match &mut assignment.value {
+            AssignmentChildValue::Object(object) => Some(object),
+            AssignmentChildValue::Other(_) => None,
+        },
+        ObjectChild::Property(prop) => match &mut prop.default_value {
+            Some(AssignmentChildValue::Object(object)) => Some(object),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The code of a child holding an object, without the object's children.
+fn emit_child_header(child: &ObjectChild) -> Vec<String> {
+    let mut child = child.clone();
+    if let Some(object) = nested_object_mut(&mut child) {
+        object.children.clear();
+    }
+    emit_child(&child)
+}
+
+/// Names the children, so that they can be matched between the two trees no matter their order.
+/// Objects are named by their type and id, everything else by its kind and name. Children which
+/// would get the same name are told apart by their index among them.
+fn keyed_children(children: &[ObjectChild]) -> Vec<(String, &ObjectChild)> {
+    let mut seen = HashMap::<String, usize>::new();
+    let mut keyed = Vec::new();
+    for child in children {
+        let key = match child {
+            ObjectChild::Object(object) => {
+                let id = object.children.iter().find_map(|e| match e {
+                    ObjectChild::Assignment(assignment) if assignment.name == "id" => {
+                        e.get_str_value()
+                    }
+                    _ => None,
+                });
+                match id {
+                    Some(id) => format!("{}#{}", object.name, id),
+                    None => object.name.clone(),
+                }
+            }
+            ObjectChild::Assignment(assignment) => assignment.name.clone(),
+            ObjectChild::ObjectAssignment(assignment) => assignment.name.clone(),
+            ObjectChild::ObjectListAssignment(list) => list.name.clone(),
+            ObjectChild::Property(prop) => format!("property {}", prop.name),
+            ObjectChild::ObjectProperty(prop) => format!("property {}", prop.name),
+            ObjectChild::Function(function) => format!("function {}", function.name),
+            ObjectChild::Signal(signal) => format!("signal {}", signal.name),
+            ObjectChild::Enum(enumeration) => format!("enum {}", enumeration.name),
+            ObjectChild::Component(component) => format!("component {}", component.name),
+            ObjectChild::Anchor(_) => continue,
+        };
+        let index = seen.entry(key.clone()).or_default();
+        let key = if *index == 0 {
+            key
+        } else {
+            format!("{}[{}]", key, index)
+        };
+        *index += 1;
+        keyed.push((key, child));
+    }
+    keyed
+}
+
+fn child_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{} > {}", path, key)
+    }
+}
+
+fn compare_children(
+    path: &str,
+    old: &ObjectChild,
+    new: &ObjectChild,
+    differences: &mut Vec<QMLDifference>,
+) {
+    if let (
+        ObjectChild::ObjectListAssignment(old_list),
+        ObjectChild::ObjectListAssignment(new_list),
+    ) = (old, new)
+    {
+        // The objects of the lists are compared like the children of an object.
+        let as_object = |list: &ObjectListAssignmentChild| Object {
+            name: list.name.clone(),
+            full_name: String::new(),
+            children: list
+                .values
+                .iter()
+                .cloned()
+                .map(ObjectChild::Object)
+                .collect(),
+        };
+        compare_objects(
+            path,
+            &as_object(old_list),
+            &as_object(new_list),
+            differences,
+        );
+    } else if let (Some(old_object), Some(new_object)) = (nested_object(old), nested_object(new)) {
+        let (old_header, new_header) = (emit_child_header(old), emit_child_header(new));
+        if old_header != new_header {
+            differences.push(QMLDifference::Changed(
+                path.to_string(),
+                old_header,
+                new_header,
+            ));
+        }
+        compare_objects(path, old_object, new_object, differences);
+    } else {
+        let (old_code, new_code) = (emit_child(old), emit_child(new));
+        if old_code != new_code {
+            differences.push(QMLDifference::Changed(path.to_string(), old_code, new_code));
+        }
+    }
+}
+
+fn compare_objects(path: &str, old: &Object, new: &Object, differences: &mut Vec<QMLDifference>) {
+    let old_children = keyed_children(&old.children);
+    let new_children = keyed_children(&new.children);
+    for (key, child) in &old_children {
+        match new_children.iter().find(|e| &e.0 == key) {
+            Some((_, new_child)) => {
+                compare_children(&child_path(path, key), child, new_child, differences)
+            }
+            None => differences.push(QMLDifference::Removed(
+                child_path(path, key),
+                emit_child(child),
+            )),
+        }
+    }
+    for (key, child) in &new_children {
+        if !old_children.iter().any(|e| &e.0 == key) {
+            differences.push(QMLDifference::Added(
+                child_path(path, key),
+                emit_child(child),
+            ));
+        }
+    }
+
+    // Properties can be declared in any order, but the order of the child objects matters.
+    let object_order = |children: &[(String, &ObjectChild)], other: &[(String, &ObjectChild)]| {
+        children
+            .iter()
+            .filter(|e| matches!(e.1, ObjectChild::Object(_)))
+            .filter(|e| other.iter().any(|o| o.0 == e.0))
+            .map(|e| e.0.clone())
+            .collect::<Vec<_>>()
+    };
+    let old_order = object_order(&old_children, &new_children);
+    let new_order = object_order(&new_children, &old_children);
+    if old_order != new_order {
+        differences.push(QMLDifference::Reordered(
+            path.to_string(),
+            old_order,
+            new_order,
+        ));
+    }
+}
+
+/// The imports and pragmas of the tree, and a pseudo-object holding its root objects.
+fn split_tree(tree: &QMLTree) -> (Vec<String>, Object) {
+    let mut header = Vec::new();
+    let mut root = Object {
+        name: String::new(),
+        full_name: String::new(),
+        children: Vec::new(),
+    };
+    for element in tree {
+        match element {
+            TreeElement::Object(object) => root.children.push(ObjectChild::Object(object.clone())),
+            TreeElement::Component(component) => root
+                .children
+                .push(ObjectChild::Component(component.clone())),
+            other => header.extend(
+                emit(&vec![other.clone()], &emit_config())
+                    .iter()
+                    .map(|e| e.to_string()),
+            ),
+        }
+    }
+    (header, root)
+}
+
+/// Compares two QML trees, ignoring their formatting and comments. Returns all the structural
+/// differences between them - an empty list means the trees are semantically equivalent.
+pub fn compare_trees(old: &QMLTree, new: &QMLTree) -> Vec<QMLDifference> {
+    let (old_header, old_root) = split_tree(&canonicalize_tree(old));
+    let (new_header, new_root) = split_tree(&canonicalize_tree(new));
+    let mut differences = Vec::new();
+
+    let removed: Vec<_> = old_header
+        .iter()
+        .filter(|e| !new_header.contains(e))
+        .cloned()
+        .collect();
+    let added: Vec<_> = new_header
+        .iter()
+        .filter(|e| !old_header.contains(e))
+        .cloned()
+        .collect();
+    if !removed.is_empty() || !added.is_empty() {
+        differences.push(QMLDifference::Changed(
+            HEADER_PATH.to_string(),
+            removed,
+            added,
+        ));
+    } else if old_header != new_header {
+        // Later imports shadow the types of the earlier ones.
+        differences.push(QMLDifference::Reordered(
+            HEADER_PATH.to_string(),
+            old_header,
+            new_header,
+        ));
+    }
+
+    compare_objects("", &old_root, &new_root, &mut differences);
+    differences
+}
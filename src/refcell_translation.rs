@@ -1,9 +1,11 @@
 use anyhow::{Error, Result};
 
-use crate::parser::qml::emitter::emit_simple_token_stream;
+use crate::id_check::IdIndex;
+use crate::parser::qml::emitter::emit_significant_token_stream;
 use crate::parser::qml::parser::{
     AssignmentChild, AssignmentChildValue, ComponentDefinition, EnumChild, FunctionChild, Object,
-    ObjectAssignmentChild, ObjectChild, PropertyChild, QMLTree, SignalChild, TreeElement,
+    ObjectAssignmentChild, ObjectChild, ObjectListAssignmentChild, PropertyChild, QMLTree,
+    SignalChild, TreeElement,
 };
 use std::cell::RefCell;
 use std::mem::take;
@@ -48,10 +50,13 @@ pub enum TranslatedObjectChild {
     ObjectProperty(PropertyChild<TranslatedObjectRef>),
     Assignment(AssignmentChild),
     ObjectAssignment(TranslatedObjectAssignmentChild),
+    /// The objects of the list are the children of its value, so that they can be traversed into.
+    ObjectListAssignment(TranslatedObjectAssignmentChild),
     Function(FunctionChild),
     Object(TranslatedObjectRef),
     Enum(TranslatedEnumChild),
     Component(TranslatedObjectAssignmentChild),
+    Anchor(String),
 }
 
 impl TranslatedObjectChild {
@@ -64,9 +69,11 @@ impl TranslatedObjectChild {
             Self::Function(f) => Self::Function(f.clone()),
             Self::Object(o) => Self::Object(deep_clone_translated_object(o)),
             Self::ObjectAssignment(a) => Self::ObjectAssignment(a.deep_clone()),
+            Self::ObjectListAssignment(a) => Self::ObjectListAssignment(a.deep_clone()),
             Self::ObjectProperty(p) => Self::ObjectProperty(deep_clone_property_child(p)),
             Self::Property(p) => Self::Property(p.clone()),
             Self::Signal(s) => Self::Signal(s.clone()),
+            Self::Anchor(a) => Self::Anchor(a.clone()),
         }
     }
 }
@@ -104,11 +111,58 @@ pub struct TranslatedObject {
     pub full_name: String,
 }
 
+impl TranslatedObject {
+    /// The object's type, followed by its id if it has one - `Rectangle#root`.
+    pub fn summary(&self) -> String {
+        match self.id() {
+            Some(id) => format!("{}#{}", self.name, id),
+            None => self.name.clone(),
+        }
+    }
+
+    /// The id the object assigns to itself.
+    pub fn id(&self) -> Option<String> {
+        self.children
+            .iter()
+            .find(|e| {
+                matches!(e, TranslatedObjectChild::Assignment(_))
+                    && e.get_name().is_some_and(|e| e == "id")
+            })
+            .and_then(|e| e.get_str_value())
+    }
+
+    /// The children declared under the name - properties, assignments, functions, signals...
+    /// Child objects don't have names, use `find_all` to look them up by their type instead.
+    pub fn children_named<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = &'a TranslatedObjectChild> + 'a {
+        self.children
+            .iter()
+            .filter(move |e| e.get_name().is_some_and(|e| e == name))
+    }
+
+    /// The value the object assigns to the property, the way selectors compare it - without
+    /// comments and whitespace (`parent.width/2`). `None` if the object doesn't assign or declare
+    /// the property, or if its value is an object.
+    pub fn get_property_value(&self, name: &str) -> Option<String> {
+        self.children_named(name)
+            .find(|e| {
+                matches!(
+                    e,
+                    TranslatedObjectChild::Assignment(_) | TranslatedObjectChild::Property(_)
+                )
+            })
+            .and_then(|e| e.get_str_value())
+    }
+}
+
 impl<'a> TranslatedObjectChild {
     pub fn get_name(&'a self) -> Option<&'a String> {
         match self {
             TranslatedObjectChild::Assignment(assi) => Some(&assi.name),
             TranslatedObjectChild::ObjectAssignment(assi) => Some(&assi.name),
+            TranslatedObjectChild::ObjectListAssignment(assi) => Some(&assi.name),
             TranslatedObjectChild::Component(cmp) => Some(&cmp.name),
             TranslatedObjectChild::Enum(e) => Some(&e.name),
             TranslatedObjectChild::Function(fnc) => Some(&fnc.name),
@@ -116,6 +170,7 @@ impl<'a> TranslatedObjectChild {
             TranslatedObjectChild::Property(prop) => Some(&prop.name),
             TranslatedObjectChild::ObjectProperty(prop) => Some(&prop.name),
             TranslatedObjectChild::Signal(signal) => Some(&signal.name),
+            TranslatedObjectChild::Anchor(_) => None,
         }
     }
 
@@ -123,40 +178,86 @@ impl<'a> TranslatedObjectChild {
         match self {
             TranslatedObjectChild::Assignment(assigned) => match &assigned.value {
                 AssignmentChildValue::Other(generic_value) => {
-                    Some(emit_simple_token_stream(generic_value))
+                    Some(emit_significant_token_stream(generic_value))
                 }
                 _ => None,
             },
             TranslatedObjectChild::ObjectAssignment(_) => None,
+            TranslatedObjectChild::ObjectListAssignment(_) => None,
             TranslatedObjectChild::Component(_) => None,
             TranslatedObjectChild::Enum(_) => None,
             TranslatedObjectChild::Function(_) => None,
             TranslatedObjectChild::Object(_) => None,
             TranslatedObjectChild::Property(prop) => match &prop.default_value {
                 Some(AssignmentChildValue::Other(generic_value)) => {
-                    Some(emit_simple_token_stream(generic_value))
+                    Some(emit_significant_token_stream(generic_value))
                 }
                 _ => None,
             },
             TranslatedObjectChild::ObjectProperty(_) => None,
             TranslatedObjectChild::Signal(_) => None,
+            TranslatedObjectChild::Anchor(_) => None,
+        }
+    }
+
+    /// A short, one-line description of the child, used by the mutation journal.
+    pub fn summary(&'a self) -> String {
+        match self {
+            TranslatedObjectChild::Assignment(assigned) => match self.get_str_value() {
+                Some(value) => format!("{}: {}", assigned.name, value),
+                None => format!("{}: <script>", assigned.name),
+            },
+            TranslatedObjectChild::ObjectAssignment(assigned) => {
+                format!("{}: {}", assigned.name, assigned.value.borrow().summary())
+            }
+            TranslatedObjectChild::ObjectListAssignment(assigned) => format!(
+                "{}: [{}]",
+                assigned.name,
+                assigned
+                    .value
+                    .borrow()
+                    .children
+                    .iter()
+                    .map(|e| e.summary())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            TranslatedObjectChild::Component(cmp) => format!("component {}", cmp.name),
+            TranslatedObjectChild::Enum(e) => format!("enum {}", e.name),
+            TranslatedObjectChild::Function(fnc) => format!("function {}", fnc.name),
+            TranslatedObjectChild::Object(obj) => obj.borrow().summary(),
+            TranslatedObjectChild::Property(prop) => match self.get_str_value() {
+                Some(value) => format!("property {}: {}", prop.name, value),
+                None => format!("property {}", prop.name),
+            },
+            TranslatedObjectChild::ObjectProperty(prop) => format!(
+                "property {}: {}",
+                prop.name,
+                prop.default_value.borrow().summary()
+            ),
+            TranslatedObjectChild::Signal(signal) => format!("signal {}", signal.name),
+            TranslatedObjectChild::Anchor(name) => format!("anchor {}", name),
         }
     }
+
     pub fn set_name(&'a mut self, name: String) -> Result<()> {
         macro_rules! error {
             () => {
-                Err(Error::msg(format!("Cannot rename object: {:?}", self)))
+                Err(Error::msg(
+                    "Cannot rename an object - only named children can be renamed!",
+                ))
             };
         }
         match self {
             TranslatedObjectChild::Assignment(assigned) => assigned.name = name,
             TranslatedObjectChild::Component(cmp) => cmp.name = name,
             TranslatedObjectChild::Function(func) => func.name = name,
-            TranslatedObjectChild::Object(_) => return error!(),
+            TranslatedObjectChild::Object(_) | TranslatedObjectChild::Anchor(_) => return error!(),
             TranslatedObjectChild::Property(prop) => prop.name = name,
             TranslatedObjectChild::ObjectProperty(prop) => prop.name = name,
             TranslatedObjectChild::Signal(sig) => sig.name = name,
             TranslatedObjectChild::ObjectAssignment(asi) => asi.name = name,
+            TranslatedObjectChild::ObjectListAssignment(asi) => asi.name = name,
             TranslatedObjectChild::Enum(enu) => enu.name = name,
         };
         Ok(())
@@ -169,6 +270,7 @@ pub fn translate_object_child(child: ObjectChild) -> TranslatedObjectChild {
         ObjectChild::Function(z) => TranslatedObjectChild::Function(z),
         ObjectChild::Property(z) => TranslatedObjectChild::Property(z),
         ObjectChild::Signal(z) => TranslatedObjectChild::Signal(z),
+        ObjectChild::Anchor(z) => TranslatedObjectChild::Anchor(z),
 
         ObjectChild::ObjectAssignment(z) => {
             TranslatedObjectChild::ObjectAssignment(TranslatedObjectAssignmentChild {
@@ -176,6 +278,16 @@ pub fn translate_object_child(child: ObjectChild) -> TranslatedObjectChild {
                 value: translate(z.value),
             })
         }
+        ObjectChild::ObjectListAssignment(z) => {
+            TranslatedObjectChild::ObjectListAssignment(TranslatedObjectAssignmentChild {
+                value: translate(Object {
+                    name: z.name.clone(),
+                    full_name: String::new(),
+                    children: z.values.into_iter().map(ObjectChild::Object).collect(),
+                }),
+                name: z.name,
+            })
+        }
         ObjectChild::ObjectProperty(z) => {
             TranslatedObjectChild::ObjectProperty(PropertyChild::<TranslatedObjectRef> {
                 name: z.name,
@@ -216,6 +328,7 @@ pub fn untranslate_object_child(child: TranslatedObjectChild) -> ObjectChild {
         TranslatedObjectChild::Function(z) => ObjectChild::Function(z),
         TranslatedObjectChild::Property(z) => ObjectChild::Property(z),
         TranslatedObjectChild::Signal(z) => ObjectChild::Signal(z),
+        TranslatedObjectChild::Anchor(z) => ObjectChild::Anchor(z),
 
         TranslatedObjectChild::Component(z) => ObjectChild::Component(ComponentDefinition {
             name: z.name,
@@ -235,6 +348,20 @@ pub fn untranslate_object_child(child: TranslatedObjectChild) -> ObjectChild {
                 value: untranslate(z.value),
             })
         }
+        TranslatedObjectChild::ObjectListAssignment(z) => {
+            ObjectChild::ObjectListAssignment(ObjectListAssignmentChild {
+                name: z.name,
+                // Only objects can be a part of the list - the processor rejects anything else.
+                values: untranslate(z.value)
+                    .children
+                    .into_iter()
+                    .filter_map(|e| match e {
+                        ObjectChild::Object(object) => Some(object),
+                        _ => None,
+                    })
+                    .collect(),
+            })
+        }
         TranslatedObjectChild::Object(z) => ObjectChild::Object(untranslate(z)),
         TranslatedObjectChild::Enum(z) => ObjectChild::Enum(EnumChild {
             name: z.name,
@@ -260,6 +387,14 @@ pub fn untranslate(object: TranslatedObjectRef) -> Object {
 pub struct TranslatedTree {
     pub root: TranslatedObjectRef,
     pub leftovers: Vec<TreeElement>,
+    pub ids: IdIndex,
+}
+
+impl TranslatedTree {
+    /// The object of the file which assigns itself the id, wherever it is.
+    pub fn find_by_id(&self, id: &str) -> Option<TranslatedObjectRef> {
+        self.ids.get(&self.root, id)
+    }
 }
 
 pub fn translate_from_root(tree: QMLTree) -> TranslatedTree {
@@ -274,13 +409,21 @@ pub fn translate_from_root(tree: QMLTree) -> TranslatedTree {
             TreeElement::Object(object) => root
                 .children
                 .push(TranslatedObjectChild::Object(translate(object))),
+            TreeElement::Component(component) => root.children.push(
+                TranslatedObjectChild::Component(TranslatedObjectAssignmentChild {
+                    name: component.name,
+                    value: translate(component.object),
+                }),
+            ),
             any => leftovers.push(any),
         }
     }
 
+    let root = Rc::new(RefCell::new(root));
     TranslatedTree {
         leftovers,
-        root: Rc::new(RefCell::new(root)),
+        ids: IdIndex::new(&root),
+        root,
     }
 }
 
@@ -288,8 +431,17 @@ pub fn untranslate_from_root(tree: TranslatedTree) -> QMLTree {
     let mut out = Vec::default();
     out.extend(tree.leftovers);
     for object in &tree.root.borrow_mut().children {
-        if let TranslatedObjectChild::Object(object) = object {
-            out.push(TreeElement::Object(untranslate(object.clone())));
+        match object {
+            TranslatedObjectChild::Object(object) => {
+                out.push(TreeElement::Object(untranslate(object.clone())))
+            }
+            TranslatedObjectChild::Component(component) => {
+                out.push(TreeElement::Component(ComponentDefinition {
+                    name: component.name.clone(),
+                    object: untranslate(component.value.clone()),
+                }))
+            }
+            _ => {}
         }
     }
 
@@ -0,0 +1,86 @@
+//! Detached ed25519 signatures of the diffs and the hashtabs loaded at runtime - so that a device
+//! only applies the packs signed by a key it trusts. The signature of a file is kept next to it,
+//! in `<file>.sig`, as the raw 64 bytes of the signature. It can be made with OpenSSL:
+//! ```text
+//! openssl pkeyutl -sign -rawin -inkey private.pem -in Main.qmd -out Main.qmd.sig
+//! ```
+
+use anyhow::{Error, Result};
+use ed25519_dalek::{Signature, VerifyingKey};
+
+pub use ed25519_dalek::PUBLIC_KEY_LENGTH;
+
+/// The public key the signatures are verified with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TrustedKey(VerifyingKey);
+
+/// The file the signature of `path` is kept in.
+pub fn signature_path(path: &str) -> String {
+    format!("{}.sig", path)
+}
+
+impl TrustedKey {
+    /// Reads the raw 32 bytes of an ed25519 public key.
+    pub fn from_bytes(key: &[u8; PUBLIC_KEY_LENGTH]) -> Result<Self> {
+        VerifyingKey::from_bytes(key)
+            .map(Self)
+            .map_err(|_| Error::msg("Invalid ed25519 public key"))
+    }
+
+    /// Checks that `signature` - the contents of the signature file - signs `contents` of the
+    /// file named `name`. Files without a signature (`None`) are rejected.
+    pub fn verify(&self, name: &str, contents: &[u8], signature: Option<&[u8]>) -> Result<()> {
+        let Some(signature) = signature else {
+            return Err(Error::msg(format!(
+                "{} is not signed - {} does not exist",
+                name,
+                signature_path(name)
+            )));
+        };
+        let signature = Signature::from_slice(signature).map_err(|_| {
+            Error::msg(format!(
+                "Invalid signature {} - expected {} bytes",
+                signature_path(name),
+                Signature::BYTE_SIZE
+            ))
+        })?;
+        self.0
+            .verify_strict(contents, &signature)
+            .map_err(|_| Error::msg(format!("The signature of {} is not valid", name)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use ed25519_dalek::{Signer, SigningKey};
+
+    use super::TrustedKey;
+
+    #[test]
+    fn test_verify() {
+        let signing_key = SigningKey::from_bytes(&[7; 32]);
+        let key = TrustedKey::from_bytes(signing_key.verifying_key().as_bytes()).unwrap();
+        let contents = b"AFFECT Main.qml\nEND AFFECT\n";
+        let signature = signing_key.sign(contents).to_bytes();
+
+        assert!(key.verify("Main.qmd", contents, Some(&signature)).is_ok());
+        assert!(key
+            .verify(
+                "Main.qmd",
+                b"AFFECT Other.qml\nEND AFFECT\n",
+                Some(&signature)
+            )
+            .is_err());
+        assert!(key
+            .verify("Main.qmd", contents, Some(&signature[1..]))
+            .is_err());
+        let error = key.verify("Main.qmd", contents, None).unwrap_err();
+        assert!(error.to_string().contains("Main.qmd.sig"));
+
+        let other_key = SigningKey::from_bytes(&[8; 32]).verifying_key();
+        let other_key = TrustedKey::from_bytes(other_key.as_bytes()).unwrap();
+        assert!(other_key
+            .verify("Main.qmd", contents, Some(&signature))
+            .is_err());
+    }
+}
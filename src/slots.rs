@@ -1,18 +1,21 @@
 use anyhow::{bail, Error, Result};
-use std::{collections::HashMap, mem::take};
+use std::{collections::HashMap, mem::take, sync::Arc};
 
 use crate::{
+    hashtab::HashTab,
+    limits::Limits,
     parser::{
         common::IteratorPipeline,
         diff::parser::{Change, FileChangeAction, Insertable, ObjectToChange, ReplaceAction},
         qml::{
             emitter::emit_object_to_token_stream,
-            lexer::TokenType,
+            lexer::{QMLExtensionToken, TokenType},
             parser::{AssignmentChildValue, ObjectChild, TreeElement},
             slot_extensions::QMLSlotRemapper,
+            template_expression::{evaluate_expression, is_expression},
         },
     },
-    util::common_util::parse_qml_from_chain,
+    util::common_util::{parse_diff, parse_qml_from_chain},
 };
 
 #[derive(Debug)]
@@ -22,16 +25,36 @@ pub struct Slot {
     pub read_back: bool,
 }
 
-pub struct Slots(pub HashMap<String, Slot>);
+/// The slots and templates by their names, along with the limits their expansion has to fit
+/// within.
+#[derive(Default)]
+pub struct Slots(pub HashMap<String, Slot>, pub Limits);
+
+/// The amount of tokens the actions insert.
+fn inserted_tokens(actions: &[FileChangeAction]) -> usize {
+    actions
+        .iter()
+        .map(|e| match e {
+            FileChangeAction::Insert(Insertable::Code(code)) => code.len(),
+            _ => 0,
+        })
+        .sum()
+}
 
 impl Slots {
     pub fn new() -> Self {
-        Slots(HashMap::new())
+        Self::with_limits(Limits::default())
+    }
+
+    pub fn with_limits(limits: Limits) -> Self {
+        Slots(HashMap::new(), limits)
     }
     pub fn update_slots(&mut self, changes: &mut Vec<Change>) {
         changes.retain(|e| match &e.destination {
-            ObjectToChange::File(_) => true,
+            ObjectToChange::File(_) | ObjectToChange::FileSuffix(_) => true,
+            ObjectToChange::HashedFile(_) => true,
             ObjectToChange::FileTokenStream(_) => true,
+            ObjectToChange::NewFile(_) | ObjectToChange::Redirect { .. } => true,
             ObjectToChange::Template(slot_name) | ObjectToChange::Slot(slot_name) => {
                 let mut created = false;
                 if !self.0.contains_key(slot_name) {
@@ -76,7 +99,7 @@ impl Slots {
             _ => panic!(),
         };
         // Go through the entries defined in the invocation. Build slots out of that
-        let mut temp_slots = Slots::new();
+        let mut temp_slots = Slots::with_limits(self.1);
         macro_rules! insert_or_append {
             ($key: expr, $contents: expr) => {
                 if temp_slots.0.contains_key(&$key) {
@@ -129,16 +152,39 @@ impl Slots {
                 FileChangeAction::Insert(Insertable::Code(c)) => c,
                 _ => unreachable!(),
             };
+            // Expressions (`~{width / 2}~`) are evaluated right away, the plain values are
+            // inserted by the slot remapper.
+            let mut resolve = |name: &str| {
+                temp_slots.resolve_slot_final_state(name).map_err(|_| {
+                    Error::msg(format!(
+                        "The invocation of template {} doesn't set {}",
+                        template_name, name
+                    ))
+                })
+            };
+            let template_contents = template_contents
+                .iter()
+                .map(|token| match token {
+                    TokenType::Extension(QMLExtensionToken::Slot(slot)) if is_expression(slot) => {
+                        evaluate_expression(slot, &mut resolve)
+                    }
+                    token => Ok(token.clone()),
+                })
+                .collect::<Result<Vec<_>>>()?;
             let res = {
                 let template_user_facing_name = format!("<TEMPLATE>({})", template_name);
                 let mut remapper = QMLSlotRemapper::new(&mut temp_slots);
                 let mut iterator: IteratorPipeline<'_, TokenType, &str> = IteratorPipeline::new(
-                    Box::new(template_contents.clone().into_iter()),
+                    Box::new(template_contents.into_iter()),
                     &template_user_facing_name,
                 );
                 iterator.add_remapper(&mut remapper);
                 iterator.collect::<Vec<_>>()
             };
+            self.1.check_expansion_tokens(
+                res.len(),
+                &format!("The invocation of template {}", template_name),
+            )?;
             if !temp_slots.all_read_back() {
                 eprintln!("Values which haven't been read back:");
                 for e in temp_slots.0 {
@@ -158,7 +204,7 @@ impl Slots {
         &mut self,
         input: Vec<FileChangeAction>,
         into: &mut Vec<FileChangeAction>,
-    ) {
+    ) -> Result<()> {
         for e in input {
             match e {
                 FileChangeAction::Replace(r_action)
@@ -176,7 +222,7 @@ impl Slots {
                     into.push(FileChangeAction::Replace(ReplaceAction {
                         selector: r_action.selector,
                         content: Insertable::Code(
-                            self.build_template_code(template_name, invocation).unwrap(),
+                            self.build_template_code(template_name, invocation)?,
                         ),
                     }));
                 }
@@ -185,16 +231,30 @@ impl Slots {
                         slot_contents.read_back = true;
                     }
                     into.push(FileChangeAction::Insert(Insertable::Code(
-                        self.build_template_code(&template_name, &invocation)
-                            .unwrap(),
+                        self.build_template_code(&template_name, &invocation)?,
                     )))
                 }
                 e => into.push(e),
             }
         }
+        Ok(())
     }
 
-    pub fn expand_slots(&mut self, input: Vec<FileChangeAction>, into: &mut Vec<FileChangeAction>) {
+    pub fn expand_slots(
+        &mut self,
+        input: Vec<FileChangeAction>,
+        into: &mut Vec<FileChangeAction>,
+    ) -> Result<()> {
+        self.expand_nested_slots(input, into, &mut Vec::new())
+    }
+
+    /// `chain` are the slots being expanded - the ones `input` comes from.
+    fn expand_nested_slots(
+        &mut self,
+        input: Vec<FileChangeAction>,
+        into: &mut Vec<FileChangeAction>,
+        chain: &mut Vec<String>,
+    ) -> Result<()> {
         for e in input {
             match e {
                 FileChangeAction::Replace(r_action)
@@ -213,8 +273,19 @@ impl Slots {
                         slot_contents.read_back = true;
                     }
                     if let Some(slot_contents) = self.0.get(slot) {
-                        self.expand_slots(slot_contents.contents.clone(), &mut all_insertions);
+                        chain.push(slot.clone());
+                        self.1.check_slot_depth(chain)?;
+                        self.expand_nested_slots(
+                            slot_contents.contents.clone(),
+                            &mut all_insertions,
+                            chain,
+                        )?;
+                        chain.pop();
                     }
+                    self.1.check_expansion_tokens(
+                        inserted_tokens(&all_insertions),
+                        &format!("Slot {}", slot),
+                    )?;
                     let qml_code_str = all_insertions
                         .into_iter()
                         .flat_map(|e| match e {
@@ -232,38 +303,208 @@ impl Slots {
                         slot_contents.read_back = true;
                     }
                     if let Some(slot_contents) = self.0.get(&slot) {
-                        self.expand_slots(slot_contents.contents.clone(), into);
+                        let start = into.len();
+                        chain.push(slot.clone());
+                        self.1.check_slot_depth(chain)?;
+                        self.expand_nested_slots(slot_contents.contents.clone(), into, chain)?;
+                        chain.pop();
+                        self.1.check_expansion_tokens(
+                            inserted_tokens(&into[start..]),
+                            &format!("Slot {}", slot),
+                        )?;
                     }
                 }
                 FileChangeAction::Insert(Insertable::Template(name, invocation)) => {
                     into.push(FileChangeAction::Insert(Insertable::Code(
-                        self.build_template_code(&name, &invocation).unwrap(),
+                        self.build_template_code(&name, &invocation)?,
                     )));
                 }
                 e => into.push(e),
             }
         }
+        Ok(())
     }
 
-    pub fn process_slots(&mut self, changes: &mut Vec<Change>) {
+    pub fn process_slots(&mut self, changes: &mut Vec<Change>) -> Result<()> {
         for change in changes {
             let old = take(&mut change.changes);
+            let (locals, old) = self.define_local_slots(old);
             let mut temp_holder = Vec::new();
-            self.expand_templates(old, &mut temp_holder);
-            self.expand_slots(temp_holder, &mut change.changes);
+            self.expand_templates(old, &mut temp_holder)?;
+            self.expand_slots(temp_holder, &mut change.changes)?;
+            if !locals.is_empty() {
+                // `~{slot}~` references are normally resolved while processing the file - by
+                // then the local slots are gone, so resolve them now.
+                for action in &mut change.changes {
+                    match action {
+                        FileChangeAction::Insert(Insertable::Code(code))
+                        | FileChangeAction::Replace(ReplaceAction {
+                            content: Insertable::Code(code),
+                            ..
+                        }) => {
+                            *code = self.expand_local_inline_slots(
+                                take(code),
+                                &locals,
+                                &mut Vec::new(),
+                            )?
+                        }
+                        _ => {}
+                    }
+                }
+                self.drop_local_slots(locals);
+            }
+        }
+        Ok(())
+    }
+
+    /// Defines the change's `LOCAL SLOT`s, temporarily shadowing the global slots of the same
+    /// names. Returns the names along with the shadowed slots, and the rest of the actions.
+    fn define_local_slots(
+        &mut self,
+        actions: Vec<FileChangeAction>,
+    ) -> (Vec<(String, Option<Slot>)>, Vec<FileChangeAction>) {
+        let mut locals: Vec<(String, Option<Slot>)> = Vec::new();
+        let mut rest = Vec::new();
+        for action in actions {
+            match action {
+                FileChangeAction::LocalSlot(name, contents) => {
+                    if locals.iter().any(|e| e.0 == name) {
+                        self.0.get_mut(&name).unwrap().contents.extend(contents);
+                    } else {
+                        let value = Slot {
+                            contents,
+                            template: false,
+                            read_back: false,
+                        };
+                        let shadowed = self.0.insert(name.clone(), value);
+                        locals.push((name, shadowed));
+                    }
+                }
+                action => rest.push(action),
+            }
+        }
+        (locals, rest)
+    }
+
+    fn expand_local_inline_slots(
+        &mut self,
+        code: Vec<TokenType>,
+        locals: &[(String, Option<Slot>)],
+        chain: &mut Vec<String>,
+    ) -> Result<Vec<TokenType>> {
+        let mut output = Vec::with_capacity(code.len());
+        for token in code {
+            match token {
+                TokenType::Extension(QMLExtensionToken::Slot(name))
+                    if locals.iter().any(|e| e.0 == name) =>
+                {
+                    let contents = self.resolve_slot_final_state(&name)?;
+                    chain.push(name);
+                    self.1.check_slot_depth(chain)?;
+                    output.extend(self.expand_local_inline_slots(contents, locals, chain)?);
+                    let name = chain.pop().unwrap();
+                    self.1
+                        .check_expansion_tokens(output.len(), &format!("Slot {}", name))?;
+                }
+                token => output.push(token),
+            }
+        }
+        Ok(output)
+    }
+
+    /// Removes the local slots, so that they neither leak into other changes nor get reported
+    /// as unused. The global slots they shadowed are restored.
+    fn drop_local_slots(&mut self, locals: Vec<(String, Option<Slot>)>) {
+        for (name, shadowed) in locals {
+            match shadowed {
+                Some(slot) => self.0.insert(name, slot),
+                None => self.0.remove(&name),
+            };
+        }
+    }
+
+    /// Writes the slots and templates as a diff, which `import` reads back - so that the slots
+    /// filled by the diffs of one run can be used by the diffs applied in a later one.
+    pub fn export(&self) -> String {
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        names
+            .into_iter()
+            .map(|name| {
+                let slot = &self.0[name];
+                let change = Change {
+                    source: Arc::new(String::new()),
+                    destination: if slot.template {
+                        ObjectToChange::Template(name.clone())
+                    } else {
+                        ObjectToChange::Slot(name.clone())
+                    },
+                    changes: slot.contents.clone(),
+                    versions_allowed: None,
+                    requirements: Vec::new(),
+                    metadata: Default::default(),
+                    ordering: Default::default(),
+                };
+                format!("{:#}\n", change)
+            })
+            .collect()
+    }
+
+    /// Reads the slots and templates written by `export`. Slots which already exist are
+    /// appended to. The imported slots count as read back - the run which filled them has
+    /// used them already.
+    pub fn import(&mut self, contents: String, source_name: &str) -> Result<()> {
+        let mut changes = parse_diff(
+            None,
+            contents,
+            source_name,
+            &HashTab::new(),
+            None,
+            false,
+            false,
+            &self.1,
+        )?;
+        let mut imported = Slots::new();
+        imported.update_slots(&mut changes);
+        if let Some(change) = changes.first() {
+            bail!(
+                "{} can only define slots and templates, got {}",
+                source_name,
+                change.destination
+            );
         }
+        for (name, mut slot) in imported.0 {
+            slot.read_back = true;
+            match self.0.get_mut(&name) {
+                None => {
+                    self.0.insert(name, slot);
+                }
+                Some(existing) if !existing.template && !slot.template => {
+                    existing.contents.extend(slot.contents)
+                }
+                Some(_) => bail!("Cannot redefine template {}", name),
+            }
+        }
+        Ok(())
     }
 
     pub fn all_read_back(&self) -> bool {
         !self.0.iter().any(|x| !x.1.read_back)
     }
 
-    fn flatten_slot(&mut self, name: &str, into: &mut Vec<TokenType>) -> Result<()> {
+    /// `chain` are the slots being flattened, ending with `name`.
+    fn flatten_slot(
+        &mut self,
+        name: &str,
+        into: &mut Vec<TokenType>,
+        chain: &mut Vec<String>,
+    ) -> Result<()> {
         if let Some(slot_mut) = self.0.get_mut(name) {
             slot_mut.read_back = true;
         } else {
             return Err(Error::msg(format!("Cannot find slot {}", name)));
         }
+        self.1.check_slot_depth(chain)?;
         // UNSAFE: I've done it this way to make it so rust allows me to
         // iterate over the slot contents recursively, while also letting
         // me mark the slots as used.
@@ -276,14 +517,20 @@ impl Slots {
         for content in &slot_contents.contents {
             if let FileChangeAction::Insert(x) = content {
                 match x {
-                    Insertable::Slot(slot_name) => self.flatten_slot(slot_name, into)?,
+                    Insertable::Slot(slot_name) => {
+                        chain.push(slot_name.clone());
+                        self.flatten_slot(slot_name, into, chain)?;
+                        chain.pop();
+                    }
                     Insertable::Code(contents) => {
                         into.extend_from_slice(contents);
                     }
                     Insertable::Template(name, invocation) => {
-                        into.extend(self.build_template_code(name, invocation).unwrap());
+                        into.extend(self.build_template_code(name, invocation)?);
                     }
                 }
+                self.1
+                    .check_expansion_tokens(into.len(), &format!("Slot {}", chain[0]))?;
             } else {
                 panic!();
             };
@@ -294,7 +541,7 @@ impl Slots {
 
     pub fn resolve_slot_final_state(&mut self, name: &str) -> Result<Vec<TokenType>> {
         let mut output = Vec::new();
-        self.flatten_slot(name, &mut output)?;
+        self.flatten_slot(name, &mut output, &mut vec![name.to_string()])?;
 
         Ok(output)
     }
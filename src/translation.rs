@@ -0,0 +1,334 @@
+use std::collections::BTreeMap;
+
+use anyhow::{bail, Result};
+
+use crate::util::json::{read_hex, JsonReader};
+
+/// The translations used by `TRANSLATE USING <catalog>`, read from a JSON or a Qt Linguist (.ts)
+/// file.
+///
+/// JSON catalogs map the source texts to their translations. The values can also be objects of
+/// the same form, which only apply to the files of the context named by their key:
+/// ```json
+/// { "Settings": "Einstellungen", "Main": { "Quit": "Beenden" } }
+/// ```
+/// Like `qsTr`, the context of a QML file is its name without the extension.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TranslationCatalog {
+    /// The translations by context, source text and disambiguation comment. Translations which
+    /// apply to every context are stored under an empty context.
+    translations: BTreeMap<(String, String, String), String>,
+}
+
+impl TranslationCatalog {
+    /// Parses a catalog - its format is told by the extension of its name.
+    pub fn parse(name: &str, contents: &str) -> Result<Self> {
+        let mut catalog = Self::default();
+        let lowercase = name.to_lowercase();
+        if lowercase.ends_with(".json") {
+            catalog.read_json(contents)?;
+        } else if lowercase.ends_with(".ts") {
+            catalog.read_ts(contents)?;
+        } else {
+            bail!(
+                "Unknown format of the translation catalog {} - expected a .json or a .ts file",
+                name
+            );
+        }
+        Ok(catalog)
+    }
+
+    pub fn len(&self) -> usize {
+        self.translations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.translations.is_empty()
+    }
+
+    /// The translation of `source` in the context, falling back to the ones which apply to every
+    /// context.
+    pub fn translate(&self, context: &str, source: &str, comment: &str) -> Option<&str> {
+        let mut key = (context.to_string(), source.to_string(), comment.to_string());
+        if let Some(translation) = self.translations.get(&key) {
+            return Some(translation);
+        }
+        key.0.clear();
+        self.translations.get(&key).map(|e| e.as_str())
+    }
+
+    fn read_json(&mut self, contents: &str) -> Result<()> {
+        let mut reader = JsonReader::new(contents.trim_start_matches('\u{feff}'));
+        reader.object(|reader, key| {
+            if reader.peek() == Some('{') {
+                reader.object(|reader, source| {
+                    let translation = reader.string()?;
+                    self.insert(&key, source, "", translation);
+                    Ok(())
+                })
+            } else {
+                let translation = reader.string()?;
+                self.insert("", key, "", translation);
+                Ok(())
+            }
+        })?;
+        if let Some(c) = reader.peek() {
+            bail!("Unexpected {} after the end of the catalog", c);
+        }
+        Ok(())
+    }
+
+    fn read_ts(&mut self, contents: &str) -> Result<()> {
+        if !contents.contains("<TS") {
+            bail!("The file is not a Qt Linguist translation file");
+        }
+        for (_, context) in xml_elements(contents, "context") {
+            let name = xml_elements(context, "name")
+                .first()
+                .map(|(_, name)| xml_text(name))
+                .unwrap_or_default();
+            for (attributes, message) in xml_elements(context, "message") {
+                // Plural forms can't be expressed by a single string.
+                if attributes.contains("numerus=\"yes\"") {
+                    continue;
+                }
+                let text = |tag| xml_elements(message, tag).first().map(|(a, e)| (*a, *e));
+                let (Some((_, source)), Some((attributes, translation))) =
+                    (text("source"), text("translation"))
+                else {
+                    continue;
+                };
+                if attributes.contains("type=\"vanished\"")
+                    || attributes.contains("type=\"obsolete\"")
+                {
+                    continue;
+                }
+                let translation = xml_text(translation);
+                // Messages which haven't been translated yet keep their source text.
+                if translation.is_empty() {
+                    continue;
+                }
+                let comment = text("comment").map(|(_, e)| xml_text(e));
+                self.insert(
+                    &name,
+                    xml_text(source),
+                    comment.as_deref().unwrap_or_default(),
+                    translation,
+                );
+            }
+        }
+        Ok(())
+    }
+
+    fn insert(&mut self, context: &str, source: String, comment: &str, translation: String) {
+        self.translations.insert(
+            (context.to_string(), source, comment.to_string()),
+            translation,
+        );
+    }
+}
+
+/// The attributes and the contents of the `tag` elements found within the XML. Nested elements
+/// of the same tag are not supported - none of the ones used by the .ts files can be nested.
+fn xml_elements<'a>(xml: &'a str, tag: &str) -> Vec<(&'a str, &'a str)> {
+    let opening = format!("<{}", tag);
+    let closing = format!("</{}>", tag);
+    let mut elements = Vec::new();
+    let mut rest = xml;
+    while let Some(start) = rest.find(&opening) {
+        rest = &rest[start + opening.len()..];
+        // Skips the tags which only start with the same name.
+        if !rest.starts_with(|c: char| c == '>' || c == '/' || c.is_whitespace()) {
+            continue;
+        }
+        let Some(end) = rest.find('>') else {
+            break;
+        };
+        let attributes = &rest[..end];
+        rest = &rest[end + 1..];
+        if let Some(attributes) = attributes.strip_suffix('/') {
+            elements.push((attributes, ""));
+            continue;
+        }
+        let Some(end) = rest.find(&closing) else {
+            break;
+        };
+        elements.push((attributes, &rest[..end]));
+        rest = &rest[end + closing.len()..];
+    }
+    elements
+}
+
+/// Decodes the text of an XML element - its entities and CDATA sections.
+fn xml_text(text: &str) -> String {
+    let mut decoded = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(['&', '<']) {
+        decoded.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(cdata) = rest.strip_prefix("<![CDATA[") {
+            let end = cdata.find("]]>").unwrap_or(cdata.len());
+            decoded.push_str(&cdata[..end]);
+            rest = cdata.get(end + 3..).unwrap_or_default();
+            continue;
+        }
+        let entity = rest.find(';').map(|end| (&rest[1..end], end));
+        let character = entity.and_then(|(entity, _)| match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => match entity.strip_prefix("#x") {
+                Some(hex) => u32::from_str_radix(hex, 16).ok(),
+                None => entity.strip_prefix('#').and_then(|e| e.parse().ok()),
+            }
+            .and_then(char::from_u32),
+        });
+        match (character, entity) {
+            (Some(character), Some((_, end))) => {
+                decoded.push(character);
+                rest = &rest[end + 1..];
+            }
+            _ => {
+                decoded.push_str(&rest[..1]);
+                rest = &rest[1..];
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// The text of the contents of a QML string literal, with its escape sequences resolved.
+pub fn unescape_string_literal(contents: &str) -> String {
+    let mut text = String::new();
+    let mut chars = contents.char_indices().peekable();
+    while let Some((_, c)) = chars.next() {
+        if c != '\\' {
+            text.push(c);
+            continue;
+        }
+        let Some((_, escaped)) = chars.next() else {
+            text.push('\\');
+            break;
+        };
+        let hex = |chars: &mut std::iter::Peekable<std::str::CharIndices>, digits| {
+            read_hex(chars, digits).ok().and_then(char::from_u32)
+        };
+        match escaped {
+            'n' => text.push('\n'),
+            't' => text.push('\t'),
+            'r' => text.push('\r'),
+            'b' => text.push('\u{8}'),
+            'f' => text.push('\u{c}'),
+            'v' => text.push('\u{b}'),
+            '0' => text.push('\0'),
+            // A line continuation.
+            '\n' => {}
+            'x' => text.extend(hex(&mut chars, 2)),
+            'u' if chars.peek().is_some_and(|e| e.1 == '{') => {
+                chars.next();
+                let code: String = chars
+                    .by_ref()
+                    .map(|e| e.1)
+                    .take_while(|e| *e != '}')
+                    .collect();
+                text.extend(u32::from_str_radix(&code, 16).ok().and_then(char::from_u32));
+            }
+            'u' => text.extend(hex(&mut chars, 4)),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// Writes the text as the contents of a QML string literal delimited by `quote`.
+pub fn escape_string_literal(text: &str, quote: char) -> String {
+    let mut contents = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '\\' => contents.push_str("\\\\"),
+            '\n' => contents.push_str("\\n"),
+            '\r' => contents.push_str("\\r"),
+            '\t' => contents.push_str("\\t"),
+            c if c == quote => {
+                contents.push('\\');
+                contents.push(c);
+            }
+            c => contents.push(c),
+        }
+    }
+    contents
+}
+
+#[cfg(test)]
+mod test {
+    use super::{escape_string_literal, unescape_string_literal, TranslationCatalog};
+
+    #[test]
+    fn test_catalogs() {
+        let json = TranslationCatalog::parse(
+            "de.json",
+            "{ \"Quit\": \"Beenden\", \"Main\": { \"Say \\\"hi\\\"\\n\": \"Sag \\u00bbhallo\\u00ab\" } }",
+        )
+        .unwrap();
+        assert_eq!(json.len(), 2);
+        assert_eq!(json.translate("Other", "Quit", ""), Some("Beenden"));
+        assert_eq!(
+            json.translate("Main", "Say \"hi\"\n", ""),
+            Some("Sag »hallo«")
+        );
+        assert_eq!(json.translate("Other", "Say \"hi\"\n", ""), None);
+        assert!(TranslationCatalog::parse("de.json", "{ \"Quit\": 1 }").is_err());
+        assert!(TranslationCatalog::parse("de.po", "").is_err());
+
+        let ts = TranslationCatalog::parse(
+            "de.ts",
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<!DOCTYPE TS>
+<TS version="2.1" language="de_DE">
+<context>
+    <name>Main</name>
+    <message>
+        <source>Fish &amp; Chips</source>
+        <translation>Fisch &amp; Pommes</translation>
+    </message>
+    <message>
+        <source>Open</source>
+        <comment>verb</comment>
+        <translation type="unfinished">Öffnen</translation>
+    </message>
+    <message>
+        <source>Close</source>
+        <translation type="unfinished"></translation>
+    </message>
+    <message numerus="yes">
+        <source>%n file(s)</source>
+        <translation><numerusform>%n Datei</numerusform></translation>
+    </message>
+</context>
+</TS>"#,
+        )
+        .unwrap();
+        assert_eq!(ts.len(), 2);
+        assert_eq!(
+            ts.translate("Main", "Fish & Chips", ""),
+            Some("Fisch & Pommes")
+        );
+        assert_eq!(ts.translate("Main", "Open", "verb"), Some("Öffnen"));
+        assert_eq!(ts.translate("Main", "Open", ""), None);
+        assert_eq!(ts.translate("Other", "Fish & Chips", ""), None);
+    }
+
+    #[test]
+    fn test_string_literal_escaping() {
+        let text = unescape_string_literal(r#"Say \"hi\"\n\u00bb\x41\u{1F600}\'"#);
+        assert_eq!(text, "Say \"hi\"\n»A😀'");
+        assert_eq!(
+            escape_string_literal("a \"b\" 'c'\n\\", '"'),
+            r#"a \"b\" 'c'\n\\"#
+        );
+        assert_eq!(escape_string_literal("'c'", '\''), r#"\'c\'"#);
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeSet;
+
+use crate::{
+    journal::MutationJournal,
+    parser::qml::emitter::{emit_object_children, flatten_lines, EmitterConfig},
+    refcell_translation::{untranslate_object_child, TranslatedObjectChild},
+};
+
+const INDENT: &str = "    ";
+
+fn is_simple_value(value: &str) -> bool {
+    !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_alphanumeric() || matches!(c, '_' | '-' | '.'))
+}
+
+/// Builds a selector which picks `child` out of its parent in the patched tree.
+/// Objects are identified by their id. If they don't have one, all their simple
+/// assignments are used instead - this might still be ambiguous.
+pub fn child_selector(child: &TranslatedObjectChild) -> String {
+    let object = match child {
+        TranslatedObjectChild::Object(obj) => obj.borrow(),
+        _ => return child.get_name().unwrap().clone(),
+    };
+    let mut selector = object.name.clone();
+    let mut props = object
+        .children
+        .iter()
+        .filter(|e| matches!(e, TranslatedObjectChild::Assignment(_)))
+        .filter_map(|e| Some((e.get_name()?, e.get_str_value()?)))
+        .filter(|(_, value)| is_simple_value(value))
+        .collect::<Vec<_>>();
+    if let Some((_, id)) = props.iter().find(|(name, _)| name.as_str() == "id") {
+        selector += &format!("#{}", id);
+    } else {
+        props.sort();
+        for (name, value) in props {
+            selector += &format!("[.{}={}]", name, value);
+        }
+    }
+    selector
+}
+
+/// Re-emits `child` as QML code, so that it can be inserted again.
+pub fn child_code(child: &TranslatedObjectChild) -> String {
+    let lines = emit_object_children(
+        &[untranslate_object_child(child.deep_clone())],
+        0,
+        &EmitterConfig::default(),
+    );
+    flatten_lines(&lines).trim_end().to_string()
+}
+
+pub fn insert_directive(code: &str) -> String {
+    let mut directive = String::from("INSERT {\n");
+    push_indented(&mut directive, 1, code);
+    directive.push('}');
+    directive
+}
+
+pub fn replace_directive(selector: &str, code: &str) -> String {
+    let mut directive = format!("REPLACE {} WITH {{\n", selector);
+    push_indented(&mut directive, 1, code);
+    directive.push('}');
+    directive
+}
+
+/// Runs the directives within the children matched by `tree`.
+pub fn traverse_directive(tree: &str, directives: &[String]) -> String {
+    let mut directive = format!("TRAVERSE {}\n", tree);
+    for nested in directives {
+        push_indented(&mut directive, 1, nested);
+    }
+    directive.push_str("END TRAVERSE");
+    directive
+}
+
+/// The directives which put a removed child back after `anchor` - the selector of
+/// the child which preceded it.
+pub fn reinsert_directives(anchor: Option<String>, code: &str) -> Vec<String> {
+    vec![
+        match anchor {
+            Some(anchor) => format!("LOCATE AFTER {}", anchor),
+            None => "LOCATE BEFORE ALL".to_string(),
+        },
+        insert_directive(code),
+    ]
+}
+
+/// How deep the directives following `scope[..index]` are nested. ASSERTs do not open blocks.
+fn scope_depth(scope: &[String], index: usize) -> usize {
+    1 + scope[..index]
+        .iter()
+        .filter(|e| !e.starts_with("ASSERT"))
+        .count()
+}
+
+fn close_block(out: &mut String, block: Option<(&String, &Vec<String>)>) {
+    if let Some((_, scope)) = block {
+        for (index, directive) in scope.iter().enumerate().rev() {
+            if !directive.starts_with("ASSERT") {
+                push_indented(out, scope_depth(scope, index), "END TRAVERSE");
+            }
+        }
+        out.push_str("END AFFECT\n");
+    }
+}
+
+fn push_indented(out: &mut String, depth: usize, text: &str) {
+    for line in text.lines() {
+        if !line.trim().is_empty() {
+            out.push_str(&INDENT.repeat(depth));
+            out.push_str(line);
+        }
+        out.push('\n');
+    }
+}
+
+/// Builds a diff, which undoes all the mutations recorded in the journal when applied
+/// to the patched tree.
+pub fn build_reverse_diff(journal: &MutationJournal) -> String {
+    let mut out =
+        String::from("; Generated by qmldiff - reverts the changes made by the following diffs:\n");
+    for source in journal.0.iter().map(|e| &e.source).collect::<BTreeSet<_>>() {
+        out.push_str(&format!("; - {}\n", source));
+    }
+
+    // Undo in the reverse order. Consecutive entries touching the same root share one block.
+    let mut current_block: Option<(&String, &Vec<String>)> = None;
+    for entry in journal.0.iter().rev() {
+        // Changes made within a REPLICATE are undone by removing the replicated children.
+        if entry.scope.iter().any(|e| e.starts_with("REPLICATE")) {
+            continue;
+        }
+        if current_block != Some((&entry.file, &entry.scope)) {
+            close_block(&mut out, current_block);
+            out.push_str(&format!("\nAFFECT {}\n", entry.file));
+            for (index, directive) in entry.scope.iter().enumerate() {
+                push_indented(&mut out, scope_depth(&entry.scope, index), directive);
+            }
+            current_block = Some((&entry.file, &entry.scope));
+        }
+        for directive in &entry.undo {
+            push_indented(
+                &mut out,
+                scope_depth(&entry.scope, entry.scope.len()),
+                directive,
+            );
+        }
+    }
+    close_block(&mut out, current_block);
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::build_reverse_diff;
+    use crate::{
+        hashtab::HashTab, journal::MutationJournal, limits::Limits, parser::diff::parser::Change,
+        processor::find_and_process_qmldir, util::common_util::parse_diff,
+    };
+
+    fn parse(diff: String) -> Vec<Change> {
+        parse_diff(
+            None,
+            diff,
+            "reverse.qmd",
+            &HashTab::new(),
+            None,
+            false,
+            false,
+            &Limits::default(),
+        )
+        .unwrap()
+    }
+
+    #[cfg(feature = "reporting")]
+    #[test]
+    fn test_reverse_diff_restores_the_original() {
+        use crate::{
+            id_check::IdCheck,
+            parser::qml::{emitter::EmitterConfig, lexer::QMLDiffExtensions},
+            processor::{find_and_process, RebuildOrder},
+            qml_compare::compare_trees,
+            slots::Slots,
+            util::common_util::{parse_qml, tokenize_qml},
+        };
+
+        let apply = |contents: &str, changes: &[Change], journal: Option<&mut MutationJournal>| {
+            let tokens = tokenize_qml(
+                contents.to_string(),
+                "Main.qml",
+                None,
+                None,
+                QMLDiffExtensions::NONE,
+            );
+            find_and_process(
+                "Main.qml",
+                tokens,
+                changes,
+                &mut Slots::new(),
+                journal,
+                None,
+                &EmitterConfig::default(),
+                IdCheck::Warn,
+                None,
+                RebuildOrder::default(),
+            )
+            .unwrap()
+            .0
+            .unwrap()
+        };
+        let original = include_str!("../tests/golden/Main.qml");
+        let mut changes = parse(include_str!("../tests/golden/insert.qmd").into());
+        changes.extend(parse(
+            include_str!("../tests/golden/replace_remove.qmd").into(),
+        ));
+
+        let mut journal = MutationJournal::new();
+        let patched = apply(original, &changes, Some(&mut journal));
+        let restored = apply(&patched, &parse(build_reverse_diff(&journal)), None);
+
+        let tree = |contents: &str| {
+            parse_qml(
+                contents.to_string(),
+                "Main.qml",
+                None,
+                None,
+                QMLDiffExtensions::NONE,
+            )
+            .unwrap()
+        };
+        assert!(!compare_trees(&tree(original), &tree(&patched)).is_empty());
+        let differences = compare_trees(&tree(original), &tree(&restored));
+        assert!(differences.is_empty(), "{:?}", differences);
+    }
+
+    #[test]
+    fn test_reverse_qmldir_changes() {
+        let changes = parse(
+            "AFFECT qmldir\n    ADD PLUGIN extraplugin\n    REMOVE ENTRY Fonts\n    REMOVE ENTRY Colors\nEND AFFECT\n".into(),
+        );
+        let mut journal = MutationJournal::new();
+        let (patched, applied) = find_and_process_qmldir(
+            "qmldir",
+            "module Theme\nColors 1.0 Colors.qml\nsingleton Fonts 1.0 Fonts.qml\n",
+            &changes,
+            Some(&mut journal),
+        )
+        .unwrap();
+        let patched = patched.unwrap();
+        assert_eq!(patched, "module Theme\nplugin extraplugin\n");
+        assert_eq!(applied.len(), 1);
+
+        let reverse = build_reverse_diff(&journal);
+        let body = reverse.split_once("\nAFFECT qmldir\n").unwrap().1;
+        assert_eq!(
+            body,
+            "    ; The entry 'Colors 1.0 Colors.qml' cannot be restored automatically.\n    ADD SINGLETON Fonts 1.0 Fonts.qml\n    REMOVE ENTRY extraplugin\nEND AFFECT\n"
+        );
+        let reverse = parse(reverse);
+        let (restored, _) = find_and_process_qmldir("qmldir", &patched, &reverse, None).unwrap();
+        assert_eq!(
+            restored.unwrap(),
+            "module Theme\nsingleton Fonts 1.0 Fonts.qml\n"
+        );
+    }
+}
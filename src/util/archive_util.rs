@@ -0,0 +1,236 @@
+use anyhow::{Error, Result};
+use flate2::write::GzEncoder;
+use std::{
+    fs::File,
+    io::{BufWriter, Cursor, Read, Write},
+    mem::take,
+};
+use zip::{write::SimpleFileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::hashtab::decompressing_reader;
+
+const ZIP_MAGIC: [u8; 4] = [0x50, 0x4b, 0x03, 0x04];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveFormat {
+    Tar,
+    TarGzip,
+    TarZstd,
+    Zip,
+}
+
+impl ArchiveFormat {
+    fn from_file_name(name: &str) -> Self {
+        if name.ends_with(".zip") {
+            Self::Zip
+        } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Self::TarGzip
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Self::TarZstd
+        } else {
+            Self::Tar
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArchiveEntryKind {
+    File,
+    Directory,
+    Symlink(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct ArchiveEntry {
+    /// The path within the archive, without any leading `./` or `/`.
+    pub path: String,
+    pub kind: ArchiveEntryKind,
+    pub data: Vec<u8>,
+    pub mode: u32,
+    pub mtime: u64,
+    pub owner: (u64, u64),
+}
+
+/// The whole contents of a tar or zip archive, kept in memory in their original order.
+#[derive(Debug, Default)]
+pub struct MemoryArchive(pub Vec<ArchiveEntry>);
+
+fn normalize_path(path: &str) -> String {
+    let path = path.strip_prefix("./").unwrap_or(path);
+    path.trim_start_matches('/').to_string()
+}
+
+impl MemoryArchive {
+    /// Reads a zip, or a (gzip / zstd compressed) tar archive.
+    pub fn read(file_name: &str) -> Result<Self> {
+        let data = std::fs::read(file_name)?;
+        if data.starts_with(&ZIP_MAGIC) {
+            Self::read_zip(data)
+        } else {
+            Self::read_tar(decompressing_reader(data.as_slice())?)
+        }
+    }
+
+    fn read_tar(reader: impl Read) -> Result<Self> {
+        let mut archive = tar::Archive::new(reader);
+        let mut entries = Vec::new();
+        for entry in archive.entries()? {
+            let mut entry = entry?;
+            let header = entry.header();
+            let kind = match header.entry_type() {
+                tar::EntryType::Directory => ArchiveEntryKind::Directory,
+                tar::EntryType::Symlink => ArchiveEntryKind::Symlink(
+                    entry
+                        .link_name()?
+                        .map(|e| e.to_string_lossy().to_string())
+                        .unwrap_or_default(),
+                ),
+                tar::EntryType::Regular | tar::EntryType::Continuous => ArchiveEntryKind::File,
+                other => {
+                    return Err(Error::msg(format!(
+                        "Unsupported tar entry type {:?} of {}",
+                        other,
+                        entry.path()?.to_string_lossy()
+                    )))
+                }
+            };
+            let mode = header.mode()?;
+            let mtime = header.mtime()?;
+            let owner = (header.uid()?, header.gid()?);
+            let path = normalize_path(&entry.path()?.to_string_lossy());
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            if path.is_empty() {
+                continue;
+            }
+            entries.push(ArchiveEntry {
+                path,
+                kind,
+                data,
+                mode,
+                mtime,
+                owner,
+            });
+        }
+        Ok(Self(entries))
+    }
+
+    fn read_zip(data: Vec<u8>) -> Result<Self> {
+        let mut archive = ZipArchive::new(Cursor::new(data))?;
+        let mut entries = Vec::new();
+        for i in 0..archive.len() {
+            let mut file = archive.by_index(i)?;
+            let is_dir = file.is_dir();
+            let is_symlink = file.is_symlink();
+            let default_mode = if is_dir { 0o755 } else { 0o644 };
+            let mut data = Vec::new();
+            file.read_to_end(&mut data)?;
+            let kind = if is_dir {
+                ArchiveEntryKind::Directory
+            } else if is_symlink {
+                // The target of a symlink is stored as its contents.
+                ArchiveEntryKind::Symlink(String::from_utf8_lossy(&take(&mut data)).to_string())
+            } else {
+                ArchiveEntryKind::File
+            };
+            entries.push(ArchiveEntry {
+                path: normalize_path(file.name())
+                    .trim_end_matches('/')
+                    .to_string(),
+                kind,
+                data,
+                // Only keep the permissions - the file type is stored in `kind`.
+                mode: file.unix_mode().map_or(default_mode, |e| e & 0o7777),
+                mtime: 0,
+                owner: (0, 0),
+            });
+        }
+        Ok(Self(entries))
+    }
+
+    pub fn get_file(&self, path: &str) -> Option<&ArchiveEntry> {
+        let path = normalize_path(path);
+        self.0
+            .iter()
+            .find(|e| e.kind == ArchiveEntryKind::File && e.path == path)
+    }
+
+    pub fn get_file_mut(&mut self, path: &str) -> Option<&mut ArchiveEntry> {
+        let path = normalize_path(path);
+        self.0
+            .iter_mut()
+            .find(|e| e.kind == ArchiveEntryKind::File && e.path == path)
+    }
+
+    /// Writes the archive. The format is chosen based on the extension of `file_name` -
+    /// `.zip`, `.tar.gz` / `.tgz`, `.tar.zst` / `.tzst`, or a plain tar otherwise.
+    pub fn write(&self, file_name: &str) -> Result<()> {
+        let file = BufWriter::new(File::create(file_name)?);
+        match ArchiveFormat::from_file_name(file_name) {
+            ArchiveFormat::Zip => self.write_zip(file),
+            ArchiveFormat::Tar => self.write_tar(file).map(|_| ()),
+            ArchiveFormat::TarGzip => {
+                let encoder = GzEncoder::new(file, flate2::Compression::default());
+                self.write_tar(encoder)?.finish()?;
+                Ok(())
+            }
+            ArchiveFormat::TarZstd => {
+                let encoder = zstd::Encoder::new(file, 0)?;
+                self.write_tar(encoder)?.finish()?;
+                Ok(())
+            }
+        }
+    }
+
+    fn write_tar<W: Write>(&self, writer: W) -> Result<W> {
+        let mut builder = tar::Builder::new(writer);
+        for entry in &self.0 {
+            let mut header = tar::Header::new_gnu();
+            header.set_mode(entry.mode);
+            header.set_mtime(entry.mtime);
+            header.set_uid(entry.owner.0);
+            header.set_gid(entry.owner.1);
+            match &entry.kind {
+                ArchiveEntryKind::File => {
+                    header.set_entry_type(tar::EntryType::Regular);
+                    header.set_size(entry.data.len() as u64);
+                    builder.append_data(&mut header, &entry.path, entry.data.as_slice())?;
+                }
+                ArchiveEntryKind::Directory => {
+                    header.set_entry_type(tar::EntryType::Directory);
+                    header.set_size(0);
+                    builder.append_data(&mut header, &entry.path, std::io::empty())?;
+                }
+                ArchiveEntryKind::Symlink(target) => {
+                    header.set_entry_type(tar::EntryType::Symlink);
+                    header.set_size(0);
+                    builder.append_link(&mut header, &entry.path, target)?;
+                }
+            }
+        }
+        Ok(builder.into_inner()?)
+    }
+
+    fn write_zip<W: Write + std::io::Seek>(&self, writer: W) -> Result<()> {
+        let mut zip = ZipWriter::new(writer);
+        for entry in &self.0 {
+            let options = SimpleFileOptions::default()
+                .compression_method(CompressionMethod::Deflated)
+                .unix_permissions(entry.mode);
+            match &entry.kind {
+                ArchiveEntryKind::File => {
+                    zip.start_file(entry.path.as_str(), options)?;
+                    zip.write_all(&entry.data)?;
+                }
+                ArchiveEntryKind::Directory => {
+                    zip.add_directory(entry.path.as_str(), options)?;
+                }
+                ArchiveEntryKind::Symlink(target) => {
+                    zip.add_symlink(entry.path.as_str(), target.as_str(), options)?;
+                }
+            }
+        }
+        zip.finish()?;
+        Ok(())
+    }
+}
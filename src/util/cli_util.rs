@@ -1,70 +1,158 @@
 use anyhow::{Error, Result};
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, BTreeSet},
     fs::{create_dir_all, read_dir, read_to_string, write},
-    path::Path,
+    path::{Path, PathBuf},
 };
 
 use crate::{
+    archive_util::{ArchiveEntryKind, MemoryArchive},
+    corpus::{generate_corpus, CorpusConfig},
+    diff_tests::{parse_manifest, run_test},
     hash::hash,
-    hashtab::{hash_token_stream, HashTab, InvHashTab},
+    hashrules::HashRules,
+    hashtab::{
+        hash_qml_source, merge_hash_file, serialize_hashtab, HashTab, HashTabSources, InvHashTab,
+    },
+    id_check::IdCheck,
+    import_check::ImportCheck,
+    journal::{escape_json, MutationJournal},
+    limits::Limits,
+    lint::lint_selectors,
+    outline::outline_tree,
+    pack_diff::{diff_packs, PackModel},
     parser::{
         common::StringCharacterTokenizer,
         diff::{
             self,
             emitter::emit_token_stream,
-            hash_processor::diff_hash_remapper,
-            lexer::{HashedValue, TokenType},
-            parser::{Change, ExternalLoader, ObjectToChange},
+            hash_processor::{find_hash_references, resolve_diff_hashes},
+            lexer::{HashedValue, Keyword, TokenType},
+            parser::{has_path_suffix, Change, Defines, ExternalLoader, ObjectToChange},
         },
-        qml::{self, hash_extension::qml_hash_remap},
+        qml::{self, emitter::Emitter, hash_extension::qml_hash_remap, lexer::QMLDiffExtensions},
+        qmldir::is_qmldir,
+    },
+    processor::{
+        build_created_file, find_and_process, find_and_process_qmldir, find_redirect_source,
+        RebuildOrder,
     },
-    processor::find_and_process,
+    qml_compare::compare_trees,
     slots::Slots,
-    util::common_util::{
-        filter_out_non_matching_versions, load_diff_file, tokenize_qml,
+    util::{
+        common_util::{
+            describe_failures, error_details, filter_out_non_matching_versions,
+            filter_out_unmet_requirements, kind_error, load_diff_file, order_changes, parse_qml,
+            tokenize_qml, ErrorKind, FailurePolicy, FileFailures, SourceFormat,
+        },
+        output::{info, verbose, warning},
     },
 };
 
-fn build_recursive_hashmap(directory: &String, dir_relative_name: &String, tab: &mut HashTab) {
-    println!("Recursing {} (qrc:{}/)", directory, dir_relative_name);
-    for file in read_dir(directory).unwrap().flatten() {
+fn build_recursive_hashmap(
+    directory: &String,
+    dir_relative_name: &String,
+    tab: &mut HashTab,
+    mut sources: Option<&mut HashTabSources>,
+) {
+    verbose!("Recursing {} (qrc:{}/)", directory, dir_relative_name);
+    let mut files = read_dir(directory).unwrap().flatten().collect::<Vec<_>>();
+    // Sorted, so that the entries are always attributed to the same files.
+    files.sort_by_key(|e| e.file_name());
+    for file in files {
         let t = file.file_type().unwrap();
         let name = file.file_name().into_string().unwrap();
         let mut relative_name = dir_relative_name.clone();
         relative_name.push('/');
         relative_name.push_str(&name);
+        if t.is_file() && name.ends_with(".qml") {
+            verbose!("Hashing {}", file.path().to_str().unwrap());
+            let contents = std::fs::read_to_string(file.path()).unwrap();
+            hash_qml_source(&relative_name, &contents, tab, sources.as_deref_mut());
+            continue;
+        }
         tab.insert(hash(&name), name.clone());
-        let hash = hash(&relative_name);
-        tab.insert(hash, relative_name);
-        if t.is_file() {
-            if name.ends_with(".qml") {
-                println!("Hashing {}", file.path().to_str().unwrap());
-                let tree = tokenize_qml(
-                    std::fs::read_to_string(file.path()).unwrap(),
-                    &name,
-                    None,
-                    None,
-                );
-                hash_token_stream(&tree, tab);
-            }
-        } else {
+        tab.insert(hash(&relative_name), relative_name.clone());
+        if !t.is_file() {
             build_recursive_hashmap(
                 &(directory.clone() + "/" + &name),
                 &(dir_relative_name.clone() + "/" + &name),
                 tab,
+                sources.as_deref_mut(),
             );
         }
     }
 }
 
-pub fn start_hashmap_build(root: &String) -> HashTab {
+/// Hashes the QML tree. If `sources` is given, the files every entry has been found in are
+/// recorded into it.
+pub fn start_hashmap_build(root: &String, sources: Option<&mut HashTabSources>) -> HashTab {
     let mut hashtab = HashTab::new();
-    build_recursive_hashmap(root, &String::new(), &mut hashtab);
+    build_recursive_hashmap(root, &String::new(), &mut hashtab, sources);
 
     hashtab
 }
 
+/// Collects the `.qmd` files within the directory and all of its subdirectories.
+fn collect_diff_files_recursively(directory: &Path, into: &mut Vec<PathBuf>) {
+    let mut entries = match read_dir(directory) {
+        Err(error) => {
+            eprintln!(
+                "Error while reading directory {}: {:?}",
+                directory.display(),
+                error
+            );
+            return;
+        }
+        Ok(e) => e.flatten().map(|e| e.path()).collect::<Vec<_>>(),
+    };
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_diff_files_recursively(&path, into);
+        } else if path.extension().is_some_and(|ext| ext == "qmd") {
+            into.push(path);
+        }
+    }
+}
+
+/// Collects the files within the directory and all of its subdirectories, named by their path
+/// within `root` - the way the diffs refer to them.
+fn collect_source_files(root: &Path, directory: &Path, into: &mut Vec<String>) -> Result<()> {
+    for entry in read_dir(directory)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            collect_source_files(root, &path, into)?;
+        } else {
+            let relative = path.strip_prefix(root).unwrap_or(&path);
+            into.push(format!("/{}", relative.to_string_lossy()));
+        }
+    }
+    Ok(())
+}
+
+/// All the files of the QML root.
+fn list_source_files(root: &Path) -> Result<Vec<String>> {
+    let mut files = Vec::new();
+    collect_source_files(root, root, &mut files)?;
+    files.sort();
+    Ok(files)
+}
+
+/// Lists the given diff files, and the `.qmd` files within the given directories.
+fn collect_diff_files(diff_files: &Vec<String>) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for file in diff_files {
+        let path = Path::new(&file);
+        if path.is_dir() {
+            collect_diff_files_recursively(path, &mut files);
+        } else if path.is_file() {
+            files.push(path.to_path_buf());
+        }
+    }
+    files
+}
+
 pub fn process_diff_tree(
     diff_files: &Vec<String>,
     hashtab: &HashTab,
@@ -72,33 +160,170 @@ pub fn process_diff_tree(
     into_hash: bool,
 ) {
     for file in diff_files {
-        let path = std::path::Path::new(&file);
-        if path.is_file() {
-            process_single_diff(file, hashtab, inv_hashtab, into_hash);
+        if !Path::new(file).exists() {
+            warning!("Warning: {} does not exist - skipping.", file);
+        }
+    }
+    let files = collect_diff_files(diff_files);
+    for file in &files {
+        let file = file.to_string_lossy().to_string();
+        if let Some(changed) = process_single_diff(&file, hashtab, inv_hashtab, into_hash) {
+            info!(
+                "{}: {} {} token(s)",
+                file,
+                if into_hash { "hashed" } else { "unhashed" },
+                changed
+            );
         }
     }
+    info!("Processed {} diff file(s).", files.len());
+}
+
+/// How many of the tokens (including the ones within QML code) are hashed.
+fn count_hashed_tokens(tokens: &[TokenType]) -> usize {
+    tokens
+        .iter()
+        .map(|token| match token {
+            TokenType::HashedValue(_) => 1,
+            // Hashed strings are stored as identifiers - see `process_single_diff`
+            TokenType::Identifier(id) if id.starts_with("[[") => 1,
+            TokenType::QMLCode { qml_code, .. } => qml_code
+                .iter()
+                .filter(|e| {
+                    matches!(
+                        e,
+                        qml::lexer::TokenType::Extension(
+                            qml::lexer::QMLExtensionToken::HashedIdentifier(_)
+                                | qml::lexer::QMLExtensionToken::HashedString(..)
+                        )
+                    )
+                })
+                .count(),
+            _ => 0,
+        })
+        .sum()
 }
 
+/// Adds the identifiers used within the QML code of the diffs, which are not yet present in the
+/// hashtab, into the supplementary hashtab file (creating it if needed). The identifiers are
+/// also added into `hashtab` and `inv_hashtab`, so that `process_diff_tree` hashes them.
+pub fn extend_supplementary_hashtab(
+    supplementary_path: &String,
+    diff_files: &Vec<String>,
+    hashtab: &mut HashTab,
+    inv_hashtab: &mut InvHashTab,
+) -> Result<()> {
+    let mut supplementary = HashTab::new();
+    if Path::new(supplementary_path).exists() {
+        merge_hash_file(supplementary_path, &mut supplementary, None, None)?;
+    }
+    for (hash, id) in &supplementary {
+        inv_hashtab.insert(id.clone(), *hash);
+        hashtab.insert(*hash, id.clone());
+    }
+
+    let mut added = 0;
+    for file in collect_diff_files(diff_files) {
+        let contents = read_to_string(&file)?;
+        let tokens = diff::lexer::Lexer::new(StringCharacterTokenizer::new(contents))
+            .tokenize()
+            .map_err(|error| {
+                Error::msg(format!(
+                    "Error while reading {}: {}",
+                    file.to_string_lossy(),
+                    error
+                ))
+            })?;
+        for token in tokens {
+            if let TokenType::QMLCode { qml_code, .. } = token {
+                for qml_token in qml_code {
+                    if let qml::lexer::TokenType::Identifier(id) = qml_token {
+                        if inv_hashtab.contains_key(&id) {
+                            continue;
+                        }
+                        let hashed = hash(&id);
+                        if let Some(existing) = hashtab.get(&hashed) {
+                            warning!(
+                                "Warning: {} collides with {} in the hashtab - leaving it unhashed.",
+                                id, existing
+                            );
+                            continue;
+                        }
+                        hashtab.insert(hashed, id.clone());
+                        inv_hashtab.insert(id.clone(), hashed);
+                        supplementary.insert(hashed, id);
+                        added += 1;
+                    }
+                }
+            }
+        }
+    }
+
+    write(supplementary_path, serialize_hashtab(&supplementary, None))?;
+    info!(
+        "Added {} new identifier(s) to {} ({} entries).",
+        added,
+        supplementary_path,
+        supplementary.len()
+    );
+    Ok(())
+}
+
+/// Absolute selectors (`/Rectangle`) are lexed as one identifier. Splits the leading `/` off,
+/// so that the rest can be hashed.
+fn split_absolute_selector(token: TokenType, inv_hashtab: &InvHashTab) -> Vec<TokenType> {
+    match token {
+        TokenType::Identifier(id)
+            if id.len() > 1
+                && id.starts_with('/')
+                && !inv_hashtab.contains_key(&id)
+                && id[1..].split('.').all(|e| inv_hashtab.contains_key(e)) =>
+        {
+            vec![
+                TokenType::Identifier("/".to_string()),
+                TokenType::Identifier(id[1..].to_string()),
+            ]
+        }
+        token => vec![token],
+    }
+}
+
+/// Hashes (or unhashes) the diff file in place. Returns by how many the count of hashed
+/// tokens changed, or `None` if the file couldn't be processed.
 fn process_single_diff(
     diff_file_path: &String,
     hashtab: &HashTab,
     inv_hashtab: &InvHashTab,
     into_hash: bool,
-) {
+) -> Option<usize> {
     let string_contents = match std::fs::read_to_string(diff_file_path) {
         Err(error) => {
-            println!("Error while reading file {}: {:?}", diff_file_path, error);
-            return;
+            eprintln!("Error while reading file {}: {:?}", diff_file_path, error);
+            return None;
         }
         Ok(e) => e,
     };
-    let mut token_stream: Vec<TokenType> =
-        diff::lexer::Lexer::new(StringCharacterTokenizer::new(string_contents))
-            .map(|e| diff_hash_remapper(hashtab, e, diff_file_path).unwrap())
-            .collect();
+    let raw_token_stream =
+        match diff::lexer::Lexer::new(StringCharacterTokenizer::new(string_contents)).tokenize() {
+            Ok(tokens) => tokens,
+            Err(error) => {
+                eprintln!("Error while reading file {}: {}", diff_file_path, error);
+                return None;
+            }
+        };
+    let hashed_before = count_hashed_tokens(&raw_token_stream);
+    let mut token_stream =
+        match resolve_diff_hashes(hashtab, raw_token_stream, diff_file_path, false) {
+            Ok(tokens) => tokens,
+            Err(error) => {
+                eprintln!("Error while unhashing file {}: {}", diff_file_path, error);
+                return None;
+            }
+        };
     if into_hash {
         token_stream = token_stream
             .into_iter()
+            .flat_map(|e| split_absolute_selector(e, inv_hashtab))
             .map(|e| match e {
                 TokenType::Identifier(id) => {
                     let splits_values = id.split('.').map(|e| inv_hashtab.get(e).cloned().unwrap_or(0)).collect::<Vec<_>>();
@@ -198,17 +423,32 @@ fn process_single_diff(
             })
             .collect();
     }
+    let hashed_after = count_hashed_tokens(&token_stream);
     let emitted = emit_token_stream(token_stream);
     if let Err(error) = std::fs::write(diff_file_path, emitted) {
-        println!("Error while writing file {}: {:?}", diff_file_path, error);
+        eprintln!("Error while writing file {}: {:?}", diff_file_path, error);
+        return None;
     }
+    Some(hashed_after.abs_diff(hashed_before))
 }
 
 struct LoggingExternalLoader {}
 impl ExternalLoader for LoggingExternalLoader {
     fn load_external(&mut self, file: &str) {
-        println!("QMD tried to load external {file}")
+        verbose!("QMD tried to load external {file}")
+    }
+}
+
+/// The slots to start out with - the ones exported by an earlier run (see `Slots::export`), if
+/// any.
+pub fn initial_slots(import_slots: Option<&str>, limits: Limits) -> Result<Slots> {
+    let mut slots = Slots::with_limits(limits);
+    if let Some(path) = import_slots {
+        let contents = read_to_string(path)
+            .map_err(|error| Error::msg(format!("Cannot read the slots {}: {}", path, error)))?;
+        slots.import(contents, path)?;
     }
+    Ok(slots)
 }
 
 pub fn build_change_structures(
@@ -216,115 +456,1456 @@ pub fn build_change_structures(
     hashtab: &HashTab,
     slots: &mut Slots,
     version: Option<String>,
+    defines: &Defines,
+    require_hashed: bool,
+    limits: &Limits,
 ) -> Result<Vec<Change>> {
     let mut all_changes = Vec::new();
     for path_str in files {
         let path = Path::new(path_str);
         if !path.exists() {
-            return Err(Error::msg(format!("File {} does not exist!", path_str)));
+            return Err(kind_error(
+                ErrorKind::Io,
+                format!("File {} does not exist!", path_str),
+            ));
         }
         if path.is_file() {
             let root_dir = String::from(path.parent().unwrap().to_string_lossy());
-            println!("Reading diff {}...", path.to_string_lossy());
+            verbose!("Reading diff {}...", path.to_string_lossy());
             let mut this_diff = load_diff_file(
                 Some(root_dir),
                 path,
                 hashtab,
                 Some(Box::new(LoggingExternalLoader {})),
+                require_hashed,
+                false,
+                limits,
             )?;
             filter_out_non_matching_versions(
                 &mut this_diff,
                 version.clone(),
                 &path.to_string_lossy(),
             );
-            slots.update_slots(&mut this_diff);
+            filter_out_unmet_requirements(&mut this_diff, defines, &path.to_string_lossy());
             all_changes.extend(this_diff);
         } else if path.is_dir() {
-            for sub_file in (read_dir(path)?).flatten() {
-                let sub_file_path = sub_file.path();
+            // The order of read_dir() depends on the file system - sort it, so that
+            // the diffs are always applied in the same order.
+            let mut sub_files = read_dir(path)?
+                .flatten()
+                .map(|e| e.path())
+                .collect::<Vec<_>>();
+            sub_files.sort();
+            for sub_file_path in sub_files {
                 if !sub_file_path.is_file() {
                     continue;
                 }
-                println!("Reading diff {}...", sub_file_path.to_string_lossy());
+                verbose!("Reading diff {}...", sub_file_path.to_string_lossy());
                 let mut this_diff = load_diff_file(
                     Some(path_str.clone()),
                     &sub_file_path,
                     hashtab,
                     Some(Box::new(LoggingExternalLoader {})),
+                    require_hashed,
+                    false,
+                    limits,
                 )?;
                 filter_out_non_matching_versions(
                     &mut this_diff,
                     version.clone(),
                     &sub_file_path.to_string_lossy(),
                 );
-                slots.update_slots(&mut this_diff);
+                filter_out_unmet_requirements(
+                    &mut this_diff,
+                    defines,
+                    &sub_file_path.to_string_lossy(),
+                );
                 all_changes.extend(this_diff);
             }
         }
     }
 
+    order_changes(&mut all_changes)?;
+    slots.update_slots(&mut all_changes);
     Ok(all_changes)
 }
 
-pub fn apply_changes(
-    qml_root_path: &str,
-    qml_destination_path: &str,
-    flatten: bool,
+/// Applies the changes to a single file, returning its new contents and the amount of changes
+/// applied, or `None` if the file doesn't exist.
+#[allow(clippy::too_many_arguments)]
+fn apply_changes_to_file(
+    file_to_edit: &str,
     slots: &mut Slots,
-    changes: &Vec<Change>,
-) -> Result<()> {
-    let file_set = changes
+    changes: &[Change],
+    journal: Option<&mut MutationJournal>,
+    emitter: &dyn Emitter,
+    id_check: IdCheck,
+    import_check: Option<&ImportCheck>,
+    rebuild_order: RebuildOrder,
+    read_source: &mut impl FnMut(&str) -> Result<Option<String>>,
+) -> Result<Option<(String, usize)>> {
+    // Open the file, or build it if it's created by the diffs.
+    let created = build_created_file(file_to_edit, changes, slots, emitter)?;
+    let redirected_from = find_redirect_source(file_to_edit, changes)?;
+    let is_created = created.is_some() || redirected_from.is_some();
+    let existing = read_source(file_to_edit)?;
+    if is_created && existing.is_some() {
+        return Err(Error::msg(format!(
+            "Cannot CREATE / REDIRECT to {} - the file already exists",
+            file_to_edit
+        )));
+    }
+    if created.is_some() && redirected_from.is_some() {
+        return Err(Error::msg(format!(
+            "Cannot both CREATE and REDIRECT to {}",
+            file_to_edit
+        )));
+    }
+    let (file_contents, source_name) = match (created, redirected_from) {
+        (Some(created), _) => (Some(created), file_to_edit.to_string()),
+        (None, Some(original)) => (read_source(&original)?, original),
+        (None, None) => (existing, file_to_edit.to_string()),
+    };
+    let Some(file_contents) = file_contents else {
+        warning!(
+            "Warning: file {} does not exist - skipping the changes affecting {}.",
+            source_name,
+            file_to_edit
+        );
+        return Ok(None);
+    };
+    let (format, contents) = SourceFormat::detect(&file_contents);
+    let (emitted, applied) = if is_qmldir(file_to_edit) {
+        find_and_process_qmldir(file_to_edit, contents, changes, journal)?
+    } else {
+        let tree = tokenize_qml(
+            contents.to_string(),
+            file_to_edit,
+            None,
+            None,
+            QMLDiffExtensions::NONE,
+        );
+        find_and_process(
+            file_to_edit,
+            tree,
+            changes,
+            slots,
+            journal,
+            None,
+            emitter,
+            id_check,
+            import_check,
+            rebuild_order,
+        )?
+    };
+
+    // Files the changes turned out not to modify are written as they were.
+    Ok(Some((
+        emitted.map(|e| format.restore(e)).unwrap_or(file_contents),
+        applied.len() + is_created as usize,
+    )))
+}
+
+/// What applying the changes has done, besides writing the files.
+pub struct AppliedChanges<'a> {
+    /// The changes affecting files which don't exist.
+    pub unapplied: Vec<&'a Change>,
+    /// The files which could not be processed under the lenient policy.
+    pub failures: Vec<(String, Error)>,
+}
+
+/// Applies the changes to every file they affect or create. The files are read and written
+/// through the callbacks, which are also given the amount of changes applied. `read_source`
+/// returns `None` for files which don't exist - these are skipped, and the changes affecting
+/// them are returned. `list_sources` lists all the files of the tree, and is only called to
+/// resolve `AFFECT SUFFIX` statements.
+#[allow(clippy::too_many_arguments)]
+/// Under the strict policy, nothing is written unless every file could be processed - all the
+/// failures are returned as a single error. Under the lenient one, they're printed and returned.
+fn apply_changes_with<'a>(
+    slots: &mut Slots,
+    changes: &'a [Change],
+    mut journal: Option<&mut MutationJournal>,
+    emitter: &dyn Emitter,
+    policy: FailurePolicy,
+    id_check: IdCheck,
+    import_check: Option<&ImportCheck>,
+    rebuild_order: RebuildOrder,
+    list_sources: impl FnOnce() -> Result<Vec<String>>,
+    mut read_source: impl FnMut(&str) -> Result<Option<String>>,
+    mut write_result: impl FnMut(&str, String, usize) -> Result<()>,
+) -> Result<AppliedChanges<'a>> {
+    let suffixes = changes
+        .iter()
+        .filter_map(|e| match &e.destination {
+            ObjectToChange::FileSuffix(suffix) => Some(suffix.as_str()),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    let suffixed_files = if suffixes.is_empty() {
+        Vec::new()
+    } else {
+        list_sources()?
+            .into_iter()
+            .filter(|file| suffixes.iter().any(|suffix| has_path_suffix(file, suffix)))
+            .collect()
+    };
+    let mut file_set = changes
         .iter()
         .filter_map(|e| match &e.destination {
-            ObjectToChange::File(f) | ObjectToChange::FileTokenStream(f) => Some(f.clone()),
+            ObjectToChange::File(f)
+            | ObjectToChange::FileTokenStream(f)
+            | ObjectToChange::NewFile(f)
+            | ObjectToChange::Redirect { redirected: f, .. } => Some(f.clone()),
             _ => None,
         })
-        .collect::<HashSet<String>>();
+        .collect::<BTreeSet<String>>();
+    file_set.extend(suffixed_files.iter().cloned());
+    let mut missing_files = BTreeSet::new();
+    let mut failures = Vec::new();
+    let mut processed = Vec::new();
+
+    for file_to_edit in file_set.iter() {
+        match apply_changes_to_file(
+            file_to_edit,
+            slots,
+            changes,
+            journal.as_deref_mut(),
+            emitter,
+            id_check,
+            import_check,
+            rebuild_order,
+            &mut read_source,
+        ) {
+            Ok(Some((emitted, count))) if policy == FailurePolicy::Strict => {
+                processed.push((file_to_edit, emitted, count));
+            }
+            Ok(Some((emitted, count))) => write_result(file_to_edit, emitted, count)?,
+            Ok(None) => {
+                missing_files.insert(file_to_edit);
+            }
+            Err(error) => failures.push((file_to_edit.clone(), error)),
+        }
+    }
+
+    if !failures.is_empty() {
+        match policy {
+            FailurePolicy::Strict => return Err(Error::new(FileFailures(failures))),
+            FailurePolicy::Lenient => {
+                warning!("Warning! {}", describe_failures(&failures));
+            }
+        }
+    }
+    for (file_to_edit, emitted, count) in processed {
+        write_result(file_to_edit, emitted, count)?;
+    }
+
+    let unapplied = changes
+        .iter()
+        .filter(|e| match &e.destination {
+            ObjectToChange::File(f)
+            | ObjectToChange::FileTokenStream(f)
+            | ObjectToChange::Redirect { redirected: f, .. } => missing_files.contains(f),
+            ObjectToChange::FileSuffix(suffix) => !suffixed_files
+                .iter()
+                .any(|file| has_path_suffix(file, suffix)),
+            _ => false,
+        })
+        .collect();
+    Ok(AppliedChanges {
+        unapplied,
+        failures,
+    })
+}
 
+/// Applies the changes to the files of the QML root, writing them into the destination.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_changes<'a>(
+    qml_root_path: &str,
+    qml_destination_path: &str,
+    flatten: bool,
+    slots: &mut Slots,
+    changes: &'a [Change],
+    journal: Option<&mut MutationJournal>,
+    emitter: &dyn Emitter,
+    policy: FailurePolicy,
+    id_check: IdCheck,
+    import_check: Option<&ImportCheck>,
+    rebuild_order: RebuildOrder,
+) -> Result<AppliedChanges<'a>> {
     let mut file_iterator = 0u32;
     let absolute_root = Path::new(qml_destination_path);
     let source_root = Path::new(qml_root_path);
 
-    for file_to_edit in file_set.iter() {
-        // Open the file.
-        let file_contents = match read_to_string(
-            source_root.join(file_to_edit.strip_prefix('/').unwrap_or(file_to_edit)),
-        ) {
-            Ok(contents) => contents,
-            Err(error) => {
+    apply_changes_with(
+        slots,
+        changes,
+        journal,
+        emitter,
+        policy,
+        id_check,
+        import_check,
+        rebuild_order,
+        || list_source_files(source_root),
+        |file_to_edit| read_source_file(source_root, file_to_edit),
+        |file_to_edit, emitted, count| {
+            // Rewrite the file in destination
+            let destination_path = if flatten {
+                let next = format!(
+                    "{}_{}",
+                    file_iterator,
+                    Path::new(&file_to_edit)
+                        .file_name()
+                        .unwrap()
+                        .to_string_lossy()
+                );
+                file_iterator += 1;
+                absolute_root.join(next)
+            } else {
+                let next = Path::new(&file_to_edit);
+                absolute_root.join(next.strip_prefix("/").unwrap_or(next))
+            };
+            create_dir_all(destination_path.parent().unwrap())?;
+            write(&destination_path, emitted)?;
+            info!(
+                "Written file {} - {} diff(s) applied.",
+                destination_path.to_string_lossy(),
+                count
+            );
+            Ok(())
+        },
+    )
+}
+
+/// Reads a file of the QML root. Returns `None` if it doesn't exist.
+fn read_source_file(source_root: &Path, file_to_edit: &str) -> Result<Option<String>> {
+    let path = source_root.join(file_to_edit.strip_prefix('/').unwrap_or(file_to_edit));
+    if !path.exists() {
+        return Ok(None);
+    }
+    match read_to_string(&path) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(error) => Err(kind_error(
+            ErrorKind::Io,
+            format!("Error: {} - cannot read file {}", error, file_to_edit),
+        )),
+    }
+}
+
+/// Applies the changes to the files of the QML root, returning the emitted files instead
+/// of writing them.
+pub fn render_changes(
+    qml_root_path: &str,
+    slots: &mut Slots,
+    changes: &[Change],
+    journal: Option<&mut MutationJournal>,
+    emitter: &dyn Emitter,
+) -> Result<BTreeMap<String, String>> {
+    let source_root = Path::new(qml_root_path);
+    let mut rendered = BTreeMap::new();
+
+    apply_changes_with(
+        slots,
+        changes,
+        journal,
+        emitter,
+        FailurePolicy::Strict,
+        IdCheck::Off,
+        None,
+        RebuildOrder::default(),
+        || list_source_files(source_root),
+        |file_to_edit| read_source_file(source_root, file_to_edit),
+        |file_to_edit, emitted, _| {
+            rendered.insert(file_to_edit.to_string(), emitted);
+            Ok(())
+        },
+    )?;
+
+    Ok(rendered)
+}
+
+/// The 1-based number of the first line which differs between `a` and `b`.
+fn first_different_line(a: &str, b: &str) -> usize {
+    let mut b_lines = b.lines();
+    for (i, line) in a.lines().enumerate() {
+        if b_lines.next() != Some(line) {
+            return i + 1;
+        }
+    }
+    a.lines().count() + 1
+}
+
+/// Applies the diffs twice, independently of each other, and compares the results byte by byte.
+/// Differences are caused by the order of hashmap iteration, or by anything generated at
+/// runtime (ids, timestamps...), and make the builds of a pack non-reproducible.
+#[allow(clippy::too_many_arguments)]
+pub fn check_determinism(
+    qml_root_path: &str,
+    files: &Vec<String>,
+    hashtab: &HashTab,
+    version: Option<String>,
+    defines: &Defines,
+    require_hashed: bool,
+    emitter: &dyn Emitter,
+    import_slots: Option<&str>,
+    limits: &Limits,
+) -> Result<()> {
+    let run = || -> Result<(BTreeMap<String, String>, String)> {
+        let mut slots = initial_slots(import_slots, *limits)?;
+        let mut changes = build_change_structures(
+            files,
+            hashtab,
+            &mut slots,
+            version.clone(),
+            defines,
+            require_hashed,
+            limits,
+        )?;
+        slots.process_slots(&mut changes)?;
+        let mut journal = MutationJournal::new();
+        let rendered = render_changes(
+            qml_root_path,
+            &mut slots,
+            &changes,
+            Some(&mut journal),
+            emitter,
+        )?;
+        Ok((rendered, journal.to_jsonl()))
+    };
+    let (first, first_journal) = run()?;
+    let (second, second_journal) = run()?;
+
+    let mut differences = 0;
+    for file in first.keys().chain(second.keys()).collect::<BTreeSet<_>>() {
+        match (first.get(file), second.get(file)) {
+            (Some(a), Some(b)) if a == b => {}
+            (Some(a), Some(b)) => {
+                differences += 1;
+                println!(
+                    "Nondeterministic output: {} (first difference at line {})",
+                    file,
+                    first_different_line(a, b)
+                );
+            }
+            _ => {
+                differences += 1;
+                println!("Nondeterministic output: {} was only written once", file);
+            }
+        }
+    }
+    if first_journal != second_journal {
+        let line = first_different_line(&first_journal, &second_journal);
+        if let Some(entry) = first_journal.lines().nth(line - 1) {
+            println!("The mutations first differ at: {}", entry);
+        }
+    }
+
+    if differences != 0 {
+        return Err(Error::msg(format!(
+            "Determinism check failed - {} of {} file(s) differ between two runs. Look for values depending on hashmap iteration order, generated ids or timestamps.",
+            differences,
+            first.len().max(second.len())
+        )));
+    }
+    println!(
+        "Determinism check passed - {} file(s) are identical across two runs.",
+        first.len()
+    );
+    Ok(())
+}
+
+/// Like `apply_changes`, but reads the QML tree from a tar / zip archive, and writes the
+/// whole tree - with the patched files replaced - into another archive. Nothing is unpacked
+/// to the disk.
+#[allow(clippy::too_many_arguments)]
+pub fn apply_changes_to_archive<'a>(
+    source_archive: &str,
+    destination_archive: &str,
+    slots: &mut Slots,
+    changes: &'a [Change],
+    journal: Option<&mut MutationJournal>,
+    emitter: &dyn Emitter,
+    policy: FailurePolicy,
+    id_check: IdCheck,
+    import_check: Option<&ImportCheck>,
+    rebuild_order: RebuildOrder,
+) -> Result<AppliedChanges<'a>> {
+    let mut archive = MemoryArchive::read(source_archive)?;
+    let mut patched = Vec::new();
+
+    let applied = apply_changes_with(
+        slots,
+        changes,
+        journal,
+        emitter,
+        policy,
+        id_check,
+        import_check,
+        rebuild_order,
+        || {
+            Ok(archive
+                .0
+                .iter()
+                .filter(|e| e.kind == ArchiveEntryKind::File)
+                .map(|e| format!("/{}", e.path))
+                .collect())
+        },
+        |file_to_edit| match archive.get_file(file_to_edit) {
+            Some(entry) => Ok(Some(String::from_utf8(entry.data.clone())?)),
+            None => Ok(None),
+        },
+        |file_to_edit, emitted, count| {
+            info!("Patched file {} - {} diff(s) applied.", file_to_edit, count);
+            patched.push((file_to_edit.to_string(), emitted));
+            Ok(())
+        },
+    )?;
+
+    for (file, emitted) in patched {
+        archive.get_file_mut(&file).unwrap().data = emitted.into_bytes();
+    }
+    archive.write(destination_archive)?;
+    info!(
+        "Written archive {} - {} entries.",
+        destination_archive,
+        archive.0.len()
+    );
+
+    Ok(applied)
+}
+
+/// Applies the changes to a single QML file, known to the diffs as `file_name`, and returns the
+/// result. Only the messages about the diffs are printed - into stderr.
+#[allow(clippy::too_many_arguments)]
+pub fn process_single_file(
+    qml_file: &str,
+    file_name: &str,
+    files: &Vec<String>,
+    hashtab: &HashTab,
+    version: Option<String>,
+    defines: &Defines,
+    emitter: &dyn Emitter,
+    id_check: IdCheck,
+    import_check: Option<&ImportCheck>,
+    rebuild_order: RebuildOrder,
+    limits: &Limits,
+    explain: bool,
+) -> Result<String> {
+    let contents = read_to_string(qml_file)?;
+    let mut slots = Slots::with_limits(*limits);
+    let mut changes =
+        build_change_structures(files, hashtab, &mut slots, version, defines, false, limits)?;
+    slots.process_slots(&mut changes)?;
+    if explain {
+        for change in &changes {
+            let affects_file = match &change.destination {
+                ObjectToChange::NewFile(file) => file == file_name,
+                ObjectToChange::Redirect { redirected, .. } => redirected == file_name,
+                destination => destination.affects_file(file_name),
+            };
+            if affects_file {
+                eprintln!("; {}\n{:#}\n", change, change);
+            }
+        }
+    }
+    let processed = apply_changes_to_file(
+        file_name,
+        &mut slots,
+        &changes,
+        None,
+        emitter,
+        id_check,
+        import_check,
+        rebuild_order,
+        &mut |name| Ok((name == file_name).then(|| contents.clone())),
+    )?;
+    match processed {
+        Some((emitted, count)) => {
+            if count == 0 {
+                warning!("Warning: no change affects {}.", file_name);
+            }
+            Ok(emitted)
+        }
+        // Redirected from a file which wasn't given.
+        None => Err(kind_error(
+            ErrorKind::Io,
+            format!("{} is REDIRECTed from another file", file_name),
+        )),
+    }
+}
+
+fn failure_to_json(file: &str, error: &Error) -> String {
+    let details = error_details(error);
+    let optional = |value: &Option<String>| value.as_deref().map_or("null".into(), escape_json);
+    format!(
+        "{{\"file\":{},\"kind\":{},\"source\":{},\"directive\":{},\"message\":{}}}",
+        escape_json(file),
+        escape_json(details.kind.name()),
+        optional(&details.source),
+        optional(&details.directive),
+        escape_json(&details.message),
+    )
+}
+
+/// Writes the JSON report of `--error-report`: the exit code, the kind and message of the error
+/// which stopped the command (or nulls, if none did), and the details of every file which could
+/// not be processed.
+pub fn write_error_report(
+    path: &str,
+    error: Option<&Error>,
+    failures: &[(String, Error)],
+) -> Result<()> {
+    let failed_files = match error.and_then(|e| e.downcast_ref::<FileFailures>()) {
+        Some(FileFailures(strict_failures)) => strict_failures.as_slice(),
+        None => failures,
+    };
+    let details = error.map(error_details);
+    let report = format!(
+        "{{\"exit_code\":{},\"kind\":{},\"message\":{},\"failures\":[{}]}}\n",
+        details.as_ref().map_or(0, |e| e.kind.exit_code()),
+        details
+            .as_ref()
+            .map_or("null".into(), |e| escape_json(e.kind.name())),
+        error.map_or("null".into(), |e| escape_json(&format!("{:#}", e))),
+        failed_files
+            .iter()
+            .map(|(file, error)| failure_to_json(file, error))
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    write(path, report)?;
+    Ok(())
+}
+
+/// The VERSION, REQUIRE, META, PRIORITY and AFTER DIFF statements found at the beginning of a
+/// diff file, along with its DEFINE SELECTOR statements. Statements of files with equal headers
+/// can be moved into one file.
+#[derive(Debug, Default, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct DiffHeader {
+    versions: Vec<String>,
+    requirements: Vec<String>,
+    metadata: Vec<String>,
+    ordering: Vec<String>,
+    selector_aliases: Vec<String>,
+}
+
+impl DiffHeader {
+    fn allows_any_of(&self, versions: &[String]) -> bool {
+        self.versions.is_empty()
+            || versions.is_empty()
+            || self.versions.iter().any(|e| versions.contains(e))
+    }
+}
+
+enum DiffStatement {
+    Verbatim {
+        destination: Option<String>,
+        tokens: Vec<TokenType>,
+    },
+    /// An `AFFECT` block whose body can be concatenated with the bodies of other
+    /// blocks affecting the same file.
+    Affect {
+        destination: String,
+        bodies: Vec<Vec<TokenType>>,
+    },
+}
+
+fn next_significant(tokens: &[TokenType], from: usize) -> Option<usize> {
+    (from..tokens.len())
+        .find(|&i| !matches!(tokens[i], TokenType::Whitespace(_) | TokenType::NewLine(_)))
+}
+
+fn collapse_whitespace(string: &str) -> String {
+    string.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Finds the keyword which closes the block starting at `from` - the one following `END`.
+/// The `END SLOT`s closing `LOCAL SLOT`s within the block are skipped.
+fn find_block_end(tokens: &[TokenType], from: usize, closers: &[Keyword]) -> Result<usize> {
+    let mut i = from;
+    let mut open_local_slots = 0usize;
+    while i < tokens.len() {
+        if tokens[i] == TokenType::Keyword(Keyword::Local) {
+            open_local_slots += 1;
+        } else if tokens[i] == TokenType::Keyword(Keyword::End) {
+            if let Some(next) = next_significant(tokens, i + 1) {
+                match &tokens[next] {
+                    TokenType::Keyword(Keyword::Slot) if open_local_slots > 0 => {
+                        open_local_slots -= 1;
+                    }
+                    TokenType::Keyword(kw) if closers.contains(kw) => return Ok(next),
+                    _ => {}
+                }
+                i = next;
+            }
+        }
+        i += 1;
+    }
+    Err(Error::msg("Unterminated block - expected END directive"))
+}
+
+/// Whether an `ASSERT` narrows down the root of the block. Such a block cannot be merged
+/// with others, as the ASSERT would affect them too.
+fn has_root_level_assert(body: &[TokenType]) -> bool {
+    let mut depth = 0usize;
+    let mut after_end = false;
+    for token in body {
+        match token {
+            TokenType::Keyword(Keyword::End) => after_end = true,
+            TokenType::Keyword(Keyword::Traverse | Keyword::Replicate) if after_end => {
+                depth = depth.saturating_sub(1);
+                after_end = false;
+            }
+            TokenType::Keyword(Keyword::Traverse | Keyword::Replicate) => depth += 1,
+            TokenType::Keyword(Keyword::Assert) if depth == 0 => return true,
+            TokenType::Whitespace(_) | TokenType::NewLine(_) => {}
+            _ => after_end = false,
+        }
+    }
+    false
+}
+
+/// Removes the comments, along with the lines they occupied entirely.
+fn strip_comments(tokens: Vec<TokenType>) -> Vec<TokenType> {
+    let mut out: Vec<TokenType> = Vec::with_capacity(tokens.len());
+    let mut skip_newline = false;
+    for token in tokens {
+        match token {
+            TokenType::Comment(_) => {
+                while let Some(TokenType::Whitespace(_)) = out.last() {
+                    out.pop();
+                }
+                skip_newline = matches!(out.last(), None | Some(TokenType::NewLine(_)));
+            }
+            TokenType::NewLine(_) if skip_newline => skip_newline = false,
+            token => {
+                skip_newline = false;
+                out.push(token);
+            }
+        }
+    }
+    out
+}
+
+struct SplitDiff {
+    header: DiffHeader,
+    statements: Vec<DiffStatement>,
+    loads: Vec<String>,
+}
+
+fn split_diff_statements(tokens: Vec<TokenType>) -> Result<SplitDiff> {
+    let tokens = strip_comments(tokens);
+    let mut header = DiffHeader::default();
+    let mut statements = Vec::new();
+    let mut loads = Vec::new();
+    let mut i = 0;
+
+    while let Some(start) = next_significant(&tokens, i) {
+        let next = |from: usize| {
+            next_significant(&tokens, from).ok_or(Error::msg("Unexpected end of file"))
+        };
+        match &tokens[start] {
+            TokenType::EndOfStream => break,
+            TokenType::Keyword(Keyword::Version) => {
+                let version = next(start + 1)?;
+                header.versions.push(
+                    emit_token_stream(vec![tokens[version].clone()])
+                        .trim_matches(['"', '\'', '`'])
+                        .to_string(),
+                );
+                i = version + 1;
+            }
+            TokenType::Keyword(Keyword::Require) => {
+                let kind = next(start + 1)?;
+                let end = (kind + 1..tokens.len())
+                    .find(|&e| matches!(tokens[e], TokenType::Keyword(_) | TokenType::EndOfStream))
+                    .unwrap_or(tokens.len());
+                header.requirements.push(collapse_whitespace(&emit_token_stream(
+                    tokens[start..end].to_vec(),
+                )));
+                i = end;
+            }
+            TokenType::Keyword(Keyword::Meta) => {
+                let key = next(start + 1)?;
+                let value = next(key + 1)?;
+                header.metadata.push(collapse_whitespace(&emit_token_stream(
+                    tokens[start..=value].to_vec(),
+                )));
+                i = value + 1;
+            }
+            TokenType::Keyword(Keyword::Priority) | TokenType::Keyword(Keyword::After) => {
+                let mut value = next(start + 1)?;
+                if tokens[start] == TokenType::Keyword(Keyword::After) {
+                    value = next(value + 1)?;
+                }
+                header.ordering.push(collapse_whitespace(&emit_token_stream(
+                    tokens[start..=value].to_vec(),
+                )));
+                i = value + 1;
+            }
+            TokenType::Keyword(Keyword::Define) => {
+                // The aliases are moved to the top of the file, so that they're defined before
+                // any of the merged blocks.
+                let end = (start + 1..tokens.len())
+                    .find(|&e| matches!(tokens[e], TokenType::Keyword(_) | TokenType::EndOfStream))
+                    .unwrap_or(tokens.len());
+                header.selector_aliases.push(collapse_whitespace(&emit_token_stream(
+                    tokens[start..end].to_vec(),
+                )));
+                i = end;
+            }
+            TokenType::Keyword(Keyword::Affect) => {
+                let mut destination = next(start + 1)?;
+                let rebuild = tokens[destination] == TokenType::Keyword(Keyword::Rebuild);
+                if rebuild {
+                    destination = next(destination + 1)?;
+                }
+                let destination_name = emit_token_stream(vec![tokens[destination].clone()]);
+                let end = if rebuild {
+                    find_block_end(&tokens, destination + 1, &[Keyword::Rebuild])?
+                } else {
+                    find_block_end(
+                        &tokens,
+                        destination + 1,
+                        &[Keyword::Affect, Keyword::Slot, Keyword::Template],
+                    )?
+                };
+                // The body spans until the `END` preceding the closing keyword.
+                let body_end = (destination + 1..end)
+                    .rev()
+                    .find(|&e| tokens[e] == TokenType::Keyword(Keyword::End))
+                    .unwrap();
+                let body = &tokens[destination + 1..body_end];
+                // Local slots of blocks which were merged together would be merged too.
+                let has_local_slots = body.contains(&TokenType::Keyword(Keyword::Local));
+                statements.push(if rebuild || has_root_level_assert(body) || has_local_slots {
+                    DiffStatement::Verbatim {
+                        destination: Some(destination_name),
+                        tokens: tokens[start..=end].to_vec(),
+                    }
+                } else {
+                    DiffStatement::Affect {
+                        destination: destination_name,
+                        bodies: vec![body.to_vec()],
+                    }
+                });
+                i = end + 1;
+            }
+            TokenType::Keyword(Keyword::Slot) => {
+                let end = find_block_end(
+                    &tokens,
+                    start + 1,
+                    &[Keyword::Affect, Keyword::Slot, Keyword::Template],
+                )?;
+                statements.push(DiffStatement::Verbatim {
+                    destination: None,
+                    tokens: tokens[start..=end].to_vec(),
+                });
+                i = end + 1;
+            }
+            TokenType::Keyword(Keyword::Template | Keyword::Create) => {
+                let end = next(next(start + 1)? + 1)?;
+                statements.push(DiffStatement::Verbatim {
+                    destination: None,
+                    tokens: tokens[start..=end].to_vec(),
+                });
+                i = end + 1;
+            }
+            TokenType::Keyword(Keyword::Redirect) => {
+                // REDIRECT <original> TO <redirected>
+                let end = next(next(next(start + 1)? + 1)? + 1)?;
+                statements.push(DiffStatement::Verbatim {
+                    destination: None,
+                    tokens: tokens[start..=end].to_vec(),
+                });
+                i = end + 1;
+            }
+            TokenType::Keyword(Keyword::Load) => {
+                let mut path = next(start + 1)?;
+                if tokens[path] == TokenType::Keyword(Keyword::External) {
+                    path = next(path + 1)?;
+                } else if let TokenType::String(path) | TokenType::Identifier(path) = &tokens[path] {
+                    loads.push(path.trim_matches(['"', '\'', '`']).to_string());
+                }
+                i = path + 1;
+            }
+            other => {
                 return Err(Error::msg(format!(
-                    "Error: {} - file {} does not exist",
-                    error, file_to_edit
+                    "Unexpected token {:?} - expected AFFECT / SLOT / VERSION / REQUIRE / META / PRIORITY / DEFINE SELECTOR / TEMPLATE / CREATE / REDIRECT / LOAD statement",
+                    other
                 )))
             }
-        };
-        let tree = tokenize_qml(file_contents, &file_to_edit, None, None);
-        let (emitted, count) = find_and_process(file_to_edit, tree, changes, slots)?;
-
-        // Rewrite the file in destination
-        let destination_path = if flatten {
-            let next = format!(
-                "{}_{}",
-                file_iterator,
-                Path::new(&file_to_edit)
-                    .file_name()
-                    .unwrap()
-                    .to_string_lossy()
+        }
+    }
+
+    header.versions.sort();
+    header.versions.dedup();
+    Ok(SplitDiff {
+        header,
+        statements,
+        loads,
+    })
+}
+
+fn emit_compacted_file(header: &DiffHeader, statements: &[DiffStatement]) -> String {
+    let mut out = String::new();
+    for version in &header.versions {
+        out += &format!("VERSION \"{}\"\n", version);
+    }
+    for requirement in &header.requirements {
+        out += &format!("{}\n", requirement);
+    }
+    for metadata in &header.metadata {
+        out += &format!("{}\n", metadata);
+    }
+    for ordering in &header.ordering {
+        out += &format!("{}\n", ordering);
+    }
+    for alias in &header.selector_aliases {
+        out += &format!("{}\n", alias);
+    }
+    for statement in statements {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        match statement {
+            DiffStatement::Verbatim { tokens, .. } => {
+                out += emit_token_stream(tokens.clone()).trim();
+            }
+            DiffStatement::Affect {
+                destination,
+                bodies,
+            } => {
+                out += &format!("AFFECT {}", destination);
+                for body in bodies {
+                    let body = emit_token_stream(body.clone());
+                    if !body.starts_with(char::is_whitespace) {
+                        out.push('\n');
+                    }
+                    out += body.trim_end();
+                }
+                out += "\nEND AFFECT";
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+/// Merges the diffs of a directory which affect the same files, drops the ones which
+/// can never be applied to any of `versions`, and writes the result into `output_directory`.
+/// Files which take part in LOAD statements are copied as-is, since their paths matter.
+pub fn compact_diff_directory(
+    directory: &str,
+    output_directory: &str,
+    versions: &[String],
+) -> Result<()> {
+    let mut files = read_dir(directory)?
+        .flatten()
+        .map(|e| e.path())
+        .filter(|e| e.is_file() && e.extension().is_some_and(|ext| ext == "qmd"))
+        .collect::<Vec<_>>();
+    files.sort();
+
+    let mut parsed = Vec::new();
+    let mut loaded_files = BTreeSet::new();
+    let mut size_before = 0;
+    for file in &files {
+        let contents = read_to_string(file)?;
+        size_before += contents.len();
+        let split = diff::lexer::Lexer::new(StringCharacterTokenizer::new(contents.clone()))
+            .tokenize()
+            .and_then(split_diff_statements)
+            .map_err(|error| {
+                Error::msg(format!(
+                    "Error while reading {}: {}",
+                    file.to_string_lossy(),
+                    error
+                ))
+            })?;
+        for load in &split.loads {
+            let candidates = [
+                file.parent().unwrap().join(load),
+                Path::new(directory).join(load),
+            ];
+            if let Some(loaded) = candidates.iter().find_map(|e| e.canonicalize().ok()) {
+                loaded_files.insert(loaded);
+            }
+        }
+        parsed.push((file, contents, split));
+    }
+
+    let mut groups: BTreeMap<DiffHeader, Vec<DiffStatement>> = BTreeMap::new();
+    let mut verbatim = Vec::new();
+    let (mut dropped_files, mut merged_blocks) = (0, 0);
+    for (file, contents, split) in parsed {
+        if !split.header.allows_any_of(versions) {
+            info!(
+                "Dropping {} - none of its versions are in the version set",
+                file.to_string_lossy()
             );
-            file_iterator += 1;
-            absolute_root.join(next)
+            dropped_files += 1;
+            continue;
+        }
+        let is_loaded = file.canonicalize().is_ok_and(|e| loaded_files.contains(&e));
+        if !split.loads.is_empty() || is_loaded {
+            verbatim.push((file, contents));
+            continue;
+        }
+
+        let group = groups.entry(split.header).or_default();
+        for statement in split.statements {
+            // Only the last block affecting a file can be extended, so that the order of
+            // the changes made to that file is preserved.
+            let last_affecting = match &statement {
+                DiffStatement::Affect { destination, .. } => group.iter_mut().rev().find(|e| {
+                    matches!(e, DiffStatement::Affect { destination: d, .. } | DiffStatement::Verbatim { destination: Some(d), .. } if d == destination)
+                }),
+                DiffStatement::Verbatim { .. } => None,
+            };
+            match (last_affecting, statement) {
+                (
+                    Some(DiffStatement::Affect { bodies, .. }),
+                    DiffStatement::Affect {
+                        bodies: new_bodies, ..
+                    },
+                ) => {
+                    bodies.extend(new_bodies);
+                    merged_blocks += 1;
+                }
+                (_, statement) => group.push(statement),
+            }
+        }
+    }
+
+    create_dir_all(output_directory)?;
+    let output_root = Path::new(output_directory);
+    let mut size_after = 0;
+    let mut files_written = 0;
+    for (index, (header, statements)) in groups.iter().enumerate() {
+        if statements.is_empty() {
+            continue;
+        }
+        let name = if groups.len() == 1 {
+            "compacted.qmd".to_string()
         } else {
-            let next = Path::new(&file_to_edit);
-            absolute_root.join(next.strip_prefix("/").unwrap_or(next))
+            format!("compacted_{}.qmd", index)
         };
-        create_dir_all(destination_path.parent().unwrap())?;
-        write(&destination_path, emitted)?;
-        println!(
-            "Written file {} - {} diff(s) applied.",
-            destination_path.to_string_lossy(),
-            count
+        let emitted = emit_compacted_file(header, statements);
+        size_after += emitted.len();
+        files_written += 1;
+        write(output_root.join(name), emitted)?;
+    }
+    for (file, contents) in verbatim {
+        info!(
+            "Copying {} as-is - it takes part in LOAD statements",
+            file.to_string_lossy()
         );
+        size_after += contents.len();
+        files_written += 1;
+        write(output_root.join(file.file_name().unwrap()), contents)?;
+    }
+
+    info!(
+        "Compacted {} diff(s) into {} - {} block(s) merged, {} file(s) dropped.",
+        files.len(),
+        files_written,
+        merged_blocks,
+        dropped_files
+    );
+    info!(
+        "Size: {} -> {} bytes ({} bytes saved, {:.1}%)",
+        size_before,
+        size_after,
+        size_before as i64 - size_after as i64,
+        if size_before == 0 {
+            0.0
+        } else {
+            (size_before as f64 - size_after as f64) * 100.0 / size_before as f64
+        }
+    );
+
+    Ok(())
+}
+
+/// Lists the diff files, along with their root directories. Directories are searched for
+/// `.qmd` files, in the order of their names.
+fn collect_diff_paths(files: &Vec<String>) -> Result<Vec<(String, PathBuf)>> {
+    let mut paths = Vec::new();
+    for path_str in files {
+        let path = Path::new(path_str);
+        if path.is_dir() {
+            let mut dir_files = read_dir(path)?
+                .flatten()
+                .map(|e| e.path())
+                .filter(|e| e.is_file() && e.extension().is_some_and(|ext| ext == "qmd"))
+                .collect::<Vec<_>>();
+            dir_files.sort();
+            paths.extend(dir_files.into_iter().map(|e| (path_str.clone(), e)));
+        } else {
+            let root_dir = String::from(path.parent().unwrap().to_string_lossy());
+            paths.push((root_dir, path.to_path_buf()));
+        }
+    }
+    Ok(paths)
+}
+
+/// Prints the fragile selectors of the diffs. Returns how many were found.
+pub fn lint_diffs(files: &Vec<String>, hashtab: &HashTab, min_score: u32) -> Result<usize> {
+    let (mut checked, mut fragile) = (0, 0);
+    for (root_dir, path) in collect_diff_paths(files)? {
+        let changes = load_diff_file(
+            Some(root_dir),
+            &path,
+            hashtab,
+            Some(Box::new(LoggingExternalLoader {})),
+            false,
+            false,
+            &Limits::default(),
+        )?;
+        for report in changes.iter().flat_map(lint_selectors) {
+            checked += 1;
+            if report.score.score < min_score {
+                continue;
+            }
+            fragile += 1;
+            println!(
+                "{}: {}: {} (fragility {})",
+                report.source, report.destination, report.directive, report.score.score
+            );
+            for reason in &report.score.reasons {
+                println!("    - {}", reason);
+            }
+            for suggestion in &report.score.suggestions {
+                println!("    Suggestion: {}", suggestion);
+            }
+        }
+    }
+    println!("{} selector(s) checked, {} fragile.", checked, fragile);
+
+    Ok(fragile)
+}
+
+fn load_pack(files: &Vec<String>, hashtab: &HashTab) -> Result<PackModel> {
+    let mut changes = Vec::new();
+    for (root_dir, path) in collect_diff_paths(files)? {
+        changes.extend(load_diff_file(
+            Some(root_dir),
+            &path,
+            hashtab,
+            Some(Box::new(LoggingExternalLoader {})),
+            false,
+            false,
+            &Limits::default(),
+        )?);
+    }
+    Ok(PackModel::new(&changes))
+}
+
+/// Compares two versions of a diff pack directive by directive. The diffs are parsed (and
+/// unhashed using the hashtab), so reformatting or rehashing them is not reported.
+/// Returns the amount of changed objects.
+pub fn diff_pack_versions(
+    old_files: &Vec<String>,
+    new_files: &Vec<String>,
+    hashtab: &HashTab,
+) -> Result<usize> {
+    let old = load_pack(old_files, hashtab)?;
+    let new = load_pack(new_files, hashtab)?;
+    let (differences, unchanged) = diff_packs(&old, &new);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    println!(
+        "{} object(s) changed, {} unchanged.",
+        differences.len(),
+        unchanged
+    );
+
+    Ok(differences.len())
+}
+
+/// Writes a generated pseudo-QML corpus into `output_directory`.
+pub fn write_corpus(output_directory: &str, config: &CorpusConfig) -> Result<()> {
+    create_dir_all(output_directory)?;
+    let mut size = 0;
+    for (name, contents) in generate_corpus(config) {
+        size += contents.len();
+        write(Path::new(output_directory).join(name), contents)?;
+    }
+    info!(
+        "Generated {} file(s), {} bytes in total.",
+        config.files, size
+    );
+
+    Ok(())
+}
+
+/// Prints the outline of a QML file - as indented text, or as a JSON array of its top-level
+/// objects. With `inv_hashtab`, all the names are replaced by their hashes.
+pub fn dump_qml_tree(file: &str, json: bool, inv_hashtab: Option<&InvHashTab>) -> Result<()> {
+    let tree = parse_qml(
+        read_to_string(file)?,
+        file,
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )?;
+    let mut outline = outline_tree(&tree);
+    if let Some(inv_hashtab) = inv_hashtab {
+        let mut missing = BTreeSet::new();
+        outline = outline
+            .iter()
+            .map(|e| e.hashed(inv_hashtab, &mut missing))
+            .collect();
+        if !missing.is_empty() {
+            warning!(
+                "Warning: {} name(s) are not in the hashtab, and have been left as they are: {}",
+                missing.len(),
+                missing.into_iter().collect::<Vec<_>>().join(", ")
+            );
+        }
+    }
+    if json {
+        let objects: Vec<_> = outline.iter().map(|e| e.to_json()).collect();
+        println!("[{}]", objects.join(","));
+    } else {
+        for object in &outline {
+            print!("{}", object);
+        }
     }
 
     Ok(())
 }
+
+/// Compares two QML files, ignoring their formatting and comments.
+/// Returns the amount of differences found.
+pub fn compare_qml_files(old_file: &str, new_file: &str) -> Result<usize> {
+    let old = parse_qml(
+        read_to_string(old_file)?,
+        old_file,
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )?;
+    let new = parse_qml(
+        read_to_string(new_file)?,
+        new_file,
+        None,
+        None,
+        QMLDiffExtensions::NONE,
+    )?;
+    let differences = compare_trees(&old, &new);
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if differences.is_empty() {
+        println!("The files are semantically equivalent.");
+    } else {
+        println!("{} difference(s) found.", differences.len());
+    }
+
+    Ok(differences.len())
+}
+
+/// Runs the tests of every manifest, printing whether they have passed.
+/// Returns the amount of failed tests.
+pub fn run_diff_tests(manifests: &[String]) -> Result<usize> {
+    let (mut passed, mut failed) = (0, 0);
+    for manifest in manifests {
+        for test in parse_manifest(Path::new(manifest))? {
+            match run_test(&test) {
+                Ok(failures) if failures.is_empty() => {
+                    println!("PASS {}", test.name);
+                    passed += 1;
+                }
+                Ok(failures) => {
+                    println!("FAIL {}", test.name);
+                    for failure in failures {
+                        println!("    {}", failure.to_string().replace('\n', "\n    "));
+                    }
+                    failed += 1;
+                }
+                Err(error) => {
+                    println!("FAIL {}", test.name);
+                    println!("    {:?}", error);
+                    failed += 1;
+                }
+            }
+        }
+    }
+    println!("{} test(s) passed, {} failed.", passed, failed);
+
+    Ok(failed)
+}
+
+/// Shows what every hashtab rule would match and derive, without writing anything.
+/// Returns the amount of strings which would be added to the hashtab.
+pub fn explain_hash_rules(hashtab: &HashTab, rules: &HashRules) -> usize {
+    let mut added = BTreeSet::new();
+    for (i, explanation) in rules.explain(hashtab).iter().enumerate() {
+        println!(
+            "Rule #{} ({}): {} match(es)",
+            i + 1,
+            explanation.condition,
+            explanation.matches.len()
+        );
+        for rule_match in &explanation.matches {
+            let indent = match rule_match.entries.as_slice() {
+                [] => "    ",
+                [entry] => {
+                    println!("    Entry '{}'", entry);
+                    for (index, capture) in rule_match.captures[0].iter().enumerate().skip(1) {
+                        println!("        ${} = '{}'", index, capture);
+                    }
+                    "        "
+                }
+                entries => {
+                    // Join rules refer to the captures as $<entry>:<group>
+                    for (i, entry) in entries.iter().enumerate() {
+                        println!("    Entry #{} '{}'", i + 1, entry);
+                        for (index, capture) in rule_match.captures[i].iter().enumerate() {
+                            println!("        ${}:{} = '{}'", i + 1, index, capture);
+                        }
+                    }
+                    "        "
+                }
+            };
+            for derived in &rule_match.derived {
+                if hashtab.contains_key(&hash(derived)) {
+                    println!("{}= '{}' (already in the hashtab)", indent, derived);
+                } else {
+                    println!("{}+ '{}'", indent, derived);
+                    added.insert(derived.clone());
+                }
+            }
+        }
+    }
+    println!("{} string(s) would be added to the hashtab.", added.len());
+
+    added.len()
+}
+
+/// Keeps only the entries of the hashtab referenced by the diffs (including the files they
+/// `LOAD`), so that a minimal hashtab can be shipped along with them. The strings derived by
+/// the rules are looked up too, and the entries the rules themselves refer to are kept.
+pub fn prune_hashtab(
+    hashtab: &HashTab,
+    files: &Vec<String>,
+    rules: Option<&HashRules>,
+) -> Result<HashTab> {
+    let mut references = BTreeSet::new();
+    let mut visited = BTreeSet::new();
+    let mut queue = collect_diff_paths(files)?;
+    while let Some((root_dir, path)) = queue.pop() {
+        if !visited.insert(path.canonicalize().unwrap_or(path.clone())) {
+            continue;
+        }
+        let tokens = diff::lexer::Lexer::new(StringCharacterTokenizer::new(read_to_string(&path)?))
+            .tokenize()
+            .map_err(|error| {
+                Error::msg(format!(
+                    "Error while reading {}: {}",
+                    path.to_string_lossy(),
+                    error
+                ))
+            })?;
+        references.extend(find_hash_references(&tokens));
+        let split = split_diff_statements(tokens).map_err(|error| {
+            Error::msg(format!(
+                "Error while reading {}: {}",
+                path.to_string_lossy(),
+                error
+            ))
+        })?;
+        for load in split.loads {
+            let candidates = [
+                path.parent().unwrap().join(&load),
+                Path::new(&root_dir).join(&load),
+            ];
+            match candidates.into_iter().find(|e| e.is_file()) {
+                Some(loaded) => queue.push((root_dir.clone(), loaded)),
+                None => warning!(
+                    "Warning: cannot find {} loaded by {}",
+                    load,
+                    path.to_string_lossy()
+                ),
+            }
+        }
+    }
+
+    let mut available = hashtab.clone();
+    if let Some(rules) = rules {
+        references.extend(rules.referenced_hashes());
+        for explanation in rules.explain(hashtab) {
+            for derived in explanation.matches.into_iter().flat_map(|e| e.derived) {
+                available.insert(hash(&derived), derived);
+            }
+        }
+    }
+
+    let pruned = references
+        .iter()
+        .filter_map(|e| available.get(e).map(|value| (*e, value.clone())))
+        .collect::<HashTab>();
+    info!(
+        "Kept {} of {} entries, referenced by {} file(s).",
+        pruned.len(),
+        hashtab.len(),
+        visited.len()
+    );
+    if pruned.len() != references.len() {
+        warning!(
+            "Warning: {} referenced hash(es) are not present in the hashtab.",
+            references.len() - pruned.len()
+        );
+    }
+
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod test {
+    use anyhow::Error;
+
+    use super::write_error_report;
+    use crate::util::common_util::{kind_error, ErrorKind, FileFailures};
+
+    #[test]
+    fn test_error_report() {
+        let path = std::env::temp_dir().join("qmldiff-error-report.json");
+        let report = |error: Option<&Error>, failures: &[(String, Error)]| {
+            write_error_report(path.to_str().unwrap(), error, failures).unwrap();
+            std::fs::read_to_string(&path).unwrap()
+        };
+
+        assert_eq!(
+            report(None, &[]),
+            "{\"exit_code\":0,\"kind\":null,\"message\":null,\"failures\":[]}\n"
+        );
+        // Lenient runs report the failed files without an error.
+        assert_eq!(
+            report(
+                None,
+                &[(
+                    "Main.qml".into(),
+                    kind_error(ErrorKind::Selector, "No \"match\"")
+                )]
+            ),
+            "{\"exit_code\":0,\"kind\":null,\"message\":null,\"failures\":[{\"file\":\"Main.qml\",\"kind\":\"selector\",\"source\":null,\"directive\":null,\"message\":\"No \\\"match\\\"\"}]}\n"
+        );
+        // Strict runs fail with all the failed files.
+        let error = Error::new(FileFailures(vec![(
+            "Main.qml".into(),
+            kind_error(ErrorKind::Parse, "Bad QML"),
+        )]));
+        let written = report(Some(&error), &[]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(written.starts_with(
+            "{\"exit_code\":3,\"kind\":\"parse\",\"message\":\"1 file(s) could not be processed:"
+        ));
+        assert!(written.ends_with(
+            ",\"failures\":[{\"file\":\"Main.qml\",\"kind\":\"parse\",\"source\":null,\"directive\":null,\"message\":\"Bad QML\"}]}\n"
+        ));
+    }
+}
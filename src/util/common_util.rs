@@ -1,34 +1,248 @@
-use std::{cell::RefCell, fs::read_to_string, path::Path, rc::Rc, sync::Arc};
+use std::{
+    cell::RefCell, fmt::Display, fs::read_to_string, path::Path, rc::Rc, str::FromStr, sync::Arc,
+};
 
 use anyhow::{Error, Result};
 
 use crate::{
     hashtab::HashTab,
+    limits::Limits,
     parser::{
         common::{IteratorPipeline, StringCharacterTokenizer},
         diff::{
             self,
-            hash_processor::diff_hash_remapper,
-            parser::{Change, ExternalLoader},
+            hash_processor::{ensure_hashed, resolve_diff_hashes},
+            parser::{Change, Defines, DiffOrdering, ExternalLoader},
         },
         qml::{
             self,
             hash_extension::QMLHashRemapper,
-            lexer::{Lexer, TokenType},
+            lexer::{Lexer, QMLDiffExtensions, TokenType},
             parser::{Object, TreeElement},
             slot_extensions::QMLSlotRemapper,
         },
     },
     slots::Slots,
+    util::output::warning,
 };
 
+/// What went wrong, as far as the scripts calling the CLI are concerned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// A diff or a QML file cannot be parsed.
+    Parse,
+    /// A diff refers to hashes missing from the hashtab, or isn't hashed when it has to be.
+    Hash,
+    Io,
+    /// A selector of a diff didn't match the QML tree.
+    Selector,
+    /// The patched file is invalid - it assigns the same id to several objects, or puts anything
+    /// but objects into a list of objects.
+    Validation,
+    /// A diff exceeds one of the limits - see `Limits`.
+    Limit,
+    Other,
+}
+
+impl ErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Parse => "parse",
+            Self::Hash => "hash",
+            Self::Io => "io",
+            Self::Selector => "selector",
+            Self::Validation => "validation",
+            Self::Limit => "limit",
+            Self::Other => "other",
+        }
+    }
+
+    /// The exit code of the CLI. 2 is used by clap for invalid arguments.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Self::Other => 1,
+            Self::Parse => 3,
+            Self::Hash => 4,
+            Self::Io => 5,
+            Self::Selector => 6,
+            Self::Validation => 7,
+            Self::Limit => 8,
+        }
+    }
+}
+
+/// An error along with where it comes from. Created by `kind_error`, and extended by
+/// `add_error_source_if_needed` and `add_directive_if_needed` as it's passed up.
+#[derive(Debug, Clone)]
+pub struct DetailedError {
+    pub kind: ErrorKind,
+    /// The diff whose change failed.
+    pub source: Option<String>,
+    /// The directive of the change which failed.
+    pub directive: Option<String>,
+    pub message: String,
+}
+
+impl Display for DetailedError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.source {
+            Some(source) => write!(f, "(On behalf of '{}'): {}", source, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
+impl std::error::Error for DetailedError {}
+
+pub fn kind_error(kind: ErrorKind, message: impl Into<String>) -> Error {
+    Error::new(DetailedError {
+        kind,
+        source: None,
+        directive: None,
+        message: message.into(),
+    })
+}
+
+/// The details of any error. Errors not created by `kind_error` are of the `Io` kind if they
+/// were caused by an IO error, and of the `Other` kind otherwise.
+pub fn error_details(error: &Error) -> DetailedError {
+    if let Some(details) = error.downcast_ref::<DetailedError>() {
+        return details.clone();
+    }
+    // Many failed files are of the kind of the first of them.
+    if let Some(FileFailures(failures)) = error.downcast_ref::<FileFailures>() {
+        if let Some((_, first)) = failures.first() {
+            return DetailedError {
+                source: None,
+                directive: None,
+                message: error.to_string(),
+                ..error_details(first)
+            };
+        }
+    }
+    let is_io = error.chain().any(|e| e.is::<std::io::Error>());
+    DetailedError {
+        kind: if is_io {
+            ErrorKind::Io
+        } else {
+            ErrorKind::Other
+        },
+        source: None,
+        directive: None,
+        message: format!("{:#}", error),
+    }
+}
+
+/// Gives the error the kind `kind`, unless it already has a more specific one.
+pub fn with_error_kind(error: Error, kind: ErrorKind) -> Error {
+    let mut details = error_details(&error);
+    if details.kind != ErrorKind::Other {
+        return error;
+    }
+    details.kind = kind;
+    Error::new(details)
+}
+
 pub fn add_error_source_if_needed<T>(result: Result<T>, source: &str) -> Result<T> {
-    match result {
-        Ok(e) => Ok(e),
-        Err(error) => Err(Error::msg(format!(
-            "(On behalf of '{}'): {:?}",
-            source, error
-        ))),
+    result.map_err(|error| {
+        let details = error_details(&error);
+        Error::new(DetailedError {
+            source: Some(source.to_string()),
+            message: format!("{:#}", error),
+            ..details
+        })
+    })
+}
+
+/// Records the directive which failed in the error, unless a nested one already has.
+pub fn add_directive_if_needed<T>(
+    result: Result<T>,
+    directive: impl FnOnce() -> String,
+) -> Result<T> {
+    result.map_err(|error| {
+        let mut details = error_details(&error);
+        if details.directive.is_some() {
+            return error;
+        }
+        details.directive = Some(directive());
+        Error::new(details)
+    })
+}
+
+/// What to do when the changes cannot be applied to some of the files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FailurePolicy {
+    /// Keep processing the other files, but leave every file unpatched, and report all the
+    /// failures as an error.
+    #[default]
+    Strict,
+    /// Patch every file which can be patched, and only report the ones which failed.
+    Lenient,
+}
+
+impl FromStr for FailurePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "strict" => Ok(Self::Strict),
+            "lenient" => Ok(Self::Lenient),
+            _ => Err(Error::msg(format!(
+                "Unknown failure policy {} - expected strict or lenient",
+                s
+            ))),
+        }
+    }
+}
+
+/// Lists the files which could not be processed, along with the reasons.
+pub fn describe_failures(failures: &[(String, Error)]) -> String {
+    let mut description = format!("{} file(s) could not be processed:", failures.len());
+    for (file, error) in failures {
+        description += &format!("\n- {}: {}", file, error);
+    }
+    description
+}
+
+/// The error returned when files cannot be processed under the strict policy.
+#[derive(Debug)]
+pub struct FileFailures(pub Vec<(String, Error)>);
+
+impl Display for FileFailures {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", describe_failures(&self.0))
+    }
+}
+
+impl std::error::Error for FileFailures {}
+
+pub fn filter_out_unmet_requirements(changes: &mut Vec<Change>, defines: &Defines, from: &str) {
+    if changes.is_empty() {
+        return;
+    }
+
+    changes.retain(|x| {
+        let unmet = x
+            .requirements
+            .iter()
+            .filter(|e| !e.is_met(defines))
+            .map(|e| e.to_string())
+            .collect::<Vec<_>>();
+        if !unmet.is_empty() {
+            warning!(
+                "[qmldiff]: Warning: {} (defined by '{}') has been removed! Unmet requirements: {}",
+                x.destination,
+                from,
+                unmet.join(", ")
+            );
+        }
+        unmet.is_empty()
+    });
+    if changes.is_empty() {
+        warning!(
+            "[qmldiff]: Warning: All changes from '{}' have been blocked due to unmet requirements!",
+            from
+        );
     }
 }
 
@@ -49,7 +263,7 @@ pub fn filter_out_non_matching_versions(
                 Some(ref vers) => {
                     let retain = vers.contains(ver);
                     if !retain {
-                        eprintln!("[qmldiff]: Warning: A change to {:?} (defined by '{}') has been removed! Compatible with versions {:?}, currently running {}", x.destination, from, vers, ver);
+                        warning!("[qmldiff]: Warning: {} (defined by '{}') has been removed! Compatible with versions {}, currently running {}", x.destination, from, vers.join(", "), ver);
                     }
 
                     retain
@@ -57,9 +271,104 @@ pub fn filter_out_non_matching_versions(
             }
         });
         if changes.is_empty() {
-            eprintln!("[qmldiff]: Warning: All changes from '{}' have been blocked due to version mismatch!", from);
+            warning!("[qmldiff]: Warning: All changes from '{}' have been blocked due to version mismatch!", from);
+        }
+    }
+}
+
+/// Sorts the changes by the `PRIORITY` and `AFTER DIFF` statements of the diffs defining them.
+/// Diffs are applied in the ascending order of their priorities, after all the diffs they have to
+/// follow. Diffs which are not ordered in any way keep the order they were loaded in, and so do
+/// the changes of every diff. Fails if the diffs have to follow each other in a cycle.
+pub fn order_changes(changes: &mut Vec<Change>) -> Result<()> {
+    // A file and the files it `LOAD`s share the same ordering, unless they define their own, so
+    // the changes are grouped by it.
+    let mut diffs: Vec<Vec<&Change>> = Vec::new();
+    for change in changes.iter() {
+        match diffs
+            .iter_mut()
+            .find(|e| Arc::ptr_eq(&e[0].ordering, &change.ordering))
+        {
+            Some(diff) => diff.push(change),
+            None => diffs.push(vec![change]),
         }
     }
+    if diffs
+        .iter()
+        .all(|e| *e[0].ordering == DiffOrdering::default())
+    {
+        return Ok(());
+    }
+
+    let is_named = |diff: &[&Change], reference: &str| {
+        diff.iter().any(|e| {
+            e.metadata.name.as_deref() == Some(reference)
+                || e.source.as_str() == reference
+                || e.source.ends_with(&format!("/{}", reference))
+        })
+    };
+    let diff_name = |diff: &[&Change]| match &diff[0].metadata.name {
+        Some(name) => name.clone(),
+        None => diff[0].source.to_string(),
+    };
+    let dependencies: Vec<Vec<usize>> = diffs
+        .iter()
+        .enumerate()
+        .map(|(i, diff)| {
+            (0..diffs.len())
+                .filter(|&j| {
+                    j != i
+                        && diff[0]
+                            .ordering
+                            .after
+                            .iter()
+                            .any(|reference| is_named(&diffs[j], reference))
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut order = Vec::new();
+    let mut placed = vec![false; diffs.len()];
+    while order.len() < diffs.len() {
+        let next = (0..diffs.len())
+            .filter(|&i| !placed[i] && dependencies[i].iter().all(|&e| placed[e]))
+            .min_by_key(|&i| (diffs[i][0].ordering.priority, i));
+        match next {
+            Some(next) => {
+                placed[next] = true;
+                order.push(next);
+            }
+            None => {
+                // Every diff left waits for another one - follow them until one repeats.
+                let mut cycle = vec![(0..diffs.len()).find(|&i| !placed[i]).unwrap()];
+                loop {
+                    let last = *cycle.last().unwrap();
+                    let waits_for = *dependencies[last].iter().find(|&&e| !placed[e]).unwrap();
+                    if let Some(start) = cycle.iter().position(|&e| e == waits_for) {
+                        cycle.drain(..start);
+                        cycle.push(waits_for);
+                        break;
+                    }
+                    cycle.push(waits_for);
+                }
+                return Err(Error::msg(format!(
+                    "Cycle in the AFTER DIFF statements: {}",
+                    cycle
+                        .iter()
+                        .map(|&e| diff_name(&diffs[e]))
+                        .collect::<Vec<_>>()
+                        .join(" -> ")
+                )));
+            }
+        }
+    }
+
+    *changes = order
+        .into_iter()
+        .flat_map(|i| diffs[i].iter().map(|&e| e.clone()))
+        .collect();
+    Ok(())
 }
 
 pub fn load_diff_file<P>(
@@ -67,6 +376,9 @@ pub fn load_diff_file<P>(
     file_path: P,
     hashtab: &HashTab,
     external_loader: Option<Box<dyn ExternalLoader>>,
+    require_hashed: bool,
+    defer_hashed_files: bool,
+    limits: &Limits,
 ) -> Result<Vec<Change>>
 where
     P: AsRef<Path>,
@@ -78,20 +390,28 @@ where
         &file_path.as_ref().to_string_lossy(),
         hashtab,
         external_loader,
+        require_hashed,
+        defer_hashed_files,
+        limits,
     )
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn parse_diff(
     root_dir: Option<String>,
     contents: String,
     diff_name: &str,
     hashtab: &HashTab,
     external_loader: Option<Box<dyn ExternalLoader>>,
+    require_hashed: bool,
+    defer_hashed_files: bool,
+    limits: &Limits,
 ) -> Result<Vec<Change>> {
-    let lexer = diff::lexer::Lexer::new(StringCharacterTokenizer::new(contents));
-    let tokens: Vec<diff::lexer::TokenType> = lexer
-        .map(|e| diff_hash_remapper(hashtab, e, diff_name).unwrap())
-        .collect();
+    let tokens = limits.tokenize_diff(contents, diff_name)?;
+    if require_hashed {
+        ensure_hashed(&tokens, hashtab, diff_name)?;
+    }
+    let tokens = resolve_diff_hashes(hashtab, tokens, diff_name, defer_hashed_files)?;
     let mut parser = diff::parser::Parser::new(
         Box::new(tokens.into_iter()),
         root_dir,
@@ -99,23 +419,79 @@ pub fn parse_diff(
         Some(hashtab),
         external_loader.map(|e| Rc::new(RefCell::new(e))),
     );
+    parser.require_hashed = require_hashed;
+    parser.defer_hashed_files = defer_hashed_files;
+    parser.limits = *limits;
 
-    parser.parse(None)
+    parser
+        .parse(None, Vec::new())
+        .map_err(|error| with_error_kind(error, ErrorKind::Parse))
 }
 
+/// The conventions of a file the emitter doesn't reproduce by itself - they're detected when the
+/// file is read, and restored on the emitted file.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SourceFormat {
+    /// The file starts with a UTF-8 byte order mark.
+    pub bom: bool,
+    /// The lines of the file end with `\r\n`.
+    pub crlf: bool,
+}
+
+const BOM: char = '\u{feff}';
+
+impl SourceFormat {
+    /// Detects the format of the file, and returns its contents without the byte order mark.
+    /// The line endings are picked by the first line of the file.
+    pub fn detect(contents: &str) -> (Self, &str) {
+        let stripped = contents.strip_prefix(BOM);
+        let format = Self {
+            bom: stripped.is_some(),
+            crlf: contents
+                .find('\n')
+                .is_some_and(|end| contents[..end].ends_with('\r')),
+        };
+        (format, stripped.unwrap_or(contents))
+    }
+
+    /// Applies the format to an emitted file. Files emitted with `\n` are left as they are unless
+    /// the original used `\r\n`.
+    pub fn restore(&self, mut emitted: String) -> String {
+        if self.crlf {
+            // Code copied verbatim from the original file already ends with `\r`.
+            emitted = emitted.replace("\r\n", "\n").replace('\n', "\r\n");
+        }
+        if self.bom && !emitted.starts_with(BOM) {
+            emitted.insert(0, BOM);
+        }
+        emitted
+    }
+}
+
+/// `extensions` are the extension tokens (`~&hash&~`, `~{slot}~`...) to recognize - `NONE` for
+/// the files the diffs are applied to.
 pub fn tokenize_qml(
     raw_qml: String,
     qml_name: &str,
     hashtab: Option<&HashTab>,
     slots: Option<&mut Slots>,
+    extensions: QMLDiffExtensions,
 ) -> Vec<TokenType> {
+    // The lexer doesn't know about byte order marks.
+    let raw_qml = match raw_qml.strip_prefix(BOM) {
+        Some(stripped) => stripped.to_string(),
+        None => raw_qml,
+    };
     let mut iterator = IteratorPipeline::new(
-        Box::from(Lexer::new(StringCharacterTokenizer::new(raw_qml))),
+        Box::from(Lexer::with_extensions(
+            StringCharacterTokenizer::new(raw_qml),
+            extensions,
+        )),
         qml_name,
     );
     let mut hash_mapper;
-    if hashtab.is_some() {
-        hash_mapper = QMLHashRemapper::new(hashtab.unwrap());
+    if let Some(hashtab) = hashtab {
+        hash_mapper = QMLHashRemapper::new(hashtab);
         iterator.add_remapper(&mut hash_mapper);
     }
 
@@ -133,16 +509,21 @@ pub fn parse_qml(
     qml_name: &str,
     hashtab: Option<&HashTab>,
     slots: Option<&mut Slots>,
+    extensions: QMLDiffExtensions,
 ) -> Result<Vec<TreeElement>> {
     let mut parser: qml::parser::Parser = qml::parser::Parser::new(Box::new(
-        tokenize_qml(raw_qml, qml_name, hashtab, slots).into_iter(),
+        tokenize_qml(raw_qml, qml_name, hashtab, slots, extensions).into_iter(),
     ));
-    parser.parse()
+    parser
+        .parse()
+        .map_err(|error| with_error_kind(error, ErrorKind::Parse))
 }
 
 pub fn parse_qml_from_chain(tokens: Vec<TokenType>) -> Result<Vec<TreeElement>> {
     let mut parser = qml::parser::Parser::new(Box::new(tokens.into_iter()));
-    parser.parse()
+    parser
+        .parse()
+        .map_err(|error| with_error_kind(error, ErrorKind::Parse))
 }
 
 pub fn parse_qml_into_simple_object(tokens: Vec<TokenType>) -> Result<Object> {
@@ -152,3 +533,75 @@ pub fn parse_qml_into_simple_object(tokens: Vec<TokenType>) -> Result<Object> {
         _ => Err(Error::msg("Invalid token stream for object recreation!")),
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeSet;
+
+    use anyhow::Error;
+
+    use super::{
+        add_directive_if_needed, add_error_source_if_needed, error_details, kind_error,
+        with_error_kind, ErrorKind, FileFailures,
+    };
+
+    #[test]
+    fn test_exit_codes() {
+        let kinds = [
+            ErrorKind::Parse,
+            ErrorKind::Hash,
+            ErrorKind::Io,
+            ErrorKind::Selector,
+            ErrorKind::Validation,
+            ErrorKind::Limit,
+            ErrorKind::Other,
+        ];
+        let codes = kinds.map(ErrorKind::exit_code);
+        assert_eq!(codes, [3, 4, 5, 6, 7, 8, 1]);
+        // Every kind has its own code, and none of them is the one of clap.
+        assert_eq!(codes.iter().collect::<BTreeSet<_>>().len(), kinds.len());
+        assert!(!codes.contains(&0) && !codes.contains(&2));
+    }
+
+    #[test]
+    fn test_error_details() {
+        let details = error_details(&Error::msg("Something"));
+        assert_eq!(details.kind, ErrorKind::Other);
+        let io = Error::new(std::io::Error::from(std::io::ErrorKind::NotFound));
+        assert_eq!(
+            error_details(&io.context("Cannot read")).kind,
+            ErrorKind::Io
+        );
+
+        // The kind is kept while the source and the directive are added.
+        let error = add_error_source_if_needed::<()>(
+            add_directive_if_needed(Err(kind_error(ErrorKind::Selector, "No match")), || {
+                "REMOVE Text".to_string()
+            }),
+            "mod.qmd",
+        )
+        .unwrap_err();
+        let details = error_details(&error);
+        assert_eq!(details.kind, ErrorKind::Selector);
+        assert_eq!(details.source.as_deref(), Some("mod.qmd"));
+        assert_eq!(details.directive.as_deref(), Some("REMOVE Text"));
+        assert_eq!(
+            error_details(&with_error_kind(error, ErrorKind::Parse)).kind,
+            ErrorKind::Selector
+        );
+        assert_eq!(
+            error_details(&with_error_kind(Error::msg("Bad"), ErrorKind::Parse)).kind,
+            ErrorKind::Parse
+        );
+
+        // Failed files are of the kind of the first of them.
+        let failures = Error::new(FileFailures(vec![
+            (
+                "Main.qml".into(),
+                kind_error(ErrorKind::Hash, "Missing hash"),
+            ),
+            ("Other.qml".into(), kind_error(ErrorKind::Parse, "Bad QML")),
+        ]));
+        assert_eq!(error_details(&failures).kind, ErrorKind::Hash);
+    }
+}
@@ -0,0 +1,300 @@
+//! The bits of JSON qmldiff reads - the translation catalogs, and the messages of the language
+//! server.
+
+use std::fmt::Display;
+
+use anyhow::{bail, Error, Result};
+
+use crate::journal::escape_json;
+
+/// A parsed JSON value. The keys of objects keep their order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonValue {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<JsonValue>),
+    Object(Vec<(String, JsonValue)>),
+}
+
+impl JsonValue {
+    pub fn parse(input: &str) -> Result<Self> {
+        let mut reader = JsonReader::new(input);
+        let value = reader.value()?;
+        if let Some(c) = reader.peek() {
+            bail!("Unexpected {} after the end of the JSON value", c);
+        }
+        Ok(value)
+    }
+
+    pub fn object<'a>(entries: impl IntoIterator<Item = (&'a str, JsonValue)>) -> Self {
+        Self::Object(
+            entries
+                .into_iter()
+                .map(|(key, value)| (key.to_string(), value))
+                .collect(),
+        )
+    }
+
+    /// The value of the key, if this is an object which has it.
+    pub fn get(&self, key: &str) -> Option<&JsonValue> {
+        match self {
+            Self::Object(entries) => entries.iter().find(|e| e.0 == key).map(|e| &e.1),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(string) => Some(string),
+            _ => None,
+        }
+    }
+
+    pub fn as_usize(&self) -> Option<usize> {
+        match self {
+            Self::Number(number) if *number >= 0.0 && number.fract() == 0.0 => {
+                Some(*number as usize)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[JsonValue]> {
+        match self {
+            Self::Array(values) => Some(values),
+            _ => None,
+        }
+    }
+}
+
+impl From<&str> for JsonValue {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_string())
+    }
+}
+
+impl From<String> for JsonValue {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<bool> for JsonValue {
+    fn from(value: bool) -> Self {
+        Self::Bool(value)
+    }
+}
+
+impl From<usize> for JsonValue {
+    fn from(value: usize) -> Self {
+        Self::Number(value as f64)
+    }
+}
+
+impl From<Vec<JsonValue>> for JsonValue {
+    fn from(value: Vec<JsonValue>) -> Self {
+        Self::Array(value)
+    }
+}
+
+impl Display for JsonValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(value) => write!(f, "{}", value),
+            // Integers are written without the fraction, so that they can be read back as such.
+            Self::Number(number) if number.fract() == 0.0 && number.abs() < 1e15 => {
+                write!(f, "{}", *number as i64)
+            }
+            Self::Number(number) if number.is_finite() => write!(f, "{}", number),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(string) => f.write_str(&escape_json(string)),
+            Self::Array(values) => {
+                f.write_str("[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                f.write_str("]")
+            }
+            Self::Object(entries) => {
+                f.write_str("{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i != 0 {
+                        f.write_str(",")?;
+                    }
+                    write!(f, "{}:{}", escape_json(key), value)?;
+                }
+                f.write_str("}")
+            }
+        }
+    }
+}
+
+pub(crate) struct JsonReader<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl<'a> JsonReader<'a> {
+    pub(crate) fn new(input: &'a str) -> Self {
+        Self { input, position: 0 }
+    }
+
+    pub(crate) fn peek(&mut self) -> Option<char> {
+        let rest = &self.input[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+        self.input[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.position += c.len_utf8();
+                Ok(())
+            }
+            Some(c) => bail!("Expected {}, got {}", expected, c),
+            None => bail!("Expected {}, got the end of the input", expected),
+        }
+    }
+
+    /// Reads an object, calling `value` to read the value of every key.
+    pub(crate) fn object(
+        &mut self,
+        mut value: impl FnMut(&mut Self, String) -> Result<()>,
+    ) -> Result<()> {
+        self.expect('{')?;
+        if self.peek() == Some('}') {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            let key = self.string()?;
+            self.expect(':')?;
+            value(self, key)?;
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some('}') => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                other => bail!("Expected , or }}, got {:?}", other),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Vec<JsonValue>> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+        if self.peek() == Some(']') {
+            self.position += 1;
+            return Ok(values);
+        }
+        loop {
+            values.push(self.value()?);
+            match self.peek() {
+                Some(',') => self.position += 1,
+                Some(']') => {
+                    self.position += 1;
+                    return Ok(values);
+                }
+                other => bail!("Expected , or ], got {:?}", other),
+            }
+        }
+    }
+
+    pub(crate) fn value(&mut self) -> Result<JsonValue> {
+        match self.peek() {
+            Some('{') => {
+                let mut entries = Vec::new();
+                self.object(|reader, key| {
+                    entries.push((key, reader.value()?));
+                    Ok(())
+                })?;
+                Ok(JsonValue::Object(entries))
+            }
+            Some('[') => Ok(JsonValue::Array(self.array()?)),
+            Some('"') => Ok(JsonValue::String(self.string()?)),
+            Some(_) => {
+                let rest = &self.input[self.position..];
+                let length = rest
+                    .find(|c: char| !(c.is_ascii_alphanumeric() || "+-.".contains(c)))
+                    .unwrap_or(rest.len());
+                let word = &rest[..length];
+                let value = match word {
+                    "null" => JsonValue::Null,
+                    "true" => JsonValue::Bool(true),
+                    "false" => JsonValue::Bool(false),
+                    _ => JsonValue::Number(
+                        word.parse()
+                            .map_err(|_| Error::msg(format!("Invalid JSON value {:?}", word)))?,
+                    ),
+                };
+                self.position += length;
+                Ok(value)
+            }
+            None => bail!("Expected a value, got the end of the input"),
+        }
+    }
+
+    pub(crate) fn string(&mut self) -> Result<String> {
+        self.expect('"')?;
+        let mut string = String::new();
+        let mut chars = self.input[self.position..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(string);
+                }
+                '\\' => {
+                    let escaped = chars.next().map(|e| e.1);
+                    string.push(match escaped {
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some('b') => '\u{8}',
+                        Some('f') => '\u{c}',
+                        Some('u') => {
+                            let mut code = read_hex(&mut chars, 4)?;
+                            // Characters outside of the BMP are written as surrogate pairs.
+                            if (0xD800..0xDC00).contains(&code) {
+                                if chars.next().map(|e| e.1) != Some('\\')
+                                    || chars.next().map(|e| e.1) != Some('u')
+                                {
+                                    bail!("Unpaired surrogate in a string");
+                                }
+                                let low = read_hex(&mut chars, 4)?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    bail!("Unpaired surrogate in a string");
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            char::from_u32(code)
+                                .ok_or_else(|| Error::msg("Invalid \\u escape in a string"))?
+                        }
+                        Some(c @ ('"' | '\\' | '/')) => c,
+                        other => bail!("Invalid escape sequence \\{:?} in a string", other),
+                    });
+                }
+                c => string.push(c),
+            }
+        }
+        bail!("Unterminated string")
+    }
+}
+
+pub(crate) fn read_hex(
+    chars: &mut impl Iterator<Item = (usize, char)>,
+    digits: usize,
+) -> Result<u32> {
+    let hex: String = chars.take(digits).map(|e| e.1).collect();
+    if hex.len() != digits {
+        bail!("Truncated escape sequence");
+    }
+    u32::from_str_radix(&hex, 16).map_err(|_| Error::msg(format!("Invalid hex number {}", hex)))
+}
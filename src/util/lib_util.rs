@@ -1,19 +1,94 @@
-use std::ffi::{c_char, CStr};
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_void, CStr, CString},
+    fmt::Display,
+    sync::Arc,
+    time::Duration,
+};
 
-use crate::{hash::hash, hashtab::hash_token_stream, util::common_util::tokenize_qml, HASHTAB};
+use crate::{
+    ffi::HASHTAB, hash::hash, hashtab::hash_token_stream, parser::qml::lexer::QMLDiffExtensions,
+    util::common_util::tokenize_qml,
+};
+
+/// Counters describing the work done by `qmldiff_process_file` since the context was created.
+#[derive(Debug, Default)]
+pub struct ProcessingStats {
+    pub files_processed: u64,
+    pub files_failed: u64,
+    /// Files which no change actually modified, and so were not re-emitted.
+    pub files_unchanged: u64,
+    /// Changes which modified the files they affect.
+    pub changes_applied: u64,
+    pub processing_time: Duration,
+    /// How many changes each diff file applied, keyed by the diff's source name.
+    pub per_diff_file: HashMap<String, u64>,
+    /// The names of the files which failed to process, in the order they were processed in.
+    pub failed_files: Vec<String>,
+}
+
+impl ProcessingStats {
+    /// Records a file processed without errors. `applied` are the sources of the changes which
+    /// modified it, as returned by the processor.
+    pub fn record_success(&mut self, applied: &[Arc<String>], time: Duration) {
+        self.files_processed += 1;
+        self.files_unchanged += applied.is_empty() as u64;
+        self.processing_time += time;
+        self.changes_applied += applied.len() as u64;
+        for source in applied {
+            *self.per_diff_file.entry(source.to_string()).or_default() += 1;
+        }
+    }
+
+    pub fn record_failure(&mut self, file_name: &str, time: Duration) {
+        self.files_failed += 1;
+        self.failed_files.push(file_name.to_string());
+        self.processing_time += time;
+    }
+}
+
+impl Display for ProcessingStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "files_processed: {}", self.files_processed)?;
+        writeln!(f, "files_failed: {}", self.files_failed)?;
+        writeln!(f, "files_unchanged: {}", self.files_unchanged)?;
+        writeln!(f, "changes_applied: {}", self.changes_applied)?;
+        writeln!(
+            f,
+            "processing_time_us: {}",
+            self.processing_time.as_micros()
+        )?;
+        let mut per_diff_file = self.per_diff_file.iter().collect::<Vec<_>>();
+        per_diff_file.sort();
+        for (file, count) in per_diff_file {
+            writeln!(f, "diff_file {}: {}", file, count)?;
+        }
+        for file in &self.failed_files {
+            writeln!(f, "failed_file {}", file)?;
+        }
+        Ok(())
+    }
+}
 
 pub fn is_building_hashtab() -> bool {
     std::env::var_os("QMLDIFF_HASHTAB_CREATE").is_some()
 }
 
-/**
- * # Safety
- * no
- */
-pub unsafe fn include_if_building_hashtab(file_name: &str, raw_contents: *const c_char) -> bool {
+/// Hashes the file into the global hashtab if one is being built, and records the `version` of the
+/// system it comes from, if it's known. Returns whether the file has been hashed.
+pub fn include_if_building_hashtab(
+    file_name: &str,
+    contents: &str,
+    extensions: QMLDiffExtensions,
+    version: Option<String>,
+) -> bool {
     if std::env::var_os("QMLDIFF_HASHTAB_CREATE").is_some() {
         eprintln!("[qmldiff]: Hashing: {}", file_name);
-        let mut hashtab = HASHTAB.lock().unwrap();
+        let mut built = HASHTAB.lock().unwrap();
+        if version.is_some() {
+            built.version = version;
+        }
+        let hashtab = &mut built.hashtab;
         for entry in file_name.split('/') {
             if !entry.is_empty() {
                 let hashed = hash(entry);
@@ -22,9 +97,8 @@ pub unsafe fn include_if_building_hashtab(file_name: &str, raw_contents: *const
         }
         hashtab.insert(hash(file_name), String::from(file_name));
         if file_name.to_lowercase().ends_with(".qml") {
-            let contents: String = CStr::from_ptr(raw_contents).to_str().unwrap().into();
-            let qml = tokenize_qml(contents, file_name, None, None);
-            hash_token_stream(&qml, &mut hashtab);
+            let qml = tokenize_qml(contents.to_string(), file_name, None, None, extensions);
+            hash_token_stream(&qml, hashtab);
         }
 
         true
@@ -32,3 +106,86 @@ pub unsafe fn include_if_building_hashtab(file_name: &str, raw_contents: *const
         false
     }
 }
+
+/// Opens `path`, returning a handle passed to the other callbacks, or NULL if it cannot be opened.
+pub type CVfsOpenFunc = unsafe extern "C" fn(path: *const c_char) -> *mut c_void;
+/// Reads up to `size` bytes into `buffer`. Returns the amount read, 0 at the end of the file,
+/// or a negative value on error.
+pub type CVfsReadFunc =
+    unsafe extern "C" fn(handle: *mut c_void, buffer: *mut c_char, size: usize) -> isize;
+pub type CVfsCloseFunc = unsafe extern "C" fn(handle: *mut c_void);
+pub type CVfsListEntryFunc = unsafe extern "C" fn(name: *const c_char, context: *mut c_void);
+/// Calls `emit` with the name of every file within `directory`, passing `context` along.
+/// Returns false if the directory cannot be listed.
+pub type CVfsListFunc = unsafe extern "C" fn(
+    directory: *const c_char,
+    emit: CVfsListEntryFunc,
+    context: *mut c_void,
+) -> bool;
+
+/// The host-provided file system. Files it cannot provide are read from the real file system.
+#[derive(Clone, Copy, Default)]
+pub struct CVirtualFileSystem {
+    pub open: Option<CVfsOpenFunc>,
+    pub read: Option<CVfsReadFunc>,
+    pub close: Option<CVfsCloseFunc>,
+    pub list: Option<CVfsListFunc>,
+}
+
+unsafe extern "C" fn collect_directory_entry(name: *const c_char, context: *mut c_void) {
+    let entries = &mut *(context as *mut Vec<String>);
+    entries.push(CStr::from_ptr(name).to_string_lossy().into());
+}
+
+impl CVirtualFileSystem {
+    pub fn is_set(&self) -> bool {
+        self.open.is_some() || self.list.is_some()
+    }
+
+    pub fn read_file(&self, path: &str) -> Option<Vec<u8>> {
+        let (open, read) = (self.open?, self.read?);
+        let c_path = CString::new(path).ok()?;
+        let handle = unsafe { open(c_path.as_ptr()) };
+        if handle.is_null() {
+            return None;
+        }
+        let mut data = Vec::new();
+        let mut buffer = vec![0u8; 64 * 1024];
+        let complete = loop {
+            let read_bytes =
+                unsafe { read(handle, buffer.as_mut_ptr() as *mut c_char, buffer.len()) };
+            match read_bytes {
+                0 => break true,
+                n if n < 0 => break false,
+                n => data.extend_from_slice(&buffer[..n as usize]),
+            }
+        };
+        if let Some(close) = self.close {
+            unsafe { close(handle) };
+        }
+        if !complete {
+            eprintln!("[qmldiff]: Error while reading {} from the host VFS", path);
+            return None;
+        }
+        Some(data)
+    }
+
+    pub fn read_to_string(&self, path: &str) -> Option<String> {
+        self.read_file(path)
+            .map(|data| String::from_utf8_lossy(&data).into())
+    }
+
+    pub fn list_directory(&self, directory: &str) -> Option<Vec<String>> {
+        let list = self.list?;
+        let c_directory = CString::new(directory).ok()?;
+        let mut entries: Vec<String> = Vec::new();
+        let listed = unsafe {
+            list(
+                c_directory.as_ptr(),
+                collect_directory_entry,
+                &mut entries as *mut Vec<String> as *mut c_void,
+            )
+        };
+        listed.then_some(entries)
+    }
+}
@@ -0,0 +1,633 @@
+//! `qmldiff lsp` - a language server for the diffs, speaking the Language Server Protocol over
+//! stdio. Editors get the errors of the diffs while they're being written: the errors of parsing
+//! them, and - with a QML root - the selectors which don't resolve against its files. Keywords and
+//! the names of the slots are completed.
+
+use std::{
+    cell::Cell,
+    collections::{BTreeMap, BTreeSet},
+    fs::read_to_string,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    sync::Arc,
+};
+
+use anyhow::{Error, Result};
+
+use crate::{
+    hashtab::HashTab,
+    id_check::IdCheck,
+    parser::{
+        common::StringCharacterTokenizer,
+        diff::{
+            hash_processor::resolve_diff_hashes,
+            lexer::{Lexer, TokenType},
+            parser::{Change, ObjectToChange, Parser},
+        },
+        qml::{emitter::EmitterConfig, lexer::QMLDiffExtensions},
+    },
+    processor::{find_and_process, RebuildOrder},
+    slots::Slots,
+    util::{
+        common_util::{error_details, tokenize_qml},
+        json::JsonValue,
+    },
+};
+
+/// The keywords offered by the completion.
+const KEYWORDS: &[&str] = &[
+    "AFFECT",
+    "TRAVERSE",
+    "ASSERT",
+    "INSERT",
+    "SLOT",
+    "TEMPLATE",
+    "LOCATE",
+    "IMPORT",
+    "PRAGMA",
+    "RENAME",
+    "TRANSFORM",
+    "TRANSLATE",
+    "USING",
+    "LOAD",
+    "EXTERNAL",
+    "ALL",
+    "BEFORE",
+    "AFTER",
+    "ANCHOR",
+    "FUNCTION",
+    "SIGNAL",
+    "PROPERTY",
+    "ROOT",
+    "NOT",
+    "ELSE",
+    "REMOVE",
+    "REPLICATE",
+    "MULTIPLE",
+    "REPLACE",
+    "STRING",
+    "WITH",
+    "TO",
+    "END",
+    "VERSION",
+    "REQUIRE",
+    "META",
+    "PRIORITY",
+    "ENV",
+    "DEFINE",
+    "CREATE",
+    "REDIRECT",
+    "PUSH",
+    "POP",
+    "SCOPE",
+    "LOCAL",
+    "SORTED",
+    "BY",
+    "ADD",
+    "SINGLETON",
+    "PLUGIN",
+    "ENTRY",
+    "SUFFIX",
+    "PROPAGATE",
+    "VALUE",
+    "UNTIL",
+    "ARGUMENT",
+    "AT",
+    "LOCATED",
+    "REBUILD",
+    "REDEFINE",
+];
+
+const SEVERITY_ERROR: usize = 1;
+const SEVERITY_WARNING: usize = 2;
+const COMPLETION_KEYWORD: usize = 14;
+const COMPLETION_VARIABLE: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// Counted from 0, like the lines of the protocol.
+    pub line: usize,
+    pub severity: usize,
+    pub message: String,
+}
+
+pub struct LanguageServer {
+    hashtab: HashTab,
+    /// The files the selectors are resolved against.
+    qml_root: Option<PathBuf>,
+    /// The contents of the open documents, by their URIs.
+    documents: BTreeMap<String, String>,
+}
+
+fn read_message(input: &mut impl BufRead) -> Result<Option<JsonValue>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse::<usize>()?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| Error::msg("A message without a Content-Length header"))?;
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    JsonValue::parse(&String::from_utf8(body)?).map(Some)
+}
+
+fn write_message(output: &mut impl Write, message: &JsonValue) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    output.flush()?;
+    Ok(())
+}
+
+/// The path of a `file://` URI.
+fn uri_to_path(uri: &str) -> PathBuf {
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let decoded = (byte == b'%')
+            .then(|| tail.get(..2))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match decoded {
+            Some(decoded) => {
+                bytes.push(decoded);
+                rest = &tail[2..];
+            }
+            None => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// How many lines the token spans.
+fn newlines(token: &TokenType) -> usize {
+    match token {
+        TokenType::NewLine(_) => 1,
+        TokenType::Whitespace(text) | TokenType::String(text) => text.matches('\n').count(),
+        TokenType::QMLCode { qml_code, .. } => qml_code
+            .iter()
+            .map(|e| e.to_string().matches('\n').count())
+            .sum(),
+        _ => 0,
+    }
+}
+
+/// The line an error of the lexer points to - `... starting on line 3`.
+fn line_in_message(error: &Error) -> usize {
+    let message = error.to_string();
+    message
+        .split("on line ")
+        .nth(1)
+        .and_then(|e| {
+            let digits = e.find(|c: char| !c.is_ascii_digit()).unwrap_or(e.len());
+            e[..digits].parse::<usize>().ok()
+        })
+        .map_or(0, |e| e.saturating_sub(1))
+}
+
+fn collapse_whitespace(string: &str) -> String {
+    string.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The first line from `from` on whose statement starts with `statement`.
+fn find_statement(text: &str, from: usize, statement: &str) -> Option<usize> {
+    let statement = collapse_whitespace(statement.lines().next().unwrap_or_default());
+    text.lines()
+        .enumerate()
+        .skip(from)
+        .find(|(_, line)| collapse_whitespace(line).starts_with(&statement))
+        .map(|e| e.0)
+}
+
+/// The names declared by the `SLOT` and `TEMPLATE` statements of the diff. Read from its text, so
+/// that they can be completed while the diff doesn't parse.
+fn declared_slots(text: &str) -> impl Iterator<Item = &str> {
+    text.lines().filter_map(|line| {
+        let mut words = line.split_whitespace();
+        match (words.next(), words.next()) {
+            (Some("SLOT" | "TEMPLATE"), Some(name)) => Some(name),
+            _ => None,
+        }
+    })
+}
+
+impl LanguageServer {
+    pub fn new(hashtab: HashTab, qml_root: Option<PathBuf>) -> Self {
+        Self {
+            hashtab,
+            qml_root,
+            documents: BTreeMap::new(),
+        }
+    }
+
+    /// Serves the client until it exits, or closes the input.
+    pub fn run(&mut self, input: &mut impl BufRead, output: &mut impl Write) -> Result<()> {
+        while let Some(message) = read_message(input)? {
+            let method = message.get("method").and_then(|e| e.as_str());
+            if method == Some("exit") {
+                break;
+            }
+            let params = message.get("params").unwrap_or(&JsonValue::Null);
+            let mut notifications = Vec::new();
+            let result = self.handle(method.unwrap_or_default(), params, &mut notifications);
+            // Notifications don't get a response.
+            if let Some(id) = message.get("id") {
+                let (key, value) = match result {
+                    Ok(Some(result)) => ("result", result),
+                    Ok(None) => ("error", error_response(-32601, "Method not found")),
+                    Err(error) => ("error", error_response(-32603, &format!("{:#}", error))),
+                };
+                write_message(
+                    output,
+                    &JsonValue::object([
+                        ("jsonrpc", "2.0".into()),
+                        ("id", id.clone()),
+                        (key, value),
+                    ]),
+                )?;
+            }
+            for notification in notifications {
+                write_message(output, &notification)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Handles a request or a notification. `None` if the method isn't supported.
+    fn handle(
+        &mut self,
+        method: &str,
+        params: &JsonValue,
+        notifications: &mut Vec<JsonValue>,
+    ) -> Result<Option<JsonValue>> {
+        let uri = params
+            .get("textDocument")
+            .and_then(|e| e.get("uri"))
+            .and_then(|e| e.as_str())
+            .unwrap_or_default()
+            .to_string();
+        match method {
+            "initialize" => Ok(Some(JsonValue::object([
+                (
+                    "capabilities",
+                    JsonValue::object([
+                        // The whole document is sent on every change.
+                        ("textDocumentSync", 1.into()),
+                        (
+                            "completionProvider",
+                            JsonValue::object([(
+                                "triggerCharacters",
+                                vec!["{".into(), " ".into()].into(),
+                            )]),
+                        ),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    JsonValue::object([("name", "qmldiff".into())]),
+                ),
+            ]))),
+            "shutdown" | "initialized" => Ok(Some(JsonValue::Null)),
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let text = match method {
+                    "textDocument/didOpen" => params
+                        .get("textDocument")
+                        .and_then(|e| e.get("text"))
+                        .and_then(|e| e.as_str()),
+                    _ => params
+                        .get("contentChanges")
+                        .and_then(|e| e.as_array())
+                        .and_then(|e| e.last())
+                        .and_then(|e| e.get("text"))
+                        .and_then(|e| e.as_str()),
+                };
+                let text = text.ok_or_else(|| Error::msg("The text of the document is missing"))?;
+                self.documents.insert(uri.clone(), text.to_string());
+                notifications.push(self.publish_diagnostics(&uri));
+                Ok(Some(JsonValue::Null))
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                notifications.push(self.publish_diagnostics(&uri));
+                Ok(Some(JsonValue::Null))
+            }
+            "textDocument/completion" => {
+                let position = params.get("position");
+                let coordinate = |name| {
+                    position
+                        .and_then(|e| e.get(name))
+                        .and_then(|e| e.as_usize())
+                        .unwrap_or_default()
+                };
+                Ok(Some(self.complete(
+                    &uri,
+                    coordinate("line"),
+                    coordinate("character"),
+                )))
+            }
+            // Optional notifications, like `$/cancelRequest`.
+            method if method.starts_with("$/") => Ok(Some(JsonValue::Null)),
+            _ => Ok(None),
+        }
+    }
+
+    fn publish_diagnostics(&self, uri: &str) -> JsonValue {
+        let diagnostics = match self.documents.get(uri) {
+            Some(text) => self.diagnose(uri, text),
+            None => Vec::new(),
+        };
+        let lines: Vec<&str> = self
+            .documents
+            .get(uri)
+            .map(|e| e.lines().collect())
+            .unwrap_or_default();
+        let diagnostics = diagnostics
+            .into_iter()
+            .map(|e| {
+                let length = lines.get(e.line).map_or(0, |e| e.encode_utf16().count());
+                let position = |character: usize| {
+                    JsonValue::object([("line", e.line.into()), ("character", character.into())])
+                };
+                JsonValue::object([
+                    (
+                        "range",
+                        JsonValue::object([("start", position(0)), ("end", position(length))]),
+                    ),
+                    ("severity", e.severity.into()),
+                    ("source", "qmldiff".into()),
+                    ("message", e.message.into()),
+                ])
+            })
+            .collect::<Vec<_>>();
+        JsonValue::object([
+            ("jsonrpc", "2.0".into()),
+            ("method", "textDocument/publishDiagnostics".into()),
+            (
+                "params",
+                JsonValue::object([("uri", uri.into()), ("diagnostics", diagnostics.into())]),
+            ),
+        ])
+    }
+
+    /// Parses the diff, keeping track of the line the parser is at - so that its errors can be
+    /// shown where they happened.
+    fn parse(&self, path: &Path, text: &str) -> Result<Vec<Change>, (usize, Error)> {
+        let name = path.to_string_lossy().to_string();
+        let tokens = Lexer::new(StringCharacterTokenizer::new(text.to_string()))
+            .tokenize()
+            .map_err(|e| (line_in_message(&e), e))?;
+        let tokens =
+            resolve_diff_hashes(&self.hashtab, tokens, &name, false).map_err(|e| (0, e))?;
+        let line = Rc::new(Cell::new(0));
+        let counter = line.clone();
+        let tokens = tokens
+            .into_iter()
+            .inspect(move |token| counter.set(counter.get() + newlines(token)));
+        let mut parser = Parser::new(
+            Box::new(tokens),
+            path.parent().map(|e| e.to_string_lossy().to_string()),
+            Arc::new(name),
+            Some(&self.hashtab),
+            None,
+        );
+        parser.parse(None, Vec::new()).map_err(|e| (line.get(), e))
+    }
+
+    /// The errors of the diff, and the selectors which don't resolve against the QML root.
+    pub fn diagnose(&self, uri: &str, text: &str) -> Vec<Diagnostic> {
+        let path = uri_to_path(uri);
+        let changes = match self.parse(&path, text) {
+            Ok(changes) => changes,
+            Err((line, error)) => {
+                return vec![Diagnostic {
+                    line,
+                    severity: SEVERITY_ERROR,
+                    message: format!("{:#}", error),
+                }]
+            }
+        };
+        let Some(qml_root) = &self.qml_root else {
+            return Vec::new();
+        };
+
+        // The slots can be declared by the other open diffs.
+        let mut changes: Vec<Change> = self
+            .documents
+            .iter()
+            .filter(|(other, _)| *other != uri)
+            .filter_map(|(other, text)| self.parse(&uri_to_path(other), text).ok())
+            .flatten()
+            .filter(|e| {
+                matches!(
+                    e.destination,
+                    ObjectToChange::Slot(_) | ObjectToChange::Template(_)
+                )
+            })
+            .chain(changes)
+            .collect();
+        let mut slots = Slots::new();
+        slots.update_slots(&mut changes);
+        if let Err(error) = slots.process_slots(&mut changes) {
+            return vec![Diagnostic {
+                line: 0,
+                severity: SEVERITY_ERROR,
+                message: format!("{:#}", error),
+            }];
+        }
+
+        let source = path.to_string_lossy();
+        let mut diagnostics = Vec::new();
+        for change in &changes {
+            // The changes of LOADed files can't be pointed to.
+            if change.source.as_str() != source {
+                continue;
+            }
+            let ObjectToChange::File(file) = &change.destination else {
+                continue;
+            };
+            let affect = text
+                .lines()
+                .position(|e| e.trim_start().starts_with("AFFECT") && e.contains(file.as_str()))
+                .unwrap_or(0);
+            let Ok(contents) = read_to_string(qml_root.join(file.trim_start_matches('/'))) else {
+                diagnostics.push(Diagnostic {
+                    line: affect,
+                    severity: SEVERITY_WARNING,
+                    message: format!("{} does not exist in the QML root", file),
+                });
+                continue;
+            };
+            let tokens = tokenize_qml(contents, file, None, None, QMLDiffExtensions::NONE);
+            if let Err(error) = find_and_process(
+                file,
+                tokens,
+                std::slice::from_ref(change),
+                &mut slots,
+                None,
+                None,
+                &EmitterConfig::default(),
+                IdCheck::Off,
+                None,
+                RebuildOrder::First,
+            ) {
+                let details = error_details(&error);
+                diagnostics.push(Diagnostic {
+                    line: details
+                        .directive
+                        .and_then(|e| find_statement(text, affect, &e))
+                        .unwrap_or(affect),
+                    severity: SEVERITY_ERROR,
+                    message: details.message,
+                });
+            }
+        }
+        diagnostics
+    }
+
+    /// The keywords, or the names of the slots after `INSERT SLOT` and `~{`.
+    pub fn complete(&self, uri: &str, line: usize, character: usize) -> JsonValue {
+        let line = self
+            .documents
+            .get(uri)
+            .and_then(|e| e.lines().nth(line))
+            .unwrap_or_default();
+        let prefix: String = line.chars().take(character).collect();
+        let context = prefix.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+        let slot_context = context.ends_with("~{")
+            || collapse_whitespace(context).ends_with("INSERT SLOT")
+                && context.ends_with(char::is_whitespace);
+        let items: Vec<JsonValue> = if slot_context {
+            let slots: BTreeSet<&str> = self
+                .documents
+                .values()
+                .flat_map(|e| declared_slots(e))
+                .collect();
+            slots
+                .into_iter()
+                .map(|e| completion_item(e, COMPLETION_VARIABLE))
+                .collect()
+        } else {
+            KEYWORDS
+                .iter()
+                .map(|e| completion_item(e, COMPLETION_KEYWORD))
+                .collect()
+        };
+        JsonValue::object([("isIncomplete", false.into()), ("items", items.into())])
+    }
+}
+
+fn completion_item(label: &str, kind: usize) -> JsonValue {
+    JsonValue::object([("label", label.into()), ("kind", kind.into())])
+}
+
+fn error_response(code: i64, message: &str) -> JsonValue {
+    JsonValue::object([
+        ("code", JsonValue::Number(code as f64)),
+        ("message", message.into()),
+    ])
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use super::{LanguageServer, KEYWORDS};
+    use crate::{hashtab::HashTab, parser::diff::lexer::Keyword, util::json::JsonValue};
+
+    fn message(body: &str) -> String {
+        format!("Content-Length: {}\r\n\r\n{}", body.len(), body)
+    }
+
+    #[test]
+    fn test_keywords() {
+        for keyword in KEYWORDS {
+            assert!(Keyword::try_from(*keyword).is_ok(), "{}", keyword);
+        }
+    }
+
+    #[test]
+    fn test_diagnostics() {
+        let root = std::env::temp_dir().join("qmldiff_lsp_test");
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("Main.qml"), "Item {\n    Rectangle {}\n}\n").unwrap();
+        let server = LanguageServer::new(HashTab::new(), Some(root.clone()));
+
+        let diagnostics = server.diagnose(
+            "file:///tmp/a.qmd",
+            "AFFECT Main.qml\n    TRAVERSE Item > Rectangle\n        LOCATE AFTR ALL\n    END TRAVERSE\nEND AFFECT\n",
+        );
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].line, 2);
+
+        let diagnostics = server.diagnose(
+            "file:///tmp/a.qmd",
+            "AFFECT Main.qml\n    TRAVERSE Item > Rectangle\n    END TRAVERSE\n    TRAVERSE Item > Text\n    END TRAVERSE\nEND AFFECT\nAFFECT Missing.qml\nEND AFFECT\n",
+        );
+        assert_eq!(
+            diagnostics.iter().map(|e| e.line).collect::<Vec<_>>(),
+            [3, 6]
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_session() {
+        let open = r#"{"jsonrpc":"2.0","method":"textDocument/didOpen","params":{"textDocument":{"uri":"file:///tmp/a.qmd","text":"SLOT buttons\nEND SLOT\nAFFECT Main.qml\n    TRAVERSE Item\n        INSERT SLOT \n"}}}"#;
+        let complete = r#"{"jsonrpc":"2.0","id":2,"method":"textDocument/completion","params":{"textDocument":{"uri":"file:///tmp/a.qmd"},"position":{"line":4,"character":20}}}"#;
+        let input = [
+            message(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#),
+            message(open),
+            message(complete),
+            message(r#"{"jsonrpc":"2.0","id":3,"method":"unknown"}"#),
+            message(r#"{"jsonrpc":"2.0","method":"exit"}"#),
+        ]
+        .concat();
+        let mut output = Vec::new();
+        LanguageServer::new(HashTab::new(), None)
+            .run(&mut Cursor::new(input), &mut output)
+            .unwrap();
+
+        let output = String::from_utf8(output).unwrap();
+        let messages: Vec<JsonValue> = output
+            .split("Content-Length: ")
+            .skip(1)
+            .map(|e| JsonValue::parse(e.split_once("\r\n\r\n").unwrap().1).unwrap())
+            .collect();
+        assert_eq!(messages.len(), 4);
+        assert!(messages[0]
+            .get("result")
+            .and_then(|e| e.get("capabilities"))
+            .is_some());
+        // The diff isn't finished yet.
+        let diagnostics = messages[1]
+            .get("params")
+            .unwrap()
+            .get("diagnostics")
+            .unwrap();
+        assert_eq!(diagnostics.as_array().unwrap().len(), 1);
+        let items = messages[2].get("result").unwrap().get("items").unwrap();
+        assert_eq!(
+            items.as_array().unwrap()[0].get("label").unwrap().as_str(),
+            Some("buttons")
+        );
+        assert_eq!(
+            messages[3].get("error").unwrap().get("code"),
+            Some(&JsonValue::Number(-32601.0))
+        );
+    }
+}
@@ -1 +1,3 @@
 pub mod common_util;
+pub mod json;
+pub mod output;
@@ -0,0 +1,63 @@
+//! Where the messages go. Only the data a command is asked for (a dumped hashtab, a processed
+//! file...) is printed to stdout - so that it can be piped into other tools. Everything else
+//! goes to stderr, filtered by the verbosity.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum Verbosity {
+    /// Only errors.
+    Quiet,
+    /// Warnings and the progress of the command.
+    #[default]
+    Normal,
+    /// Also every file being read, hashed or recursed into.
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+pub fn verbosity() -> Verbosity {
+    match VERBOSITY.load(Ordering::Relaxed) {
+        0 => Verbosity::Quiet,
+        1 => Verbosity::Normal,
+        _ => Verbosity::Verbose,
+    }
+}
+
+/// Prints a warning to stderr, unless running quietly.
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        if $crate::util::output::verbosity() >= $crate::util::output::Verbosity::Normal {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints the progress of the command to stderr, unless running quietly.
+#[allow(unused_macros)]
+macro_rules! info {
+    ($($arg:tt)*) => {
+        if $crate::util::output::verbosity() >= $crate::util::output::Verbosity::Normal {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// Prints the details of the progress to stderr, only when running verbosely.
+#[allow(unused_macros)]
+macro_rules! verbose {
+    ($($arg:tt)*) => {
+        if $crate::util::output::verbosity() >= $crate::util::output::Verbosity::Verbose {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+// `info!` and `verbose!` are only used by the CLI.
+#[allow(unused_imports)]
+pub(crate) use {info, verbose, warning};
@@ -0,0 +1,27 @@
+//! Applies the diffs of `tests/golden` to the QML files next to them, and compares the results
+//! with the expected files. New tests are added to `tests/golden/tests.manifest`.
+#![cfg(feature = "reporting")]
+
+use std::path::Path;
+
+use qmldiff::diff_tests::{parse_manifest, run_test};
+
+#[test]
+fn golden_files() {
+    let manifest = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/golden/tests.manifest");
+    let tests = parse_manifest(&manifest).unwrap();
+    assert!(!tests.is_empty());
+
+    let mut failures = Vec::new();
+    for test in &tests {
+        match run_test(test) {
+            Ok(test_failures) => failures.extend(
+                test_failures
+                    .into_iter()
+                    .map(|failure| format!("{}: {}", test.name, failure)),
+            ),
+            Err(error) => failures.push(format!("{}: {:?}", test.name, error)),
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n"));
+}
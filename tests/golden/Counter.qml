@@ -0,0 +1,7 @@
+import QtQuick 2.15
+
+Item {
+    id: counter
+    property int step: 1
+    width: 100
+}
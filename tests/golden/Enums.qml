@@ -0,0 +1,13 @@
+import QtQuick 2.15
+
+Item {
+    id: root
+
+    enum Mode {
+        Idle,
+        Busy = 3,
+        Done
+    }
+
+    property int mode: Item.Idle
+}
@@ -0,0 +1,23 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+
+    Text {
+        id: title
+        anchors { left: parent.left; right: parent.right } // Stretched
+        font {
+            // Keep in sync with the subtitle { }
+            pixelSize: 24
+            family: "Sans { Bold }"
+        }
+    }
+
+    Text {
+        id: subtitle
+        anchors.left: parent.left
+        font { pixelSize: 12 /* small */ }
+    }
+
+    border { width: 1; color: "gray" }
+}
@@ -0,0 +1,37 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+    width: 400
+    height: 300
+    property int count: 0
+
+    function increment() {
+        count += 1;
+    }
+
+    Column {
+        id: content
+
+        Text {
+            id: title
+            text: "Welcome"
+        }
+
+        Text {
+            id: subtitle
+            text: "Counter"
+        }
+
+        Rectangle {
+            id: divider
+            height: 1
+            color: "gray"
+        }
+    }
+
+    MouseArea {
+        anchors.fill: parent
+        onClicked: root.increment()
+    }
+}
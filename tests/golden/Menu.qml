@@ -0,0 +1,18 @@
+import QtQuick 2.15
+
+Column {
+    id: menu
+
+    Column {
+        id: numbered
+        Text { text: "Top"; order: 2 }
+        Text { text: "Bottom"; order: 10 }
+        Rectangle { id: footer }
+    }
+
+    Column {
+        id: named
+        Text { name: "beta" }
+        Text { name: "delta" }
+    }
+}
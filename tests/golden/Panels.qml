@@ -0,0 +1,17 @@
+import QtQuick 2.15
+
+Item {
+    Rectangle {
+        id: first
+        color: "red"
+
+        Text {
+            text: "Installed"
+        }
+    }
+
+    Rectangle {
+        id: second
+        color: "red"
+    }
+}
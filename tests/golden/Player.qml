@@ -0,0 +1,19 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: player
+    Column {
+        Row {
+            Slider {
+                id: volumeSlider
+                value: 0.5
+            }
+        }
+    }
+    Component {
+        id: delegate
+        Slider {
+            id: volumeSlider
+        }
+    }
+}
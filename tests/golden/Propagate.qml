@@ -0,0 +1,33 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+    property int count: 0
+    property int doubled: count * 2
+    width: root.count > 10 ? 200 : 100
+
+    function increment() {
+        count += 1;
+        console.log(root.count);
+    }
+
+    function reset(count) {
+        root.count = count;
+    }
+
+    function total() {
+        var count = 5;
+        return count + root.count;
+    }
+
+    Text {
+        text: "Clicked " + count + " times"
+        color: label.count > 0 ? "black" : "gray"
+    }
+
+    Text {
+        id: label
+        property int count: 3
+        text: count
+    }
+}
@@ -0,0 +1,23 @@
+import QtQuick 2.15
+
+Item {
+    id: root
+    property bool checked: false
+
+    states: [
+        State {
+            name: "on"
+            when: root.checked
+        },
+        State {
+            name: "off"
+            when: !root.checked
+        }
+    ]
+
+    transitions: [
+        Transition {
+            from: "on"
+        }
+    ]
+}
@@ -0,0 +1,21 @@
+import QtQuick 2.15
+
+Item {
+    Rectangle {
+        id: first
+        color: "red"
+
+        Text {
+            text: "Installed"
+        }
+    }
+
+    Rectangle {
+        id: second
+        color: "red"
+
+        Text {
+            text: "New"
+        }
+    }
+}
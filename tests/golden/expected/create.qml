@@ -0,0 +1,10 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: badge
+    color: "red"
+
+    Text {
+        text: "New"
+    }
+}
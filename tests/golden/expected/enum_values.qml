@@ -0,0 +1,14 @@
+import QtQuick 2.15
+
+Item {
+    id: root
+
+    enum Mode {
+        Idle,
+        Starting,
+        Working = 3,
+        Paused = 7
+    }
+
+    property int mode: Item.Idle
+}
@@ -0,0 +1,22 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+
+    Text {
+        id: title
+        anchors { left: parent.left; leftMargin: 8 }
+        font {
+            pixelSize: 24
+            family: "Sans { Bold }"
+        }
+    }
+
+    Text {
+        id: subtitle
+        anchors.left: parent.left
+        font { pixelSize: 12; italic: true }
+    }
+
+    border { width: 2; color: "gray" }
+}
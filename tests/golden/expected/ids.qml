@@ -0,0 +1,24 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: player
+    Column {
+        Row {
+            Slider {
+                id: volumeSlider
+                value: 0.5
+                stepSize: 0.1
+                Text {
+                    id: volumeLabel
+                    text: "Volume"
+                }
+            }
+        }
+    }
+    Component {
+        id: delegate
+        Slider {
+            id: volumeSlider
+        }
+    }
+}
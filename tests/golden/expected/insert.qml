@@ -0,0 +1,44 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+    width: 400
+    height: 300
+    property int count: 0
+    property bool enabled: true
+
+    function increment() {
+        count += 1;
+    }
+
+    Column {
+        id: content
+        spacing: 8
+
+        Text {
+            id: title
+            text: "Welcome"
+        }
+
+        Text {
+            id: greeting
+            text: "Hello"
+        }
+
+        Text {
+            id: subtitle
+            text: "Counter"
+        }
+
+        Rectangle {
+            id: divider
+            height: 1
+            color: "gray"
+        }
+    }
+
+    MouseArea {
+        anchors.fill: parent
+        onClicked: root.increment()
+    }
+}
@@ -0,0 +1,31 @@
+import QtQuick 2.15
+
+Item {
+    id: root
+    property bool checked: false
+
+    states: [
+        State {
+            name: "on"
+            when: root.checked
+
+            PropertyChanges {
+                target: root
+                opacity: 1
+            }
+        },
+        State {
+            name: "disabled"
+        }
+    ]
+
+    transitions: [
+        Transition {
+            from: "on"
+        },
+        Transition {
+            from: "off"
+            to: "on"
+        }
+    ]
+}
@@ -0,0 +1,33 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+    property int clicks: 0
+    property int doubled: clicks * 2
+    width: root.clicks > 10 ? 200 : 100
+
+    function increment() {
+        clicks += 1;
+        console.log(root.clicks);
+    }
+
+    function reset(count) {
+        root.clicks = count;
+    }
+
+    function total() {
+        var count = 5;
+        return count + root.clicks;
+    }
+
+    Text {
+        text: "Clicked " + clicks + " times"
+        color: label.count > 0 ? "black" : "gray"
+    }
+
+    Text {
+        id: label
+        property int count: 3
+        text: count
+    }
+}
@@ -0,0 +1,8 @@
+import QtQuick 2.15
+
+Item {
+    id: counter
+    property int step: 2
+    width: 100
+    height: width / 2
+}
@@ -0,0 +1,42 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+    width: 400
+    height: 300
+    property int clicks: 0
+
+    function originalIncrement() {
+        clicks += 1;
+    }
+
+    function increment() {
+        originalIncrement();
+        console.log(clicks);
+    }
+
+    Column {
+        id: content
+
+        Text {
+            id: title
+            text: "Welcome"
+        }
+
+        Text {
+            id: subtitle
+            text: "Counter"
+        }
+
+        Rectangle {
+            id: divider
+            height: 1
+            color: "gray"
+        }
+    }
+
+    MouseArea {
+        anchors.fill: parent
+        onClicked: root.increment()
+    }
+}
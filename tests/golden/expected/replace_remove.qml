@@ -0,0 +1,26 @@
+import QtQuick 2.15
+
+Rectangle {
+    id: root
+    width: 640
+    height: 300
+    property int count: 0
+
+    function increment() {
+        count += 1;
+    }
+
+    Column {
+        id: content
+
+        Text {
+            id: title
+            text: "Welcome"
+        }
+
+        Label {
+            id: subtitle
+            text: "Clicks: " + root.count
+        }
+    }
+}
@@ -0,0 +1,22 @@
+import QtQuick 2.15
+
+Column {
+    id: menu
+
+    Column {
+        id: numbered
+        Text { text: "Top"; order: 2 }
+        Text { text: "Middle"; order: 9 }
+        Text { text: "Bottom"; order: 10 }
+        Text { text: "Last"; order: 20 }
+        Rectangle { id: footer }
+    }
+
+    Column {
+        id: named
+        Text { name: "alpha" }
+        Text { name: "beta" }
+        Text { name: "delta" }
+        Text { name: "gamma" }
+    }
+}
@@ -0,0 +1,11 @@
+pragma ComponentBehavior: Bound
+import QtQuick 2.15
+
+Item {
+    id: root
+}
+component Badge: Rectangle {
+    radius: 4
+}
+component Label: Text {
+}